@@ -1,17 +1,37 @@
 use anyhow::*;
-use fs_extra::copy_items;
-use fs_extra::dir::CopyOptions;
 use std::env;
+use std::path::Path;
 
 fn main() -> Result<()> {
     println!("cargo:rerun-if-changed=res/*");
 
-    let out_dir = env::var("OUT_DIR")?;
-    let mut copy_options = CopyOptions::new();
-    copy_options.overwrite = true;
-    let mut paths_to_copy = Vec::new();
-    paths_to_copy.push("res/");
-    copy_items(&paths_to_copy, out_dir, &copy_options)?;
+    if env::var_os("CARGO_FEATURE_EMBEDDED_RESOURCES").is_some() {
+        write_embedded_resources(&env::var("OUT_DIR")?)?;
+    }
+
+    Ok(())
+}
+
+/// Writes a slice of every file in `res/` and its contents by path relative
+/// to `res/`, to be `include!`d by `resources.rs`.
+fn write_embedded_resources(out_dir: &str) -> Result<()> {
+    let res_dir = env::current_dir()?.join("res");
+    let mut entries = String::new();
+    for path in glob::glob("res/**/*")? {
+        let path = env::current_dir()?.join(path?);
+        if !path.is_file() {
+            continue;
+        }
+        let name = path
+            .strip_prefix(&res_dir)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        entries += &format!("({:?}, include_bytes!({:?})),\n", name, path);
+    }
+    std::fs::write(
+        Path::new(out_dir).join("embedded_resources.rs"),
+        format!("&[\n{}]\n", entries),
+    )?;
 
     Ok(())
 }
@@ -0,0 +1,564 @@
+//! Cached loading of models and textures behind reference-counted handles.
+//!
+//! Loading a path that is already cached hands out another handle to the
+//! same asset, so bodies share models and models share textures. An asset
+//! lives for as long as any handle to it, or to a model built from it, does.
+//!
+//! Files are read asynchronously and turned into GPU resources in
+//! [`AssetServer::update`], with placeholders standing in until then.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::rc::{Rc, Weak};
+use std::task::{Context, Poll, Waker};
+
+use cgmath::One;
+
+use crate::model::Model;
+use crate::resources::{self, MeshOptions, ModelSource, ResourceError};
+use crate::texture::{SamplerSettings, Texture};
+use crate::{physics, procedural};
+
+/// A shared reference to an asset of type `T` in an [`AssetServer`].
+pub struct Handle<T> {
+    id: Rc<usize>,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Handle<T> {
+    fn new(id: Rc<usize>) -> Self {
+        Self {
+            id,
+            _marker: PhantomData,
+        }
+    }
+
+    fn id(&self) -> usize {
+        *self.id
+    }
+}
+
+impl<T> Clone for Handle<T> {
+    fn clone(&self) -> Self {
+        Self::new(self.id.clone())
+    }
+}
+
+impl<T> PartialEq for Handle<T> {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.id, &other.id)
+    }
+}
+
+impl<T> Eq for Handle<T> {}
+
+impl<T> std::fmt::Debug for Handle<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Handle({})", self.id())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LoadState {
+    Loading,
+    Loaded,
+    /// Loading failed with this error; the placeholder, or the previous
+    /// version when reloading, is used instead.
+    Failed(ResourceError),
+}
+
+struct Entry<K, T> {
+    key: K,
+    handle: Weak<usize>,
+    state: LoadState,
+    asset: Option<Rc<T>>,
+    /// Textures the asset was built from, kept cached for as long as it is.
+    dependencies: Vec<Handle<Texture>>,
+    /// Other files the asset was read from, such as the levels of detail of
+    /// a model, which reload it too when they change.
+    files: Vec<String>,
+}
+
+/// The assets of one type, deduplicated by key.
+struct Assets<K, T> {
+    entries: Vec<Option<Entry<K, T>>>,
+    ids: HashMap<K, usize>,
+}
+
+impl<K: Hash + Eq + Clone, T> Assets<K, T> {
+    fn new() -> Self {
+        Self {
+            entries: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    /// The handle of the asset for `key`, and whether it was just added and
+    /// still has to be loaded.
+    fn handle(&mut self, key: &K) -> (Handle<T>, bool) {
+        let existing = self
+            .ids
+            .get(key)
+            .and_then(|&id| self.entries[id].as_ref()?.handle.upgrade());
+        if let Some(id) = existing {
+            return (Handle::new(id), false);
+        }
+
+        let id = match self.entries.iter().position(Option::is_none) {
+            Some(id) => id,
+            None => {
+                self.entries.push(None);
+                self.entries.len() - 1
+            }
+        };
+        let handle = Rc::new(id);
+        self.entries[id] = Some(Entry {
+            key: key.clone(),
+            handle: Rc::downgrade(&handle),
+            state: LoadState::Loading,
+            asset: None,
+            dependencies: Vec::new(),
+            files: Vec::new(),
+        });
+        self.ids.insert(key.clone(), id);
+        (Handle::new(handle), true)
+    }
+
+    fn entry(&self, handle: &Handle<T>) -> &Entry<K, T> {
+        self.entries[handle.id()]
+            .as_ref()
+            .expect("asset entries live as long as their handles")
+    }
+
+    /// The entry a load in flight was started for, unless every handle to it
+    /// has been dropped since.
+    fn entry_mut(&mut self, handle: &Weak<usize>) -> Option<&mut Entry<K, T>> {
+        let id = *handle.upgrade()?;
+        self.entries[id].as_mut()
+    }
+
+    fn get(&self, handle: &Handle<T>) -> Option<&Rc<T>> {
+        self.entry(handle).asset.as_ref()
+    }
+
+    /// Forgets the assets no handle refers to anymore.
+    fn collect_garbage(&mut self) {
+        for (id, slot) in self.entries.iter_mut().enumerate() {
+            if slot.as_ref().is_some_and(|e| e.handle.strong_count() == 0) {
+                let entry = slot.take().unwrap();
+                // The key may already map to a newer entry loaded after this
+                // one was dropped.
+                if self.ids.get(&entry.key) == Some(&id) {
+                    self.ids.remove(&entry.key);
+                }
+            }
+        }
+    }
+}
+
+type TextureKey = (String, SamplerSettings);
+type ModelKey = (String, MeshOptions);
+
+/// What a load in flight produces once it is read.
+enum Loaded {
+    Texture(Vec<u8>),
+    Model(ModelSource),
+}
+
+/// Which asset a load in flight is for.
+enum Target {
+    Texture(Weak<usize>, SamplerSettings),
+    Model(Weak<usize>),
+}
+
+struct PendingLoad {
+    target: Target,
+    future: Pin<Box<dyn Future<Output = Result<Loaded, ResourceError>>>>,
+}
+
+impl PendingLoad {
+    fn texture(handle: Weak<usize>, file_name: &str, sampler: SamplerSettings) -> Self {
+        let file_name = file_name.to_string();
+        Self {
+            target: Target::Texture(handle, sampler),
+            future: Box::pin(async move {
+                Ok(Loaded::Texture(resources::load_binary(&file_name).await?))
+            }),
+        }
+    }
+
+    fn model(handle: Weak<usize>, (file_name, options): &ModelKey) -> Self {
+        let (file_name, options) = (file_name.clone(), *options);
+        Self {
+            target: Target::Model(handle),
+            future: Box::pin(async move {
+                Ok(Loaded::Model(
+                    resources::load_model_source(&file_name, &options).await?,
+                ))
+            }),
+        }
+    }
+}
+
+/// A model whose meshes are read, waiting for its textures.
+struct PendingModel {
+    handle: Weak<usize>,
+    source: ModelSource,
+    /// Per material, absent for materials without a texture.
+    textures: Vec<Option<Handle<Texture>>>,
+}
+
+pub struct AssetServer {
+    textures: Assets<TextureKey, Texture>,
+    models: Assets<ModelKey, Model>,
+    pending: Vec<PendingLoad>,
+    pending_models: Vec<PendingModel>,
+    texture_bind_group_layout: wgpu::BindGroupLayout,
+    /// Stands in for textures that failed to load.
+    placeholder_texture: Rc<Texture>,
+    /// Bound by materials without a texture.
+    white_texture: Rc<Texture>,
+    placeholder_model: Model,
+}
+
+impl AssetServer {
+    pub fn new(device: &wgpu::Device, queue: &wgpu::Queue) -> Self {
+        let texture_bind_group_layout =
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Texture {
+                            multisampled: false,
+                            view_dimension: wgpu::TextureViewDimension::D2,
+                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                        },
+                        count: None,
+                    },
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 1,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                        count: None,
+                    },
+                ],
+                label: Some("texture_bind_group_layout"),
+            });
+
+        let placeholder_texture = Rc::new(Texture::checkerboard(device, queue, 64));
+        let white_texture = Rc::new(Texture::from_color(
+            device,
+            queue,
+            [255; 4],
+            "white_texture",
+        ));
+        let cube = physics::Shape::Cuboid(physics::Obb {
+            center: cgmath::Point3::new(0.0, 0.0, 0.0),
+            half_extents: cgmath::Vector3::new(1.0, 1.0, 1.0),
+            rotation: cgmath::Quaternion::one(),
+        });
+        let placeholder_model = Model {
+            meshes: vec![procedural::shape_mesh("placeholder", device, &cube, 0)],
+            materials: vec![resources::create_material(
+                device,
+                &texture_bind_group_layout,
+                "placeholder",
+                placeholder_texture.clone(),
+            )],
+            lods: Vec::new(),
+        };
+
+        Self {
+            textures: Assets::new(),
+            models: Assets::new(),
+            pending: Vec::new(),
+            pending_models: Vec::new(),
+            texture_bind_group_layout,
+            placeholder_texture,
+            white_texture,
+            placeholder_model,
+        }
+    }
+
+    /// Layout of the material bind group the models' textures are bound with.
+    pub fn texture_bind_group_layout(&self) -> &wgpu::BindGroupLayout {
+        &self.texture_bind_group_layout
+    }
+
+    /// Starts loading a texture, or shares the one already loaded from
+    /// `file_name` with the same sampler.
+    pub fn load_texture(&mut self, file_name: &str, sampler: SamplerSettings) -> Handle<Texture> {
+        let (handle, new) = self.textures.handle(&(file_name.to_string(), sampler));
+        if new {
+            self.pending.push(PendingLoad::texture(
+                Rc::downgrade(&handle.id),
+                file_name,
+                sampler,
+            ));
+        }
+        handle
+    }
+
+    /// Starts loading an OBJ model with its materials and levels of detail,
+    /// or shares the one already loaded from `file_name`.
+    pub fn load_model(&mut self, file_name: &str) -> Handle<Model> {
+        self.load_model_with(file_name, MeshOptions::default())
+    }
+
+    /// Like [`AssetServer::load_model`], generating the attributes the file
+    /// leaves out as `options` say.
+    pub fn load_model_with(&mut self, file_name: &str, options: MeshOptions) -> Handle<Model> {
+        let key = (file_name.to_string(), options);
+        let (handle, new) = self.models.handle(&key);
+        if new {
+            self.pending
+                .push(PendingLoad::model(Rc::downgrade(&handle.id), &key));
+        }
+        handle
+    }
+
+    /// Reads the assets loaded from `file_name` again, keeping the current
+    /// versions in use until the new ones are ready or if they fail to load.
+    /// Models are also rebuilt when one of their textures changes, and every
+    /// model when an MTL file does, since materials aren't tracked by file.
+    pub fn reload(&mut self, file_name: &str) {
+        for entry in self.textures.entries.iter().flatten() {
+            if entry.key.0 == file_name {
+                self.pending.push(PendingLoad::texture(
+                    entry.handle.clone(),
+                    file_name,
+                    entry.key.1,
+                ));
+            }
+        }
+        let is_material = file_name.ends_with(".mtl");
+        for entry in self.models.entries.iter().flatten() {
+            if is_material
+                || entry.key.0 == file_name
+                || entry.files.iter().any(|file| file == file_name)
+            {
+                self.pending
+                    .push(PendingLoad::model(entry.handle.clone(), &entry.key));
+            }
+        }
+    }
+
+    pub fn texture_state(&self, handle: &Handle<Texture>) -> &LoadState {
+        &self.textures.entry(handle).state
+    }
+
+    pub fn model_state(&self, handle: &Handle<Model>) -> &LoadState {
+        &self.models.entry(handle).state
+    }
+
+    /// The texture once it is loaded.
+    pub fn texture(&self, handle: &Handle<Texture>) -> Option<&Texture> {
+        self.textures.get(handle).map(|texture| &**texture)
+    }
+
+    /// The model once it is loaded.
+    pub fn model(&self, handle: &Handle<Model>) -> Option<&Model> {
+        self.models.get(handle).map(|model| &**model)
+    }
+
+    /// The model, or a checkered cube while it loads or if it failed to.
+    pub fn model_or_placeholder(&self, handle: &Handle<Model>) -> &Model {
+        self.model(handle).unwrap_or(&self.placeholder_model)
+    }
+
+    /// Whether any load is still in flight.
+    pub fn is_loading(&self) -> bool {
+        !self.pending.is_empty() || !self.pending_models.is_empty()
+    }
+
+    /// Finishes the loads whose files have been read and forgets assets that
+    /// are no longer used, returning the models that were built or rebuilt.
+    /// Call once per frame.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<Handle<Model>> {
+        let mut cx = Context::from_waker(Waker::noop());
+        for mut load in std::mem::take(&mut self.pending) {
+            match load.future.as_mut().poll(&mut cx) {
+                Poll::Ready(result) => self.finish(device, queue, load.target, result),
+                Poll::Pending => self.pending.push(load),
+            }
+        }
+        let built = self.build_models(device);
+
+        self.models.collect_garbage();
+        self.textures.collect_garbage();
+        built
+    }
+
+    /// Waits for every load in flight, including the textures of models that
+    /// finish meanwhile.
+    pub async fn load_all(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        while self.is_loading() {
+            for load in std::mem::take(&mut self.pending) {
+                let result = load.future.await;
+                self.finish(device, queue, load.target, result);
+            }
+            self.build_models(device);
+        }
+    }
+
+    fn finish(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        target: Target,
+        result: Result<Loaded, ResourceError>,
+    ) {
+        match (target, result) {
+            (Target::Texture(handle, sampler), Ok(Loaded::Texture(bytes))) => {
+                let Some(entry) = self.textures.entry_mut(&handle) else {
+                    return;
+                };
+                match Texture::from_bytes(device, queue, &bytes, &entry.key.0, &sampler) {
+                    Ok(texture) => {
+                        let reloaded = entry.asset.replace(Rc::new(texture)).is_some();
+                        entry.state = LoadState::Loaded;
+                        if reloaded {
+                            self.rebuild_dependents(&handle);
+                        }
+                    }
+                    Err(e) => fail(&mut entry.state, &entry.key.0, e),
+                }
+            }
+            (Target::Model(handle), Ok(Loaded::Model(source))) => {
+                let textures = source
+                    .materials
+                    .iter()
+                    .map(|m| {
+                        let (file_name, sampler) = resources::parse_diffuse_map(m);
+                        (!file_name.is_empty()).then(|| self.load_texture(file_name, sampler))
+                    })
+                    .collect();
+                self.pending_models.push(PendingModel {
+                    handle,
+                    source,
+                    textures,
+                });
+            }
+            (Target::Texture(handle, _), Err(e)) => {
+                if let Some(entry) = self.textures.entry_mut(&handle) {
+                    fail(&mut entry.state, &entry.key.0, e);
+                }
+            }
+            (Target::Model(handle), Err(e)) => {
+                if let Some(entry) = self.models.entry_mut(&handle) {
+                    fail(&mut entry.state, &entry.key.0, e);
+                }
+            }
+            (_, Ok(_)) => unreachable!("loads produce the asset type they target"),
+        }
+    }
+
+    /// Reads the models built from a reloaded texture again, to bind it in
+    /// their materials.
+    fn rebuild_dependents(&mut self, texture: &Weak<usize>) {
+        for entry in self.models.entries.iter().flatten() {
+            if entry
+                .dependencies
+                .iter()
+                .any(|dependency| Weak::ptr_eq(&Rc::downgrade(&dependency.id), texture))
+            {
+                self.pending
+                    .push(PendingLoad::model(entry.handle.clone(), &entry.key));
+            }
+        }
+    }
+
+    /// Uploads the models whose textures are all done, falling back to the
+    /// placeholder texture for those that failed, and returns them.
+    fn build_models(&mut self, device: &wgpu::Device) -> Vec<Handle<Model>> {
+        let mut built = Vec::new();
+        for mut model in std::mem::take(&mut self.pending_models) {
+            let ready = model
+                .textures
+                .iter()
+                .flatten()
+                .all(|texture| *self.texture_state(texture) != LoadState::Loading);
+            if !ready {
+                self.pending_models.push(model);
+                continue;
+            }
+            if model.handle.strong_count() == 0 {
+                continue;
+            }
+
+            let mut materials = model
+                .source
+                .materials
+                .iter()
+                .zip(&model.textures)
+                .map(|(m, texture)| {
+                    let texture = match texture {
+                        Some(texture) => self
+                            .textures
+                            .get(texture)
+                            .unwrap_or(&self.placeholder_texture),
+                        None => &self.white_texture,
+                    };
+                    resources::create_material(
+                        device,
+                        &self.texture_bind_group_layout,
+                        &m.name,
+                        texture.clone(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            if materials.is_empty() {
+                materials.push(resources::create_material(
+                    device,
+                    &self.texture_bind_group_layout,
+                    "default",
+                    self.white_texture.clone(),
+                ));
+            }
+            let files = std::mem::take(&mut model.source.lod_files);
+            let asset = model.source.upload(device, materials);
+
+            if let Some(entry) = self.models.entry_mut(&model.handle) {
+                entry.asset = Some(Rc::new(asset));
+                entry.state = LoadState::Loaded;
+                entry.dependencies = model.textures.into_iter().flatten().collect();
+                entry.files = files;
+            }
+            if let Some(id) = model.handle.upgrade() {
+                built.push(Handle::new(id));
+            }
+        }
+        built
+    }
+}
+
+fn fail(state: &mut LoadState, file_name: &str, error: ResourceError) {
+    log::warn!("Failed to load {}: {}", file_name, error);
+    *state = LoadState::Failed(error);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn collecting_a_dropped_entry_keeps_its_replacement() {
+        let mut assets = Assets::<&str, ()>::new();
+        let (first, new) = assets.handle(&"a");
+        assert!(new);
+        drop(first);
+
+        let (second, new) = assets.handle(&"a");
+        assert!(new);
+        assets.collect_garbage();
+
+        let (third, new) = assets.handle(&"a");
+        assert!(!new);
+        assert_eq!(second.id(), third.id());
+        assert!(assets.entry(&third).handle.strong_count() > 0);
+    }
+}
@@ -52,6 +52,8 @@ impl Camera {
 pub struct CameraUniform {
     view_position: [f32; 4],
     view_proj: [[f32; 4]; 4],
+    /// Maps clip space back to world space, for the skybox.
+    inv_view_proj: [[f32; 4]; 4],
 }
 
 impl CameraUniform {
@@ -59,12 +61,19 @@ impl CameraUniform {
         Self {
             view_position: [0.0; 4],
             view_proj: cgmath::Matrix4::identity().into(),
+            inv_view_proj: cgmath::Matrix4::identity().into(),
         }
     }
 
+    pub fn view_proj(&self) -> Matrix4<f32> {
+        self.view_proj.into()
+    }
+
     pub fn update_view_proj(&mut self, camera: &Camera, projection: &Projection) {
         self.view_position = camera.position.to_homogeneous().into();
-        self.view_proj = (projection.calc_matrix() * camera.calc_matrix()).into();
+        let view_proj = projection.calc_matrix() * camera.calc_matrix();
+        self.view_proj = view_proj.into();
+        self.inv_view_proj = view_proj.invert().unwrap_or_else(Matrix4::identity).into();
     }
 }
 
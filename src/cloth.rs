@@ -0,0 +1,208 @@
+//! The cloth demo: a flag flapping on a pole in gusty wind, and a sheet
+//! dropped over a ball and whatever bodies lie under it.
+
+use cgmath::{Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::model::{self, MeshData};
+use crate::physics::{self, BodyHandle, Capsule, Cloth, ClothSettings, Shape, Sphere, World};
+use crate::procedural;
+
+const TIMESTEP: f32 = 1.0 / 60.0;
+const MAX_STEPS: u32 = 2;
+
+const POLE: Capsule = Capsule {
+    a: Point3::new(-4.5, -1.0, 1.5),
+    b: Point3::new(-4.5, 4.2, 1.5),
+    radius: 0.08,
+};
+const BALL: Sphere = Sphere {
+    center: Point3::new(1.5, -0.2, 1.5),
+    radius: 0.8,
+};
+
+/// A cloth with the render mesh it is drawn with.
+struct Piece {
+    sim: Cloth,
+    mesh: model::Mesh,
+    /// Color at a point of the texture coordinates.
+    pattern: fn([f32; 2]) -> [f32; 3],
+}
+
+impl Piece {
+    fn new(
+        name: &str,
+        device: &wgpu::Device,
+        sim: Cloth,
+        pattern: fn([f32; 2]) -> [f32; 3],
+    ) -> Self {
+        let mesh = model::Mesh::from_colored_data(
+            name.to_string(),
+            device,
+            Self::mesh_data(&sim, pattern),
+            0,
+        );
+        Self { sim, mesh, pattern }
+    }
+
+    fn mesh_data(sim: &Cloth, pattern: fn([f32; 2]) -> [f32; 3]) -> MeshData {
+        let mut data = MeshData::from_cloth(sim);
+        data.colors = data.tex_coords.iter().map(|&uv| pattern(uv)).collect();
+        data
+    }
+}
+
+fn stripes(uv: [f32; 2]) -> [f32; 3] {
+    match (uv[1] * 3.0) as u32 {
+        0 => [0.7, 0.1, 0.1],
+        1 => [0.9, 0.9, 0.85],
+        _ => [0.1, 0.2, 0.6],
+    }
+}
+
+fn checks(uv: [f32; 2]) -> [f32; 3] {
+    if ((uv[0] * 8.0) as u32 + (uv[1] * 8.0) as u32).is_multiple_of(2) {
+        [0.85, 0.8, 0.6]
+    } else {
+        [0.35, 0.55, 0.3]
+    }
+}
+
+pub struct Cloths {
+    pieces: Vec<Piece>,
+    /// The pole and the ball, added to the world while the demo runs.
+    obstacles: Vec<(BodyHandle, model::Mesh)>,
+    instance_buffer: wgpu::Buffer,
+    accumulator: f32,
+    time: f32,
+}
+
+impl Cloths {
+    pub fn new(device: &wgpu::Device, world: &mut World) -> Self {
+        // Stiffer from more substeps, and caught by the wind enough to fly.
+        let mut flag_settings = ClothSettings::new(0.13);
+        flag_settings.substeps = 20;
+        flag_settings.drag = 1.0;
+        flag_settings.self_collision = false;
+        let mut flag = Cloth::grid(
+            POLE.b + Vector3::new(POLE.radius + 0.05, -0.2, 0.0),
+            Vector3::new(3.0, 0.0, 0.0),
+            Vector3::new(0.0, -2.0, 0.0),
+            24,
+            16,
+            flag_settings,
+        );
+        for row in 0..flag.rows() {
+            flag.pin(row, 0);
+        }
+        let sheet = Cloth::grid(
+            Point3::new(-0.1, 3.0, -0.1),
+            Vector3::new(3.2, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 3.2),
+            28,
+            28,
+            ClothSettings::new(0.12),
+        );
+        log::info!(
+            "Cloth particles: {}",
+            flag.particles.len() + sheet.particles.len()
+        );
+
+        let obstacles = [
+            ("pole", Shape::Capsule(POLE), [0.5, 0.45, 0.4]),
+            ("ball", Shape::Sphere(BALL), [0.8, 0.8, 0.75]),
+        ]
+        .into_iter()
+        .map(|(name, shape, color)| {
+            let mesh = procedural::shape_colored_mesh(name, device, &shape, color);
+            let body = physics::RigidBody::fixed(shape, physics::Transform::identity());
+            (world.add_body(body), mesh)
+        })
+        .collect();
+        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Cloth Instance Buffer"),
+            contents: bytemuck::cast_slice(&[model::Instance::new(None, None, 1.0).to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        Self {
+            pieces: vec![
+                Piece::new("flag", device, flag, stripes),
+                Piece::new("sheet", device, sheet, checks),
+            ],
+            obstacles,
+            instance_buffer,
+            accumulator: 0.0,
+            time: 0.0,
+        }
+    }
+
+    /// Takes the obstacles back out of the world.
+    pub fn remove(self, world: &mut World) {
+        for (handle, _) in self.obstacles {
+            world.remove_body(handle);
+        }
+    }
+
+    /// Steps the cloths against the bodies of `world` in fixed steps and
+    /// updates their meshes.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dt: f32,
+        world: &mut World,
+    ) {
+        self.accumulator = (self.accumulator + dt).min(TIMESTEP * MAX_STEPS as f32);
+        let mut stepped = false;
+        while self.accumulator >= TIMESTEP {
+            self.accumulator -= TIMESTEP;
+            self.time += TIMESTEP;
+            let t = self.time;
+            self.pieces[0].sim.settings.wind = Vector3::new(
+                8.0 + 3.0 * (1.3 * t).sin() * (0.4 * t).sin(),
+                0.0,
+                3.0 * (0.7 * t).sin(),
+            );
+            for piece in &mut self.pieces {
+                piece.sim.step(TIMESTEP, world);
+            }
+            stepped = true;
+        }
+        if !stepped {
+            return;
+        }
+        for piece in &mut self.pieces {
+            let data = Piece::mesh_data(&piece.sim, piece.pattern);
+            piece
+                .mesh
+                .update_vertices(device, queue, &data.colored_vertices());
+        }
+    }
+
+    /// Draws the cloths and the obstacles. Expects the vertex-colored
+    /// pipeline to be bound.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        shading_bind_group: &'a wgpu::BindGroup,
+    ) {
+        use model::DrawColoredModel;
+        render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+        let meshes = self
+            .pieces
+            .iter()
+            .map(|piece| &piece.mesh)
+            .chain(self.obstacles.iter().map(|(_, mesh)| mesh));
+        for mesh in meshes {
+            render_pass.draw_colored_mesh(
+                mesh,
+                camera_bind_group,
+                light_bind_group,
+                shading_bind_group,
+            );
+        }
+    }
+}
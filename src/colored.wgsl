@@ -0,0 +1,66 @@
+// Models colored per vertex instead of by a texture, compiled after
+// lighting.wgsl.
+
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+@group(1) @binding(0)
+var<uniform> light: Light;
+
+@group(2) @binding(0)
+var<uniform> shading: Shading;
+@group(2) @binding(1)
+var t_irradiance: texture_cube<f32>;
+@group(2) @binding(2)
+var t_prefiltered: texture_cube<f32>;
+@group(2) @binding(3)
+var t_brdf: texture_2d<f32>;
+@group(2) @binding(4)
+var s_environment: sampler;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec3<f32>,
+    @location(2) normal: vec3<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) vertex_color: vec3<f32>,
+    @location(1) world_normal: vec3<f32>,
+    @location(2) world_position: vec3<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) emissive_scalar: vec4<f32>,
+    @location(5) @interpolate(flat) material: u32,
+}
+
+@vertex
+fn vs_main(
+    model: VertexInput,
+    instance: InstanceInput,
+) -> VertexOutput {
+    var out: VertexOutput;
+    out.vertex_color = model.color;
+    out.world_normal = normalize(normal_matrix(instance) * model.normal);
+    let world_position = model_matrix(instance) * vec4<f32>(model.position, 1.0);
+    out.world_position = world_position.xyz;
+    out.clip_position = camera.view_proj * world_position;
+    out.color = instance.color;
+    out.emissive_scalar = instance.emissive_scalar;
+    out.material = instance.material;
+    return out;
+}
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    var object_color = vec4<f32>(in.vertex_color, 1.0);
+    if (instance_color_map(in.material) == COLOR_MAP_VIRIDIS) {
+        object_color = vec4<f32>(viridis(in.emissive_scalar.w), 1.0);
+    }
+    object_color = object_color * in.color;
+
+    let result = light_surface(object_color.rgb, in.world_normal, in.world_position, instance_material(in.material))
+        + in.emissive_scalar.xyz;
+
+    return vec4<f32>(result, object_color.a);
+}
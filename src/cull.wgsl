@@ -0,0 +1,92 @@
+// Frustum culling compute shader. Copies the instances whose bounding sphere
+// touches the view frustum into the region of `visible` for their level of
+// detail and counts them in the indirect draw arguments of the level's meshes.
+
+// Words in one `InstanceRaw`; the model matrix comes first.
+const INSTANCE_WORDS: u32 = 34u;
+// Word holding the alpha of the instance color.
+const ALPHA_WORD: u32 = 28u;
+// Words in one `DrawIndexedIndirect`; the instance count is the second.
+const ARGS_WORDS: u32 = 5u;
+
+const MAX_LODS: u32 = 4u;
+
+struct LodRange {
+    first_args: u32,
+    mesh_count: u32,
+    // Drawn below this fraction of the screen height.
+    screen_size: f32,
+    _padding: u32,
+}
+
+struct Cull {
+    // xyz: inward normal, w: distance
+    planes: array<vec4<f32>, 6>,
+    // xyz: model space center, w: radius
+    sphere: vec4<f32>,
+    // xyz: camera position, w: cotangent of half the vertical field of view
+    eye: vec4<f32>,
+    lods: array<LodRange, MAX_LODS>,
+    instance_count: u32,
+    lod_count: u32,
+}
+@group(0) @binding(0)
+var<uniform> cull: Cull;
+@group(0) @binding(1)
+var<storage, read> instances: array<u32>;
+@group(0) @binding(2)
+var<storage, read_write> visible: array<u32>;
+@group(0) @binding(3)
+var<storage, read_write> args: array<atomic<u32>>;
+
+fn column(base: u32, i: u32) -> vec4<f32> {
+    let o = base + i * 4u;
+    return vec4<f32>(
+        bitcast<f32>(instances[o]),
+        bitcast<f32>(instances[o + 1u]),
+        bitcast<f32>(instances[o + 2u]),
+        bitcast<f32>(instances[o + 3u]),
+    );
+}
+
+@compute @workgroup_size(64)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= cull.instance_count) {
+        return;
+    }
+    let base = index * INSTANCE_WORDS;
+    // Translucent instances are drawn sorted by the transparent pass.
+    if (bitcast<f32>(instances[base + ALPHA_WORD]) < 1.0) {
+        return;
+    }
+    let model = mat4x4<f32>(column(base, 0u), column(base, 1u), column(base, 2u), column(base, 3u));
+
+    let center = (model * vec4<f32>(cull.sphere.xyz, 1.0)).xyz;
+    let scale = max(length(model[0].xyz), max(length(model[1].xyz), length(model[2].xyz)));
+    let radius = cull.sphere.w * scale;
+    for (var i = 0u; i < 6u; i++) {
+        if (dot(cull.planes[i].xyz, center) + cull.planes[i].w < -radius) {
+            return;
+        }
+    }
+
+    let distance = length(center - cull.eye.xyz);
+    var level = 0u;
+    if (distance > radius) {
+        let screen_size = radius * cull.eye.w / distance;
+        while (level + 1u < cull.lod_count && screen_size < cull.lods[level + 1u].screen_size) {
+            level++;
+        }
+    }
+
+    let lod = cull.lods[level];
+    let slot = atomicAdd(&args[lod.first_args * ARGS_WORDS + 1u], 1u);
+    for (var m = 1u; m < lod.mesh_count; m++) {
+        atomicAdd(&args[(lod.first_args + m) * ARGS_WORDS + 1u], 1u);
+    }
+    let dst = (level * cull.instance_count + slot) * INSTANCE_WORDS;
+    for (var w = 0u; w < INSTANCE_WORDS; w++) {
+        visible[dst + w] = instances[base + w];
+    }
+}
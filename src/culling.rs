@@ -0,0 +1,446 @@
+use std::ops::Range;
+
+use bytemuck::Zeroable;
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector4};
+
+use crate::model::{self, InstanceBuffer, InstanceRaw};
+use crate::physics::Sphere;
+
+/// The six planes of a view frustum with normals pointing inwards.
+#[derive(Clone, Copy, Debug)]
+pub struct Frustum {
+    planes: [Vector4<f32>; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of a wgpu projection, whose depth runs from 0 to 1.
+    pub fn from_matrix(view_proj: Matrix4<f32>) -> Self {
+        let [r0, r1, r2, r3] = [0, 1, 2, 3].map(|i| view_proj.row(i));
+        let planes = [r3 + r0, r3 - r0, r3 + r1, r3 - r1, r2, r3 - r2].map(|p| {
+            let length = p.truncate().magnitude();
+            if length > 0.0 {
+                p / length
+            } else {
+                p
+            }
+        });
+        Self { planes }
+    }
+
+    pub fn intersects_sphere(&self, sphere: Sphere) -> bool {
+        let center = sphere.center.to_vec();
+        self.planes
+            .iter()
+            .all(|p| p.truncate().dot(center) + p.w >= -sphere.radius)
+    }
+}
+
+/// What the camera sees, for culling and picking levels of detail.
+#[derive(Clone, Copy, Debug)]
+pub struct View {
+    pub frustum: Frustum,
+    pub eye: Point3<f32>,
+    /// Cotangent of half the vertical field of view.
+    pub projection_scale: f32,
+}
+
+impl View {
+    pub fn new(eye: Point3<f32>, view_proj: Matrix4<f32>, projection: Matrix4<f32>) -> Self {
+        Self {
+            frustum: Frustum::from_matrix(view_proj),
+            eye,
+            projection_scale: projection.y.y,
+        }
+    }
+
+    /// Fraction of the screen height covered by the sphere.
+    pub fn screen_size(&self, sphere: Sphere) -> f32 {
+        let distance = self.eye.distance(sphere.center);
+        if distance <= sphere.radius {
+            f32::INFINITY
+        } else {
+            sphere.radius * self.projection_scale / distance
+        }
+    }
+}
+
+/// Levels of detail the cull shader has room for; coarser ones are ignored.
+pub const MAX_LODS: usize = 4;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct LodRange {
+    first_args: u32,
+    mesh_count: u32,
+    screen_size: f32,
+    _padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct CullUniform {
+    planes: [[f32; 4]; 6],
+    sphere: [f32; 4],
+    eye: [f32; 4],
+    lods: [LodRange; MAX_LODS],
+    instance_count: u32,
+    lod_count: u32,
+    _padding: [u32; 2],
+}
+
+const WORKGROUP_SIZE: u32 = 64;
+
+// `cull.wgsl` copies instances as `INSTANCE_WORDS` words.
+const _: () = assert!(std::mem::size_of::<InstanceRaw>() == 34 * 4);
+
+type BufferIds = (wgpu::Id<wgpu::Buffer>, wgpu::Id<wgpu::Buffer>);
+
+struct GpuCuller {
+    pipeline: wgpu::ComputePipeline,
+    layout: wgpu::BindGroupLayout,
+    uniform_buffer: wgpu::Buffer,
+    indirect_buffer: wgpu::Buffer,
+    /// Draw arguments with zero instances, written before every dispatch.
+    reset_args: Vec<u8>,
+    lods: [LodRange; MAX_LODS],
+    /// Bind group and the instance buffers it was made for, which change
+    /// whenever they grow or the culler is used with another source.
+    bind_group: Option<(BufferIds, wgpu::BindGroup)>,
+}
+
+/// Keeps the opaque instances of one model whose bounding sphere is inside the
+/// view frustum and sorts them by level of detail.
+///
+/// Where compute shaders and indirect draws are available the visible
+/// instances are compacted on the GPU and drawn with `draw_indexed_indirect`.
+/// Otherwise, as on WebGL, they are tested on the CPU and drawn instanced.
+///
+/// Each level's instances occupy their own region of the visible buffer,
+/// bound from its start so that no draw needs a base instance.
+pub struct InstanceCuller {
+    local_sphere: Sphere,
+    lod_count: usize,
+    visible: InstanceBuffer,
+    /// Instances in the visible buffer per level. On the GPU only the starts
+    /// are known; the counts live in the indirect arguments.
+    regions: Vec<Range<usize>>,
+    gpu: Option<GpuCuller>,
+}
+
+impl InstanceCuller {
+    pub fn new(
+        device: &wgpu::Device,
+        downlevel: &wgpu::DownlevelCapabilities,
+        model: &model::Model,
+    ) -> Self {
+        let gpu_capable = downlevel.flags.contains(
+            wgpu::DownlevelFlags::COMPUTE_SHADERS | wgpu::DownlevelFlags::INDIRECT_EXECUTION,
+        );
+        let (visible, gpu) = if gpu_capable {
+            (
+                InstanceBuffer::with_usage(device, 1, wgpu::BufferUsages::STORAGE),
+                Some(GpuCuller::new(device, model)),
+            )
+        } else {
+            (InstanceBuffer::new(device, 1), None)
+        };
+        let lod_count = model.lod_count().min(MAX_LODS);
+        Self {
+            local_sphere: model.bounding_sphere(),
+            lod_count,
+            visible,
+            regions: vec![0..0; lod_count],
+            gpu,
+        }
+    }
+
+    /// Switches to culling instances of `model`, e.g. after it was reloaded
+    /// with different bounds or levels of detail.
+    pub fn set_model(&mut self, device: &wgpu::Device, model: &model::Model) {
+        if self.gpu.is_some() {
+            self.gpu = Some(GpuCuller::new(device, model));
+        }
+        self.local_sphere = model.bounding_sphere();
+        self.lod_count = model.lod_count().min(MAX_LODS);
+        self.regions = vec![0..0; self.lod_count];
+    }
+
+    pub fn is_gpu(&self) -> bool {
+        self.gpu.is_some()
+    }
+
+    /// Extra usages `source` needs for `cull`.
+    pub fn source_usage(&self) -> wgpu::BufferUsages {
+        if self.is_gpu() {
+            wgpu::BufferUsages::STORAGE
+        } else {
+            wgpu::BufferUsages::empty()
+        }
+    }
+
+    /// Culls `instances` of `model`, whose raw data is in `source`. The GPU
+    /// path records a compute pass into `encoder`, which must run before the
+    /// draw.
+    #[allow(clippy::too_many_arguments)]
+    pub fn cull<'i>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &View,
+        model: &model::Model,
+        instances: impl IntoIterator<Item = &'i model::Instance>,
+        source: &InstanceBuffer,
+    ) {
+        match &mut self.gpu {
+            Some(gpu) => {
+                let len = source.len();
+                self.visible.reserve(device, len * self.lod_count);
+                self.visible.set_len(len * self.lod_count);
+                for (level, region) in self.regions.iter_mut().enumerate() {
+                    *region = level * len..(level + 1) * len;
+                }
+                gpu.cull(
+                    device,
+                    queue,
+                    encoder,
+                    view,
+                    self.local_sphere,
+                    source,
+                    &self.visible,
+                );
+            }
+            None => {
+                let mut levels = vec![Vec::new(); self.lod_count];
+                for instance in instances {
+                    if instance.is_transparent() {
+                        continue;
+                    }
+                    let sphere = instance.bounding_sphere(self.local_sphere);
+                    if view.frustum.intersects_sphere(sphere) {
+                        let level = model.select_lod(view.screen_size(sphere));
+                        levels[level.min(self.lod_count - 1)].push(instance.to_raw());
+                    }
+                }
+                let mut start = 0;
+                for (region, level) in self.regions.iter_mut().zip(&levels) {
+                    *region = start..start + level.len();
+                    start = region.end;
+                }
+                self.visible.write(device, queue, &levels.concat());
+            }
+        }
+    }
+
+    /// Draws the instances kept by the last `cull`, one batch per level.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a model::Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        use model::DrawModel;
+
+        for (level, region) in self.regions.iter().enumerate() {
+            if region.is_empty() {
+                continue;
+            }
+            render_pass.set_vertex_buffer(1, self.visible.slice_range(region.clone()));
+            match &self.gpu {
+                Some(gpu) => render_pass.draw_model_indirect(
+                    model,
+                    level,
+                    &gpu.indirect_buffer,
+                    gpu.lods[level].first_args as wgpu::BufferAddress * ARGS_SIZE,
+                    camera_bind_group,
+                    light_bind_group,
+                ),
+                None => render_pass.draw_model_lod_instanced(
+                    model,
+                    level,
+                    0..region.len() as u32,
+                    camera_bind_group,
+                    light_bind_group,
+                ),
+            }
+        }
+    }
+}
+
+const ARGS_SIZE: wgpu::BufferAddress =
+    std::mem::size_of::<wgpu::util::DrawIndexedIndirect>() as wgpu::BufferAddress;
+
+impl GpuCuller {
+    fn new(device: &wgpu::Device, model: &model::Model) -> Self {
+        let storage = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage(1, true),
+                storage(2, false),
+                storage(3, false),
+            ],
+            label: Some("cull_bind_group_layout"),
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Cull Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Cull Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("cull.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Cull Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Cull Buffer"),
+            size: std::mem::size_of::<CullUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // One set of arguments per mesh, level after level.
+        let mut lods = [LodRange::zeroed(); MAX_LODS];
+        let mut reset_args = Vec::new();
+        for (level, range) in lods.iter_mut().enumerate().take(model.lod_count()) {
+            let meshes = model.lod_meshes(level);
+            *range = LodRange {
+                first_args: (reset_args.len() / ARGS_SIZE as usize) as u32,
+                mesh_count: meshes.len() as u32,
+                screen_size: match level {
+                    0 => f32::INFINITY,
+                    _ => model.lods[level - 1].screen_size,
+                },
+                _padding: 0,
+            };
+            for mesh in meshes {
+                let args = wgpu::util::DrawIndexedIndirect {
+                    vertex_count: mesh.num_elements(),
+                    instance_count: 0,
+                    base_index: 0,
+                    vertex_offset: 0,
+                    base_instance: 0,
+                };
+                reset_args.extend_from_slice(args.as_bytes());
+            }
+        }
+        let indirect_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Indirect Buffer"),
+            size: (reset_args.len() as wgpu::BufferAddress).max(ARGS_SIZE),
+            usage: wgpu::BufferUsages::INDIRECT
+                | wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            pipeline,
+            layout,
+            uniform_buffer,
+            indirect_buffer,
+            reset_args,
+            lods,
+            bind_group: None,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn cull(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        encoder: &mut wgpu::CommandEncoder,
+        view: &View,
+        local_sphere: Sphere,
+        source: &InstanceBuffer,
+        visible: &InstanceBuffer,
+    ) {
+        if self.reset_args.is_empty() {
+            return;
+        }
+        queue.write_buffer(&self.indirect_buffer, 0, &self.reset_args);
+        if source.is_empty() {
+            return;
+        }
+
+        let uniform = CullUniform {
+            planes: view.frustum.planes.map(Into::into),
+            sphere: local_sphere
+                .center
+                .to_vec()
+                .extend(local_sphere.radius)
+                .into(),
+            eye: view.eye.to_vec().extend(view.projection_scale).into(),
+            lods: self.lods,
+            instance_count: source.len() as u32,
+            lod_count: self.lods.iter().filter(|lod| lod.mesh_count > 0).count() as u32,
+            _padding: [0; 2],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let buffers = (source.buffer().global_id(), visible.buffer().global_id());
+        let bind_group = match &self.bind_group {
+            Some((made_for, bind_group)) if *made_for == buffers => bind_group,
+            _ => {
+                let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    layout: &self.layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: 0,
+                            resource: self.uniform_buffer.as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 1,
+                            resource: source.buffer().as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: visible.buffer().as_entire_binding(),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 3,
+                            resource: self.indirect_buffer.as_entire_binding(),
+                        },
+                    ],
+                    label: Some("cull_bind_group"),
+                });
+                &self.bind_group.insert((buffers, bind_group)).1
+            }
+        };
+
+        let mut compute_pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Cull Pass"),
+            timestamp_writes: None,
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, bind_group, &[]);
+        compute_pass.dispatch_workgroups((source.len() as u32).div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+}
@@ -0,0 +1,618 @@
+use crate::post::HDR_FORMAT;
+use crate::texture;
+
+const SKY_SIZE: u32 = 256;
+const IRRADIANCE_SIZE: u32 = 32;
+/// Sky mip level the irradiance is integrated from, about as large as the
+/// irradiance map itself.
+const IRRADIANCE_LOD: f32 = 3.0;
+const PREFILTERED_SIZE: u32 = 128;
+/// Mip levels of the prefiltered map, for roughness 0 to 1. Matches
+/// `PREFILTERED_MIPS` in `shader.wgsl`.
+pub const PREFILTERED_MIPS: u32 = 5;
+const BRDF_SIZE: u32 = 256;
+
+/// Where the sky comes from.
+pub enum Sky<'a> {
+    /// An equirectangular image, or six square cube faces side by side in
+    /// +X, -X, +Y, -Y, +Z, -Z order.
+    Image(&'a image::Rgba32FImage),
+    /// A procedural gradient with a sun, for when there is no image.
+    Gradient,
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PassUniform {
+    face: u32,
+    source: u32,
+    roughness: f32,
+    lod: f32,
+}
+
+/// Sky cubemap and the image-based lighting derived from it: diffuse
+/// irradiance, specular radiance prefiltered per roughness and the split-sum
+/// BRDF lookup table. Everything is baked on the GPU with render passes, so
+/// it works without compute shaders.
+pub struct Environment {
+    irradiance_view: wgpu::TextureView,
+    prefiltered_view: wgpu::TextureView,
+    brdf_view: wgpu::TextureView,
+    sampler: wgpu::Sampler,
+    sky_bind_group: wgpu::BindGroup,
+    sky_pipeline_layout: wgpu::PipelineLayout,
+    sky_pipeline: wgpu::RenderPipeline,
+}
+
+impl Environment {
+    pub fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sky: Sky,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("environment_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let sky_mips = SKY_SIZE.ilog2() + 1;
+        let sky_texture = create_texture(device, "sky_texture", SKY_SIZE, sky_mips, 6);
+        let irradiance_texture =
+            create_texture(device, "irradiance_texture", IRRADIANCE_SIZE, 1, 6);
+        let prefiltered_texture = create_texture(
+            device,
+            "prefiltered_texture",
+            PREFILTERED_SIZE,
+            PREFILTERED_MIPS,
+            6,
+        );
+        let brdf_texture = create_texture(device, "brdf_texture", BRDF_SIZE, 1, 1);
+
+        let cube_view = |texture: &wgpu::Texture, base_mip_level, mip_level_count| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::Cube),
+                base_mip_level,
+                mip_level_count,
+                ..Default::default()
+            })
+        };
+        let sky_view = cube_view(&sky_texture, 0, None);
+        let irradiance_view = cube_view(&irradiance_texture, 0, None);
+        let prefiltered_view = cube_view(&prefiltered_texture, 0, None);
+        let brdf_view = brdf_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        Baker::new(device, queue, &sampler, sky).bake(
+            device,
+            queue,
+            &sky_texture,
+            &sky_view,
+            &irradiance_texture,
+            &prefiltered_texture,
+            &brdf_texture,
+            |mip| cube_view(&sky_texture, mip, Some(1)),
+        );
+
+        let sky_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                cube_layout_entry(0, wgpu::ShaderStages::FRAGMENT),
+                sampler_layout_entry(1, wgpu::ShaderStages::FRAGMENT),
+            ],
+            label: Some("sky_bind_group_layout"),
+        });
+        let sky_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &sky_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&sky_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: Some("sky_bind_group"),
+        });
+        let sky_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Sky Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout, &sky_layout],
+            push_constant_ranges: &[],
+        });
+        let sky_pipeline = create_sky_pipeline(device, &sky_pipeline_layout, sample_count);
+
+        Self {
+            irradiance_view,
+            prefiltered_view,
+            brdf_view,
+            sampler,
+            sky_bind_group,
+            sky_pipeline_layout,
+            sky_pipeline,
+        }
+    }
+
+    /// Layout entries for sampling the lighting maps from the material
+    /// shader: irradiance, prefiltered radiance, BRDF table and sampler.
+    pub fn layout_entries(first_binding: u32) -> [wgpu::BindGroupLayoutEntry; 4] {
+        let stage = wgpu::ShaderStages::FRAGMENT;
+        [
+            cube_layout_entry(first_binding, stage),
+            cube_layout_entry(first_binding + 1, stage),
+            wgpu::BindGroupLayoutEntry {
+                binding: first_binding + 2,
+                visibility: stage,
+                ty: wgpu::BindingType::Texture {
+                    multisampled: false,
+                    view_dimension: wgpu::TextureViewDimension::D2,
+                    sample_type: wgpu::TextureSampleType::Float { filterable: true },
+                },
+                count: None,
+            },
+            sampler_layout_entry(first_binding + 3, stage),
+        ]
+    }
+
+    /// Bind group entries matching `layout_entries`.
+    pub fn bind_group_entries(&self, first_binding: u32) -> [wgpu::BindGroupEntry<'_>; 4] {
+        [
+            wgpu::BindGroupEntry {
+                binding: first_binding,
+                resource: wgpu::BindingResource::TextureView(&self.irradiance_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 1,
+                resource: wgpu::BindingResource::TextureView(&self.prefiltered_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 2,
+                resource: wgpu::BindingResource::TextureView(&self.brdf_view),
+            },
+            wgpu::BindGroupEntry {
+                binding: first_binding + 3,
+                resource: wgpu::BindingResource::Sampler(&self.sampler),
+            },
+        ]
+    }
+
+    /// Rebuilds the skybox pipeline for a new MSAA sample count.
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.sky_pipeline = create_sky_pipeline(device, &self.sky_pipeline_layout, sample_count);
+    }
+
+    /// Draws the sky wherever nothing has been drawn yet. Leaves the skybox
+    /// pipeline bound.
+    pub fn draw_sky<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.sky_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.sky_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+}
+
+/// Pipelines and bindings for baking, dropped once the maps are filled.
+struct Baker<'a> {
+    layout: wgpu::BindGroupLayout,
+    image_view: wgpu::TextureView,
+    /// `SOURCE_*` constant of `environment.wgsl`, or `None` for the gradient.
+    source: Option<u32>,
+    sampler: &'a wgpu::Sampler,
+    shader: wgpu::ShaderModule,
+    pipeline_layout: wgpu::PipelineLayout,
+}
+
+/// A single face draw of the bake.
+struct Draw<'a> {
+    entry_point: &'static str,
+    target: wgpu::TextureView,
+    sky: &'a wgpu::TextureView,
+    uniform: PassUniform,
+}
+
+impl<'a> Baker<'a> {
+    fn new(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sampler: &'a wgpu::Sampler,
+        sky: Sky,
+    ) -> Self {
+        let stage = wgpu::ShaderStages::FRAGMENT;
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: stage,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: wgpu::BufferSize::new(
+                            std::mem::size_of::<PassUniform>() as u64
+                        ),
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: stage,
+                    ty: wgpu::BindingType::Texture {
+                        multisampled: false,
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                    },
+                    count: None,
+                },
+                cube_layout_entry(2, stage),
+                sampler_layout_entry(3, stage),
+            ],
+            label: Some("environment_bake_bind_group_layout"),
+        });
+
+        let (image_view, source) = match sky {
+            Sky::Image(image) => {
+                let image = fit_image(device, image);
+                let (width, height) = image.dimensions();
+                let source = if width == 6 * height { 1 } else { 0 };
+                let view = upload_image(device, queue, image.as_raw(), width, height);
+                (view, Some(source))
+            }
+            Sky::Gradient => (upload_image(device, queue, &[0.0; 4], 1, 1), None),
+        };
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Environment Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("environment.wgsl").into()),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Environment Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            layout,
+            image_view,
+            source,
+            sampler,
+            shader,
+            pipeline_layout,
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn bake(
+        &self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        sky_texture: &wgpu::Texture,
+        sky_view: &wgpu::TextureView,
+        irradiance_texture: &wgpu::Texture,
+        prefiltered_texture: &wgpu::Texture,
+        brdf_texture: &wgpu::Texture,
+        sky_mip_view: impl Fn(u32) -> wgpu::TextureView,
+    ) {
+        let face_view = |texture: &wgpu::Texture, face, mip| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                dimension: Some(wgpu::TextureViewDimension::D2),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                base_array_layer: face,
+                array_layer_count: Some(1),
+                ..Default::default()
+            })
+        };
+        let uniform = |face, roughness, lod| PassUniform {
+            face,
+            source: self.source.unwrap_or(0),
+            roughness,
+            lod,
+        };
+
+        // Passes that write the sky sample the irradiance map instead, which
+        // is not written until later.
+        let placeholder = irradiance_texture.create_view(&wgpu::TextureViewDescriptor {
+            dimension: Some(wgpu::TextureViewDimension::Cube),
+            ..Default::default()
+        });
+        let sky_mip_views = (0..sky_texture.mip_level_count())
+            .map(sky_mip_view)
+            .collect::<Vec<_>>();
+
+        let mut draws = Vec::new();
+        for face in 0..6 {
+            draws.push(Draw {
+                entry_point: match self.source {
+                    Some(_) => "fs_image",
+                    None => "fs_gradient",
+                },
+                target: face_view(sky_texture, face, 0),
+                sky: &placeholder,
+                uniform: uniform(face, 0.0, 0.0),
+            });
+        }
+        for mip in 1..sky_texture.mip_level_count() {
+            for face in 0..6 {
+                draws.push(Draw {
+                    entry_point: "fs_downsample",
+                    target: face_view(sky_texture, face, mip),
+                    sky: &sky_mip_views[mip as usize - 1],
+                    uniform: uniform(face, 0.0, 0.0),
+                });
+            }
+        }
+        for face in 0..6 {
+            draws.push(Draw {
+                entry_point: "fs_irradiance",
+                target: face_view(irradiance_texture, face, 0),
+                sky: sky_view,
+                uniform: uniform(face, 0.0, IRRADIANCE_LOD),
+            });
+        }
+        for mip in 0..PREFILTERED_MIPS {
+            let roughness = mip as f32 / (PREFILTERED_MIPS - 1) as f32;
+            for face in 0..6 {
+                draws.push(Draw {
+                    entry_point: "fs_prefilter",
+                    target: face_view(prefiltered_texture, face, mip),
+                    sky: sky_view,
+                    uniform: uniform(face, roughness, 0.0),
+                });
+            }
+        }
+        draws.push(Draw {
+            entry_point: "fs_brdf",
+            target: face_view(brdf_texture, 0, 0),
+            sky: sky_view,
+            uniform: uniform(0, 0.0, 0.0),
+        });
+
+        // One uniform per draw, each at an offset usable as dynamic offset.
+        let stride = device
+            .limits()
+            .min_uniform_buffer_offset_alignment
+            .max(std::mem::size_of::<PassUniform>() as u32) as usize;
+        let mut uniforms = vec![0; stride * draws.len()];
+        for (i, draw) in draws.iter().enumerate() {
+            uniforms[i * stride..][..std::mem::size_of::<PassUniform>()]
+                .copy_from_slice(bytemuck::bytes_of(&draw.uniform));
+        }
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Environment Pass Buffer"),
+            size: uniforms.len() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        queue.write_buffer(&uniform_buffer, 0, &uniforms);
+
+        let mut pipelines = std::collections::HashMap::new();
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Environment Encoder"),
+        });
+        for (i, draw) in draws.iter().enumerate() {
+            let pipeline = pipelines
+                .entry(draw.entry_point)
+                .or_insert_with(|| self.create_pipeline(device, draw.entry_point));
+            let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &self.layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                            buffer: &uniform_buffer,
+                            offset: 0,
+                            size: wgpu::BufferSize::new(std::mem::size_of::<PassUniform>() as u64),
+                        }),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&self.image_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: wgpu::BindingResource::TextureView(draw.sky),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 3,
+                        resource: wgpu::BindingResource::Sampler(self.sampler),
+                    },
+                ],
+                label: Some("environment_bake_bind_group"),
+            });
+
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Environment Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &draw.target,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &bind_group, &[(i * stride) as u32]);
+            render_pass.draw(0..3, 0..1);
+        }
+        queue.submit(std::iter::once(encoder.finish()));
+    }
+
+    fn create_pipeline(&self, device: &wgpu::Device, entry_point: &str) -> wgpu::RenderPipeline {
+        device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some(entry_point),
+            layout: Some(&self.pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &self.shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &self.shader,
+                entry_point,
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: HDR_FORMAT,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        })
+    }
+}
+
+fn create_texture(
+    device: &wgpu::Device,
+    label: &str,
+    size: u32,
+    mip_level_count: u32,
+    layers: u32,
+) -> wgpu::Texture {
+    device.create_texture(&wgpu::TextureDescriptor {
+        label: Some(label),
+        size: wgpu::Extent3d {
+            width: size,
+            height: size,
+            depth_or_array_layers: layers,
+        },
+        mip_level_count,
+        sample_count: 1,
+        dimension: wgpu::TextureDimension::D2,
+        format: HDR_FORMAT,
+        usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+        view_formats: &[],
+    })
+}
+
+/// Shrinks images that exceed the device's texture size limit.
+fn fit_image<'a>(
+    device: &wgpu::Device,
+    image: &'a image::Rgba32FImage,
+) -> std::borrow::Cow<'a, image::Rgba32FImage> {
+    let max = device.limits().max_texture_dimension_2d;
+    let (width, height) = image.dimensions();
+    if width <= max && height <= max {
+        return std::borrow::Cow::Borrowed(image);
+    }
+    let scale = max as f32 / width.max(height) as f32;
+    std::borrow::Cow::Owned(image::imageops::resize(
+        image,
+        ((width as f32 * scale) as u32).max(1),
+        ((height as f32 * scale) as u32).max(1),
+        image::imageops::FilterType::Triangle,
+    ))
+}
+
+fn upload_image(
+    device: &wgpu::Device,
+    queue: &wgpu::Queue,
+    pixels: &[f32],
+    width: u32,
+    height: u32,
+) -> wgpu::TextureView {
+    use wgpu::util::DeviceExt;
+    device
+        .create_texture_with_data(
+            queue,
+            &wgpu::TextureDescriptor {
+                label: Some("environment_image"),
+                size: wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format: wgpu::TextureFormat::Rgba32Float,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            },
+            bytemuck::cast_slice(pixels),
+        )
+        .create_view(&wgpu::TextureViewDescriptor::default())
+}
+
+fn create_sky_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Sky Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("sky.wgsl").into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Sky Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: HDR_FORMAT,
+                blend: None,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        // Drawn at the far plane, only where the depth buffer is still clear.
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::LessEqual,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
+
+fn cube_layout_entry(binding: u32, visibility: wgpu::ShaderStages) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Texture {
+            multisampled: false,
+            view_dimension: wgpu::TextureViewDimension::Cube,
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+        },
+        count: None,
+    }
+}
+
+fn sampler_layout_entry(
+    binding: u32,
+    visibility: wgpu::ShaderStages,
+) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility,
+        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+        count: None,
+    }
+}
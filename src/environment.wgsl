@@ -0,0 +1,258 @@
+// Environment baking: fills the sky cubemap from an image or a gradient and
+// derives the image-based lighting maps from it, one cube face per draw.
+
+const PI: f32 = 3.14159265359;
+
+const SOURCE_EQUIRECTANGULAR: u32 = 0u;
+const SOURCE_CUBE_STRIP: u32 = 1u;
+
+struct Pass {
+    face: u32,
+    source: u32,
+    roughness: f32,
+    // Mip level of `t_sky` to read.
+    lod: f32,
+}
+@group(0) @binding(0)
+var<uniform> pass_: Pass;
+@group(0) @binding(1)
+var t_image: texture_2d<f32>;
+@group(0) @binding(2)
+var t_sky: texture_cube<f32>;
+@group(0) @binding(3)
+var s_sky: sampler;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// Direction through a texel of a cube face, in the usual +X, -X, +Y, -Y, +Z,
+// -Z layer order.
+fn face_direction(face: u32, uv: vec2<f32>) -> vec3<f32> {
+    let s = uv.x * 2.0 - 1.0;
+    let t = uv.y * 2.0 - 1.0;
+    var dir: vec3<f32>;
+    switch face {
+        case 0u: { dir = vec3<f32>(1.0, -t, -s); }
+        case 1u: { dir = vec3<f32>(-1.0, -t, s); }
+        case 2u: { dir = vec3<f32>(s, 1.0, t); }
+        case 3u: { dir = vec3<f32>(s, -1.0, -t); }
+        case 4u: { dir = vec3<f32>(s, -t, 1.0); }
+        default: { dir = vec3<f32>(-s, -t, -1.0); }
+    }
+    return normalize(dir);
+}
+
+// Inverse of `face_direction`: the face and its uv for a direction.
+fn direction_face(dir: vec3<f32>) -> vec3<f32> {
+    let a = abs(dir);
+    if (a.x >= a.y && a.x >= a.z) {
+        if (dir.x > 0.0) {
+            return vec3<f32>(-dir.z / a.x, -dir.y / a.x, 0.0);
+        }
+        return vec3<f32>(dir.z / a.x, -dir.y / a.x, 1.0);
+    }
+    if (a.y >= a.z) {
+        if (dir.y > 0.0) {
+            return vec3<f32>(dir.x / a.y, dir.z / a.y, 2.0);
+        }
+        return vec3<f32>(dir.x / a.y, -dir.z / a.y, 3.0);
+    }
+    if (dir.z > 0.0) {
+        return vec3<f32>(dir.x / a.z, -dir.y / a.z, 4.0);
+    }
+    return vec3<f32>(-dir.x / a.z, -dir.y / a.z, 5.0);
+}
+
+// Bilinear fetch, since 32-bit float images are not filterable everywhere.
+fn load_bilinear(uv: vec2<f32>) -> vec3<f32> {
+    let size = vec2<i32>(textureDimensions(t_image));
+    let p = uv * vec2<f32>(size) - 0.5;
+    let base = vec2<i32>(floor(p));
+    let f = p - floor(p);
+    let lo = vec2<i32>(0, 0);
+    let hi = size - 1;
+    // Wrap horizontally for the equirectangular seam.
+    let x0 = (base.x + size.x) % size.x;
+    let x1 = (base.x + 1 + size.x) % size.x;
+    let y0 = clamp(base.y, lo.y, hi.y);
+    let y1 = clamp(base.y + 1, lo.y, hi.y);
+    let a = textureLoad(t_image, vec2<i32>(x0, y0), 0).rgb;
+    let b = textureLoad(t_image, vec2<i32>(x1, y0), 0).rgb;
+    let c = textureLoad(t_image, vec2<i32>(x0, y1), 0).rgb;
+    let d = textureLoad(t_image, vec2<i32>(x1, y1), 0).rgb;
+    return mix(mix(a, b, f.x), mix(c, d, f.x), f.y);
+}
+
+@fragment
+fn fs_image(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dir = face_direction(pass_.face, in.uv);
+    var uv: vec2<f32>;
+    if (pass_.source == SOURCE_CUBE_STRIP) {
+        // Six square faces side by side in layer order.
+        let face = direction_face(dir);
+        let inset = clamp(face.xy * 0.5 + 0.5, vec2<f32>(0.001), vec2<f32>(0.999));
+        uv = vec2<f32>((face.z + inset.x) / 6.0, inset.y);
+    } else {
+        uv = vec2<f32>(atan2(dir.z, dir.x) / (2.0 * PI) + 0.5, acos(clamp(dir.y, -1.0, 1.0)) / PI);
+    }
+    return vec4<f32>(load_bilinear(uv), 1.0);
+}
+
+// Sky used when no environment image is available.
+@fragment
+fn fs_gradient(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dir = face_direction(pass_.face, in.uv);
+    let zenith = vec3<f32>(0.15, 0.35, 0.85);
+    let horizon = vec3<f32>(0.75, 0.85, 1.0);
+    let ground = vec3<f32>(0.25, 0.22, 0.2);
+    var color: vec3<f32>;
+    if (dir.y >= 0.0) {
+        color = mix(horizon, zenith, pow(dir.y, 0.5));
+    } else {
+        color = mix(horizon, ground, pow(-dir.y, 0.3));
+    }
+    let sun_dir = normalize(vec3<f32>(0.4, 0.6, 0.3));
+    let sun = smoothstep(0.9995, 0.9998, dot(dir, sun_dir)) * 50.0;
+    return vec4<f32>(color + vec3<f32>(sun), 1.0);
+}
+
+// Copies `t_sky` at `lod` into the next smaller mip level.
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let dir = face_direction(pass_.face, in.uv);
+    return vec4<f32>(textureSampleLevel(t_sky, s_sky, dir, pass_.lod).rgb, 1.0);
+}
+
+fn tangent_frame(n: vec3<f32>) -> mat3x3<f32> {
+    var up = vec3<f32>(0.0, 1.0, 0.0);
+    if (abs(n.y) > 0.999) {
+        up = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    let tangent = normalize(cross(up, n));
+    return mat3x3<f32>(tangent, cross(n, tangent), n);
+}
+
+// Cosine-weighted hemisphere integral of the sky around the normal.
+@fragment
+fn fs_irradiance(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = face_direction(pass_.face, in.uv);
+    let frame = tangent_frame(n);
+    let delta = 0.05;
+    var sum = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var phi = 0.0; phi < 2.0 * PI; phi += delta) {
+        for (var theta = 0.0; theta < 0.5 * PI; theta += delta) {
+            let local = vec3<f32>(sin(theta) * cos(phi), sin(theta) * sin(phi), cos(theta));
+            let color = textureSampleLevel(t_sky, s_sky, frame * local, pass_.lod).rgb;
+            sum += color * cos(theta) * sin(theta);
+            count += 1.0;
+        }
+    }
+    return vec4<f32>(PI * sum / count, 1.0);
+}
+
+// Van der Corput radical inverse without `reverseBits`, which GLSL ES 3.0
+// lacks.
+fn radical_inverse(index: u32) -> f32 {
+    var bits = index;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return f32(bits) * 2.3283064365386963e-10;
+}
+
+fn hammersley(i: u32, count: u32) -> vec2<f32> {
+    return vec2<f32>(f32(i) / f32(count), radical_inverse(i));
+}
+
+// GGX-distributed half vector in tangent space.
+fn importance_sample_ggx(xi: vec2<f32>, roughness: f32) -> vec3<f32> {
+    let a = roughness * roughness;
+    let phi = 2.0 * PI * xi.x;
+    let cos_theta = sqrt((1.0 - xi.y) / (1.0 + (a * a - 1.0) * xi.y));
+    let sin_theta = sqrt(1.0 - cos_theta * cos_theta);
+    return vec3<f32>(sin_theta * cos(phi), sin_theta * sin(phi), cos_theta);
+}
+
+fn distribution_ggx(n_dot_h: f32, roughness: f32) -> f32 {
+    let a2 = roughness * roughness * roughness * roughness;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * d * d);
+}
+
+const PREFILTER_SAMPLES: u32 = 256u;
+
+// Split-sum prefiltered radiance for `roughness`, taking each sample from a
+// mip level matching its solid angle to avoid bright speckles.
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n = face_direction(pass_.face, in.uv);
+    let frame = tangent_frame(n);
+    let roughness = max(pass_.roughness, 0.001);
+    let sky_size = f32(textureDimensions(t_sky).x);
+    let texel_solid_angle = 4.0 * PI / (6.0 * sky_size * sky_size);
+
+    var sum = vec3<f32>(0.0);
+    var weight = 0.0;
+    for (var i = 0u; i < PREFILTER_SAMPLES; i++) {
+        let h = frame * importance_sample_ggx(hammersley(i, PREFILTER_SAMPLES), roughness);
+        let l = normalize(2.0 * dot(n, h) * h - n);
+        let n_dot_l = dot(n, l);
+        if (n_dot_l > 0.0) {
+            let n_dot_h = max(dot(n, h), 0.0);
+            let pdf = distribution_ggx(n_dot_h, roughness) / 4.0 + 1e-4;
+            let sample_solid_angle = 1.0 / (f32(PREFILTER_SAMPLES) * pdf);
+            let lod = max(0.5 * log2(sample_solid_angle / texel_solid_angle) + 1.0, 0.0);
+            sum += textureSampleLevel(t_sky, s_sky, l, lod).rgb * n_dot_l;
+            weight += n_dot_l;
+        }
+    }
+    return vec4<f32>(sum / max(weight, 1e-4), 1.0);
+}
+
+fn geometry_schlick_ggx(n_dot_v: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    return n_dot_v / (n_dot_v * (1.0 - k) + k);
+}
+
+const BRDF_SAMPLES: u32 = 512u;
+
+// Scale and bias to F0 of the split-sum specular term, by view angle (x) and
+// roughness (y).
+@fragment
+fn fs_brdf(in: VertexOutput) -> @location(0) vec4<f32> {
+    let n_dot_v = max(in.uv.x, 1e-3);
+    let roughness = in.uv.y;
+    let v = vec3<f32>(sqrt(1.0 - n_dot_v * n_dot_v), 0.0, n_dot_v);
+
+    var scale = 0.0;
+    var bias = 0.0;
+    for (var i = 0u; i < BRDF_SAMPLES; i++) {
+        let h = importance_sample_ggx(hammersley(i, BRDF_SAMPLES), roughness);
+        let l = normalize(2.0 * dot(v, h) * h - v);
+        let n_dot_l = max(l.z, 0.0);
+        let n_dot_h = max(h.z, 0.0);
+        let v_dot_h = max(dot(v, h), 0.0);
+        if (n_dot_l > 0.0) {
+            let g = geometry_schlick_ggx(n_dot_v, roughness) * geometry_schlick_ggx(n_dot_l, roughness);
+            let g_vis = g * v_dot_h / (n_dot_h * n_dot_v);
+            let fc = pow(1.0 - v_dot_h, 5.0);
+            scale += (1.0 - fc) * g_vis;
+            bias += fc * g_vis;
+        }
+    }
+    return vec4<f32>(scale / f32(BRDF_SAMPLES), bias / f32(BRDF_SAMPLES), 0.0, 1.0);
+}
@@ -0,0 +1,537 @@
+//! The fluid demo: a column of water released in an invisible tank around
+//! some of the rigid bodies, drawn either as sprites or as a surface
+//! reconstructed in screen space.
+
+use cgmath::prelude::*;
+use cgmath::{Matrix4, Point3, Vector3};
+
+use crate::physics::{Aabb, Fluid, FluidSettings, World};
+use crate::post::HDR_FORMAT;
+use crate::texture;
+
+const SPACING: f32 = 0.25;
+/// The tank, whose bottom is the top of the ground.
+const TANK: Aabb = Aabb {
+    min: Point3::new(-3.5, -1.0, -3.5),
+    max: Point3::new(3.5, 8.0, 3.5),
+};
+/// Corner of the water column opposite the tank's minimum.
+const COLUMN_MAX: Point3<f32> = Point3::new(-1.0, 1.5, 3.4);
+
+const TIMESTEP: f32 = 1.0 / 60.0;
+const MAX_STEPS: u32 = 2;
+const MAX_SPEED: f32 = 6.0;
+
+/// Linear view depth the surface is reconstructed from.
+const LINEAR_DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+const THICKNESS_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R16Float;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FluidRendering {
+    /// Every particle as a shaded sphere.
+    Sprites,
+    /// A smoothed, translucent surface over the particles.
+    Surface,
+}
+
+impl FluidRendering {
+    pub fn next(self) -> Self {
+        match self {
+            FluidRendering::Sprites => FluidRendering::Surface,
+            FluidRendering::Surface => FluidRendering::Sprites,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct FluidUniform {
+    view: [[f32; 4]; 4],
+    projection: [[f32; 4]; 4],
+    light_position: [f32; 4],
+    radius: f32,
+    max_speed: f32,
+    size: [f32; 2],
+}
+
+struct Pipelines {
+    sprite: wgpu::RenderPipeline,
+    depth: wgpu::RenderPipeline,
+    thickness: wgpu::RenderPipeline,
+    /// Horizontal and vertical.
+    blur: [wgpu::RenderPipeline; 2],
+    surface: wgpu::RenderPipeline,
+}
+
+/// Size-dependent targets of the surface, recreated on resize.
+struct Targets {
+    depth_texture: texture::Texture,
+    /// Linear depth, blurred from the first into the second and back.
+    linear_depth: [wgpu::TextureView; 2],
+    thickness: wgpu::TextureView,
+    /// Each linear depth with the thickness.
+    bind_groups: [wgpu::BindGroup; 2],
+}
+
+pub struct Tank {
+    sim: Fluid,
+    accumulator: f32,
+    rendering: FluidRendering,
+    uniform: FluidUniform,
+    uniform_buffer: wgpu::Buffer,
+    uniform_bind_group: wgpu::BindGroup,
+    texture_layout: wgpu::BindGroupLayout,
+    sprite_layout: wgpu::PipelineLayout,
+    screen_layout: wgpu::PipelineLayout,
+    pipelines: Pipelines,
+    targets: Targets,
+    vertex_buffer: wgpu::Buffer,
+    /// Particles the buffer has room for.
+    capacity: usize,
+    count: u32,
+}
+
+impl Tank {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) -> Self {
+        let mut settings = FluidSettings::new(SPACING);
+        settings.bounds = Some(TANK);
+        let mut sim = Fluid::new(settings);
+        let count = sim.fill(Aabb {
+            min: TANK.min + Vector3::from_value(0.5 * SPACING),
+            max: COLUMN_MAX,
+        });
+        log::info!("Fluid particles: {}", count);
+
+        let uniform = FluidUniform {
+            view: Matrix4::identity().into(),
+            projection: Matrix4::identity().into(),
+            light_position: [0.0; 4],
+            radius: sim.settings.particle_radius,
+            max_speed: MAX_SPEED,
+            size: [config.width as f32, config.height as f32],
+        };
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Fluid Uniform Buffer"),
+            size: std::mem::size_of::<FluidUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let uniform_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("fluid_uniform_bind_group_layout"),
+        });
+        let uniform_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &uniform_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: uniform_buffer.as_entire_binding(),
+            }],
+            label: Some("fluid_uniform_bind_group"),
+        });
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let texture_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0), texture_entry(1)],
+            label: Some("fluid_texture_bind_group_layout"),
+        });
+        let sprite_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fluid Sprite Pipeline Layout"),
+            bind_group_layouts: &[&uniform_layout],
+            push_constant_ranges: &[],
+        });
+        let screen_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Fluid Screen Pipeline Layout"),
+            bind_group_layouts: &[&uniform_layout, &texture_layout],
+            push_constant_ranges: &[],
+        });
+
+        Self {
+            sim,
+            accumulator: 0.0,
+            rendering: FluidRendering::Surface,
+            uniform,
+            uniform_buffer,
+            uniform_bind_group,
+            pipelines: Pipelines::new(device, &sprite_layout, &screen_layout, sample_count),
+            targets: Targets::new(device, config, &texture_layout),
+            texture_layout,
+            sprite_layout,
+            screen_layout,
+            vertex_buffer: create_vertex_buffer(device, count),
+            capacity: count,
+            count: 0,
+        }
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipelines = Pipelines::new(
+            device,
+            &self.sprite_layout,
+            &self.screen_layout,
+            sample_count,
+        );
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.uniform.size = [config.width as f32, config.height as f32];
+        self.targets = Targets::new(device, config, &self.texture_layout);
+    }
+
+    pub fn toggle_rendering(&mut self) {
+        self.rendering = self.rendering.next();
+        log::info!("Fluid rendering: {:?}", self.rendering);
+    }
+
+    /// Steps the fluid against the bodies of `world` in fixed steps and
+    /// uploads the particles.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dt: f32,
+        world: &mut World,
+    ) {
+        self.accumulator = (self.accumulator + dt).min(TIMESTEP * MAX_STEPS as f32);
+        while self.accumulator >= TIMESTEP {
+            self.accumulator -= TIMESTEP;
+            self.sim.step(TIMESTEP, world);
+        }
+
+        let data = self
+            .sim
+            .particles
+            .iter()
+            .map(|p| p.position.extend(p.velocity.magnitude()).into())
+            .collect::<Vec<[f32; 4]>>();
+        if data.len() > self.capacity {
+            self.capacity = data.len().next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.capacity);
+        }
+        if !data.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&data));
+        }
+        self.count = data.len() as u32;
+    }
+
+    /// Uploads the camera and light for this frame.
+    pub fn prepare(
+        &mut self,
+        queue: &wgpu::Queue,
+        view: Matrix4<f32>,
+        projection: Matrix4<f32>,
+        light_position: [f32; 3],
+    ) {
+        self.uniform.view = view.into();
+        self.uniform.projection = projection.into();
+        self.uniform.light_position =
+            [light_position[0], light_position[1], light_position[2], 1.0];
+        queue.write_buffer(
+            &self.uniform_buffer,
+            0,
+            bytemuck::cast_slice(&[self.uniform]),
+        );
+    }
+
+    /// Renders the depth and thickness of the particles and smooths the
+    /// depth, when drawing the surface. Must come before the main pass.
+    pub fn render_surface(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.rendering != FluidRendering::Surface || self.count == 0 {
+            return;
+        }
+        let targets = &self.targets;
+        let color_attachment = |view| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::TRANSPARENT),
+                    store: wgpu::StoreOp::Store,
+                },
+            })
+        };
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Fluid Depth Pass"),
+                color_attachments: &[color_attachment(&targets.linear_depth[0])],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &targets.depth_texture.view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(1.0),
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(&self.pipelines.depth);
+            self.draw_sprites(&mut render_pass);
+        }
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Fluid Thickness Pass"),
+                color_attachments: &[color_attachment(&targets.thickness)],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(&self.pipelines.thickness);
+            self.draw_sprites(&mut render_pass);
+        }
+        for (i, pipeline) in self.pipelines.blur.iter().enumerate() {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Fluid Blur Pass"),
+                color_attachments: &[color_attachment(&targets.linear_depth[1 - i])],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(pipeline);
+            render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+            render_pass.set_bind_group(1, &targets.bind_groups[i], &[]);
+            render_pass.draw(0..3, 0..1);
+        }
+    }
+
+    /// Draws the sprites, or composites the surface prepared by
+    /// `render_surface` against the scene's depth. Leaves the fluid pipeline
+    /// bound.
+    pub fn draw<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        if self.count == 0 {
+            return;
+        }
+        match self.rendering {
+            FluidRendering::Sprites => {
+                render_pass.set_pipeline(&self.pipelines.sprite);
+                self.draw_sprites(render_pass);
+            }
+            FluidRendering::Surface => {
+                render_pass.set_pipeline(&self.pipelines.surface);
+                render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+                render_pass.set_bind_group(1, &self.targets.bind_groups[0], &[]);
+                render_pass.draw(0..3, 0..1);
+            }
+        }
+    }
+
+    fn draw_sprites<'a>(&'a self, render_pass: &mut wgpu::RenderPass<'a>) {
+        render_pass.set_bind_group(0, &self.uniform_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.count);
+    }
+}
+
+impl Pipelines {
+    fn new(
+        device: &wgpu::Device,
+        sprite_layout: &wgpu::PipelineLayout,
+        screen_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+    ) -> Self {
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Fluid Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("fluid.wgsl").into()),
+        });
+        let sprite_buffers = [wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<[f32; 4]>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &wgpu::vertex_attr_array![0 => Float32x4],
+        }];
+        let depth_stencil = |depth_write_enabled| {
+            Some(wgpu::DepthStencilState {
+                format: texture::Texture::DEPTH_FORMAT,
+                depth_write_enabled,
+                depth_compare: wgpu::CompareFunction::Less,
+                stencil: wgpu::StencilState::default(),
+                bias: wgpu::DepthBiasState::default(),
+            })
+        };
+        let pipeline = |label,
+                        sprites: bool,
+                        fragment,
+                        target: wgpu::ColorTargetState,
+                        depth_stencil,
+                        sample_count| {
+            let (layout, vertex, buffers): (_, _, &[_]) = if sprites {
+                (sprite_layout, "vs_sprite", &sprite_buffers)
+            } else {
+                (screen_layout, "vs_fullscreen", &[])
+            };
+            device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+                label: Some(label),
+                layout: Some(layout),
+                vertex: wgpu::VertexState {
+                    module: &shader,
+                    entry_point: vertex,
+                    buffers,
+                },
+                fragment: Some(wgpu::FragmentState {
+                    module: &shader,
+                    entry_point: fragment,
+                    targets: &[Some(target)],
+                }),
+                primitive: wgpu::PrimitiveState::default(),
+                depth_stencil,
+                multisample: wgpu::MultisampleState {
+                    count: sample_count,
+                    mask: !0,
+                    alpha_to_coverage_enabled: false,
+                },
+                multiview: None,
+            })
+        };
+        let target = |format, blend| wgpu::ColorTargetState {
+            format,
+            blend,
+            write_mask: wgpu::ColorWrites::ALL,
+        };
+        let additive = wgpu::BlendComponent {
+            src_factor: wgpu::BlendFactor::One,
+            dst_factor: wgpu::BlendFactor::One,
+            operation: wgpu::BlendOperation::Add,
+        };
+
+        Self {
+            sprite: pipeline(
+                "Fluid Sprite Pipeline",
+                true,
+                "fs_sprite",
+                target(HDR_FORMAT, None),
+                depth_stencil(true),
+                sample_count,
+            ),
+            depth: pipeline(
+                "Fluid Depth Pipeline",
+                true,
+                "fs_depth",
+                target(LINEAR_DEPTH_FORMAT, None),
+                depth_stencil(true),
+                1,
+            ),
+            thickness: pipeline(
+                "Fluid Thickness Pipeline",
+                true,
+                "fs_thickness",
+                target(
+                    THICKNESS_FORMAT,
+                    Some(wgpu::BlendState {
+                        color: additive,
+                        alpha: additive,
+                    }),
+                ),
+                None,
+                1,
+            ),
+            blur: ["fs_blur_x", "fs_blur_y"].map(|fragment| {
+                pipeline(
+                    "Fluid Blur Pipeline",
+                    false,
+                    fragment,
+                    target(LINEAR_DEPTH_FORMAT, None),
+                    None,
+                    1,
+                )
+            }),
+            surface: pipeline(
+                "Fluid Surface Pipeline",
+                false,
+                "fs_surface",
+                target(HDR_FORMAT, Some(wgpu::BlendState::ALPHA_BLENDING)),
+                depth_stencil(false),
+                sample_count,
+            ),
+        }
+    }
+}
+
+impl Targets {
+    fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        texture_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let create_view = |label, format| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: config.width,
+                        height: config.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let linear_depth = [
+            create_view("fluid_linear_depth_texture", LINEAR_DEPTH_FORMAT),
+            create_view("fluid_blurred_depth_texture", LINEAR_DEPTH_FORMAT),
+        ];
+        let thickness = create_view("fluid_thickness_texture", THICKNESS_FORMAT);
+        let bind_groups = [0, 1].map(|i| {
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: texture_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(&linear_depth[i]),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&thickness),
+                    },
+                ],
+                label: Some("fluid_texture_bind_group"),
+            })
+        });
+
+        Self {
+            depth_texture: texture::Texture::create_depth_texture(
+                device,
+                config,
+                1,
+                "fluid_depth_texture",
+            ),
+            linear_depth,
+            thickness,
+            bind_groups,
+        }
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Fluid Vertex Buffer"),
+        size: (capacity.max(1) * std::mem::size_of::<[f32; 4]>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
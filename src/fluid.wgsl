@@ -0,0 +1,237 @@
+// fluid.wgsl
+// The fluid particles as sphere sprites, either shaded one by one or turned
+// into a surface in screen space: their depth and thickness are rendered
+// offscreen, the depth is smoothed with a bilateral blur, and the surface is
+// shaded from the normals of the smoothed depth.
+
+struct Fluid {
+    view: mat4x4<f32>,
+    projection: mat4x4<f32>,
+    light_position: vec4<f32>,
+    radius: f32,
+    max_speed: f32,
+    size: vec2<f32>,
+}
+@group(0) @binding(0)
+var<uniform> fluid: Fluid;
+
+// Linear view depth, zero where there is no fluid, and the summed thickness.
+@group(1) @binding(0)
+var t_depth: texture_2d<f32>;
+@group(1) @binding(1)
+var t_thickness: texture_2d<f32>;
+
+const DEEP: vec3<f32> = vec3<f32>(0.05, 0.3, 0.65);
+const FOAM: vec3<f32> = vec3<f32>(0.8, 0.9, 1.0);
+const SKY: vec3<f32> = vec3<f32>(0.55, 0.7, 0.9);
+// Light absorbed per unit of thickness, red first.
+const ABSORPTION: vec3<f32> = vec3<f32>(0.9, 0.3, 0.15);
+
+struct SpriteOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) center: vec3<f32>,
+    @location(2) speed: f32,
+};
+
+@vertex
+fn vs_sprite(
+    @builtin(vertex_index) vertex_index: u32,
+    @location(0) particle: vec4<f32>,
+) -> SpriteOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    // Billboards face the camera in view space, where it looks down -z.
+    let center = (fluid.view * vec4<f32>(particle.xyz, 1.0)).xyz;
+    let corner = corners[vertex_index];
+    var out: SpriteOutput;
+    out.clip_position = fluid.projection
+        * vec4<f32>(center + vec3<f32>(corner * fluid.radius, 0.0), 1.0);
+    out.corner = corner;
+    out.center = center;
+    out.speed = particle.w;
+    return out;
+}
+
+// The point of the sphere under a sprite fragment and its normal, in view
+// space.
+struct SpherePoint {
+    position: vec3<f32>,
+    normal: vec3<f32>,
+    depth: f32,
+}
+
+fn sphere_point(in: SpriteOutput) -> SpherePoint {
+    let r2 = dot(in.corner, in.corner);
+    var point: SpherePoint;
+    point.normal = vec3<f32>(in.corner, sqrt(max(1.0 - r2, 0.0)));
+    point.position = in.center + point.normal * fluid.radius;
+    point.depth = ndc_depth(point.position);
+    return point;
+}
+
+fn ndc_depth(position: vec3<f32>) -> f32 {
+    let clip = fluid.projection * vec4<f32>(position, 1.0);
+    return clip.z / clip.w;
+}
+
+fn shade(position: vec3<f32>, normal: vec3<f32>, color: vec3<f32>) -> vec3<f32> {
+    let light = (fluid.view * vec4<f32>(fluid.light_position.xyz, 1.0)).xyz;
+    let light_dir = normalize(light - position);
+    let view_dir = normalize(-position);
+    let diffuse = 0.35 + 0.65 * max(dot(normal, light_dir), 0.0);
+    let specular = pow(max(dot(normal, normalize(light_dir + view_dir)), 0.0), 64.0);
+    return color * diffuse + vec3<f32>(specular);
+}
+
+struct SpriteFragment {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@fragment
+fn fs_sprite(in: SpriteOutput) -> SpriteFragment {
+    if dot(in.corner, in.corner) > 1.0 {
+        discard;
+    }
+    let point = sphere_point(in);
+    let color = mix(DEEP, FOAM, clamp(in.speed / fluid.max_speed, 0.0, 1.0));
+    var out: SpriteFragment;
+    out.color = vec4<f32>(shade(point.position, point.normal, color), 1.0);
+    out.depth = point.depth;
+    return out;
+}
+
+struct DepthFragment {
+    @location(0) depth: f32,
+    @builtin(frag_depth) frag_depth: f32,
+};
+
+@fragment
+fn fs_depth(in: SpriteOutput) -> DepthFragment {
+    if dot(in.corner, in.corner) > 1.0 {
+        discard;
+    }
+    let point = sphere_point(in);
+    var out: DepthFragment;
+    out.depth = -point.position.z;
+    out.frag_depth = point.depth;
+    return out;
+}
+
+@fragment
+fn fs_thickness(in: SpriteOutput) -> @location(0) f32 {
+    if dot(in.corner, in.corner) > 1.0 {
+        discard;
+    }
+    return 2.0 * fluid.radius * sphere_point(in).normal.z;
+}
+
+@vertex
+fn vs_fullscreen(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+}
+
+// Taps on each side of the blurred pixel.
+const BLUR_TAPS: i32 = 8;
+
+// Gaussian in screen space over about two particles, cut off across depth
+// discontinuities so that separate sheets of fluid stay apart.
+fn blur(coords: vec2<i32>, direction: vec2<i32>) -> f32 {
+    let center = textureLoad(t_depth, coords, 0).r;
+    if center <= 0.0 {
+        return 0.0;
+    }
+    let last = vec2<i32>(textureDimensions(t_depth)) - 1;
+    let pixels = 2.0 * fluid.radius * fluid.projection[1][1] * 0.5 * fluid.size.y / center;
+    let step = i32(clamp(pixels / f32(BLUR_TAPS), 1.0, 4.0));
+    var sum = 0.0;
+    var weight_sum = 0.0;
+    for (var i = -BLUR_TAPS; i <= BLUR_TAPS; i++) {
+        let depth = textureLoad(t_depth, clamp(coords + direction * i * step, vec2<i32>(0), last), 0).r;
+        if depth <= 0.0 {
+            continue;
+        }
+        let x = 2.0 * f32(i) / f32(BLUR_TAPS);
+        let z = (depth - center) / (2.0 * fluid.radius);
+        let weight = exp(-0.5 * x * x - z * z);
+        sum += depth * weight;
+        weight_sum += weight;
+    }
+    return sum / weight_sum;
+}
+
+@fragment
+fn fs_blur_x(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    return blur(vec2<i32>(position.xy), vec2<i32>(1, 0));
+}
+
+@fragment
+fn fs_blur_y(@builtin(position) position: vec4<f32>) -> @location(0) f32 {
+    return blur(vec2<i32>(position.xy), vec2<i32>(0, 1));
+}
+
+// View space position of a pixel of the smoothed depth, the origin if it
+// shows no fluid.
+fn view_position(coords: vec2<i32>) -> vec3<f32> {
+    let last = vec2<i32>(textureDimensions(t_depth)) - 1;
+    let depth = textureLoad(t_depth, clamp(coords, vec2<i32>(0), last), 0).r;
+    let ndc = (vec2<f32>(coords) + 0.5) / fluid.size * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0);
+    return vec3<f32>(
+        ndc.x * depth / fluid.projection[0][0],
+        ndc.y * depth / fluid.projection[1][1],
+        -depth,
+    );
+}
+
+struct SurfaceFragment {
+    @location(0) color: vec4<f32>,
+    @builtin(frag_depth) depth: f32,
+};
+
+@fragment
+fn fs_surface(@builtin(position) frag_position: vec4<f32>) -> SurfaceFragment {
+    let coords = vec2<i32>(frag_position.xy);
+    let position = view_position(coords);
+    if position.z >= 0.0 {
+        discard;
+    }
+
+    // Differences toward whichever neighbor is closer in depth, so edges
+    // don't bend the normals.
+    var ddx = view_position(coords + vec2<i32>(1, 0)) - position;
+    let ddx_back = position - view_position(coords - vec2<i32>(1, 0));
+    if abs(ddx_back.z) < abs(ddx.z) {
+        ddx = ddx_back;
+    }
+    var ddy = view_position(coords - vec2<i32>(0, 1)) - position;
+    let ddy_back = position - view_position(coords + vec2<i32>(0, 1));
+    if abs(ddy_back.z) < abs(ddy.z) {
+        ddy = ddy_back;
+    }
+    var normal = cross(ddx, ddy);
+    if dot(normal, normal) < 1e-12 {
+        normal = vec3<f32>(0.0, 0.0, 1.0);
+    }
+    normal = normalize(normal);
+
+    let thickness = textureLoad(t_thickness, coords, 0).r;
+    let transmittance = exp(-ABSORPTION * thickness);
+    let fresnel = 0.02 + 0.98 * pow(1.0 - max(dot(normal, normalize(-position)), 0.0), 5.0);
+    let body = shade(position, normal, DEEP * (1.0 - transmittance));
+    let color = mix(body, SKY, fresnel);
+    // The scene shows through where the fluid is thin.
+    let opacity = 1.0 - dot(transmittance, vec3<f32>(1.0 / 3.0));
+
+    var out: SurfaceFragment;
+    out.color = vec4<f32>(color, max(opacity, fresnel));
+    out.depth = ndc_depth(position);
+    return out;
+}
@@ -0,0 +1,96 @@
+//! Picks up edits to the shaders in the source tree and to the resources,
+//! including the scene file, while the app runs. Native debug builds only.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Minimum time between two scans of the watched directories.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// The source tree's `src/` directory, where the shaders live.
+pub fn shader_dir() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("src")
+}
+
+/// The existing directories resources are read from, resolved so that each
+/// is watched once.
+pub fn resource_dirs() -> Vec<PathBuf> {
+    let mut dirs = Vec::<PathBuf>::new();
+    for dir in &crate::resources::resource_root().search_paths {
+        if let Ok(dir) = dir.canonicalize() {
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+    }
+    dirs
+}
+
+/// The name a changed file under one of the [`resource_dirs`] is loaded by.
+/// Other paths are ignored.
+pub fn resource_name(path: &Path) -> Option<String> {
+    let relative = resource_dirs()
+        .into_iter()
+        .find_map(|dir| Some(path.strip_prefix(dir).ok()?.to_path_buf()))?;
+    Some(relative.to_string_lossy().replace('\\', "/"))
+}
+
+/// Watches directories for changed files by polling modification times,
+/// which needs no platform notification API.
+pub struct FileWatcher {
+    roots: Vec<PathBuf>,
+    modified: HashMap<PathBuf, SystemTime>,
+    last_poll: instant::Instant,
+}
+
+impl FileWatcher {
+    pub fn new(roots: Vec<PathBuf>) -> Self {
+        let mut watcher = Self {
+            roots,
+            modified: HashMap::new(),
+            last_poll: instant::Instant::now(),
+        };
+        watcher.modified = watcher.scan();
+        watcher
+    }
+
+    /// Files created or modified since the last scan, scanning at most every
+    /// `POLL_INTERVAL`.
+    pub fn poll(&mut self) -> Vec<PathBuf> {
+        if self.last_poll.elapsed() < POLL_INTERVAL {
+            return Vec::new();
+        }
+        self.last_poll = instant::Instant::now();
+
+        let modified = self.scan();
+        let changed = modified
+            .iter()
+            .filter(|&(path, time)| self.modified.get(path) != Some(time))
+            .map(|(path, _)| path.clone())
+            .collect();
+        self.modified = modified;
+        changed
+    }
+
+    fn scan(&self) -> HashMap<PathBuf, SystemTime> {
+        let mut modified = HashMap::new();
+        let mut dirs = self.roots.clone();
+        while let Some(dir) = dirs.pop() {
+            let Ok(entries) = std::fs::read_dir(&dir) else {
+                continue;
+            };
+            for entry in entries.flatten() {
+                let Ok(metadata) = entry.metadata() else {
+                    continue;
+                };
+                if metadata.is_dir() {
+                    dirs.push(entry.path());
+                } else if let Ok(time) = metadata.modified() {
+                    modified.insert(entry.path(), time);
+                }
+            }
+        }
+        modified
+    }
+}
@@ -1,3 +1,4 @@
+use std::borrow::Cow;
 use std::iter;
 
 use cgmath::prelude::*;
@@ -11,12 +12,27 @@ use winit::{
 #[cfg(target_arch = "wasm32")]
 use wasm_bindgen::prelude::*;
 
+pub mod assets;
 mod camera;
-mod model;
-mod resources;
+mod cloth;
+mod culling;
+mod environment;
+mod fluid;
+#[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+mod hot_reload;
+pub mod model;
+mod orbits;
+mod particles;
+pub mod physics;
+mod post;
+pub mod procedural;
+pub mod resources;
+mod scene;
 mod texture;
+mod trails;
+mod transparency;
 
-use model::{DrawModel, Vertex};
+use model::Vertex;
 
 #[repr(C)]
 #[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
@@ -27,15 +43,207 @@ struct LightUniform {
     _padding2: u32,
 }
 
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ShadingUniform {
+    /// Scale of the image-based ambient and reflected light.
+    ibl_intensity: f32,
+    _padding: [u32; 3],
+    /// Specular strength and shininess, indexed by the instance material.
+    materials: [[f32; 4]; 8],
+}
+
+impl ShadingUniform {
+    fn new() -> Self {
+        let mut materials = [[1.0, 32.0, 0.0, 0.0]; 8];
+        // Glossy
+        materials[1] = [1.0, 128.0, 0.0, 0.0];
+        // Matte
+        materials[2] = [0.1, 8.0, 0.0, 0.0];
+        Self {
+            ibl_intensity: 1.0,
+            _padding: [0; 3],
+            materials,
+        }
+    }
+}
+
+/// What the body instances get colored by.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum ColorMode {
+    Texture,
+    Velocity,
+    Mass,
+    Island,
+    Sleep,
+}
+
+impl ColorMode {
+    fn next(self) -> Self {
+        match self {
+            ColorMode::Texture => ColorMode::Velocity,
+            ColorMode::Velocity => ColorMode::Mass,
+            ColorMode::Mass => ColorMode::Island,
+            ColorMode::Island => ColorMode::Sleep,
+            ColorMode::Sleep => ColorMode::Texture,
+        }
+    }
+
+    /// Ramp the body instances are drawn with; other draws never use one.
+    fn color_map(self) -> model::ColorMap {
+        match self {
+            ColorMode::Velocity | ColorMode::Mass => model::ColorMap::Viridis,
+            _ => model::ColorMap::Off,
+        }
+    }
+}
+
+/// WGSL of the shaders that can be hot reloaded, built in by default.
+struct ShaderSources {
+    /// Lighting shared by the textured and vertex-colored model shaders.
+    lighting: Cow<'static, str>,
+    shader: Cow<'static, str>,
+    colored: Cow<'static, str>,
+    light: Cow<'static, str>,
+}
+
+impl ShaderSources {
+    fn built_in() -> Self {
+        Self {
+            lighting: include_str!("lighting.wgsl").into(),
+            shader: include_str!("shader.wgsl").into(),
+            colored: include_str!("colored.wgsl").into(),
+            light: include_str!("light.wgsl").into(),
+        }
+    }
+
+    /// The files of the shaders, in the source tree's `src/`.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    const FILES: [&'static str; 4] = ["lighting.wgsl", "shader.wgsl", "colored.wgsl", "light.wgsl"];
+
+    /// Reads the shaders' current versions from the source tree.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    fn read() -> std::io::Result<Self> {
+        let dir = hot_reload::shader_dir();
+        let read = |name| std::fs::read_to_string(dir.join(name)).map(Cow::Owned);
+        let [lighting, shader, colored, light] = Self::FILES;
+        Ok(Self {
+            lighting: read(lighting)?,
+            shader: read(shader)?,
+            colored: read(colored)?,
+            light: read(light)?,
+        })
+    }
+
+    /// The textured model shader with the lighting it uses.
+    fn model_shader(&self) -> String {
+        format!("{}\n{}", self.lighting, self.shader)
+    }
+
+    /// The vertex-colored model shader with the lighting it uses.
+    fn colored_shader(&self) -> String {
+        format!("{}\n{}", self.lighting, self.colored)
+    }
+}
+
+/// Why the app couldn't start.
+#[derive(Debug, thiserror::Error)]
+pub enum InitError {
+    #[error("failed to create the window: {0}")]
+    Window(#[from] winit::error::OsError),
+    #[error("failed to create the surface: {0}")]
+    Surface(#[from] wgpu::CreateSurfaceError),
+    #[error("no compatible graphics adapter found")]
+    NoAdapter,
+    #[error("failed to create the device: {0}")]
+    Device(#[from] wgpu::RequestDeviceError),
+}
+
+#[cfg(target_arch = "wasm32")]
+impl From<InitError> for JsValue {
+    fn from(error: InitError) -> Self {
+        JsValue::from_str(&error.to_string())
+    }
+}
+
+/// How a pipeline's fragments are combined with what is already drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Blending {
+    /// Replaces the color and writes depth.
+    Opaque,
+    /// Blends over the color by alpha without writing depth.
+    Alpha,
+    /// Accumulates into the weighted blended transparency targets through
+    /// `fs_oit` without writing depth.
+    WeightedBlended,
+}
+
+#[allow(clippy::too_many_arguments)]
 fn create_render_pipeline(
     device: &wgpu::Device,
     layout: &wgpu::PipelineLayout,
     color_format: wgpu::TextureFormat,
     depth_format: Option<wgpu::TextureFormat>,
+    sample_count: u32,
+    blending: Blending,
     vertex_layouts: &[wgpu::VertexBufferLayout],
     shader: wgpu::ShaderModuleDescriptor,
 ) -> wgpu::RenderPipeline {
     let shader = device.create_shader_module(shader);
+    let color_target = |format, blend| {
+        Some(wgpu::ColorTargetState {
+            format,
+            blend: Some(blend),
+            write_mask: wgpu::ColorWrites::ALL,
+        })
+    };
+    let (entry_point, targets) = match blending {
+        Blending::Opaque => (
+            "fs_main",
+            vec![color_target(
+                color_format,
+                wgpu::BlendState {
+                    alpha: wgpu::BlendComponent::REPLACE,
+                    color: wgpu::BlendComponent::REPLACE,
+                },
+            )],
+        ),
+        Blending::Alpha => (
+            "fs_main",
+            vec![color_target(color_format, wgpu::BlendState::ALPHA_BLENDING)],
+        ),
+        Blending::WeightedBlended => {
+            let sum = wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            };
+            let transmit = wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                operation: wgpu::BlendOperation::Add,
+            };
+            (
+                "fs_oit",
+                vec![
+                    color_target(
+                        color_format,
+                        wgpu::BlendState {
+                            alpha: sum,
+                            color: sum,
+                        },
+                    ),
+                    color_target(
+                        transparency::REVEALAGE_FORMAT,
+                        wgpu::BlendState {
+                            alpha: transmit,
+                            color: transmit,
+                        },
+                    ),
+                ],
+            )
+        }
+    };
 
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some("Render Pipeline"),
@@ -47,15 +255,8 @@ fn create_render_pipeline(
         },
         fragment: Some(wgpu::FragmentState {
             module: &shader,
-            entry_point: "fs_main",
-            targets: &[Some(wgpu::ColorTargetState {
-                format: color_format,
-                blend: Some(wgpu::BlendState {
-                    alpha: wgpu::BlendComponent::REPLACE,
-                    color: wgpu::BlendComponent::REPLACE,
-                }),
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
+            entry_point,
+            targets: &targets,
         }),
         primitive: wgpu::PrimitiveState {
             topology: wgpu::PrimitiveTopology::TriangleList,
@@ -68,13 +269,13 @@ fn create_render_pipeline(
         },
         depth_stencil: depth_format.map(|format| wgpu::DepthStencilState {
             format,
-            depth_write_enabled: true,
+            depth_write_enabled: blending == Blending::Opaque,
             depth_compare: wgpu::CompareFunction::Less,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
         multisample: wgpu::MultisampleState {
-            count: 1,
+            count: sample_count,
             mask: !0,
             alpha_to_coverage_enabled: false,
         },
@@ -89,13 +290,32 @@ struct State {
     config: wgpu::SurfaceConfiguration,
     size: winit::dpi::PhysicalSize<u32>,
     render_pipeline: wgpu::RenderPipeline,
-    obj_model: model::Model,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    light_pipeline_layout: wgpu::PipelineLayout,
+    colored_pipeline_layout: wgpu::PipelineLayout,
+    /// Draws meshes colored per vertex, which need no material.
+    colored_render_pipeline: wgpu::RenderPipeline,
+    sample_count: u32,
+    shaders: ShaderSources,
+    /// Watches the source tree for edited shaders and resources.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    watcher: hot_reload::FileWatcher,
+    /// MSAA sample counts supported by both the surface and depth formats.
+    sample_counts: Vec<u32>,
+    /// Multisampled color target, absent without MSAA.
+    msaa_texture: Option<texture::Texture>,
+    present_modes: Vec<wgpu::PresentMode>,
+    post: post::PostProcess,
+    environment: environment::Environment,
+    assets: assets::AssetServer,
+    obj_model: assets::Handle<model::Model>,
 
     camera: camera::Camera,
     projection: camera::Projection,
     camera_controller: camera::CameraController,
     camera_uniform: camera::CameraUniform,
     camera_buffer: wgpu::Buffer,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
     camera_bind_group: wgpu::BindGroup,
     mouse_pressed: bool,
 
@@ -104,14 +324,37 @@ struct State {
     light_bind_group: wgpu::BindGroup,
     light_render_pipeline: wgpu::RenderPipeline,
 
-    instances: Vec<model::Instance>,
-    instance_buffer: wgpu::Buffer,
+    world: physics::World,
+    /// Dynamic bodies in the same order as their instances in `instance_buffer`.
+    bodies: Vec<(physics::BodyHandle, model::Instance)>,
+    /// The bodies placed by the scene file, replaced when it's re-applied.
+    scene_bodies: Vec<physics::BodyHandle>,
+    instance_buffer: model::InstanceBuffer,
+    culler: culling::InstanceCuller,
+    ground: model::Mesh,
+    ground_instance_buffer: wgpu::Buffer,
+    spawned: u32,
+    /// The N-body demo, shown instead of the rigid bodies while running.
+    orbits: Option<orbits::Orbits>,
+    /// The particle fountain, shown next to the rigid bodies while running.
+    particles: Option<particles::Particles>,
+    /// The fluid tank, stepped against the rigid bodies while running.
+    tank: Option<fluid::Tank>,
+    /// The flag and sheet, stepped against the rigid bodies while running.
+    cloths: Option<cloth::Cloths>,
+    downlevel_flags: wgpu::DownlevelFlags,
+
+    color_mode: ColorMode,
+    /// Draws the bodies translucent, to see through stacks.
+    ghosts: bool,
+    transparency: transparency::TransparentPass,
+    shading_bind_group: wgpu::BindGroup,
     depth_texture: texture::Texture,
     window: Window,
 }
 
 impl State {
-    async fn new(window: Window) -> Self {
+    async fn new(window: Window) -> Result<Self, InitError> {
         let size = window.inner_size();
 
         let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -119,7 +362,7 @@ impl State {
             ..Default::default()
         });
 
-        let surface = unsafe { instance.create_surface(&window) }.unwrap();
+        let surface = unsafe { instance.create_surface(&window) }?;
 
         let adapter = instance
             .request_adapter(&wgpu::RequestAdapterOptions {
@@ -128,13 +371,16 @@ impl State {
                 force_fallback_adapter: false,
             })
             .await
-            .unwrap();
+            .ok_or(InitError::NoAdapter)?;
 
         let (device, queue) = adapter
             .request_device(
                 &wgpu::DeviceDescriptor {
                     label: None,
-                    features: wgpu::Features::empty(),
+                    // Lets MSAA use every sample count the adapter supports
+                    // rather than only 1 and 4.
+                    features: adapter.features()
+                        & wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES,
                     limits: if cfg!(target_arch = "wasm32") {
                         wgpu::Limits::downlevel_webgl2_defaults()
                     } else {
@@ -143,8 +389,7 @@ impl State {
                 },
                 None,
             )
-            .await
-            .unwrap();
+            .await?;
 
         let surface_caps = surface.get_capabilities(&adapter);
         let surface_format = surface_caps
@@ -158,35 +403,43 @@ impl State {
             format: surface_format,
             width: size.width,
             height: size.height,
-            present_mode: surface_caps.present_modes[0],
+            present_mode: wgpu::PresentMode::Fifo,
             alpha_mode: surface_caps.alpha_modes[0],
             view_formats: vec![],
         };
 
         surface.configure(&device, &config);
 
-        let texture_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            multisampled: false,
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-                label: Some("texture_bind_group_layout"),
-            });
+        let sample_counts = [1, 2, 4, 8]
+            .into_iter()
+            .filter(|&count| {
+                if !device
+                    .features()
+                    .contains(wgpu::Features::TEXTURE_ADAPTER_SPECIFIC_FORMAT_FEATURES)
+                {
+                    return count == 1 || count == 4;
+                }
+                [
+                    post::HDR_FORMAT,
+                    transparency::REVEALAGE_FORMAT,
+                    texture::Texture::DEPTH_FORMAT,
+                ]
+                .into_iter()
+                .all(|format| {
+                    adapter
+                        .get_texture_format_features(format)
+                        .flags
+                        .sample_count_supported(count)
+                })
+            })
+            .collect::<Vec<_>>();
+        let sample_count = if sample_counts.contains(&4) { 4 } else { 1 };
+
+        let post = post::PostProcess::new(&device, &config);
+
+        let mut assets = assets::AssetServer::new(&device, &queue);
+        let obj_model = assets.load_model("cube.obj");
+        assets.load_all(&device, &queue).await;
 
         let camera = camera::Camera::new((0.0, 5.0, 10.0), cgmath::Deg(-90.0), cgmath::Deg(-20.0));
         let projection =
@@ -202,41 +455,6 @@ impl State {
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
         });
 
-        const NUM_INSTANCES_PER_ROW: u32 = 10;
-        const SPACE_BETWEEN: f32 = 3.0;
-        let instances = (0..NUM_INSTANCES_PER_ROW)
-            .flat_map(|z| {
-                (0..NUM_INSTANCES_PER_ROW).map(move |x| {
-                    let x = SPACE_BETWEEN * (x as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-                    let z = SPACE_BETWEEN * (z as f32 - NUM_INSTANCES_PER_ROW as f32 / 2.0);
-
-                    let position = cgmath::Vector3 { x, y: 0.0, z };
-
-                    let rotation = if position.is_zero() {
-                        cgmath::Quaternion::from_axis_angle(
-                            cgmath::Vector3::unit_z(),
-                            cgmath::Deg(0.0),
-                        )
-                    } else {
-                        cgmath::Quaternion::from_axis_angle(position.normalize(), cgmath::Deg(45.0))
-                    };
-
-                    let size = (x.abs() + z.abs()) as f32 / 10.0 + 0.2;
-                    model::Instance::new(Some(position), Some(rotation), size)
-                })
-            })
-            .collect::<Vec<_>>();
-
-        let instance_data = instances
-            .iter()
-            .map(model::Instance::to_raw)
-            .collect::<Vec<_>>();
-        let instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some("Instance Buffer"),
-            contents: bytemuck::cast_slice(&instance_data),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
         let camera_bind_group_layout =
             device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
                 entries: &[wgpu::BindGroupLayoutEntry {
@@ -261,13 +479,67 @@ impl State {
             label: Some("camera_bind_group"),
         });
 
-        let obj_model =
-            resources::load_model("cube.obj", &device, &queue, &texture_bind_group_layout)
-                .await
-                .unwrap();
+        let mut world = physics::World::new();
+        let ground_shape = physics::Shape::Cuboid(physics::Obb {
+            center: cgmath::Point3::new(0.0, 0.0, 0.0),
+            half_extents: cgmath::Vector3::new(20.0, 0.5, 20.0),
+            rotation: cgmath::Quaternion::one(),
+        });
+        let ground =
+            procedural::shape_colored_mesh("ground", &device, &ground_shape, [0.6, 0.6, 0.6]);
+        let ground_transform =
+            physics::Transform::from_position(cgmath::Vector3::new(0.0, -1.5, 0.0));
+        world.add_body(physics::RigidBody::fixed(ground_shape, ground_transform));
+        let ground_instance_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Ground Instance Buffer"),
+            contents: bytemuck::cast_slice(&[model::Instance::new(
+                Some(ground_transform.position),
+                Some(ground_transform.rotation),
+                1.0,
+            )
+            .to_raw()]),
+            usage: wgpu::BufferUsages::VERTEX,
+        });
+
+        let model_bounds = assets.model_or_placeholder(&obj_model).bounding_box();
+        let scene = scene::Scene::load(scene::SCENE_FILE)
+            .await
+            .unwrap_or_else(|e| {
+                log::warn!("Failed to load the scene: {}", e);
+                scene::Scene::default()
+            });
+        let bodies = scene
+            .bodies
+            .iter()
+            .map(|body| Self::spawn_body(&mut world, model_bounds, body.transform, body.scale))
+            .collect::<Vec<_>>();
+        let scene_bodies = bodies.iter().map(|(handle, _)| *handle).collect();
+
+        let instance_data = bodies
+            .iter()
+            .map(|(_, instance)| instance.to_raw())
+            .collect::<Vec<_>>();
+        let downlevel = adapter.get_downlevel_capabilities();
+        let culler = culling::InstanceCuller::new(
+            &device,
+            &downlevel,
+            assets.model_or_placeholder(&obj_model),
+        );
+        let mut instance_buffer =
+            model::InstanceBuffer::with_usage(&device, instance_data.len(), culler.source_usage());
+        instance_buffer.write(&device, &queue, &instance_data);
 
         let depth_texture =
-            texture::Texture::create_depth_texture(&device, &config, "depth_texture");
+            texture::Texture::create_depth_texture(&device, &config, sample_count, "depth_texture");
+        let msaa_texture = (sample_count > 1).then(|| {
+            texture::Texture::create_msaa_texture(
+                &device,
+                &config,
+                post::HDR_FORMAT,
+                sample_count,
+                "msaa_texture",
+            )
+        });
 
         let light_uniform = LightUniform {
             position: [2.0, 2.0, 2.0],
@@ -306,64 +578,150 @@ impl State {
             label: None,
         });
 
+        let shading_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Shading Buffer"),
+            contents: bytemuck::cast_slice(&[ShadingUniform::new()]),
+            usage: wgpu::BufferUsages::UNIFORM,
+        });
+
+        let sky = match resources::load_environment_image("sky.hdr").await {
+            Ok(image) => Some(image),
+            Err(e) => {
+                log::info!("No sky image, using a gradient: {}", e);
+                None
+            }
+        };
+        let environment = environment::Environment::new(
+            &device,
+            &queue,
+            sky.as_ref()
+                .map_or(environment::Sky::Gradient, environment::Sky::Image),
+            &camera_bind_group_layout,
+            sample_count,
+        );
+
+        let shading_bind_group_layout = {
+            let [irradiance, prefiltered, brdf, sampler] =
+                environment::Environment::layout_entries(1);
+            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+                entries: &[
+                    wgpu::BindGroupLayoutEntry {
+                        binding: 0,
+                        visibility: wgpu::ShaderStages::FRAGMENT,
+                        ty: wgpu::BindingType::Buffer {
+                            ty: wgpu::BufferBindingType::Uniform,
+                            has_dynamic_offset: false,
+                            min_binding_size: None,
+                        },
+                        count: None,
+                    },
+                    irradiance,
+                    prefiltered,
+                    brdf,
+                    sampler,
+                ],
+                label: Some("shading_bind_group_layout"),
+            })
+        };
+
+        let shading_bind_group = {
+            let [irradiance, prefiltered, brdf, sampler] = environment.bind_group_entries(1);
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout: &shading_bind_group_layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: shading_buffer.as_entire_binding(),
+                    },
+                    irradiance,
+                    prefiltered,
+                    brdf,
+                    sampler,
+                ],
+                label: Some("shading_bind_group"),
+            })
+        };
+
         let render_pipeline_layout =
             device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Render Pipeline Layout"),
                 bind_group_layouts: &[
-                    &texture_bind_group_layout,
+                    assets.texture_bind_group_layout(),
                     &camera_bind_group_layout,
                     &light_bind_group_layout,
+                    &shading_bind_group_layout,
                 ],
                 push_constant_ranges: &[],
             });
 
-        let render_pipeline = {
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Normal Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("shader.wgsl").into()),
-            };
-            create_render_pipeline(
-                &device,
-                &render_pipeline_layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
-                shader,
-            )
-        };
-
-        let light_render_pipeline = {
-            let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        let light_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
                 label: Some("Light Pipeline Layout"),
                 bind_group_layouts: &[&camera_bind_group_layout, &light_bind_group_layout],
                 push_constant_ranges: &[],
             });
-            let shader = wgpu::ShaderModuleDescriptor {
-                label: Some("Light Shader"),
-                source: wgpu::ShaderSource::Wgsl(include_str!("light.wgsl").into()),
-            };
-            create_render_pipeline(
+
+        let colored_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Colored Pipeline Layout"),
+                bind_group_layouts: &[
+                    &camera_bind_group_layout,
+                    &light_bind_group_layout,
+                    &shading_bind_group_layout,
+                ],
+                push_constant_ranges: &[],
+            });
+
+        let shaders = ShaderSources::built_in();
+        let (render_pipeline, light_render_pipeline, colored_render_pipeline) =
+            Self::create_pipelines(
                 &device,
-                &layout,
-                config.format,
-                Some(texture::Texture::DEPTH_FORMAT),
-                &[model::ModelVertex::desc()],
-                shader,
-            )
-        };
+                &render_pipeline_layout,
+                &light_pipeline_layout,
+                &colored_pipeline_layout,
+                post::HDR_FORMAT,
+                sample_count,
+                &shaders,
+            );
+        let transparency = transparency::TransparentPass::new(
+            &device,
+            &config,
+            &render_pipeline_layout,
+            sample_count,
+            &shaders.model_shader(),
+        );
 
-        Self {
+        Ok(Self {
             surface,
             device,
             queue,
             config,
             size,
             render_pipeline,
+            render_pipeline_layout,
+            light_pipeline_layout,
+            colored_pipeline_layout,
+            colored_render_pipeline,
+            sample_count,
+            shaders,
+            #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+            watcher: hot_reload::FileWatcher::new(
+                iter::once(hot_reload::shader_dir())
+                    .chain(hot_reload::resource_dirs())
+                    .collect(),
+            ),
+            sample_counts,
+            msaa_texture,
+            present_modes: surface_caps.present_modes,
+            post,
+            environment,
+            assets,
             obj_model,
             camera,
             projection,
             camera_controller,
             camera_buffer,
+            camera_bind_group_layout,
             camera_bind_group,
             camera_uniform,
             mouse_pressed: false,
@@ -371,17 +729,217 @@ impl State {
             light_buffer,
             light_bind_group,
             light_render_pipeline,
-            instances,
+            world,
+            bodies,
+            scene_bodies,
             instance_buffer,
+            culler,
+            ground,
+            ground_instance_buffer,
+            spawned: 0,
+            orbits: None,
+            particles: None,
+            tank: None,
+            cloths: None,
+            downlevel_flags: downlevel.flags,
+            color_mode: ColorMode::Texture,
+            ghosts: false,
+            transparency,
+            shading_bind_group,
             depth_texture,
             window,
-        }
+        })
     }
 
     pub fn window(&self) -> &Window {
         &self.window
     }
 
+    fn create_pipelines(
+        device: &wgpu::Device,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        light_pipeline_layout: &wgpu::PipelineLayout,
+        colored_pipeline_layout: &wgpu::PipelineLayout,
+        color_format: wgpu::TextureFormat,
+        sample_count: u32,
+        shaders: &ShaderSources,
+    ) -> (
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+        wgpu::RenderPipeline,
+    ) {
+        let render_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Normal Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders.model_shader().into()),
+            };
+            create_render_pipeline(
+                device,
+                render_pipeline_layout,
+                color_format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                sample_count,
+                Blending::Opaque,
+                &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
+                shader,
+            )
+        };
+
+        let light_render_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Light Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders.light.as_ref().into()),
+            };
+            create_render_pipeline(
+                device,
+                light_pipeline_layout,
+                color_format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                sample_count,
+                Blending::Opaque,
+                &[model::ModelVertex::desc()],
+                shader,
+            )
+        };
+
+        let colored_render_pipeline = {
+            let shader = wgpu::ShaderModuleDescriptor {
+                label: Some("Colored Shader"),
+                source: wgpu::ShaderSource::Wgsl(shaders.colored_shader().into()),
+            };
+            create_render_pipeline(
+                device,
+                colored_pipeline_layout,
+                color_format,
+                Some(texture::Texture::DEPTH_FORMAT),
+                sample_count,
+                Blending::Opaque,
+                &[
+                    model::ModelVertexColored::desc(),
+                    model::InstanceRaw::desc(),
+                ],
+                shader,
+            )
+        };
+
+        (
+            render_pipeline,
+            light_render_pipeline,
+            colored_render_pipeline,
+        )
+    }
+
+    /// Switches MSAA to `sample_count`, rebuilding the pipelines and render
+    /// targets. Unsupported counts are ignored.
+    fn set_sample_count(&mut self, sample_count: u32) {
+        if !self.sample_counts.contains(&sample_count) {
+            log::warn!("{}x MSAA is not supported", sample_count);
+            return;
+        }
+        self.sample_count = sample_count;
+        (
+            self.render_pipeline,
+            self.light_render_pipeline,
+            self.colored_render_pipeline,
+        ) = Self::create_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.light_pipeline_layout,
+            &self.colored_pipeline_layout,
+            post::HDR_FORMAT,
+            sample_count,
+            &self.shaders,
+        );
+        self.environment
+            .set_sample_count(&self.device, sample_count);
+        if let Some(orbits) = &mut self.orbits {
+            orbits.set_sample_count(&self.device, sample_count);
+        }
+        if let Some(particles) = &mut self.particles {
+            particles.set_sample_count(&self.device, sample_count);
+        }
+        if let Some(tank) = &mut self.tank {
+            tank.set_sample_count(&self.device, sample_count);
+        }
+        self.transparency.set_sample_count(
+            &self.device,
+            &self.config,
+            &self.render_pipeline_layout,
+            sample_count,
+            &self.shaders.model_shader(),
+        );
+        self.create_render_targets();
+        log::info!("MSAA: {}x", sample_count);
+    }
+
+    /// Switches to `present_mode` if the surface supports it.
+    fn set_present_mode(&mut self, present_mode: wgpu::PresentMode) {
+        if !self.present_modes.contains(&present_mode) {
+            log::warn!("{:?} present mode is not supported", present_mode);
+            return;
+        }
+        self.config.present_mode = present_mode;
+        self.surface.configure(&self.device, &self.config);
+        log::info!("Present mode: {:?}", present_mode);
+    }
+
+    fn create_render_targets(&mut self) {
+        self.depth_texture = texture::Texture::create_depth_texture(
+            &self.device,
+            &self.config,
+            self.sample_count,
+            "depth_texture",
+        );
+        self.msaa_texture = (self.sample_count > 1).then(|| {
+            texture::Texture::create_msaa_texture(
+                &self.device,
+                &self.config,
+                post::HDR_FORMAT,
+                self.sample_count,
+                "msaa_texture",
+            )
+        });
+    }
+
+    /// Adds a cube body matching `obj_model` stretched by `scale`.
+    fn spawn_body(
+        world: &mut physics::World,
+        model_bounds: physics::Aabb,
+        transform: physics::Transform,
+        scale: cgmath::Vector3<f32>,
+    ) -> (physics::BodyHandle, model::Instance) {
+        let shape = physics::Shape::Cuboid(model_bounds.to_obb()).scaled(scale);
+        let handle = world.add_body(physics::RigidBody::dynamic(shape, 1.0, transform));
+        let mut instance =
+            model::Instance::new(Some(transform.position), Some(transform.rotation), 1.0);
+        instance.set_scale(scale);
+        (handle, instance)
+    }
+
+    /// Drops a new plank from above the center of the scene.
+    fn spawn(&mut self) {
+        self.spawned += 1;
+        let angle = cgmath::Deg(137.5 * self.spawned as f32);
+        let axis =
+            cgmath::Vector3::new(angle.0.to_radians().cos(), 1.0, angle.0.to_radians().sin());
+        let transform = physics::Transform::new(
+            cgmath::Vector3::new(0.0, 10.0, 0.0),
+            cgmath::Quaternion::from_axis_angle(axis.normalize(), angle),
+        );
+        let model_bounds = self
+            .assets
+            .model_or_placeholder(&self.obj_model)
+            .bounding_box();
+        let (handle, mut instance) = Self::spawn_body(
+            &mut self.world,
+            model_bounds,
+            transform,
+            cgmath::Vector3::new(0.8, 0.15, 0.3),
+        );
+        instance.set_material(1);
+        self.bodies.push((handle, instance));
+    }
+
     fn resize(&mut self, new_size: winit::dpi::PhysicalSize<u32>) {
         if new_size.width > 0 && new_size.height > 0 {
             self.projection.resize(new_size.width, new_size.height);
@@ -389,12 +947,284 @@ impl State {
             self.config.width = new_size.width;
             self.config.height = new_size.height;
             self.surface.configure(&self.device, &self.config);
-            self.depth_texture =
-                texture::Texture::create_depth_texture(&self.device, &self.config, "depth_texture");
+            self.create_render_targets();
+            self.post.resize(&self.device, &self.config);
+            self.transparency
+                .resize(&self.device, &self.config, self.sample_count);
+            if let Some(tank) = &mut self.tank {
+                tank.resize(&self.device, &self.config);
+            }
         }
     }
     fn input(&mut self, event: &WindowEvent) -> bool {
         match event {
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::Return),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.spawn();
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::C),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.set_color_mode(self.color_mode.next());
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode:
+                            Some(key @ (VirtualKeyCode::Minus | VirtualKeyCode::Equals)),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                const EXPOSURE_STEP: f32 = 0.5;
+                let settings = &mut self.post.settings;
+                settings.exposure += match key {
+                    VirtualKeyCode::Minus => -EXPOSURE_STEP,
+                    _ => EXPOSURE_STEP,
+                };
+                log::info!("Exposure: {:+} EV", settings.exposure);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::T),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                let settings = &mut self.post.settings;
+                settings.tone_mapping = settings.tone_mapping.next();
+                log::info!("Tone mapping: {:?}", settings.tone_mapping);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::B),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.post.settings.bloom = !self.post.settings.bloom;
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::M),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                let next = self
+                    .sample_counts
+                    .iter()
+                    .position(|&count| count == self.sample_count)
+                    .map_or(0, |i| (i + 1) % self.sample_counts.len());
+                self.set_sample_count(self.sample_counts[next]);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::V),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                const MODES: [wgpu::PresentMode; 3] = [
+                    wgpu::PresentMode::Fifo,
+                    wgpu::PresentMode::Mailbox,
+                    wgpu::PresentMode::Immediate,
+                ];
+                let current = MODES
+                    .iter()
+                    .position(|&mode| mode == self.config.present_mode)
+                    .unwrap_or(0);
+                let next = (1..=MODES.len())
+                    .map(|i| MODES[(current + i) % MODES.len()])
+                    .find(|mode| self.present_modes.contains(mode))
+                    .unwrap_or(wgpu::PresentMode::Fifo);
+                self.set_present_mode(next);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::G),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.ghosts = !self.ghosts;
+                self.set_color_mode(self.color_mode);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::O),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                let transparency = &mut self.transparency;
+                transparency.mode = transparency.mode.next();
+                log::info!("Transparency: {:?}", transparency.mode);
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::N),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.orbits = match self.orbits.take() {
+                    Some(_) => None,
+                    None => Some(orbits::Orbits::new(
+                        &self.device,
+                        &self.camera_bind_group_layout,
+                        self.sample_count,
+                        self.culler.source_usage(),
+                    )),
+                };
+                log::info!("N-body demo: {}", self.orbits.is_some());
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::K),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if self.orbits.is_some() => {
+                if let Some(orbits) = &mut self.orbits {
+                    orbits.toggle_kernel();
+                }
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::P),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.particles = match self.particles.take() {
+                    Some(_) => None,
+                    None => Some(particles::Particles::new(
+                        &self.device,
+                        self.downlevel_flags,
+                        &self.camera_bind_group_layout,
+                        self.sample_count,
+                    )),
+                };
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::F),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.tank = match self.tank.take() {
+                    Some(_) => None,
+                    None => Some(fluid::Tank::new(
+                        &self.device,
+                        &self.config,
+                        self.sample_count,
+                    )),
+                };
+                log::info!("Fluid demo: {}", self.tank.is_some());
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::H),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if self.tank.is_some() => {
+                if let Some(tank) = &mut self.tank {
+                    tank.toggle_rendering();
+                }
+                true
+            }
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::L),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } => {
+                self.cloths = match self.cloths.take() {
+                    Some(cloths) => {
+                        cloths.remove(&mut self.world);
+                        None
+                    }
+                    None => Some(cloth::Cloths::new(&self.device, &mut self.world)),
+                };
+                log::info!("Cloth demo: {}", self.cloths.is_some());
+                true
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            WindowEvent::KeyboardInput {
+                input:
+                    KeyboardInput {
+                        virtual_keycode: Some(VirtualKeyCode::R),
+                        state: ElementState::Pressed,
+                        ..
+                    },
+                ..
+            } if self.particles.is_some() => {
+                if let Some(particles) = &mut self.particles {
+                    match particles.compare_with_reference(&self.device, &self.queue) {
+                        Some(error) => log::info!(
+                            "GPU particles differ from the CPU reference by up to {:e}",
+                            error
+                        ),
+                        None => log::info!("Particles are stepped by the CPU reference"),
+                    }
+                }
+                true
+            }
             WindowEvent::KeyboardInput {
                 input:
                     KeyboardInput {
@@ -420,7 +1250,122 @@ impl State {
         }
     }
 
+    /// Rebuilds the pipelines from the shaders in the source tree. If they
+    /// don't compile, the errors are logged and the current pipelines stay.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    fn reload_shaders(&mut self) {
+        let shaders = match ShaderSources::read() {
+            Ok(shaders) => shaders,
+            Err(e) => {
+                log::error!("Failed to read shaders: {}", e);
+                return;
+            }
+        };
+
+        self.device.push_error_scope(wgpu::ErrorFilter::Validation);
+        let pipelines = Self::create_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            &self.light_pipeline_layout,
+            &self.colored_pipeline_layout,
+            post::HDR_FORMAT,
+            self.sample_count,
+            &shaders,
+        );
+        let transparent_pipelines = transparency::create_pipelines(
+            &self.device,
+            &self.render_pipeline_layout,
+            self.sample_count,
+            &shaders.model_shader(),
+        );
+        if let Some(error) = pollster::block_on(self.device.pop_error_scope()) {
+            log::error!(
+                "Shader reload failed, keeping the previous shaders:\n{}",
+                error
+            );
+            return;
+        }
+
+        (
+            self.render_pipeline,
+            self.light_render_pipeline,
+            self.colored_render_pipeline,
+        ) = pipelines;
+        self.transparency.set_pipelines(transparent_pipelines);
+        self.shaders = shaders;
+        log::info!("Reloaded shaders");
+    }
+
+    /// Applies the shaders and resources edited since the last frame. Only
+    /// the model and light shaders are reloaded; the other passes keep the
+    /// WGSL they were built with.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    fn hot_reload(&mut self) {
+        let mut shaders_changed = false;
+        for path in self.watcher.poll() {
+            if path.parent() == Some(&hot_reload::shader_dir()) {
+                shaders_changed |= path
+                    .file_name()
+                    .is_some_and(|name| ShaderSources::FILES.iter().any(|file| name == *file));
+            } else if let Some(file_name) = hot_reload::resource_name(&path) {
+                log::info!("Reloading {}", file_name);
+                if file_name == scene::SCENE_FILE {
+                    self.reload_scene();
+                } else {
+                    self.assets.reload(&file_name);
+                }
+            }
+        }
+        if shaders_changed {
+            self.reload_shaders();
+        }
+    }
+
+    /// Replaces the bodies placed by the scene file with the ones it lists
+    /// now, keeping the spawned ones. A scene that fails to load leaves the
+    /// bodies as they are.
+    #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+    fn reload_scene(&mut self) {
+        let scene = match pollster::block_on(scene::Scene::load(scene::SCENE_FILE)) {
+            Ok(scene) => scene,
+            Err(e) => {
+                log::warn!("Failed to reload the scene: {}", e);
+                return;
+            }
+        };
+        let removed = std::mem::take(&mut self.scene_bodies);
+        for handle in &removed {
+            self.world.remove_body(*handle);
+        }
+        self.bodies.retain(|(handle, _)| !removed.contains(handle));
+
+        let model_bounds = self
+            .assets
+            .model_or_placeholder(&self.obj_model)
+            .bounding_box();
+        let placed = scene
+            .bodies
+            .iter()
+            .map(|body| Self::spawn_body(&mut self.world, model_bounds, body.transform, body.scale))
+            .collect::<Vec<_>>();
+        self.scene_bodies = placed.iter().map(|(handle, _)| *handle).collect();
+        self.bodies.splice(0..0, placed);
+        // Colors the new instances and uploads them all.
+        self.set_color_mode(self.color_mode);
+    }
+
     fn update(&mut self, dt: std::time::Duration) {
+        #[cfg(all(debug_assertions, not(target_arch = "wasm32")))]
+        self.hot_reload();
+        let built = self.assets.update(&self.device, &self.queue);
+        if built.contains(&self.obj_model) {
+            // Its bounds or levels of detail may differ from the placeholder
+            // or the previous version.
+            self.culler.set_model(
+                &self.device,
+                self.assets.model_or_placeholder(&self.obj_model),
+            );
+        }
         self.camera_controller.update_camera(&mut self.camera, dt);
         self.camera_uniform
             .update_view_proj(&self.camera, &self.projection);
@@ -441,6 +1386,152 @@ impl State {
             0,
             bytemuck::cast_slice(&[self.light_uniform]),
         );
+
+        if let Some(particles) = &mut self.particles {
+            particles.update(&self.device, &self.queue, dt.as_secs_f32());
+        }
+        match &mut self.orbits {
+            Some(orbits) => {
+                orbits.update(&self.device, &self.queue, dt.as_secs_f32(), self.color_mode)
+            }
+            None => self.update_bodies(dt.as_secs_f32()),
+        }
+        if let Some(tank) = &mut self.tank {
+            tank.update(&self.device, &self.queue, dt.as_secs_f32(), &mut self.world);
+            tank.prepare(
+                &self.queue,
+                self.camera.calc_matrix(),
+                self.projection.calc_matrix(),
+                self.light_uniform.position,
+            );
+        }
+        if let Some(cloths) = &mut self.cloths {
+            cloths.update(&self.device, &self.queue, dt.as_secs_f32(), &mut self.world);
+        }
+    }
+
+    /// Steps the simulation and uploads the instances of bodies that moved.
+    fn update_bodies(&mut self, dt: f32) {
+        const KILL_HEIGHT: f32 = -20.0;
+
+        self.world.update(dt);
+
+        let mut rewrite_all = self.bodies.len() != self.instance_buffer.len();
+        let world = &mut self.world;
+        let scene_bodies = &mut self.scene_bodies;
+        self.bodies.retain(|(handle, _)| {
+            let fallen = world
+                .body(*handle)
+                .is_none_or(|body| body.transform.position.y < KILL_HEIGHT);
+            if fallen {
+                world.remove_body(*handle);
+                // Its handle may be reused by a later body.
+                scene_bodies.retain(|scene_body| scene_body != handle);
+            }
+            !fallen
+        });
+
+        let mut dirty: Option<std::ops::Range<usize>> = None;
+        for (i, (handle, instance)) in self.bodies.iter_mut().enumerate() {
+            let Some(body) = self.world.body(*handle) else {
+                continue;
+            };
+            if body.is_sleeping() {
+                continue;
+            }
+            instance.set_transform(&body.transform);
+            dirty = Some(match dirty {
+                Some(range) => range.start..i + 1,
+                None => i..i + 1,
+            });
+        }
+
+        rewrite_all |= self.bodies.len() != self.instance_buffer.len();
+        if self.color_mode != ColorMode::Texture || self.ghosts {
+            self.color_bodies();
+            rewrite_all = true;
+        }
+
+        if rewrite_all {
+            let instance_data = self
+                .bodies
+                .iter()
+                .map(|(_, instance)| instance.to_raw())
+                .collect::<Vec<_>>();
+            self.instance_buffer
+                .write(&self.device, &self.queue, &instance_data);
+        } else if let Some(range) = dirty {
+            let instance_data = self.bodies[range.clone()]
+                .iter()
+                .map(|(_, instance)| instance.to_raw())
+                .collect::<Vec<_>>();
+            self.instance_buffer
+                .write_range(&self.queue, range.start, &instance_data);
+        }
+    }
+
+    fn set_color_mode(&mut self, mode: ColorMode) {
+        self.color_mode = mode;
+        self.color_bodies();
+        let instance_data = self
+            .bodies
+            .iter()
+            .map(|(_, instance)| instance.to_raw())
+            .collect::<Vec<_>>();
+        self.instance_buffer
+            .write(&self.device, &self.queue, &instance_data);
+    }
+
+    /// Sets the instance tint, highlight, color map and ramp value for
+    /// `color_mode`, and the opacity for `ghosts`.
+    fn color_bodies(&mut self) {
+        const MAX_SPEED: f32 = 10.0;
+        const GHOST_ALPHA: f32 = 0.35;
+        const ISLAND_COLORS: [[f32; 4]; 6] = [
+            [0.90, 0.30, 0.25, 1.0],
+            [0.30, 0.70, 0.35, 1.0],
+            [0.25, 0.45, 0.90, 1.0],
+            [0.95, 0.75, 0.20, 1.0],
+            [0.65, 0.35, 0.80, 1.0],
+            [0.20, 0.75, 0.80, 1.0],
+        ];
+
+        let (min_mass, max_mass) = self
+            .bodies
+            .iter()
+            .filter_map(|(handle, _)| self.world.body(*handle))
+            .map(|body| body.mass().ln())
+            .fold((f32::MAX, f32::MIN), |(lo, hi), m| (lo.min(m), hi.max(m)));
+
+        for (handle, instance) in &mut self.bodies {
+            let Some(body) = self.world.body(*handle) else {
+                continue;
+            };
+            let (color, emissive, scalar) = match self.color_mode {
+                ColorMode::Texture => ([1.0; 4], [0.0; 3], 0.0),
+                ColorMode::Velocity => (
+                    [1.0; 4],
+                    [0.0; 3],
+                    body.linear_velocity.magnitude() / MAX_SPEED,
+                ),
+                ColorMode::Mass => {
+                    let range = (max_mass - min_mass).max(f32::EPSILON);
+                    ([1.0; 4], [0.0; 3], (body.mass().ln() - min_mass) / range)
+                }
+                ColorMode::Island => (
+                    ISLAND_COLORS[body.island() % ISLAND_COLORS.len()],
+                    [0.0; 3],
+                    0.0,
+                ),
+                ColorMode::Sleep if body.is_sleeping() => ([0.4, 0.45, 0.6, 1.0], [0.0; 3], 0.0),
+                ColorMode::Sleep => ([1.0; 4], [0.25, 0.12, 0.0], 0.0),
+            };
+            let alpha = if self.ghosts { GHOST_ALPHA } else { 1.0 };
+            instance.set_color([color[0], color[1], color[2], color[3] * alpha]);
+            instance.set_emissive(emissive);
+            instance.set_scalar(scalar);
+            instance.set_color_map(self.color_mode.color_map());
+        }
     }
 
     fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
@@ -455,12 +1546,52 @@ impl State {
                 label: Some("Render Encoder"),
             });
 
+        let model = self.assets.model_or_placeholder(&self.obj_model);
+        let camera_view = culling::View::new(
+            self.camera.position,
+            self.camera_uniform.view_proj(),
+            self.projection.calc_matrix(),
+        );
+        let (instances, instance_buffer): (Vec<_>, _) = match &self.orbits {
+            Some(orbits) => (
+                orbits.instances().iter().collect(),
+                orbits.instance_buffer(),
+            ),
+            None => (
+                self.bodies.iter().map(|(_, instance)| instance).collect(),
+                &self.instance_buffer,
+            ),
+        };
+        self.culler.cull(
+            &self.device,
+            &self.queue,
+            &mut encoder,
+            &camera_view,
+            model,
+            instances.iter().copied(),
+            instance_buffer,
+        );
+        self.transparency.prepare(
+            &self.device,
+            &self.queue,
+            &camera_view,
+            model,
+            instances.iter().copied(),
+        );
+
+        if let Some(tank) = &self.tank {
+            tank.render_surface(&mut encoder);
+        }
+
         {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: Some("Render Pass"),
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
+                    view: self
+                        .msaa_texture
+                        .as_ref()
+                        .map_or(self.post.hdr_view(), |t| &t.view),
+                    resolve_target: self.msaa_texture.as_ref().map(|_| self.post.hdr_view()),
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Clear(wgpu::Color {
                             r: 0.1,
@@ -483,24 +1614,78 @@ impl State {
                 timestamp_writes: None,
             });
 
-            render_pass.set_vertex_buffer(1, self.instance_buffer.slice(..));
+            render_pass.set_vertex_buffer(1, self.instance_buffer.slice());
             use crate::model::DrawLight;
             render_pass.set_pipeline(&self.light_render_pipeline);
-            render_pass.draw_light_model(
-                &self.obj_model,
+            render_pass.draw_light_model(model, &self.camera_bind_group, &self.light_bind_group);
+
+            render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(3, &self.shading_bind_group, &[]);
+            self.culler.draw(
+                &mut render_pass,
+                model,
                 &self.camera_bind_group,
                 &self.light_bind_group,
             );
 
-            render_pass.set_pipeline(&self.render_pipeline);
-            render_pass.draw_model_instanced(
-                &self.obj_model,
-                0..self.instances.len() as u32,
+            use crate::model::DrawColoredModel;
+            render_pass.set_pipeline(&self.colored_render_pipeline);
+            render_pass.set_vertex_buffer(1, self.ground_instance_buffer.slice(..));
+            render_pass.draw_colored_mesh(
+                &self.ground,
+                &self.camera_bind_group,
+                &self.light_bind_group,
+                &self.shading_bind_group,
+            );
+            if let Some(cloths) = &self.cloths {
+                cloths.draw(
+                    &mut render_pass,
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                    &self.shading_bind_group,
+                );
+            }
+            if let Some(particles) = &self.particles {
+                particles.draw_obstacles(
+                    &mut render_pass,
+                    &self.camera_bind_group,
+                    &self.light_bind_group,
+                    &self.shading_bind_group,
+                );
+                particles.draw(&mut render_pass, &self.camera_bind_group);
+            }
+
+            self.environment
+                .draw_sky(&mut render_pass, &self.camera_bind_group);
+
+            if let Some(orbits) = &self.orbits {
+                orbits.draw_trails(&mut render_pass, &self.camera_bind_group);
+            }
+            if let Some(tank) = &self.tank {
+                tank.draw(&mut render_pass);
+            }
+
+            self.transparency.draw_sorted(
+                &mut render_pass,
+                model,
                 &self.camera_bind_group,
                 &self.light_bind_group,
+                &self.shading_bind_group,
             );
         }
 
+        self.transparency.render_weighted(
+            &mut encoder,
+            &self.depth_texture.view,
+            self.post.hdr_view(),
+            model,
+            &self.camera_bind_group,
+            &self.light_bind_group,
+            &self.shading_bind_group,
+        );
+
+        self.post.render(&mut encoder, &self.queue, &view);
+
         self.queue.submit(iter::once(encoder.finish()));
         output.present();
 
@@ -509,7 +1694,7 @@ impl State {
 }
 
 #[cfg_attr(target_arch = "wasm32", wasm_bindgen(start))]
-pub async fn run() {
+pub async fn run() -> Result<(), InitError> {
     cfg_if::cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
             std::panic::set_hook(Box::new(console_error_panic_hook::hook));
@@ -523,8 +1708,7 @@ pub async fn run() {
     let title = env!("CARGO_PKG_NAME");
     let window = winit::window::WindowBuilder::new()
         .with_title(title)
-        .build(&event_loop)
-        .unwrap();
+        .build(&event_loop)?;
 
     #[cfg(target_arch = "wasm32")]
     {
@@ -543,7 +1727,7 @@ pub async fn run() {
             .expect("Couldn't append canvas to document body.");
     }
 
-    let mut state = State::new(window).await;
+    let mut state = State::new(window).await?;
     let mut last_render_time = instant::Instant::now();
 
     event_loop.run(move |event, _, control_flow| {
@@ -553,11 +1737,7 @@ pub async fn run() {
             Event::DeviceEvent {
                 event: DeviceEvent::MouseMotion { delta },
                 ..
-            } => {
-                if state.mouse_pressed {
-                    state.camera_controller.process_mouse(delta.0, delta.1)
-                }
-            }
+            } if state.mouse_pressed => state.camera_controller.process_mouse(delta.0, delta.1),
             Event::WindowEvent {
                 ref event,
                 window_id,
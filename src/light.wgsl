@@ -4,6 +4,7 @@
 struct Camera {
     view_pos: vec4<f32>,
     view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
 }
 @group(0) @binding(0)
 var<uniform> camera: Camera;
@@ -37,7 +38,11 @@ fn vs_main(
 
 // Fragment shader
 
+// The gizmo stands in for the light source, so it is brighter than anything
+// it lights and blooms.
+const INTENSITY: f32 = 8.0;
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(in.color, 1.0);
+    return vec4<f32>(in.color * INTENSITY, 1.0);
 }
@@ -0,0 +1,119 @@
+// Lighting shared by the model shaders, which are compiled with this file
+// prepended. They declare the `camera`, `light`, `shading` and environment
+// bindings used here in their own bind groups.
+
+struct Light {
+    position: vec3<f32>,
+    color: vec3<f32>,
+}
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+}
+
+struct InstanceInput {
+    @location(5) model_matrix_0: vec4<f32>,
+    @location(6) model_matrix_1: vec4<f32>,
+    @location(7) model_matrix_2: vec4<f32>,
+    @location(8) model_matrix_3: vec4<f32>,
+    @location(9) normal_matrix_0: vec3<f32>,
+    @location(10) normal_matrix_1: vec3<f32>,
+    @location(11) normal_matrix_2: vec3<f32>,
+    @location(12) color: vec4<f32>,
+    @location(13) emissive_scalar: vec4<f32>,
+    @location(14) material: u32,
+}
+
+fn model_matrix(instance: InstanceInput) -> mat4x4<f32> {
+    return mat4x4<f32>(
+        instance.model_matrix_0,
+        instance.model_matrix_1,
+        instance.model_matrix_2,
+        instance.model_matrix_3,
+    );
+}
+
+fn normal_matrix(instance: InstanceInput) -> mat3x3<f32> {
+    return mat3x3<f32>(
+        instance.normal_matrix_0,
+        instance.normal_matrix_1,
+        instance.normal_matrix_2,
+    );
+}
+
+// Matches `model::ColorMap`, stored in the high half of the instance
+// material word.
+const COLOR_MAP_OFF: u32 = 0u;
+const COLOR_MAP_VIRIDIS: u32 = 1u;
+
+fn instance_material(material: u32) -> u32 {
+    return material & 0xffffu;
+}
+
+fn instance_color_map(material: u32) -> u32 {
+    return material >> 16u;
+}
+
+struct Shading {
+    ibl_intensity: f32,
+    // x: specular strength, y: shininess
+    materials: array<vec4<f32>, 8>,
+}
+
+// Matches `environment::PREFILTERED_MIPS`.
+const PREFILTERED_MIPS: f32 = 5.0;
+
+// Polynomial fit of matplotlib's viridis color map.
+fn viridis(t: f32) -> vec3<f32> {
+    let c0 = vec3<f32>(0.2777273272234177, 0.005407344544966578, 0.3340998053353061);
+    let c1 = vec3<f32>(0.1050930431085774, 1.404613529898575, 1.384590162594685);
+    let c2 = vec3<f32>(-0.3308618287255563, 0.214847559468213, 0.09509516302823659);
+    let c3 = vec3<f32>(-4.634230498983486, -5.799100973351585, -19.33244095627987);
+    let c4 = vec3<f32>(6.228269936347081, 14.17993336680509, 56.69055260068105);
+    let c5 = vec3<f32>(4.776384997670288, -13.74514537774601, -65.35303263337234);
+    let c6 = vec3<f32>(-5.435455855934631, 4.645852612178535, 26.3124352495832);
+    let x = clamp(t, 0.0, 1.0);
+    return c0 + x * (c1 + x * (c2 + x * (c3 + x * (c4 + x * (c5 + x * c6)))));
+}
+
+// Color of a surface of `albedo` lit by the point light and the environment.
+fn light_surface(
+    albedo: vec3<f32>,
+    normal: vec3<f32>,
+    world_position: vec3<f32>,
+    material_index: u32,
+) -> vec3<f32> {
+    let material = shading.materials[min(material_index, 7u)];
+
+    let light_dir = normalize(light.position - world_position);
+
+    let world_normal = normalize(normal);
+    let diffuse_strength = max(dot(world_normal, light_dir), 0.0);
+    let diffuse_color = light.color * diffuse_strength;
+
+    let view_dir = normalize(camera.view_pos.xyz - world_position);
+    let half_dir = normalize(view_dir + light_dir);
+
+    let specular_strength = pow(max(dot(world_normal, half_dir), 0.0), material.y);
+    let specular_color = specular_strength * material.x * light.color;
+
+    // Image-based lighting. Shininess maps to a roughness for the prefiltered
+    // environment, and specular strength scales a dielectric reflectance.
+    let ambient_color = textureSample(t_irradiance, s_environment, world_normal).rgb
+        * shading.ibl_intensity;
+    let roughness = sqrt(2.0 / (material.y + 2.0));
+    let n_dot_v = max(dot(world_normal, view_dir), 0.0);
+    let reflected = textureSampleLevel(
+        t_prefiltered,
+        s_environment,
+        reflect(-view_dir, world_normal),
+        roughness * (PREFILTERED_MIPS - 1.0),
+    ).rgb;
+    let brdf = textureSample(t_brdf, s_environment, vec2<f32>(n_dot_v, roughness)).rg;
+    let f0 = 0.04 * material.x;
+    let reflection_color = reflected * (f0 * brdf.x + brdf.y) * shading.ibl_intensity;
+
+    return (ambient_color + diffuse_color + specular_color) * albedo + reflection_color;
+}
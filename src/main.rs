@@ -1,5 +1,8 @@
 use physics_engine::run;
 
 fn main() {
-    pollster::block_on(run());
+    if let Err(e) = pollster::block_on(run()) {
+        log::error!("{}", e);
+        std::process::exit(1);
+    }
 }
@@ -1,13 +1,20 @@
-use cgmath::Zero;
+use cgmath::{InnerSpace, One, SquareMatrix, Zero};
 use wgpu::util::DeviceExt;
 
+use crate::physics::{self, fitting, Aabb, Sphere};
 use crate::texture::Texture;
+use std::any::TypeId;
 use std::ops::Range;
 
 pub trait Vertex {
     fn desc() -> wgpu::VertexBufferLayout<'static>;
 }
 
+/// Vertices `Mesh` can compute its bounds from.
+pub trait HasPosition {
+    fn position(&self) -> [f32; 3];
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertex {
@@ -32,6 +39,15 @@ impl Vertex for ModelVertex {
     }
 }
 
+impl HasPosition for ModelVertex {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
+/// Vertex of meshes colored per vertex rather than by a texture.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct ModelVertexColored {
     pub position: [f32; 3],
     pub color: [f32; 3],
@@ -54,10 +70,30 @@ impl Vertex for ModelVertexColored {
     }
 }
 
+impl HasPosition for ModelVertexColored {
+    fn position(&self) -> [f32; 3] {
+        self.position
+    }
+}
+
 pub struct Instance {
     position: cgmath::Vector3<f32>,
     rotation: cgmath::Quaternion<f32>,
-    size: f32,
+    scale: cgmath::Vector3<f32>,
+    color: [f32; 4],
+    emissive: [f32; 3],
+    scalar: f32,
+    material: u32,
+    color_map: ColorMap,
+}
+
+/// Color ramps an instance's scalar can be drawn with. The values match the
+/// `COLOR_MAP_*` constants in `lighting.wgsl`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorMap {
+    #[default]
+    Off = 0,
+    Viridis = 1,
 }
 
 impl Instance {
@@ -68,33 +104,129 @@ impl Instance {
     ) -> Self {
         Self {
             position: position.unwrap_or(cgmath::Vector3::zero()),
-            rotation: rotation.unwrap_or(cgmath::Quaternion::zero()),
-            size,
+            rotation: rotation.unwrap_or(cgmath::Quaternion::one()),
+            scale: cgmath::Vector3::new(size, size, size),
+            color: [1.0; 4],
+            emissive: [0.0; 3],
+            scalar: 0.0,
+            material: 0,
+            color_map: ColorMap::Off,
+        }
+    }
+
+    /// Moves the instance to follow a physics body.
+    pub fn set_transform(&mut self, transform: &physics::Transform) {
+        self.position = transform.position;
+        self.rotation = transform.rotation;
+    }
+
+    /// Scale along each local axis, applied before the rotation.
+    pub fn set_scale(&mut self, scale: cgmath::Vector3<f32>) {
+        self.scale = scale;
+    }
+
+    pub fn scale(&self) -> cgmath::Vector3<f32> {
+        self.scale
+    }
+
+    /// Bounds of the instance given the bounds of the model it draws.
+    pub fn bounding_sphere(&self, local: Sphere) -> Sphere {
+        let center = self.position
+            + self.rotation
+                * cgmath::Vector3::new(
+                    local.center.x * self.scale.x,
+                    local.center.y * self.scale.y,
+                    local.center.z * self.scale.z,
+                );
+        let scale = self
+            .scale
+            .x
+            .abs()
+            .max(self.scale.y.abs())
+            .max(self.scale.z.abs());
+        Sphere {
+            center: cgmath::Point3::new(center.x, center.y, center.z),
+            radius: local.radius * scale,
         }
     }
 
+    /// Tint multiplied with the texture color.
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        self.color = color;
+    }
+
+    /// Whether the tint lets the background show through, which moves the
+    /// instance to the transparent pass.
+    pub fn is_transparent(&self) -> bool {
+        self.color[3] < 1.0
+    }
+
+    /// Light emitted regardless of the scene lighting, for highlights.
+    pub fn set_emissive(&mut self, emissive: [f32; 3]) {
+        self.emissive = emissive;
+    }
+
+    /// Value in `[0, 1]` looked up in the instance's color map.
+    pub fn set_scalar(&mut self, scalar: f32) {
+        self.scalar = scalar;
+    }
+
+    /// Color ramp that replaces the texture color, so instances drawn
+    /// without one keep their own colors.
+    pub fn set_color_map(&mut self, color_map: ColorMap) {
+        self.color_map = color_map;
+    }
+
+    /// Index into the shader's table of material parameters.
+    pub fn set_material(&mut self, material: u32) {
+        self.material = material;
+    }
+
     pub fn to_raw(&self) -> InstanceRaw {
+        let rotation = cgmath::Matrix3::from(self.rotation);
+        // Normals transform with the inverse transpose of the linear part,
+        // which for rotation * scale is rotation * scale⁻¹.
+        let inverse_scale = cgmath::Vector3::new(
+            recip_or_zero(self.scale.x),
+            recip_or_zero(self.scale.y),
+            recip_or_zero(self.scale.z),
+        );
         InstanceRaw {
             model: (cgmath::Matrix4::from_translation(self.position)
                 * cgmath::Matrix4::from(self.rotation)
-                * cgmath::Matrix4::from_scale(self.size))
+                * cgmath::Matrix4::from_nonuniform_scale(self.scale.x, self.scale.y, self.scale.z))
             .into(),
-            normal: cgmath::Matrix3::from(self.rotation).into(),
-            size: self.size,
+            normal: (rotation * cgmath::Matrix3::from_diagonal(inverse_scale)).into(),
+            color: self.color,
+            emissive: self.emissive,
+            scalar: self.scalar,
+            material: self.material | (self.color_map as u32) << 16,
         }
     }
 }
 
+fn recip_or_zero(x: f32) -> f32 {
+    if x == 0.0 {
+        0.0
+    } else {
+        1.0 / x
+    }
+}
+
 #[repr(C)]
 #[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
 pub struct InstanceRaw {
     model: [[f32; 4]; 4],
     normal: [[f32; 3]; 3],
-    size: f32,
+    color: [f32; 4],
+    emissive: [f32; 3],
+    scalar: f32,
+    /// Material index in the low half, `ColorMap` in the high half.
+    material: u32,
 }
 
 impl InstanceRaw {
-    const ATTRIBS: [wgpu::VertexAttribute; 8] = wgpu::vertex_attr_array![
+    const ATTRIBS: [wgpu::VertexAttribute; 10] = wgpu::vertex_attr_array![
         5 => Float32x4,
         6 => Float32x4,
         7 => Float32x4,
@@ -102,7 +234,9 @@ impl InstanceRaw {
         9 => Float32x3,
         10 => Float32x3,
         11 => Float32x3,
-        12 => Float32,
+        12 => Float32x4,
+        13 => Float32x4,
+        14 => Uint32,
     ];
 
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
@@ -115,23 +249,596 @@ impl InstanceRaw {
     }
 }
 
+/// Instance data on the GPU that grows to fit however many instances are
+/// written, so the instance count can change from frame to frame.
+pub struct InstanceBuffer {
+    buffer: wgpu::Buffer,
+    usage: wgpu::BufferUsages,
+    capacity: usize,
+    len: usize,
+}
+
+impl InstanceBuffer {
+    pub fn new(device: &wgpu::Device, capacity: usize) -> Self {
+        Self::with_usage(device, capacity, wgpu::BufferUsages::empty())
+    }
+
+    /// Like `new`, with extra usages such as `STORAGE` for compute passes.
+    pub fn with_usage(device: &wgpu::Device, capacity: usize, usage: wgpu::BufferUsages) -> Self {
+        let capacity = capacity.max(1);
+        let usage = usage | wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST;
+        Self {
+            buffer: Self::create_buffer(device, capacity, usage),
+            usage,
+            capacity,
+            len: 0,
+        }
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        capacity: usize,
+        usage: wgpu::BufferUsages,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Instance Buffer"),
+            size: (capacity * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+
+    /// Makes room for at least `capacity` instances. Growing discards the
+    /// current contents.
+    pub fn reserve(&mut self, device: &wgpu::Device, capacity: usize) {
+        if capacity > self.capacity {
+            self.capacity = capacity.next_power_of_two();
+            self.buffer = Self::create_buffer(device, self.capacity, self.usage);
+            self.len = 0;
+        }
+    }
+
+    /// Sets the number of instances to draw when the contents were written
+    /// on the GPU.
+    ///
+    /// Panics if `len` exceeds the capacity.
+    pub fn set_len(&mut self, len: usize) {
+        assert!(len <= self.capacity, "instance count exceeds capacity");
+        self.len = len;
+    }
+
+    /// Replaces all instances, reallocating with room to spare when they no
+    /// longer fit.
+    pub fn write(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, instances: &[InstanceRaw]) {
+        self.reserve(device, instances.len());
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(instances));
+        self.len = instances.len();
+    }
+
+    /// Overwrites the instances starting at `start`, leaving the rest as is.
+    ///
+    /// Panics if the range goes past the current length.
+    pub fn write_range(&self, queue: &wgpu::Queue, start: usize, instances: &[InstanceRaw]) {
+        assert!(
+            start + instances.len() <= self.len,
+            "instance range out of bounds"
+        );
+        let offset = (start * std::mem::size_of::<InstanceRaw>()) as wgpu::BufferAddress;
+        queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(instances));
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn buffer(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    pub fn slice(&self) -> wgpu::BufferSlice<'_> {
+        self.slice_range(0..self.len)
+    }
+
+    /// Slice starting at instance `instances.start`, for drawing a part of
+    /// the buffer from instance 0.
+    pub fn slice_range(&self, instances: Range<usize>) -> wgpu::BufferSlice<'_> {
+        assert!(
+            instances.start < self.capacity && instances.end <= self.capacity,
+            "instance range out of bounds"
+        );
+        let stride = std::mem::size_of::<InstanceRaw>();
+        let end = instances.end.max(instances.start + 1);
+        self.buffer.slice(
+            (instances.start * stride) as wgpu::BufferAddress
+                ..(end * stride) as wgpu::BufferAddress,
+        )
+    }
+}
+
 pub struct Model {
     pub meshes: Vec<Mesh>,
     pub materials: Vec<Material>,
+    /// Coarser versions of `meshes`, from most to least detailed.
+    pub lods: Vec<Lod>,
+}
+
+/// A level of detail, drawn once an instance covers less than `screen_size`
+/// of the screen height.
+pub struct Lod {
+    pub meshes: Vec<Mesh>,
+    pub screen_size: f32,
+}
+
+impl Lod {
+    /// Screen size below which `level` is drawn: a half, quarter, eighth and
+    /// so on of the screen height.
+    pub fn default_screen_size(level: usize) -> f32 {
+        0.5f32.powi(level as i32)
+    }
+}
+
+impl Model {
+    /// Number of levels of detail, counting the full-detail `meshes`.
+    pub fn lod_count(&self) -> usize {
+        1 + self.lods.len()
+    }
+
+    /// Meshes of a level of detail, where level 0 is `meshes`.
+    pub fn lod_meshes(&self, level: usize) -> &[Mesh] {
+        match level {
+            0 => &self.meshes,
+            _ => &self.lods[level - 1].meshes,
+        }
+    }
+
+    /// Level of detail for an instance covering `screen_size` of the screen
+    /// height.
+    pub fn select_lod(&self, screen_size: f32) -> usize {
+        self.lods
+            .iter()
+            .take_while(|lod| screen_size < lod.screen_size)
+            .count()
+    }
+
+    /// Appends `count` levels simplified from the retained geometry of
+    /// `meshes`, halving the clustering resolution at every level.
+    pub fn generate_lods(&mut self, device: &wgpu::Device, count: usize) {
+        const FINEST_RESOLUTION: f32 = 32.0;
+        let size = self.bounding_box().half_extents().magnitude() * 2.0;
+        for level in self.lod_count()..self.lod_count() + count {
+            let cell_size = size / FINEST_RESOLUTION * 2f32.powi(level as i32 - 1);
+            let meshes = self
+                .meshes
+                .iter()
+                .filter_map(|mesh| {
+                    let data = mesh.data()?.simplified(cell_size);
+                    if data.indices.is_empty() {
+                        return None;
+                    }
+                    let name = format!("{}_lod{}", mesh.name, level);
+                    Some(Mesh::from_data(name, device, data, mesh.material))
+                })
+                .collect::<Vec<_>>();
+            if meshes.is_empty() {
+                break;
+            }
+            self.lods.push(Lod {
+                meshes,
+                screen_size: Lod::default_screen_size(level),
+            });
+        }
+    }
+
+    /// Builds a collision shape from the retained geometry of all meshes.
+    ///
+    /// Returns `None` if no mesh kept its CPU-side data.
+    pub fn collider(&self, kind: physics::ColliderKind) -> Option<physics::Shape> {
+        let mut positions = Vec::new();
+        let mut indices = Vec::new();
+        for data in self.meshes.iter().filter_map(Mesh::data) {
+            let offset = positions.len() as u32;
+            positions.extend_from_slice(&data.positions);
+            indices.extend(data.indices.iter().map(|i| i + offset));
+        }
+        physics::collider_from_geometry(&positions, &indices, kind)
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.meshes
+            .iter()
+            .fold(Aabb::empty(), |aabb, m| aabb.merge(m.bounding_box))
+    }
+
+    /// Sphere around the bounding box center enclosing every mesh's sphere.
+    pub fn bounding_sphere(&self) -> Sphere {
+        use cgmath::MetricSpace;
+        let center = self.bounding_box().center();
+        let radius = self
+            .meshes
+            .iter()
+            .map(|m| m.bounding_sphere.center.distance(center) + m.bounding_sphere.radius)
+            .fold(0.0, f32::max);
+        Sphere { center, radius }
+    }
 }
 
 pub struct Material {
     pub name: String,
-    pub diffuse_texture: Texture,
+    /// Shared between the materials of every model using the same image.
+    pub diffuse_texture: std::rc::Rc<Texture>,
     pub bind_group: wgpu::BindGroup,
 }
 
+/// How texture coordinates are made up for meshes that have none.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum UvProjection {
+    /// Projected from above onto the XZ plane.
+    Planar,
+    /// Projected onto the side of the bounding box each vertex's normal
+    /// faces.
+    Box,
+}
+
+/// CPU-side copy of a mesh's geometry.
+#[derive(Clone, Debug, Default)]
+pub struct MeshData {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    pub tex_coords: Vec<[f32; 2]>,
+    /// Linear RGB per vertex, empty for meshes without vertex colors.
+    pub colors: Vec<[f32; 3]>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    /// Flat-shaded render mesh for a static triangle collider, with UVs
+    /// projected from above.
+    pub fn from_trimesh(mesh: &physics::TriMesh) -> Self {
+        Self::flat_shaded(mesh.vertices(), mesh.triangles())
+    }
+
+    /// Gives every triangle its own vertices so that it gets its face normal.
+    pub fn flat_shaded(points: &[cgmath::Point3<f32>], triangles: &[[u32; 3]]) -> Self {
+        let mut data = Self::default();
+        for tri in triangles {
+            let corners = tri.map(|v| points[v as usize]);
+            let normal = physics::triangle::face_normal(corners);
+            for p in corners {
+                data.indices.push(data.positions.len() as u32);
+                data.positions.push(p.into());
+                data.normals.push(normal.into());
+                data.tex_coords.push([p.x, p.z]);
+            }
+        }
+        data
+    }
+
+    /// Render mesh sharing the heightfield's triangulation, with UVs
+    /// stretched over the whole grid.
+    pub fn from_heightfield(heightfield: &physics::Heightfield) -> Self {
+        let (rows, cols) = (heightfield.rows(), heightfield.cols());
+        let mut data = Self::default();
+        for row in 0..rows {
+            for col in 0..cols {
+                data.positions.push(heightfield.point(row, col).into());
+                data.normals.push(heightfield.normal(row, col).into());
+                data.tex_coords.push([
+                    col as f32 / (cols - 1) as f32,
+                    row as f32 / (rows - 1) as f32,
+                ]);
+            }
+        }
+        for row in 0..rows - 1 {
+            for col in 0..cols - 1 {
+                let p00 = (row * cols + col) as u32;
+                let p10 = p00 + 1;
+                let p01 = p00 + cols as u32;
+                let p11 = p01 + 1;
+                data.indices
+                    .extend_from_slice(&[p00, p01, p11, p00, p11, p10]);
+            }
+        }
+        data
+    }
+
+    /// Render mesh of the cloth as it is now, with UVs stretched over the
+    /// whole grid. Both sides get their own vertices, the back ones after
+    /// the front ones, so that each is lit from its own side.
+    pub fn from_cloth(cloth: &physics::Cloth) -> Self {
+        let (rows, cols) = (cloth.rows(), cloth.cols());
+        let count = cloth.particles.len() as u32;
+        let normals = cloth.normals();
+        let mut data = Self::default();
+        for side in [1.0, -1.0] {
+            for (i, (particle, normal)) in cloth.particles.iter().zip(&normals).enumerate() {
+                data.positions.push(particle.position.into());
+                data.normals.push((normal * side).into());
+                data.tex_coords.push([
+                    (i % cols) as f32 / (cols - 1) as f32,
+                    (i / cols) as f32 / (rows - 1) as f32,
+                ]);
+            }
+        }
+        for &[a, b, c] in cloth.triangles() {
+            data.indices.extend_from_slice(&[a, b, c]);
+        }
+        for &[a, b, c] in cloth.triangles() {
+            data.indices
+                .extend_from_slice(&[a + count, c + count, b + count]);
+        }
+        data
+    }
+
+    /// Replaces the normals with ones computed from the triangles, weighted
+    /// by area. Faces meeting at up to `crease_angle` are smoothed across,
+    /// so zero gives flat shading and half a turn smooths everything;
+    /// vertices on sharper edges are split.
+    pub fn generate_normals(&mut self, crease_angle: cgmath::Rad<f32>) {
+        use std::collections::HashMap;
+
+        let old = std::mem::take(self);
+        let position = |i: u32| cgmath::Vector3::from(old.positions[i as usize]);
+        let face_normals = old
+            .indices
+            .chunks_exact(3)
+            .map(|tri| {
+                let [a, b, c] = [tri[0], tri[1], tri[2]].map(position);
+                (b - a).cross(c - a)
+            })
+            .collect::<Vec<_>>();
+        let unit = |n: cgmath::Vector3<f32>| if n.is_zero() { n } else { n.normalize() };
+
+        // Corners are grouped by position rather than index so that the
+        // normals are smooth across texture seams too.
+        let mut corners = HashMap::<_, Vec<usize>>::new();
+        for (corner, &i) in old.indices.iter().enumerate() {
+            let key = old.positions[i as usize].map(f32::to_bits);
+            corners.entry(key).or_default().push(corner / 3);
+        }
+
+        let min_cos = crease_angle.0.cos();
+        let mut vertices = HashMap::new();
+        for (corner, &i) in old.indices.iter().enumerate() {
+            let face = corner / 3;
+            let own = unit(face_normals[face]);
+            let key = old.positions[i as usize].map(f32::to_bits);
+            let sum = corners[&key]
+                .iter()
+                .filter(|&&other| other == face || unit(face_normals[other]).dot(own) >= min_cos)
+                .fold(cgmath::Vector3::zero(), |sum, &other| {
+                    sum + face_normals[other]
+                });
+            let normal: [f32; 3] = if sum.is_zero() {
+                cgmath::Vector3::unit_y().into()
+            } else {
+                sum.normalize().into()
+            };
+
+            let index = *vertices
+                .entry((i, normal.map(f32::to_bits)))
+                .or_insert_with(|| {
+                    let i = i as usize;
+                    self.positions.push(old.positions[i]);
+                    self.normals.push(normal);
+                    if !old.tex_coords.is_empty() {
+                        self.tex_coords.push(old.tex_coords[i]);
+                    }
+                    if !old.colors.is_empty() {
+                        self.colors.push(old.colors[i]);
+                    }
+                    self.positions.len() as u32 - 1
+                });
+            self.indices.push(index);
+        }
+    }
+
+    /// Replaces the texture coordinates with a projection that fits the
+    /// bounding box into the unit square.
+    pub fn generate_tex_coords(&mut self, projection: UvProjection) {
+        let points = self.positions.iter().map(|&p| p.into()).collect::<Vec<_>>();
+        let bounding_box = Aabb::from_points(&points);
+        let extent = bounding_box.max - bounding_box.min;
+        let size = extent.x.max(extent.y).max(extent.z);
+        let size = if size > 0.0 { size } else { 1.0 };
+
+        self.tex_coords = self
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| {
+                let p = (cgmath::Point3::from(p) - bounding_box.min) / size;
+                let axis = match projection {
+                    UvProjection::Planar => 1,
+                    UvProjection::Box => {
+                        let n = self.normals.get(i).copied().unwrap_or([0.0, 1.0, 0.0]);
+                        let n = n.map(f32::abs);
+                        if n[0] >= n[1] && n[0] >= n[2] {
+                            0
+                        } else if n[1] >= n[2] {
+                            1
+                        } else {
+                            2
+                        }
+                    }
+                };
+                match axis {
+                    0 => [p.z, 1.0 - p.y],
+                    1 => [p.x, p.z],
+                    _ => [p.x, 1.0 - p.y],
+                }
+            })
+            .collect();
+    }
+
+    /// Moves the geometry in place, rotating the normals along.
+    pub fn transform(&mut self, transform: &physics::Transform) {
+        for p in &mut self.positions {
+            *p = transform.transform_point((*p).into()).into();
+        }
+        for n in &mut self.normals {
+            *n = transform.transform_vector((*n).into()).into();
+        }
+    }
+
+    /// Vertex clustering simplification: vertices in the same grid cell with
+    /// similar normals are merged and triangles that collapse are dropped.
+    pub fn simplified(&self, cell_size: f32) -> Self {
+        use std::collections::HashMap;
+
+        struct Cluster {
+            count: f32,
+            position: cgmath::Vector3<f32>,
+            normal: cgmath::Vector3<f32>,
+            tex_coords: cgmath::Vector2<f32>,
+            color: cgmath::Vector3<f32>,
+        }
+
+        let mut clusters: Vec<Cluster> = Vec::new();
+        let mut keys = HashMap::new();
+        let remap = self
+            .positions
+            .iter()
+            .enumerate()
+            .map(|(i, &p)| {
+                let normal = self.normals.get(i).copied().unwrap_or_default();
+                let cell = p.map(|x| (x / cell_size).floor() as i32);
+                // Hard edges keep apart since opposing normals never share a key.
+                let facing = normal.map(|n| (n * 2.0).round() as i32);
+                let index = *keys.entry((cell, facing)).or_insert_with(|| {
+                    clusters.push(Cluster {
+                        count: 0.0,
+                        position: cgmath::Vector3::zero(),
+                        normal: cgmath::Vector3::zero(),
+                        tex_coords: cgmath::Vector2::zero(),
+                        color: cgmath::Vector3::zero(),
+                    });
+                    clusters.len() - 1
+                });
+                let cluster = &mut clusters[index];
+                cluster.count += 1.0;
+                cluster.position += cgmath::Vector3::from(p);
+                cluster.normal += cgmath::Vector3::from(normal);
+                cluster.tex_coords +=
+                    cgmath::Vector2::from(self.tex_coords.get(i).copied().unwrap_or_default());
+                cluster.color +=
+                    cgmath::Vector3::from(self.colors.get(i).copied().unwrap_or_default());
+                index as u32
+            })
+            .collect::<Vec<_>>();
+
+        let mut data = Self::default();
+        for cluster in &clusters {
+            let normal = cluster.normal / cluster.count;
+            data.positions
+                .push((cluster.position / cluster.count).into());
+            data.normals.push(if normal.is_zero() {
+                normal.into()
+            } else {
+                normal.normalize().into()
+            });
+            data.tex_coords
+                .push((cluster.tex_coords / cluster.count).into());
+            if !self.colors.is_empty() {
+                data.colors.push((cluster.color / cluster.count).into());
+            }
+        }
+        for tri in self.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|k| remap[tri[k] as usize]);
+            if a != b && b != c && c != a {
+                data.indices.extend_from_slice(&[a, b, c]);
+            }
+        }
+        data
+    }
+
+    pub fn append(&mut self, other: MeshData) {
+        let offset = self.positions.len() as u32;
+        // Meshes without colors are white where the other one has them.
+        if self.colors.is_empty() != other.colors.is_empty() {
+            self.colors.resize(offset as usize, [1.0; 3]);
+            self.colors.extend(
+                other
+                    .colors
+                    .iter()
+                    .copied()
+                    .chain(std::iter::repeat([1.0; 3]))
+                    .take(other.positions.len()),
+            );
+        } else {
+            self.colors.extend(other.colors);
+        }
+        self.positions.extend(other.positions);
+        self.normals.extend(other.normals);
+        self.tex_coords.extend(other.tex_coords);
+        self.indices
+            .extend(other.indices.iter().map(|i| i + offset));
+    }
+
+    pub fn vertices(&self) -> Vec<ModelVertex> {
+        self.positions
+            .iter()
+            .enumerate()
+            .map(|(i, &position)| ModelVertex {
+                position,
+                tex_coords: self.tex_coords.get(i).copied().unwrap_or_default(),
+                normal: self.normals.get(i).copied().unwrap_or_default(),
+            })
+            .collect()
+    }
+
+    /// Vertices with the colors in place of texture coordinates, white for
+    /// meshes without vertex colors.
+    pub fn colored_vertices(&self) -> Vec<ModelVertexColored> {
+        self.positions
+            .iter()
+            .enumerate()
+            .map(|(i, &position)| ModelVertexColored {
+                position,
+                color: self.colors.get(i).copied().unwrap_or([1.0; 3]),
+                normal: self.normals.get(i).copied().unwrap_or_default(),
+            })
+            .collect()
+    }
+}
+
 pub struct Mesh {
     name: String,
     vertex_buffer: wgpu::Buffer,
     index_buffer: wgpu::Buffer,
     num_elements: u32,
     material: usize,
+    data: Option<MeshData>,
+    /// The vertex type `data` is uploaded as.
+    vertex_kind: VertexKind,
+    bounding_box: Aabb,
+    bounding_sphere: Sphere,
+}
+
+/// Which vertices a mesh built from [`MeshData`] draws with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VertexKind {
+    /// `ModelVertex`, for the textured pipeline.
+    Textured,
+    /// `ModelVertexColored`, for the vertex-colored pipeline.
+    Colored,
+}
+
+impl VertexKind {
+    fn of<V: 'static>() -> Self {
+        if TypeId::of::<V>() == TypeId::of::<ModelVertexColored>() {
+            Self::Colored
+        } else {
+            Self::Textured
+        }
+    }
 }
 
 impl Mesh {
@@ -143,30 +850,167 @@ impl Mesh {
         material: usize,
     ) -> Self
     where
-        V: Vertex + bytemuck::Pod,
+        V: Vertex + HasPosition + bytemuck::Pod,
     {
-        let vertex_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{}_vertex_buffer", name)),
-            contents: bytemuck::cast_slice(vertices),
-            usage: wgpu::BufferUsages::VERTEX,
-        });
-
-        let index_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
-            label: Some(&format!("{}_index_buffer", name)),
-            contents: bytemuck::cast_slice(indices),
-            usage: wgpu::BufferUsages::INDEX,
-        });
-
+        let vertex_buffer = create_vertex_buffer(device, &name, vertices);
+        let index_buffer = create_index_buffer(device, &name, indices);
+        let (bounding_box, bounding_sphere) = bounds(vertices.iter().map(HasPosition::position));
         Self {
             name,
             vertex_buffer,
             index_buffer,
             num_elements: indices.len() as u32,
             material,
+            data: None,
+            vertex_kind: VertexKind::of::<V>(),
+            bounding_box,
+            bounding_sphere,
+        }
+    }
+
+    /// Creates a mesh from `ModelVertex` attributes and keeps the CPU-side copy.
+    pub fn from_data(name: String, device: &wgpu::Device, data: MeshData, material: usize) -> Self {
+        let mut mesh = Self::new(name, device, &data.vertices(), &data.indices, material);
+        mesh.data = Some(data);
+        mesh
+    }
+
+    /// Creates a mesh from `ModelVertexColored` attributes for the
+    /// vertex-colored pipeline, and keeps the CPU-side copy.
+    pub fn from_colored_data(
+        name: String,
+        device: &wgpu::Device,
+        data: MeshData,
+        material: usize,
+    ) -> Self {
+        let mut mesh = Self::new(
+            name,
+            device,
+            &data.colored_vertices(),
+            &data.indices,
+            material,
+        );
+        mesh.data = Some(data);
+        mesh
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn num_elements(&self) -> u32 {
+        self.num_elements
+    }
+
+    pub fn material(&self) -> usize {
+        self.material
+    }
+
+    pub fn data(&self) -> Option<&MeshData> {
+        self.data.as_ref()
+    }
+
+    pub fn positions(&self) -> Option<&[[f32; 3]]> {
+        self.data.as_ref().map(|d| d.positions.as_slice())
+    }
+
+    pub fn normals(&self) -> Option<&[[f32; 3]]> {
+        self.data.as_ref().map(|d| d.normals.as_slice())
+    }
+
+    pub fn tex_coords(&self) -> Option<&[[f32; 2]]> {
+        self.data.as_ref().map(|d| d.tex_coords.as_slice())
+    }
+
+    pub fn indices(&self) -> Option<&[u32]> {
+        self.data.as_ref().map(|d| d.indices.as_slice())
+    }
+
+    pub fn bounding_box(&self) -> Aabb {
+        self.bounding_box
+    }
+
+    pub fn bounding_sphere(&self) -> Sphere {
+        self.bounding_sphere
+    }
+
+    /// Drops the CPU-side copy once nothing needs it anymore.
+    pub fn release_data(&mut self) -> Option<MeshData> {
+        self.data.take()
+    }
+
+    /// Rewrites the vertex buffer, reusing it when the new data fits.
+    ///
+    /// A retained CPU-side copy is dropped since it no longer matches; use
+    /// `update_data` to keep it in sync.
+    pub fn update_vertices<V>(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, vertices: &[V])
+    where
+        V: Vertex + HasPosition + bytemuck::Pod,
+    {
+        let contents: &[u8] = bytemuck::cast_slice(vertices);
+        if contents.len() as wgpu::BufferAddress <= self.vertex_buffer.size() {
+            queue.write_buffer(&self.vertex_buffer, 0, contents);
+        } else {
+            self.vertex_buffer = create_vertex_buffer(device, &self.name, vertices);
+        }
+        (self.bounding_box, self.bounding_sphere) =
+            bounds(vertices.iter().map(HasPosition::position));
+        self.data = None;
+    }
+
+    pub fn update_indices(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, indices: &[u32]) {
+        let contents: &[u8] = bytemuck::cast_slice(indices);
+        if contents.len() as wgpu::BufferAddress <= self.index_buffer.size() {
+            queue.write_buffer(&self.index_buffer, 0, contents);
+        } else {
+            self.index_buffer = create_index_buffer(device, &self.name, indices);
+        }
+        self.num_elements = indices.len() as u32;
+        if let Some(data) = &mut self.data {
+            data.indices = indices.to_vec();
+        }
+    }
+
+    /// Replaces both the GPU buffers and the retained CPU-side copy, with
+    /// the same kind of vertices the mesh was created with.
+    pub fn update_data(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, data: MeshData) {
+        let indices_changed = self.data.as_ref().map(|d| &d.indices) != Some(&data.indices);
+        match self.vertex_kind {
+            VertexKind::Textured => self.update_vertices(device, queue, &data.vertices()),
+            VertexKind::Colored => self.update_vertices(device, queue, &data.colored_vertices()),
+        }
+        if indices_changed {
+            self.update_indices(device, queue, &data.indices);
         }
+        self.data = Some(data);
     }
 }
 
+fn create_vertex_buffer<V: bytemuck::Pod>(
+    device: &wgpu::Device,
+    name: &str,
+    vertices: &[V],
+) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{}_vertex_buffer", name)),
+        contents: bytemuck::cast_slice(vertices),
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn create_index_buffer(device: &wgpu::Device, name: &str, indices: &[u32]) -> wgpu::Buffer {
+    device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+        label: Some(&format!("{}_index_buffer", name)),
+        contents: bytemuck::cast_slice(indices),
+        usage: wgpu::BufferUsages::INDEX | wgpu::BufferUsages::COPY_DST,
+    })
+}
+
+fn bounds(positions: impl Iterator<Item = [f32; 3]>) -> (Aabb, Sphere) {
+    let points = positions.map(cgmath::Point3::from).collect::<Vec<_>>();
+    (fitting::fit_aabb(&points), fitting::fit_sphere(&points))
+}
+
 pub trait DrawModel<'a> {
     fn draw_mesh(
         &mut self,
@@ -183,6 +1027,15 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'a Mesh,
+        material: &'a Material,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
     fn draw_model(
         &mut self,
         model: &'a Model,
@@ -196,6 +1049,26 @@ pub trait DrawModel<'a> {
         camera_bind_group: &'a wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     );
+    fn draw_model_lod_instanced(
+        &mut self,
+        model: &'a Model,
+        level: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
+    /// Draws every mesh of a level of detail with consecutive
+    /// `wgpu::util::DrawIndexedIndirect` arguments starting at
+    /// `indirect_offset`.
+    fn draw_model_indirect(
+        &mut self,
+        model: &'a Model,
+        level: usize,
+        indirect_buffer: &'a wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    );
 }
 
 impl<'a, 'b> DrawModel<'b> for wgpu::RenderPass<'a>
@@ -228,6 +1101,23 @@ where
         self.draw_indexed(0..mesh.num_elements, 0, instances);
     }
 
+    fn draw_mesh_indirect(
+        &mut self,
+        mesh: &'b Mesh,
+        material: &'b Material,
+        indirect_buffer: &'b wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, &material.bind_group, &[]);
+        self.set_bind_group(1, camera_bind_group, &[]);
+        self.set_bind_group(2, light_bind_group, &[]);
+        self.draw_indexed_indirect(indirect_buffer, indirect_offset);
+    }
+
     fn draw_model(
         &mut self,
         model: &'b Model,
@@ -244,7 +1134,18 @@ where
         camera_bind_group: &'b wgpu::BindGroup,
         light_bind_group: &'a wgpu::BindGroup,
     ) {
-        for mesh in &model.meshes {
+        self.draw_model_lod_instanced(model, 0, instances, camera_bind_group, light_bind_group);
+    }
+
+    fn draw_model_lod_instanced(
+        &mut self,
+        model: &'b Model,
+        level: usize,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        for mesh in model.lod_meshes(level) {
             let material = &model.materials[mesh.material];
             self.draw_mesh_instanced(
                 mesh,
@@ -255,6 +1156,29 @@ where
             );
         }
     }
+
+    fn draw_model_indirect(
+        &mut self,
+        model: &'b Model,
+        level: usize,
+        indirect_buffer: &'b wgpu::Buffer,
+        indirect_offset: wgpu::BufferAddress,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        let stride = std::mem::size_of::<wgpu::util::DrawIndexedIndirect>() as wgpu::BufferAddress;
+        for (i, mesh) in model.lod_meshes(level).iter().enumerate() {
+            let material = &model.materials[mesh.material];
+            self.draw_mesh_indirect(
+                mesh,
+                material,
+                indirect_buffer,
+                indirect_offset + i as wgpu::BufferAddress * stride,
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
 }
 
 pub trait DrawLight<'a> {
@@ -339,3 +1263,121 @@ where
         }
     }
 }
+
+/// Draws meshes built with [`Mesh::from_colored_data`] through the
+/// vertex-colored pipeline, which binds no material.
+pub trait DrawColoredModel<'a> {
+    fn draw_colored_mesh(
+        &mut self,
+        mesh: &'a Mesh,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        shading_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_colored_mesh_instanced(
+        &mut self,
+        mesh: &'a Mesh,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        shading_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_colored_model(
+        &mut self,
+        model: &'a Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        shading_bind_group: &'a wgpu::BindGroup,
+    );
+    fn draw_colored_model_instanced(
+        &mut self,
+        model: &'a Model,
+        instances: Range<u32>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        shading_bind_group: &'a wgpu::BindGroup,
+    );
+}
+
+impl<'a, 'b> DrawColoredModel<'b> for wgpu::RenderPass<'a>
+where
+    'b: 'a,
+{
+    fn draw_colored_mesh(
+        &mut self,
+        mesh: &'b Mesh,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        shading_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.draw_colored_mesh_instanced(
+            mesh,
+            0..1,
+            camera_bind_group,
+            light_bind_group,
+            shading_bind_group,
+        );
+    }
+
+    fn draw_colored_mesh_instanced(
+        &mut self,
+        mesh: &'b Mesh,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        shading_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.set_vertex_buffer(0, mesh.vertex_buffer.slice(..));
+        self.set_index_buffer(mesh.index_buffer.slice(..), wgpu::IndexFormat::Uint32);
+        self.set_bind_group(0, camera_bind_group, &[]);
+        self.set_bind_group(1, light_bind_group, &[]);
+        self.set_bind_group(2, shading_bind_group, &[]);
+        self.draw_indexed(0..mesh.num_elements, 0, instances);
+    }
+
+    fn draw_colored_model(
+        &mut self,
+        model: &'b Model,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        shading_bind_group: &'b wgpu::BindGroup,
+    ) {
+        self.draw_colored_model_instanced(
+            model,
+            0..1,
+            camera_bind_group,
+            light_bind_group,
+            shading_bind_group,
+        );
+    }
+
+    fn draw_colored_model_instanced(
+        &mut self,
+        model: &'b Model,
+        instances: Range<u32>,
+        camera_bind_group: &'b wgpu::BindGroup,
+        light_bind_group: &'b wgpu::BindGroup,
+        shading_bind_group: &'b wgpu::BindGroup,
+    ) {
+        for mesh in &model.meshes {
+            self.draw_colored_mesh_instanced(
+                mesh,
+                instances.clone(),
+                camera_bind_group,
+                light_bind_group,
+                shading_bind_group,
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vertex_kind_follows_the_vertex_type() {
+        assert_eq!(VertexKind::of::<ModelVertex>(), VertexKind::Textured);
+        assert_eq!(VertexKind::of::<ModelVertexColored>(), VertexKind::Colored);
+    }
+}
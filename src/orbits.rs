@@ -0,0 +1,172 @@
+//! The N-body demo: a disk of bodies circling a heavy one, drawn through the
+//! instanced model path with trails behind them.
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use crate::model::{self, InstanceBuffer};
+use crate::physics::{GravityKernel, NBody};
+use crate::trails::Trails;
+use crate::ColorMode;
+
+const PARTICLES: usize = 500;
+const CENTRAL_MASS: f32 = 170.0;
+const RADIUS: f32 = 12.0;
+const THETA: f32 = 0.5;
+/// Where the disk is drawn, above the ground.
+const CENTER: Vector3<f32> = Vector3::new(0.0, 6.0, 0.0);
+
+const TIMESTEP: f32 = 1.0 / 120.0;
+const MAX_STEPS: u32 = 4;
+/// Steps between two trail samples.
+const TRAIL_STEPS: u32 = 3;
+const TRAIL_LENGTH: usize = 48;
+/// Seconds of simulated time between two diagnostics log lines.
+const REPORT_INTERVAL: f32 = 2.0;
+
+/// Instance size of the heaviest body. Lighter ones shrink with the cube
+/// root of their mass, down to `MIN_SIZE`.
+const BODY_SIZE: f32 = 0.5;
+const MIN_SIZE: f32 = 0.06;
+const MAX_SPEED: f32 = 10.0;
+
+pub struct Orbits {
+    sim: NBody,
+    instances: Vec<model::Instance>,
+    instance_buffer: InstanceBuffer,
+    trails: Trails,
+    accumulator: f32,
+    steps: u32,
+    /// Energy at the start, which the drift is reported against.
+    initial_energy: f64,
+    last_report: f32,
+}
+
+impl Orbits {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+        instance_usage: wgpu::BufferUsages,
+    ) -> Self {
+        let mut sim = NBody::disk(PARTICLES, CENTRAL_MASS, RADIUS, 1.0);
+        sim.kernel = GravityKernel::BarnesHut { theta: THETA };
+        let max_mass = sim.particles.iter().map(|p| p.mass).fold(0.0, f32::max);
+        let instances = sim
+            .particles
+            .iter()
+            .map(|p| {
+                let size = (BODY_SIZE * (p.mass / max_mass).cbrt()).max(MIN_SIZE);
+                model::Instance::new(Some(CENTER + p.position), None, size)
+            })
+            .collect::<Vec<_>>();
+        let initial_energy = sim.diagnostics().energy();
+        Self {
+            sim,
+            instance_buffer: InstanceBuffer::with_usage(device, instances.len(), instance_usage),
+            instances,
+            trails: Trails::new(device, camera_bind_group_layout, sample_count, TRAIL_LENGTH),
+            accumulator: 0.0,
+            steps: 0,
+            initial_energy,
+            last_report: 0.0,
+        }
+    }
+
+    pub fn instances(&self) -> &[model::Instance] {
+        &self.instances
+    }
+
+    pub fn instance_buffer(&self) -> &InstanceBuffer {
+        &self.instance_buffer
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.trails.set_sample_count(device, sample_count);
+    }
+
+    /// Switches between the exact and the Barnes-Hut kernel.
+    pub fn toggle_kernel(&mut self) {
+        self.sim.kernel = match self.sim.kernel {
+            GravityKernel::Exact => GravityKernel::BarnesHut { theta: THETA },
+            GravityKernel::BarnesHut { .. } => GravityKernel::Exact,
+        };
+        log::info!("N-body kernel: {:?}", self.sim.kernel);
+    }
+
+    /// Steps the simulation in fixed steps and uploads the instances and
+    /// trails.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        dt: f32,
+        color_mode: ColorMode,
+    ) {
+        self.accumulator = (self.accumulator + dt).min(TIMESTEP * MAX_STEPS as f32);
+        while self.accumulator >= TIMESTEP {
+            self.accumulator -= TIMESTEP;
+            self.sim.step(TIMESTEP);
+            self.steps += 1;
+            if self.steps.is_multiple_of(TRAIL_STEPS) {
+                self.trails.record(self.sim.particles.iter().map(|p| {
+                    let color = if p.mass >= CENTRAL_MASS {
+                        [1.0, 0.7, 0.3]
+                    } else {
+                        [0.4, 0.7, 1.0]
+                    };
+                    ((CENTER + p.position).into(), color)
+                }));
+            }
+        }
+
+        if self.sim.time() - self.last_report >= REPORT_INTERVAL {
+            self.last_report = self.sim.time();
+            let diagnostics = self.sim.diagnostics();
+            let energy = diagnostics.energy();
+            log::info!(
+                "N-body t = {:.1}: E = {:.3} ({:+.2e} relative drift), |p| = {:.3e}, |L| = {:.3}",
+                self.sim.time(),
+                energy,
+                (energy - self.initial_energy) / self.initial_energy.abs(),
+                diagnostics.momentum.magnitude(),
+                diagnostics.angular_momentum.magnitude(),
+            );
+        }
+
+        let (min_mass, max_mass) = self
+            .sim
+            .particles
+            .iter()
+            .map(|p| p.mass.ln())
+            .fold((f32::MAX, f32::MIN), |(lo, hi), m| (lo.min(m), hi.max(m)));
+        for (instance, particle) in self.instances.iter_mut().zip(&self.sim.particles) {
+            instance.set_transform(&crate::physics::Transform::from_position(
+                CENTER + particle.position,
+            ));
+            instance.set_scalar(match color_mode {
+                ColorMode::Velocity => particle.velocity.magnitude() / MAX_SPEED,
+                ColorMode::Mass => {
+                    (particle.mass.ln() - min_mass) / (max_mass - min_mass).max(f32::EPSILON)
+                }
+                _ => 0.0,
+            });
+            instance.set_color_map(color_mode.color_map());
+        }
+        let instance_data = self
+            .instances
+            .iter()
+            .map(model::Instance::to_raw)
+            .collect::<Vec<_>>();
+        self.instance_buffer.write(device, queue, &instance_data);
+        self.trails.upload(device, queue);
+    }
+
+    pub fn draw_trails<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        self.trails.draw(render_pass, camera_bind_group);
+    }
+}
@@ -0,0 +1,98 @@
+// particle.wgsl
+// Camera-facing billboards read straight from the particle buffer, shaded
+// like small spheres and colored by speed.
+
+struct Camera {
+    view_pos: vec4<f32>,
+    view_proj: mat4x4<f32>,
+    inv_view_proj: mat4x4<f32>,
+}
+@group(0) @binding(0)
+var<uniform> camera: Camera;
+
+// The leading fields of `Params` in `particle_sim.wgsl`.
+struct Params {
+    gravity: vec3<f32>,
+    dt: f32,
+    emitter_position: vec3<f32>,
+    damping: f32,
+    emitter_velocity: vec3<f32>,
+    emitter_radius: f32,
+    spread: f32,
+    lifetime: f32,
+    radius: f32,
+    restitution: f32,
+    friction: f32,
+    frame: u32,
+    steps: u32,
+    count: u32,
+    plane_count: u32,
+    sphere_count: u32,
+    max_speed: f32,
+    _padding: u32,
+}
+@group(1) @binding(0)
+var<uniform> params: Params;
+
+struct ParticleInput {
+    @location(0) position: vec4<f32>,
+    @location(1) velocity: vec4<f32>,
+};
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) corner: vec2<f32>,
+    @location(1) speed: f32,
+};
+
+@vertex
+fn vs_main(
+    @builtin(vertex_index) vertex_index: u32,
+    particle: ParticleInput,
+) -> VertexOutput {
+    var corners = array<vec2<f32>, 6>(
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, -1.0),
+        vec2<f32>(1.0, 1.0),
+        vec2<f32>(-1.0, 1.0),
+    );
+    var out: VertexOutput;
+    if particle.position.w < 0.0 {
+        // Not born yet, so outside the clip volume.
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+
+    let center = particle.position.xyz;
+    let to_eye = normalize(camera.view_pos.xyz - center);
+    var right = cross(vec3<f32>(0.0, 1.0, 0.0), to_eye);
+    if dot(right, right) < 1e-6 {
+        right = vec3<f32>(1.0, 0.0, 0.0);
+    }
+    right = normalize(right);
+    let up = cross(to_eye, right);
+
+    let corner = corners[vertex_index];
+    let world_position = center + (right * corner.x + up * corner.y) * params.radius;
+    out.clip_position = camera.view_proj * vec4<f32>(world_position, 1.0);
+    out.corner = corner;
+    out.speed = length(particle.velocity.xyz);
+    return out;
+}
+
+const SLOW: vec3<f32> = vec3<f32>(0.1, 0.35, 1.0);
+const FAST: vec3<f32> = vec3<f32>(1.0, 0.55, 0.15);
+
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    let r2 = dot(in.corner, in.corner);
+    if r2 > 1.0 {
+        discard;
+    }
+    // Lit from the camera as if it were a sphere.
+    let facing = sqrt(1.0 - r2);
+    let color = mix(SLOW, FAST, clamp(in.speed / params.max_speed, 0.0, 1.0));
+    return vec4<f32>(color * (0.35 + 0.65 * facing), 1.0);
+}
@@ -0,0 +1,130 @@
+// particle_sim.wgsl
+// Integrates the particles in place, mirroring `physics::particles`.
+
+const MAX_PLANES: u32 = 8u;
+const MAX_SPHERES: u32 = 8u;
+const TAU: f32 = 6.283185307179586;
+
+struct Params {
+    gravity: vec3<f32>,
+    dt: f32,
+    emitter_position: vec3<f32>,
+    damping: f32,
+    emitter_velocity: vec3<f32>,
+    emitter_radius: f32,
+    spread: f32,
+    lifetime: f32,
+    radius: f32,
+    restitution: f32,
+    friction: f32,
+    // Step of the first of `steps` steps, which seeds the respawns.
+    frame: u32,
+    steps: u32,
+    count: u32,
+    plane_count: u32,
+    sphere_count: u32,
+    max_speed: f32,
+    _padding: u32,
+    // Normal and offset.
+    planes: array<vec4<f32>, MAX_PLANES>,
+    // Center and radius.
+    spheres: array<vec4<f32>, MAX_SPHERES>,
+}
+@group(0) @binding(0)
+var<uniform> params: Params;
+
+struct Particle {
+    // Age in w, negative while waiting to be born.
+    position: vec4<f32>,
+    velocity: vec4<f32>,
+}
+@group(0) @binding(1)
+var<storage, read_write> particles: array<Particle>;
+
+fn hash(x: u32) -> u32 {
+    let state = x * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+var<private> seed: u32;
+
+fn random() -> f32 {
+    seed = hash(seed);
+    return f32(seed >> 8u) / 16777216.0;
+}
+
+fn spawn(index: u32, frame: u32) -> Particle {
+    seed = hash(index ^ hash(frame));
+    let angle = TAU * random();
+    let distance = params.emitter_radius * sqrt(random());
+    let jitter = vec3<f32>(random() - 0.5, random() - 0.5, random() - 0.5);
+    var particle: Particle;
+    particle.position = vec4<f32>(
+        params.emitter_position + vec3<f32>(cos(angle) * distance, 0.0, sin(angle) * distance),
+        0.0,
+    );
+    particle.velocity = vec4<f32>(params.emitter_velocity + jitter * (2.0 * params.spread), 0.0);
+    return particle;
+}
+
+// Pushes the particle out along `normal` and reflects the approaching
+// velocity.
+fn collide(p: ptr<function, vec3<f32>>, v: ptr<function, vec3<f32>>, normal: vec3<f32>, depth: f32) {
+    *p += normal * depth;
+    let vn = dot(*v, normal);
+    if vn < 0.0 {
+        let tangent = *v - normal * vn;
+        *v = tangent * (1.0 - params.friction) - normal * (vn * params.restitution);
+    }
+}
+
+fn step(particle: Particle, index: u32, frame: u32) -> Particle {
+    let dt = params.dt;
+    var age = particle.position.w;
+    if age < 0.0 {
+        age += dt;
+        if age >= 0.0 {
+            return spawn(index, frame);
+        }
+        return Particle(vec4<f32>(particle.position.xyz, age), particle.velocity);
+    }
+
+    var v = (particle.velocity.xyz + params.gravity * dt) / (1.0 + params.damping * dt);
+    var p = particle.position.xyz + v * dt;
+    for (var i = 0u; i < params.plane_count; i++) {
+        let plane = params.planes[i];
+        let depth = plane.w + params.radius - dot(plane.xyz, p);
+        if depth > 0.0 {
+            collide(&p, &v, plane.xyz, depth);
+        }
+    }
+    for (var i = 0u; i < params.sphere_count; i++) {
+        let sphere = params.spheres[i];
+        let offset = p - sphere.xyz;
+        let distance = length(offset);
+        let depth = sphere.w + params.radius - distance;
+        if depth > 0.0 && distance > 0.0 {
+            collide(&p, &v, offset / distance, depth);
+        }
+    }
+
+    age += dt;
+    if age >= params.lifetime {
+        return spawn(index, frame);
+    }
+    return Particle(vec4<f32>(p, age), vec4<f32>(v, 0.0));
+}
+
+@compute @workgroup_size(256)
+fn cs_main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= params.count {
+        return;
+    }
+    var particle = particles[index];
+    for (var i = 0u; i < params.steps; i++) {
+        particle = step(particle, index, params.frame + i);
+    }
+    particles[index] = particle;
+}
@@ -0,0 +1,557 @@
+//! The particle demo: a fountain spraying onto the ground and a few spheres.
+//!
+//! With compute shaders the particles never leave the GPU: `particle_sim.wgsl`
+//! integrates them in a storage buffer that is then bound as the instance
+//! buffer of the billboards. Without, as on WebGL, fewer particles are
+//! stepped by the CPU reference in `physics::particles` and uploaded.
+
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+use wgpu::util::DeviceExt;
+
+use crate::model;
+use crate::physics::{self, Emitter, HalfSpace, ParticleSettings, ParticleState, ParticleSystem};
+use crate::post::HDR_FORMAT;
+use crate::{procedural, texture};
+
+/// Matches `MAX_PLANES` and `MAX_SPHERES` in `particle_sim.wgsl`.
+const MAX_PLANES: usize = 8;
+const MAX_SPHERES: usize = 8;
+const WORKGROUP_SIZE: u32 = 256;
+
+const GPU_PARTICLES: usize = 1 << 20;
+const CPU_PARTICLES: usize = 1 << 14;
+const TIMESTEP: f32 = 1.0 / 120.0;
+const MAX_STEPS: u32 = 4;
+/// Speed shown in the hottest color.
+const MAX_SPEED: f32 = 12.0;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParamsUniform {
+    gravity: [f32; 3],
+    dt: f32,
+    emitter_position: [f32; 3],
+    damping: f32,
+    emitter_velocity: [f32; 3],
+    emitter_radius: f32,
+    spread: f32,
+    lifetime: f32,
+    radius: f32,
+    restitution: f32,
+    friction: f32,
+    frame: u32,
+    steps: u32,
+    count: u32,
+    plane_count: u32,
+    sphere_count: u32,
+    max_speed: f32,
+    _padding: u32,
+    planes: [[f32; 4]; MAX_PLANES],
+    spheres: [[f32; 4]; MAX_SPHERES],
+}
+
+impl ParamsUniform {
+    fn new(settings: &ParticleSettings, count: usize) -> Self {
+        let mut planes = [[0.0; 4]; MAX_PLANES];
+        for (plane, p) in planes.iter_mut().zip(&settings.planes) {
+            *plane = p.normal.extend(p.offset).into();
+        }
+        let mut spheres = [[0.0; 4]; MAX_SPHERES];
+        for (sphere, s) in spheres.iter_mut().zip(&settings.spheres) {
+            *sphere = s.center.to_vec().extend(s.radius).into();
+        }
+        let emitter = &settings.emitter;
+        Self {
+            gravity: settings.gravity.into(),
+            dt: TIMESTEP,
+            emitter_position: emitter.position.into(),
+            damping: settings.damping,
+            emitter_velocity: emitter.velocity.into(),
+            emitter_radius: emitter.radius,
+            spread: emitter.spread,
+            lifetime: emitter.lifetime,
+            radius: settings.radius,
+            restitution: settings.restitution,
+            friction: settings.friction,
+            frame: 0,
+            steps: 0,
+            count: count as u32,
+            plane_count: settings.planes.len().min(MAX_PLANES) as u32,
+            sphere_count: settings.spheres.len().min(MAX_SPHERES) as u32,
+            max_speed: MAX_SPEED,
+            _padding: 0,
+            planes,
+            spheres,
+        }
+    }
+}
+
+/// A particle as laid out in the storage buffer, with the age in
+/// `position[3]`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct ParticleRaw {
+    position: [f32; 4],
+    velocity: [f32; 4],
+}
+
+impl ParticleRaw {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x4, 1 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<ParticleRaw>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+impl From<&ParticleState> for ParticleRaw {
+    fn from(particle: &ParticleState) -> Self {
+        Self {
+            position: particle.position.extend(particle.age).into(),
+            velocity: particle.velocity.extend(0.0).into(),
+        }
+    }
+}
+
+impl From<&ParticleRaw> for ParticleState {
+    fn from(particle: &ParticleRaw) -> Self {
+        let [x, y, z, age] = particle.position;
+        let [vx, vy, vz, _] = particle.velocity;
+        Self {
+            position: Vector3::new(x, y, z),
+            velocity: Vector3::new(vx, vy, vz),
+            age,
+        }
+    }
+}
+
+fn demo_settings() -> ParticleSettings {
+    ParticleSettings {
+        gravity: Vector3::new(0.0, -9.81, 0.0),
+        damping: 0.05,
+        radius: 0.03,
+        restitution: 0.4,
+        friction: 0.1,
+        emitter: Emitter {
+            position: Vector3::new(0.0, 0.0, 0.0),
+            radius: 0.3,
+            velocity: Vector3::new(0.5, 11.0, 0.0),
+            spread: 1.5,
+            lifetime: 4.0,
+        },
+        // The top of the ground box.
+        planes: vec![HalfSpace {
+            normal: Vector3::unit_y(),
+            offset: -1.0,
+        }],
+        spheres: vec![
+            physics::Sphere {
+                center: Point3::new(2.5, 1.0, 0.0),
+                radius: 1.5,
+            },
+            physics::Sphere {
+                center: Point3::new(-1.0, 4.0, 1.5),
+                radius: 0.8,
+            },
+        ],
+    }
+}
+
+/// Where the particles are stepped.
+enum Simulation {
+    Gpu {
+        pipeline: wgpu::ComputePipeline,
+        bind_group: wgpu::BindGroup,
+    },
+    Cpu(ParticleSystem),
+}
+
+pub struct Particles {
+    settings: ParticleSettings,
+    count: usize,
+    params: ParamsUniform,
+    params_buffer: wgpu::Buffer,
+    particle_buffer: wgpu::Buffer,
+    simulation: Simulation,
+    render_pipeline_layout: wgpu::PipelineLayout,
+    render_pipeline: wgpu::RenderPipeline,
+    render_bind_group: wgpu::BindGroup,
+    obstacles: Vec<model::Mesh>,
+    obstacle_instance_buffer: wgpu::Buffer,
+    accumulator: f32,
+}
+
+impl Particles {
+    pub fn new(
+        device: &wgpu::Device,
+        downlevel_flags: wgpu::DownlevelFlags,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+    ) -> Self {
+        let settings = demo_settings();
+        let gpu = downlevel_flags.contains(wgpu::DownlevelFlags::COMPUTE_SHADERS);
+        let count = if gpu { GPU_PARTICLES } else { CPU_PARTICLES };
+        let reference = ParticleSystem::new(settings.clone(), count);
+
+        let params = ParamsUniform::new(&settings, count);
+        let params_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Params Buffer"),
+            contents: bytemuck::cast_slice(&[params]),
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+        });
+        let particle_data = reference
+            .particles
+            .iter()
+            .map(ParticleRaw::from)
+            .collect::<Vec<_>>();
+        let storage_usage = if gpu {
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC
+        } else {
+            wgpu::BufferUsages::empty()
+        };
+        let particle_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+            label: Some("Particle Buffer"),
+            contents: bytemuck::cast_slice(&particle_data),
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST | storage_usage,
+        });
+
+        let simulation = if gpu {
+            Self::create_simulation(device, &params_buffer, &particle_buffer)
+        } else {
+            Simulation::Cpu(reference)
+        };
+
+        let render_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX | wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            }],
+            label: Some("particle_bind_group_layout"),
+        });
+        let render_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &render_layout,
+            entries: &[wgpu::BindGroupEntry {
+                binding: 0,
+                resource: params_buffer.as_entire_binding(),
+            }],
+            label: Some("particle_bind_group"),
+        });
+        let render_pipeline_layout =
+            device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some("Particle Pipeline Layout"),
+                bind_group_layouts: &[camera_bind_group_layout, &render_layout],
+                push_constant_ranges: &[],
+            });
+        let render_pipeline = create_render_pipeline(device, &render_pipeline_layout, sample_count);
+
+        let obstacles = settings
+            .spheres
+            .iter()
+            .enumerate()
+            .map(|(i, sphere)| {
+                procedural::shape_colored_mesh(
+                    &format!("obstacle_{}", i),
+                    device,
+                    &physics::Shape::Sphere(*sphere),
+                    [0.8, 0.8, 0.75],
+                )
+            })
+            .collect();
+        let obstacle_instance_buffer =
+            device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Obstacle Instance Buffer"),
+                contents: bytemuck::cast_slice(&[model::Instance::new(None, None, 1.0).to_raw()]),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+        log::info!(
+            "Particles: {} on the {}",
+            count,
+            if gpu { "GPU" } else { "CPU" }
+        );
+        Self {
+            settings,
+            count,
+            params,
+            params_buffer,
+            particle_buffer,
+            simulation,
+            render_pipeline_layout,
+            render_pipeline,
+            render_bind_group,
+            obstacles,
+            obstacle_instance_buffer,
+            accumulator: 0.0,
+        }
+    }
+
+    fn create_simulation(
+        device: &wgpu::Device,
+        params_buffer: &wgpu::Buffer,
+        particle_buffer: &wgpu::Buffer,
+    ) -> Simulation {
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+            label: Some("particle_sim_bind_group_layout"),
+        });
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: params_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: particle_buffer.as_entire_binding(),
+                },
+            ],
+            label: Some("particle_sim_bind_group"),
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Particle Sim Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Particle Sim Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("particle_sim.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Particle Sim Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "cs_main",
+        });
+        Simulation::Gpu {
+            pipeline,
+            bind_group,
+        }
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.render_pipeline =
+            create_render_pipeline(device, &self.render_pipeline_layout, sample_count);
+    }
+
+    /// Advances the particles in fixed steps. On the GPU the steps are
+    /// dispatched right away, ahead of the frame's rendering.
+    pub fn update(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, dt: f32) {
+        self.accumulator = (self.accumulator + dt).min(TIMESTEP * MAX_STEPS as f32);
+        let steps = (self.accumulator / TIMESTEP) as u32;
+        self.accumulator -= steps as f32 * TIMESTEP;
+        if steps > 0 {
+            self.simulate(device, queue, steps);
+        }
+    }
+
+    fn simulate(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, steps: u32) {
+        match &mut self.simulation {
+            Simulation::Gpu {
+                pipeline,
+                bind_group,
+            } => {
+                self.params.steps = steps;
+                queue.write_buffer(&self.params_buffer, 0, bytemuck::cast_slice(&[self.params]));
+                let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                    label: Some("Particle Encoder"),
+                });
+                {
+                    let mut compute_pass =
+                        encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                            label: Some("Particle Pass"),
+                            timestamp_writes: None,
+                        });
+                    compute_pass.set_pipeline(pipeline);
+                    compute_pass.set_bind_group(0, bind_group, &[]);
+                    compute_pass.dispatch_workgroups(
+                        (self.count as u32).div_ceil(WORKGROUP_SIZE),
+                        1,
+                        1,
+                    );
+                }
+                queue.submit(std::iter::once(encoder.finish()));
+                self.params.frame = self.params.frame.wrapping_add(steps);
+            }
+            Simulation::Cpu(system) => {
+                for _ in 0..steps {
+                    system.step(TIMESTEP);
+                }
+                let particle_data = system
+                    .particles
+                    .iter()
+                    .map(ParticleRaw::from)
+                    .collect::<Vec<_>>();
+                queue.write_buffer(
+                    &self.particle_buffer,
+                    0,
+                    bytemuck::cast_slice(&particle_data),
+                );
+            }
+        }
+    }
+
+    /// Takes one GPU step and compares it with the CPU reference stepping
+    /// from the same state. Returns the largest position difference, or
+    /// `None` when the CPU does the stepping anyway.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn compare_with_reference(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+    ) -> Option<f32> {
+        if matches!(self.simulation, Simulation::Cpu(_)) {
+            return None;
+        }
+        let before = self.read_back(device, queue);
+        let mut reference =
+            ParticleSystem::from_particles(self.settings.clone(), before, self.params.frame);
+        self.simulate(device, queue, 1);
+        reference.step(TIMESTEP);
+        let after = self.read_back(device, queue);
+        let error = after
+            .iter()
+            .zip(&reference.particles)
+            .map(|(gpu, cpu)| (gpu.position - cpu.position).magnitude())
+            .fold(0.0, f32::max);
+        Some(error)
+    }
+
+    /// Copies the particles back from the GPU, waiting for the copy.
+    #[cfg(not(target_arch = "wasm32"))]
+    fn read_back(&self, device: &wgpu::Device, queue: &wgpu::Queue) -> Vec<ParticleState> {
+        let size = (self.count * std::mem::size_of::<ParticleRaw>()) as wgpu::BufferAddress;
+        let staging = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Particle Read Back Buffer"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Particle Read Back Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&self.particle_buffer, 0, &staging, 0, size);
+        queue.submit(std::iter::once(encoder.finish()));
+
+        let slice = staging.slice(..);
+        slice.map_async(wgpu::MapMode::Read, |_| {});
+        device.poll(wgpu::Maintain::Wait);
+        let particles = bytemuck::cast_slice::<_, ParticleRaw>(&slice.get_mapped_range())
+            .iter()
+            .map(ParticleState::from)
+            .collect();
+        staging.unmap();
+        particles
+    }
+
+    /// Draws the spheres the particles bounce off. Expects the vertex-colored
+    /// pipeline to be bound.
+    pub fn draw_obstacles<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        shading_bind_group: &'a wgpu::BindGroup,
+    ) {
+        use model::DrawColoredModel;
+        render_pass.set_vertex_buffer(1, self.obstacle_instance_buffer.slice(..));
+        for mesh in &self.obstacles {
+            render_pass.draw_colored_mesh(
+                mesh,
+                camera_bind_group,
+                light_bind_group,
+                shading_bind_group,
+            );
+        }
+    }
+
+    /// Draws a billboard per particle straight from the particle buffer.
+    /// Leaves the particle pipeline bound.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        render_pass.set_pipeline(&self.render_pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_bind_group(1, &self.render_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.particle_buffer.slice(..));
+        render_pass.draw(0..6, 0..self.count as u32);
+    }
+}
+
+fn create_render_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Particle Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("particle.wgsl").into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Particle Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[ParticleRaw::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: HDR_FORMAT,
+                blend: Some(wgpu::BlendState::REPLACE),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        // Billboards always face the camera, whichever way they wind.
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: true,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
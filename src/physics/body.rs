@@ -0,0 +1,245 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Point3, Quaternion, Vector3};
+
+use super::shape::{Aabb, Shape};
+use super::transform::Transform;
+
+/// A rigid body moved by the `World`. Fixed bodies have no mass and never
+/// move on their own.
+#[derive(Clone, Debug)]
+pub struct RigidBody {
+    pub transform: Transform,
+    pub linear_velocity: Vector3<f32>,
+    pub angular_velocity: Vector3<f32>,
+    pub restitution: f32,
+    pub friction: f32,
+    pub linear_damping: f32,
+    pub angular_damping: f32,
+    /// Bit mask of the layers the body is on, used to scope force generators.
+    pub layers: u32,
+    shape: Shape,
+    inverse_mass: f32,
+    /// Inverse inertia tensor about the body origin, in body space.
+    inverse_inertia: Matrix3<f32>,
+    /// Force and torque accumulated for the current step.
+    force: Vector3<f32>,
+    torque: Vector3<f32>,
+    pub(crate) sleep_time: f32,
+    pub(crate) sleeping: bool,
+    pub(crate) island: usize,
+}
+
+impl RigidBody {
+    /// Creates a body whose mass follows from the shape volume. Static-only
+    /// shapes always make a fixed body.
+    pub fn dynamic(shape: Shape, density: f32, transform: Transform) -> Self {
+        let mass = if shape.is_static_only() {
+            0.0
+        } else {
+            shape.volume() * density
+        };
+        let mut body = Self::fixed(shape, transform);
+        if mass > 0.0 {
+            body.inverse_mass = 1.0 / mass;
+            let inertia = inertia(&body.shape, mass);
+            // Shapes without thickness can't turn about some axis at all.
+            body.inverse_inertia = inertia.invert().unwrap_or_else(|| {
+                Matrix3::from_diagonal(
+                    inertia
+                        .diagonal()
+                        .map(|i| if i > 0.0 { 1.0 / i } else { 0.0 }),
+                )
+            });
+        }
+        body
+    }
+
+    pub fn fixed(shape: Shape, transform: Transform) -> Self {
+        Self {
+            transform,
+            linear_velocity: Vector3::zero(),
+            angular_velocity: Vector3::zero(),
+            restitution: 0.2,
+            friction: 0.6,
+            linear_damping: 0.01,
+            angular_damping: 0.05,
+            layers: 1,
+            shape,
+            inverse_mass: 0.0,
+            inverse_inertia: Matrix3::zero(),
+            force: Vector3::zero(),
+            torque: Vector3::zero(),
+            sleep_time: 0.0,
+            sleeping: false,
+            island: 0,
+        }
+    }
+
+    pub fn shape(&self) -> &Shape {
+        &self.shape
+    }
+
+    pub fn is_fixed(&self) -> bool {
+        self.inverse_mass == 0.0
+    }
+
+    pub fn mass(&self) -> f32 {
+        if self.is_fixed() {
+            f32::INFINITY
+        } else {
+            1.0 / self.inverse_mass
+        }
+    }
+
+    pub fn inverse_mass(&self) -> f32 {
+        self.inverse_mass
+    }
+
+    /// Inverse inertia tensor rotated into world space.
+    pub fn world_inverse_inertia(&self) -> Matrix3<f32> {
+        let rotation = Matrix3::from(self.transform.rotation);
+        rotation * self.inverse_inertia * rotation.transpose()
+    }
+
+    pub fn is_sleeping(&self) -> bool {
+        self.sleeping
+    }
+
+    /// Index of the group of touching bodies this body was last solved with.
+    pub fn island(&self) -> usize {
+        self.island
+    }
+
+    pub fn wake(&mut self) {
+        self.sleeping = false;
+        self.sleep_time = 0.0;
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.shape.local_aabb().transformed(&self.transform)
+    }
+
+    pub fn velocity_at(&self, point: Point3<f32>) -> Vector3<f32> {
+        let r = point.to_vec() - self.transform.position;
+        self.linear_velocity + self.angular_velocity.cross(r)
+    }
+
+    pub fn apply_impulse(&mut self, impulse: Vector3<f32>, point: Point3<f32>) {
+        if self.is_fixed() {
+            return;
+        }
+        let r = point.to_vec() - self.transform.position;
+        self.linear_velocity += impulse * self.inverse_mass;
+        self.angular_velocity += self.world_inverse_inertia() * r.cross(impulse);
+        self.wake();
+    }
+
+    /// Adds a force through the center of mass for the current step.
+    pub fn add_force(&mut self, force: Vector3<f32>) {
+        self.force += force;
+    }
+
+    /// Adds a force acting at a world space point, which also spins the body.
+    pub fn add_force_at(&mut self, force: Vector3<f32>, point: Point3<f32>) {
+        let r = point.to_vec() - self.transform.position;
+        self.force += force;
+        self.torque += r.cross(force);
+    }
+
+    pub fn add_torque(&mut self, torque: Vector3<f32>) {
+        self.torque += torque;
+    }
+
+    /// Turns the accumulated force and torque into velocity and clears them.
+    pub(crate) fn apply_forces(&mut self, dt: f32) {
+        if !self.is_fixed() {
+            self.linear_velocity += self.force * (self.inverse_mass * dt);
+            self.angular_velocity += self.world_inverse_inertia() * self.torque * dt;
+        }
+        self.force = Vector3::zero();
+        self.torque = Vector3::zero();
+    }
+
+    /// Semi-implicit Euler step of the position and orientation.
+    pub(crate) fn integrate(&mut self, dt: f32) {
+        self.linear_velocity /= 1.0 + dt * self.linear_damping;
+        self.angular_velocity /= 1.0 + dt * self.angular_damping;
+        self.transform.position += self.linear_velocity * dt;
+        let spin = Quaternion::from_sv(0.0, self.angular_velocity) * self.transform.rotation;
+        self.transform.rotation = (self.transform.rotation + spin * (0.5 * dt)).normalize();
+    }
+}
+
+/// Inertia tensor about the body origin. Shapes other than centered
+/// spheres are approximated by their bounding box, moved off the origin by
+/// the parallel axis theorem.
+fn inertia(shape: &Shape, mass: f32) -> Matrix3<f32> {
+    match shape {
+        Shape::Sphere(s) if s.center.to_vec().is_zero() => {
+            Matrix3::from_value(0.4 * mass * s.radius * s.radius)
+        }
+        _ => {
+            let aabb = shape.local_aabb();
+            let h = aabb.half_extents();
+            let d = aabb.center().to_vec();
+            let about_center = Matrix3::from_diagonal(Vector3::new(
+                mass / 3.0 * (h.y * h.y + h.z * h.z),
+                mass / 3.0 * (h.x * h.x + h.z * h.z),
+                mass / 3.0 * (h.x * h.x + h.y * h.y),
+            ));
+            // m (|d|² I - d dᵀ), with the outer product column by column.
+            let outer = Matrix3::from_cols(d * d.x, d * d.y, d * d.z);
+            about_center + (Matrix3::from_value(d.magnitude2()) - outer) * mass
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::shape::Obb;
+
+    /// A unit cube centered at `center` in body space, with a mass of 6.
+    fn offset_cube(center: Point3<f32>) -> RigidBody {
+        let shape = Shape::Cuboid(Obb {
+            center,
+            half_extents: Vector3::from_value(0.5),
+            rotation: Quaternion::one(),
+        });
+        RigidBody::dynamic(shape, 6.0, Transform::identity())
+    }
+
+    fn assert_inverse_of(inverse: Matrix3<f32>, inertia: Matrix3<f32>) {
+        let product = inverse * inertia;
+        for (i, column) in AsRef::<[[f32; 3]; 3]>::as_ref(&product).iter().enumerate() {
+            for (j, &value) in column.iter().enumerate() {
+                let expected = if i == j { 1.0 } else { 0.0 };
+                assert!((value - expected).abs() < 1e-5, "{:?}", product);
+            }
+        }
+    }
+
+    #[test]
+    fn centered_cube_inertia() {
+        let body = offset_cube(Point3::origin());
+        assert_eq!(body.mass(), 6.0);
+        assert_inverse_of(body.world_inverse_inertia(), Matrix3::from_value(1.0));
+    }
+
+    #[test]
+    fn offset_inertia_follows_the_parallel_axis_theorem() {
+        // Turning about the X axis the cube sits on costs no extra inertia.
+        let body = offset_cube(Point3::new(1.0, 0.0, 0.0));
+        let expected = Matrix3::from_diagonal(Vector3::new(1.0, 7.0, 7.0));
+        assert_inverse_of(body.world_inverse_inertia(), expected);
+
+        let body = offset_cube(Point3::new(1.0, 1.0, 0.0));
+        #[rustfmt::skip]
+        let expected = Matrix3::new(
+            7.0, -6.0, 0.0,
+            -6.0, 7.0, 0.0,
+            0.0, 0.0, 13.0,
+        );
+        assert_inverse_of(body.world_inverse_inertia(), expected);
+    }
+}
@@ -0,0 +1,169 @@
+use super::shape::Aabb;
+
+const MAX_LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Debug)]
+struct Node {
+    aabb: Aabb,
+    /// First child for inner nodes (the second one follows it), first entry
+    /// of `items` for leaves.
+    first: u32,
+    /// Number of items in a leaf, zero for inner nodes.
+    count: u32,
+}
+
+/// Bounding volume hierarchy over a fixed set of primitives, used to find
+/// the triangles of a static mesh near a dynamic shape.
+#[derive(Clone, Debug)]
+pub struct Bvh {
+    nodes: Vec<Node>,
+    items: Vec<u32>,
+    /// Bounds of every primitive, by primitive index.
+    aabbs: Vec<Aabb>,
+}
+
+impl Bvh {
+    pub fn build(aabbs: &[Aabb]) -> Self {
+        let mut bvh = Self {
+            nodes: Vec::with_capacity(aabbs.len() * 2),
+            items: (0..aabbs.len() as u32).collect(),
+            aabbs: aabbs.to_vec(),
+        };
+        if aabbs.is_empty() {
+            return bvh;
+        }
+        bvh.nodes.push(Node {
+            aabb: Aabb::empty(),
+            first: 0,
+            count: 0,
+        });
+        bvh.subdivide(0, 0, aabbs.len(), aabbs);
+        bvh
+    }
+
+    fn subdivide(&mut self, node: usize, start: usize, end: usize, aabbs: &[Aabb]) {
+        let items = &mut self.items[start..end];
+        let aabb = items
+            .iter()
+            .fold(Aabb::empty(), |acc, &i| acc.merge(aabbs[i as usize]));
+        self.nodes[node].aabb = aabb;
+
+        if items.len() <= MAX_LEAF_SIZE {
+            self.nodes[node].first = start as u32;
+            self.nodes[node].count = items.len() as u32;
+            return;
+        }
+
+        let centers = items.iter().fold(Aabb::empty(), |acc, &i| {
+            acc.grow(aabbs[i as usize].center())
+        });
+        let extent = centers.max - centers.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+
+        let mid = items.len() / 2;
+        items.select_nth_unstable_by(mid, |&a, &b| {
+            let ca = aabbs[a as usize].center()[axis];
+            let cb = aabbs[b as usize].center()[axis];
+            ca.partial_cmp(&cb).unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let left = self.nodes.len();
+        for _ in 0..2 {
+            self.nodes.push(Node {
+                aabb: Aabb::empty(),
+                first: 0,
+                count: 0,
+            });
+        }
+        self.nodes[node].first = left as u32;
+        self.subdivide(left, start, start + mid, aabbs);
+        self.subdivide(left + 1, start + mid, end, aabbs);
+    }
+
+    /// Calls `f` with the index of every primitive whose bounds overlap `aabb`.
+    pub fn query(&self, aabb: &Aabb, mut f: impl FnMut(usize)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = vec![0usize];
+        while let Some(i) = stack.pop() {
+            let node = &self.nodes[i];
+            if !node.aabb.intersects(aabb) {
+                continue;
+            }
+            if node.count > 0 {
+                let start = node.first as usize;
+                for &item in &self.items[start..start + node.count as usize] {
+                    if self.aabbs[item as usize].intersects(aabb) {
+                        f(item as usize);
+                    }
+                }
+            } else {
+                stack.push(node.first as usize);
+                stack.push(node.first as usize + 1);
+            }
+        }
+    }
+
+    pub fn root_aabb(&self) -> Aabb {
+        self.nodes.first().map_or(Aabb::empty(), |n| n.aabb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Point3;
+
+    use super::*;
+
+    fn unit_box(x: f32, y: f32, z: f32) -> Aabb {
+        Aabb {
+            min: Point3::new(x, y, z),
+            max: Point3::new(x + 1.0, y + 1.0, z + 1.0),
+        }
+    }
+
+    #[test]
+    fn query_matches_brute_force() {
+        let aabbs = (0..200)
+            .map(|i| {
+                let i = i as f32;
+                unit_box((i * 7.3) % 23.0, (i * 3.1) % 11.0, (i * 5.7) % 17.0)
+            })
+            .collect::<Vec<_>>();
+        let bvh = Bvh::build(&aabbs);
+        for query in [
+            unit_box(5.0, 5.0, 5.0),
+            unit_box(-3.0, 0.0, 0.0),
+            Aabb {
+                min: Point3::new(0.0, 0.0, 0.0),
+                max: Point3::new(12.0, 3.0, 8.0),
+            },
+        ] {
+            let mut found = Vec::new();
+            bvh.query(&query, |i| found.push(i));
+            found.sort();
+            let expected = (0..aabbs.len())
+                .filter(|&i| aabbs[i].intersects(&query))
+                .collect::<Vec<_>>();
+            assert_eq!(found, expected);
+        }
+        let root = bvh.root_aabb();
+        assert!(aabbs
+            .iter()
+            .all(|b| root.contains(b.min) && root.contains(b.max)));
+    }
+
+    #[test]
+    fn empty() {
+        let bvh = Bvh::build(&[]);
+        bvh.query(&unit_box(0.0, 0.0, 0.0), |_| panic!("nothing to find"));
+        assert!(bvh.root_aabb().is_empty());
+    }
+}
@@ -0,0 +1,621 @@
+//! Cloth as a grid of particles joined by springs, solved as extended
+//! position based dynamics (Macklin, Müller and Chentanez 2016): every
+//! spring is a distance constraint with a compliance, the inverse of its
+//! stiffness, so that the cloth stretches the same however many steps it
+//! takes. Solving a single pass per substep (Macklin et al. 2019) keeps even
+//! stiff cloth from sagging like the iterations would leave it to.
+
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use super::contact::contacts;
+use super::shape::{Shape, Sphere};
+use super::spatial_hash::SpatialHash;
+use super::transform::Transform;
+use super::world::World;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ClothSettings {
+    /// Compliance of the springs along the rows and columns, in meters per
+    /// newton. Zero makes them rigid.
+    pub stretch_compliance: f32,
+    /// Compliance of the springs across the diagonals of each quad.
+    pub shear_compliance: f32,
+    /// Compliance of the springs that skip a particle, which resist folding.
+    pub bend_compliance: f32,
+    /// Mass per unit area.
+    pub density: f32,
+    /// Substeps per step, each with a single pass over the constraints.
+    pub substeps: u32,
+    /// Fraction of the velocity lost per second.
+    pub damping: f32,
+    /// Distance kept between particles of different parts of the cloth,
+    /// and between the particles and bodies.
+    pub thickness: f32,
+    /// Coulomb friction against bodies, combined with theirs.
+    pub friction: f32,
+    pub self_collision: bool,
+    /// Velocity of the air, which pushes on the triangles facing it.
+    pub wind: Vector3<f32>,
+    /// Force on a triangle per unit area and unit of air speed across it.
+    pub drag: f32,
+    /// Layers the particles are on, for picking the world's gravity
+    /// generators that pull on them.
+    pub layers: u32,
+}
+
+impl ClothSettings {
+    /// Settings for a light fabric with particles about `spacing` apart.
+    pub fn new(spacing: f32) -> Self {
+        Self {
+            stretch_compliance: 0.0,
+            shear_compliance: 1e-4,
+            bend_compliance: 1e-2,
+            density: 0.2,
+            substeps: 10,
+            damping: 0.1,
+            thickness: spacing,
+            friction: 0.5,
+            self_collision: true,
+            wind: Vector3::zero(),
+            drag: 0.1,
+            layers: 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ClothParticle {
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    /// Pinned particles stay where they are put.
+    pub pinned: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Spring {
+    Stretch,
+    Shear,
+    Bend,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct DistanceConstraint {
+    a: usize,
+    b: usize,
+    rest_length: f32,
+    kind: Spring,
+}
+
+/// A rectangular sheet of cloth, separate from the rigid body `World` but
+/// stepped against it: bodies hold the cloth back, and dynamic ones are
+/// pushed by it in turn.
+#[derive(Clone, Debug)]
+pub struct Cloth {
+    pub settings: ClothSettings,
+    /// Row by row, `cols` per row.
+    pub particles: Vec<ClothParticle>,
+    rows: usize,
+    cols: usize,
+    particle_mass: f32,
+    /// The positions the cloth was made with, which tell apart the
+    /// particles that are close because they are neighbors.
+    rest_positions: Vec<Vector3<f32>>,
+    constraints: Vec<DistanceConstraint>,
+    triangles: Vec<[u32; 3]>,
+    hash: SpatialHash,
+    previous: Vec<Vector3<f32>>,
+    /// Gravity at each particle, sampled at the start of the step.
+    gravity: Vec<Vector3<f32>>,
+    /// Particles that may collide with each other during the current step.
+    close_pairs: Vec<(usize, usize)>,
+}
+
+impl Cloth {
+    /// A flat sheet of `cols` by `rows` particles starting at `origin`, with
+    /// the rows running along `across` and the columns along `down`, which
+    /// are the full edges of the sheet. Its front faces `across × down`.
+    pub fn grid(
+        origin: Point3<f32>,
+        across: Vector3<f32>,
+        down: Vector3<f32>,
+        cols: usize,
+        rows: usize,
+        settings: ClothSettings,
+    ) -> Self {
+        let (cols, rows) = (cols.max(2), rows.max(2));
+        let index = |row: usize, col: usize| row * cols + col;
+
+        let mut rest_positions = Vec::with_capacity(rows * cols);
+        for row in 0..rows {
+            for col in 0..cols {
+                let u = col as f32 / (cols - 1) as f32;
+                let v = row as f32 / (rows - 1) as f32;
+                rest_positions.push(origin.to_vec() + across * u + down * v);
+            }
+        }
+
+        let mut constraints = Vec::new();
+        let mut spring = |a: usize, b: usize, kind: Spring| {
+            constraints.push(DistanceConstraint {
+                a,
+                b,
+                rest_length: (rest_positions[a] - rest_positions[b]).magnitude(),
+                kind,
+            });
+        };
+        for row in 0..rows {
+            for col in 0..cols {
+                let p = index(row, col);
+                if col + 1 < cols {
+                    spring(p, index(row, col + 1), Spring::Stretch);
+                }
+                if row + 1 < rows {
+                    spring(p, index(row + 1, col), Spring::Stretch);
+                }
+                if col + 1 < cols && row + 1 < rows {
+                    spring(p, index(row + 1, col + 1), Spring::Shear);
+                    spring(index(row, col + 1), index(row + 1, col), Spring::Shear);
+                }
+                if col + 2 < cols {
+                    spring(p, index(row, col + 2), Spring::Bend);
+                }
+                if row + 2 < rows {
+                    spring(p, index(row + 2, col), Spring::Bend);
+                }
+            }
+        }
+
+        let mut triangles = Vec::with_capacity(2 * (rows - 1) * (cols - 1));
+        for row in 0..rows - 1 {
+            for col in 0..cols - 1 {
+                let p00 = index(row, col) as u32;
+                let p10 = p00 + 1;
+                let p01 = p00 + cols as u32;
+                let p11 = p01 + 1;
+                triangles.push([p00, p11, p01]);
+                triangles.push([p00, p10, p11]);
+            }
+        }
+
+        let area = across.cross(down).magnitude();
+        Self {
+            particle_mass: settings.density * area / (rows * cols) as f32,
+            hash: SpatialHash::new(settings.thickness),
+            settings,
+            particles: rest_positions
+                .iter()
+                .map(|&position| ClothParticle {
+                    position,
+                    velocity: Vector3::zero(),
+                    pinned: false,
+                })
+                .collect(),
+            rows,
+            cols,
+            rest_positions,
+            constraints,
+            triangles,
+            previous: Vec::new(),
+            gravity: Vec::new(),
+            close_pairs: Vec::new(),
+        }
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn index(&self, row: usize, col: usize) -> usize {
+        row * self.cols + col
+    }
+
+    pub fn particle_mass(&self) -> f32 {
+        self.particle_mass
+    }
+
+    /// Two per quad, wound counterclockwise seen from the front.
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        &self.triangles
+    }
+
+    pub fn pin(&mut self, row: usize, col: usize) {
+        let i = self.index(row, col);
+        self.particles[i].pinned = true;
+        self.particles[i].velocity = Vector3::zero();
+    }
+
+    pub fn unpin(&mut self, row: usize, col: usize) {
+        let i = self.index(row, col);
+        self.particles[i].pinned = false;
+    }
+
+    /// Unit normals of the front at the particles, averaged over the
+    /// triangles around each weighted by area.
+    pub fn normals(&self) -> Vec<Vector3<f32>> {
+        let mut normals = vec![Vector3::zero(); self.particles.len()];
+        for tri in &self.triangles {
+            let [a, b, c] = tri.map(|i| self.particles[i as usize].position);
+            let normal = (b - a).cross(c - a);
+            for &i in tri {
+                normals[i as usize] += normal;
+            }
+        }
+        for normal in &mut normals {
+            if !normal.is_zero() {
+                *normal = normal.normalize();
+            }
+        }
+        normals
+    }
+
+    /// Advances the cloth by `dt` under the world's gravity and the wind.
+    /// Bodies are not moved, only given the impulses of the particles they
+    /// hold back.
+    pub fn step(&mut self, dt: f32, world: &mut World) {
+        let substeps = self.settings.substeps.max(1);
+        if dt <= 0.0 {
+            return;
+        }
+        let sdt = dt / substeps as f32;
+        let thickness = self.settings.thickness;
+        // Moving less than the thickness per substep, particles can't pass
+        // through each other between checks.
+        let max_speed = 0.2 * thickness / sdt;
+
+        self.apply_wind(dt);
+        let layers = self.settings.layers;
+        self.gravity.clear();
+        self.gravity.extend(
+            self.particles
+                .iter()
+                .map(|p| world.gravity_at(Point3::from_vec(p.position), layers)),
+        );
+        let fastest = self
+            .particles
+            .iter()
+            .zip(&self.gravity)
+            .map(|(p, g)| p.velocity.magnitude() + g.magnitude() * dt)
+            .fold(0.0, f32::max);
+        let travel = (fastest * dt).min(max_speed * dt);
+
+        // Bodies near each particle, with room for how far it may move.
+        let colliders = world
+            .bodies()
+            .map(|(handle, body)| (handle, body, body.aabb().expand(thickness + travel)))
+            .collect::<Vec<_>>();
+        let mut candidates = Vec::new();
+        for (i, particle) in self.particles.iter().enumerate() {
+            if particle.pinned {
+                continue;
+            }
+            for (c, (_, _, aabb)) in colliders.iter().enumerate() {
+                if aabb.contains(Point3::from_vec(particle.position)) {
+                    candidates.push((i, c));
+                }
+            }
+        }
+        if self.settings.self_collision {
+            self.find_close_pairs(2.0 * travel);
+        } else {
+            self.close_pairs.clear();
+        }
+
+        let particle_shape = Shape::Sphere(Sphere {
+            center: Point3::origin(),
+            radius: thickness,
+        });
+        let mut impulses = Vec::new();
+        for _ in 0..substeps {
+            self.previous.clear();
+            for (particle, &gravity) in self.particles.iter_mut().zip(&self.gravity) {
+                self.previous.push(particle.position);
+                if particle.pinned {
+                    continue;
+                }
+                particle.velocity += gravity * sdt;
+                let speed = particle.velocity.magnitude();
+                if speed > max_speed {
+                    particle.velocity *= max_speed / speed;
+                }
+                particle.position += particle.velocity * sdt;
+            }
+
+            self.solve_constraints(sdt);
+            self.solve_self_collisions();
+
+            for &(i, c) in &candidates {
+                let (handle, body, _) = colliders[c];
+                let position = self.particles[i].position;
+                let transform = Transform::from_position(position);
+                let deepest = contacts(&particle_shape, &transform, body.shape(), &body.transform)
+                    .into_iter()
+                    .max_by(|a, b| a.depth.total_cmp(&b.depth));
+                let Some(contact) = deepest.filter(|contact| contact.depth > 0.0) else {
+                    continue;
+                };
+                let mut push = -contact.normal * contact.depth;
+                // Friction takes back the sliding along the surface over
+                // the substep, up to the cone of the push.
+                let moved = position - self.previous[i] - body.velocity_at(contact.point) * sdt;
+                let sliding = moved - contact.normal * moved.dot(contact.normal);
+                let friction = (self.settings.friction * body.friction).sqrt();
+                let slide = sliding.magnitude();
+                if slide > f32::EPSILON {
+                    push -= sliding * (friction * contact.depth / slide).min(1.0);
+                }
+                self.particles[i].position += push;
+                if !body.is_fixed() {
+                    impulses.push((handle, -push / sdt, contact.point));
+                }
+            }
+
+            let damping = (1.0 - self.settings.damping * sdt).max(0.0);
+            for (particle, &previous) in self.particles.iter_mut().zip(&self.previous) {
+                if !particle.pinned {
+                    particle.velocity = (particle.position - previous) / sdt * damping;
+                }
+            }
+        }
+
+        // Whatever momentum a body took from a particle, it gets back.
+        for (handle, impulse, point) in impulses {
+            if let Some(body) = world.body_mut(handle) {
+                body.apply_impulse(impulse * self.particle_mass, point);
+            }
+        }
+    }
+
+    /// Adds the impulse of the air on every triangle to its corners: the
+    /// part of the relative air velocity along the normal pushes it.
+    fn apply_wind(&mut self, dt: f32) {
+        if self.settings.drag == 0.0 {
+            return;
+        }
+        let mut impulses = vec![Vector3::zero(); self.particles.len()];
+        for tri in &self.triangles {
+            let [a, b, c] = tri.map(|i| self.particles[i as usize]);
+            let normal = (b.position - a.position).cross(c.position - a.position);
+            // Half the cross product is the area, and the normal is unit.
+            let double_area = normal.magnitude();
+            if double_area <= f32::EPSILON {
+                continue;
+            }
+            let normal = normal / double_area;
+            let velocity = (a.velocity + b.velocity + c.velocity) / 3.0;
+            let flow = (self.settings.wind - velocity).dot(normal);
+            let impulse = normal * (self.settings.drag * 0.5 * double_area * flow * dt / 3.0);
+            for &i in tri {
+                impulses[i as usize] += impulse;
+            }
+        }
+        for (particle, impulse) in self.particles.iter_mut().zip(impulses) {
+            if !particle.pinned {
+                particle.velocity += impulse / self.particle_mass;
+            }
+        }
+    }
+
+    /// One Gauss-Seidel pass, which is all XPBD needs per substep, so the
+    /// multipliers start from zero and need not be kept.
+    fn solve_constraints(&mut self, sdt: f32) {
+        let inverse_mass = 1.0 / self.particle_mass;
+        for constraint in &self.constraints {
+            let (a, b) = (&self.particles[constraint.a], &self.particles[constraint.b]);
+            let wa = if a.pinned { 0.0 } else { inverse_mass };
+            let wb = if b.pinned { 0.0 } else { inverse_mass };
+            let d = a.position - b.position;
+            let length = d.magnitude();
+            if wa + wb == 0.0 || length <= f32::EPSILON {
+                continue;
+            }
+            let compliance = match constraint.kind {
+                Spring::Stretch => self.settings.stretch_compliance,
+                Spring::Shear => self.settings.shear_compliance,
+                Spring::Bend => self.settings.bend_compliance,
+            } / (sdt * sdt);
+            let lambda = -(length - constraint.rest_length) / (wa + wb + compliance);
+            let correction = d * (lambda / length);
+            self.particles[constraint.a].position += correction * wa;
+            self.particles[constraint.b].position -= correction * wb;
+        }
+    }
+
+    /// Pairs of particles within the thickness plus `margin` that aren't
+    /// already that close in the rest shape, where the springs keep them
+    /// apart.
+    fn find_close_pairs(&mut self, margin: f32) {
+        let thickness = self.settings.thickness;
+        if self.hash.spacing() != thickness {
+            self.hash = SpatialHash::new(thickness);
+        }
+        let positions = self
+            .particles
+            .iter()
+            .map(|p| p.position)
+            .collect::<Vec<_>>();
+        self.hash.build(&positions);
+
+        self.close_pairs.clear();
+        let reach = thickness + margin;
+        for (i, &p) in positions.iter().enumerate() {
+            self.hash.query(p, reach, |j| {
+                if j > i
+                    && (positions[j] - p).magnitude2() < reach * reach
+                    && (self.rest_positions[j] - self.rest_positions[i]).magnitude()
+                        > 2.0 * thickness
+                {
+                    self.close_pairs.push((i, j));
+                }
+            });
+        }
+    }
+
+    fn solve_self_collisions(&mut self) {
+        let thickness = self.settings.thickness;
+        for &(i, j) in &self.close_pairs {
+            let (a, b) = (&self.particles[i], &self.particles[j]);
+            let wa = if a.pinned { 0.0 } else { 1.0 };
+            let wb = if b.pinned { 0.0 } else { 1.0 };
+            let d = a.position - b.position;
+            let distance = d.magnitude();
+            if wa + wb == 0.0 || distance >= thickness || distance <= f32::EPSILON {
+                continue;
+            }
+            let correction = d * ((thickness - distance) / (distance * (wa + wb)));
+            self.particles[i].position += correction * wa;
+            self.particles[j].position -= correction * wb;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::body::RigidBody;
+
+    /// A horizontal sheet of `cols` by `rows` particles 0.1 apart, with its
+    /// first corner at `origin`.
+    fn sheet(origin: Point3<f32>, cols: usize, rows: usize) -> Cloth {
+        Cloth::grid(
+            origin,
+            Vector3::new(0.1 * (cols - 1) as f32, 0.0, 0.0),
+            Vector3::new(0.0, 0.0, 0.1 * (rows - 1) as f32),
+            cols,
+            rows,
+            ClothSettings::new(0.1),
+        )
+    }
+
+    #[test]
+    fn grid_joins_neighbors_along_rows_columns_and_diagonals() {
+        let cloth = sheet(Point3::origin(), 4, 3);
+        let springs = |kind| {
+            cloth
+                .constraints
+                .iter()
+                .filter(|c| c.kind == kind)
+                .map(|c| c.rest_length)
+                .collect::<Vec<_>>()
+        };
+        // Each row and column of the 4x3 grid.
+        let stretch = springs(Spring::Stretch);
+        assert_eq!(stretch.len(), 3 * 3 + 2 * 4);
+        assert!(stretch.iter().all(|&l| (l - 0.1).abs() < 1e-5));
+        // Both diagonals of each of the 3x2 quads.
+        let shear = springs(Spring::Shear);
+        assert_eq!(shear.len(), 2 * 3 * 2);
+        assert!(shear.iter().all(|&l| (l - 0.1 * 2f32.sqrt()).abs() < 1e-5));
+        // Skipping one particle along each row and column.
+        let bend = springs(Spring::Bend);
+        assert_eq!(bend.len(), 3 * 2 + 4);
+        assert!(bend.iter().all(|&l| (l - 0.2).abs() < 1e-5));
+        assert_eq!(cloth.triangles().len(), 2 * 3 * 2);
+    }
+
+    #[test]
+    fn pinned_particles_stay_put() {
+        let mut cloth = sheet(Point3::origin(), 8, 8);
+        cloth.pin(0, 0);
+        cloth.pin(0, 7);
+        let pinned = [cloth.index(0, 0), cloth.index(0, 7)];
+        let start = pinned.map(|i| cloth.particles[i].position);
+        let mut world = World::new();
+
+        for _ in 0..60 {
+            cloth.step(1.0 / 60.0, &mut world);
+        }
+
+        for (i, start) in pinned.into_iter().zip(start) {
+            assert_eq!(cloth.particles[i].position, start);
+            assert_eq!(cloth.particles[i].velocity, Vector3::zero());
+        }
+        let hanging = cloth.particles[cloth.index(7, 3)].position;
+        assert!(hanging.y < -0.3, "{:?}", hanging);
+    }
+
+    #[test]
+    fn folded_layers_stay_a_thickness_apart() {
+        // Folded in half across the rows: the first eight lie flat and
+        // pinned, the other eight start a thickness above them and are
+        // pressed onto them by gravity.
+        let mut cloth = sheet(Point3::origin(), 4, 16);
+        // Limp, so that the bend springs don't unfold it.
+        cloth.settings.bend_compliance = 1e3;
+        for row in 0..16 {
+            for col in 0..4 {
+                let i = cloth.index(row, col);
+                if row < 8 {
+                    cloth.pin(row, col);
+                } else {
+                    // Half a spacing aside, so they come to rest in the
+                    // grooves between the columns below.
+                    cloth.particles[i].position.x += 0.05;
+                    cloth.particles[i].position.y = 0.1;
+                    cloth.particles[i].position.z = 0.1 * (15 - row) as f32;
+                }
+            }
+        }
+        let mut world = World::new();
+        let thickness = cloth.settings.thickness;
+
+        for _ in 0..60 {
+            cloth.step(1.0 / 60.0, &mut world);
+            let (bottom, top) = cloth.particles.split_at(cloth.index(8, 0));
+            // Away from the fold, where the layers are joined.
+            for a in &top[2 * 4..] {
+                for b in bottom {
+                    let distance = (a.position - b.position).magnitude();
+                    assert!(
+                        distance > 0.95 * thickness,
+                        "{:?} {:?}",
+                        a.position,
+                        b.position
+                    );
+                }
+            }
+        }
+
+        for particle in &cloth.particles[cloth.index(10, 0)..] {
+            let height = particle.position.y;
+            assert!(height > 0.5 * thickness && height < 0.15, "{}", height);
+        }
+    }
+
+    #[test]
+    fn cloth_drapes_over_a_sphere() {
+        let radius = 0.3;
+        let mut world = World::new();
+        world.add_body(RigidBody::fixed(
+            Shape::Sphere(Sphere {
+                center: Point3::origin(),
+                radius,
+            }),
+            Transform::identity(),
+        ));
+        let mut cloth = sheet(Point3::new(-0.5, 0.5, -0.5), 11, 11);
+
+        for _ in 0..120 {
+            cloth.step(1.0 / 60.0, &mut world);
+        }
+
+        let thickness = cloth.settings.thickness;
+        for particle in &cloth.particles {
+            let distance = particle.position.magnitude();
+            assert!(
+                distance > radius + 0.9 * thickness,
+                "{:?}",
+                particle.position
+            );
+        }
+        // Held up in the middle, hanging down toward the corners.
+        let middle = cloth.particles[cloth.index(5, 5)].position;
+        assert!(middle.y > radius, "{:?}", middle);
+        let corner = cloth.particles[cloth.index(0, 0)].position;
+        assert!(corner.y < middle.y - 0.2, "{:?}", corner);
+    }
+}
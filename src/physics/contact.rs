@@ -0,0 +1,807 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use super::shape::{Aabb, Shape};
+use super::transform::Transform;
+use super::triangle::{Feature, Triangle};
+
+/// A single contact point between two shapes.
+#[derive(Copy, Clone, Debug)]
+pub struct Contact {
+    /// Point on the surface of the second shape, in world space.
+    pub point: Point3<f32>,
+    /// Unit normal pointing from the first shape toward the second.
+    pub normal: Vector3<f32>,
+    pub depth: f32,
+}
+
+/// Plane of a convex polytope, `normal · p = offset` with an outward normal.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Plane {
+    pub normal: Vector3<f32>,
+    pub offset: f32,
+}
+
+impl Plane {
+    pub fn distance(&self, p: Point3<f32>) -> f32 {
+        self.normal.dot(p.to_vec()) - self.offset
+    }
+}
+
+/// A convex primitive moved into some common frame.
+pub(crate) enum Convex {
+    Sphere {
+        center: Point3<f32>,
+        radius: f32,
+    },
+    Capsule {
+        a: Point3<f32>,
+        b: Point3<f32>,
+        radius: f32,
+    },
+    Polytope(Polytope),
+}
+
+/// Convex polytope with one face polygon per plane.
+pub(crate) struct Polytope {
+    pub vertices: Vec<Point3<f32>>,
+    pub planes: Vec<Plane>,
+    pub faces: Vec<Vec<u32>>,
+}
+
+impl Convex {
+    /// Splits `shape` into convex pieces placed by `transform`. Static-only
+    /// shapes produce nothing.
+    pub fn decompose(shape: &Shape, transform: &Transform, out: &mut Vec<Convex>) {
+        match shape {
+            Shape::Sphere(s) => out.push(Convex::Sphere {
+                center: transform.transform_point(s.center),
+                radius: s.radius,
+            }),
+            Shape::Capsule(c) => out.push(Convex::Capsule {
+                a: transform.transform_point(c.a),
+                b: transform.transform_point(c.b),
+                radius: c.radius,
+            }),
+            Shape::Cuboid(obb) => {
+                let vertices = obb
+                    .corners()
+                    .iter()
+                    .map(|&p| transform.transform_point(p))
+                    .collect();
+                let center = transform.transform_point(obb.center);
+                let mut planes = Vec::with_capacity(6);
+                let mut faces = Vec::with_capacity(6);
+                let half_extents = [obb.half_extents.x, obb.half_extents.y, obb.half_extents.z];
+                for (k, axis) in obb.axes().into_iter().enumerate() {
+                    let axis = transform.transform_vector(axis);
+                    // Corner indices carry one bit per axis, see `Obb::corners`.
+                    let (i, j) = ((k + 1) % 3, (k + 2) % 3);
+                    for (normal, side) in [(axis, 1), (-axis, 0)] {
+                        planes.push(Plane {
+                            normal,
+                            offset: normal.dot(center.to_vec()) + half_extents[k],
+                        });
+                        faces.push(
+                            [(0, 0), (1, 0), (1, 1), (0, 1)]
+                                .map(|(u, v)| (side << k | u << i | v << j) as u32)
+                                .to_vec(),
+                        );
+                    }
+                }
+                out.push(Convex::Polytope(Polytope {
+                    vertices,
+                    planes,
+                    faces,
+                }));
+            }
+            Shape::ConvexHull(hull) => {
+                let vertices = hull
+                    .points
+                    .iter()
+                    .map(|&p| transform.transform_point(p))
+                    .collect::<Vec<_>>();
+                let (planes, faces) = hull
+                    .triangles
+                    .iter()
+                    .filter_map(|t| {
+                        let [a, b, c] = t.map(|i| vertices[i as usize]);
+                        let normal = (b - a).cross(c - a).normalize();
+                        let plane = Plane {
+                            normal,
+                            offset: normal.dot(a.to_vec()),
+                        };
+                        normal.x.is_finite().then(|| (plane, t.to_vec()))
+                    })
+                    .unzip();
+                out.push(Convex::Polytope(Polytope {
+                    vertices,
+                    planes,
+                    faces,
+                }));
+            }
+            Shape::Compound(shapes) => {
+                for shape in shapes {
+                    Convex::decompose(shape, transform, out);
+                }
+            }
+            Shape::TriMesh(_) | Shape::Heightfield(_) => {}
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        match self {
+            Convex::Sphere { center, radius } => Aabb::from_points(&[*center]).expand(*radius),
+            Convex::Capsule { a, b, radius } => Aabb::from_points(&[*a, *b]).expand(*radius),
+            Convex::Polytope(polytope) => Aabb::from_points(&polytope.vertices),
+        }
+    }
+
+    /// Contacts against a one-sided triangle, normals pointing away from it.
+    pub fn collide_triangle(&self, tri: &Triangle, out: &mut Vec<Contact>) {
+        match self {
+            Convex::Sphere { center, radius } => sphere_triangle(*center, *radius, tri, out),
+            Convex::Capsule { a, b, radius } => {
+                sphere_triangle(*a, *radius, tri, out);
+                sphere_triangle(*b, *radius, tri, out);
+                // A capsule lying across a ridge touches it between its end caps.
+                let (on_segment, on_edge) = segment_triangle_edges(*a, *b, tri);
+                let d = on_segment - on_edge;
+                let touching_ends =
+                    on_segment.distance2(*a) < 1e-8 || on_segment.distance2(*b) < 1e-8;
+                if !touching_ends && d.magnitude2() < radius * radius {
+                    sphere_triangle(on_segment, *radius, tri, out);
+                }
+            }
+            Convex::Polytope(polytope) => {
+                polytope_triangle(&polytope.vertices, &polytope.planes, tri, out)
+            }
+        }
+    }
+}
+
+fn sphere_triangle(center: Point3<f32>, radius: f32, tri: &Triangle, out: &mut Vec<Contact>) {
+    let height = tri.signed_distance(center);
+    if height < -radius || height > radius {
+        return;
+    }
+
+    let (closest, feature) = tri.closest_point(center);
+    let offset = center - closest;
+    let distance = offset.magnitude();
+    if distance >= radius {
+        return;
+    }
+
+    // Centers that sank below the surface are pushed back out along the face.
+    let raw_normal = if height <= 0.0 || distance < 1e-6 {
+        tri.normal
+    } else {
+        offset / distance
+    };
+    let normal = tri.smooth_normal(feature, raw_normal);
+    let depth = radius - offset.dot(normal);
+    if depth > 0.0 && (feature == Feature::Face || height > 0.0) {
+        out.push(Contact {
+            point: center - normal * radius,
+            normal,
+            depth,
+        });
+    }
+}
+
+/// Closest points between a segment and the edges of a triangle.
+fn segment_triangle_edges(
+    a: Point3<f32>,
+    b: Point3<f32>,
+    tri: &Triangle,
+) -> (Point3<f32>, Point3<f32>) {
+    (0..3)
+        .map(|i| closest_segment_segment(a, b, tri.vertices[i], tri.vertices[(i + 1) % 3]))
+        .min_by(|(p0, q0), (p1, q1)| {
+            p0.distance2(*q0)
+                .partial_cmp(&p1.distance2(*q1))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .unwrap()
+}
+
+pub(crate) fn closest_segment_segment(
+    p1: Point3<f32>,
+    q1: Point3<f32>,
+    p2: Point3<f32>,
+    q2: Point3<f32>,
+) -> (Point3<f32>, Point3<f32>) {
+    let d1 = q1 - p1;
+    let d2 = q2 - p2;
+    let r = p1 - p2;
+    let a = d1.magnitude2();
+    let e = d2.magnitude2();
+    let f = d2.dot(r);
+
+    let (s, t) = if a <= f32::EPSILON && e <= f32::EPSILON {
+        (0.0, 0.0)
+    } else if a <= f32::EPSILON {
+        (0.0, (f / e).clamp(0.0, 1.0))
+    } else {
+        let c = d1.dot(r);
+        if e <= f32::EPSILON {
+            ((-c / a).clamp(0.0, 1.0), 0.0)
+        } else {
+            let b = d1.dot(d2);
+            let denom = a * e - b * b;
+            let mut s = if denom > f32::EPSILON {
+                ((b * f - c * e) / denom).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let mut t = (b * s + f) / e;
+            if t < 0.0 {
+                t = 0.0;
+                s = (-c / a).clamp(0.0, 1.0);
+            } else if t > 1.0 {
+                t = 1.0;
+                s = ((b - c) / a).clamp(0.0, 1.0);
+            }
+            (s, t)
+        }
+    };
+    (p1 + d1 * s, p2 + d2 * t)
+}
+
+/// Vertex-face contacts in both directions. Edge-edge contacts are left out,
+/// the vertex contacts of neighboring triangles catch those cases for terrain.
+fn polytope_triangle(
+    vertices: &[Point3<f32>],
+    planes: &[Plane],
+    tri: &Triangle,
+    out: &mut Vec<Contact>,
+) {
+    let size = Aabb::from_points(vertices).half_extents().magnitude() * 2.0;
+
+    for &v in vertices {
+        let height = tri.signed_distance(v);
+        if height < 0.0 && height > -size && tri.contains_projection(v) {
+            out.push(Contact {
+                point: v,
+                normal: tri.normal,
+                depth: -height,
+            });
+        }
+    }
+
+    for &p in &tri.vertices {
+        let deepest = planes
+            .iter()
+            .map(|plane| (plane, plane.distance(p)))
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let Some((plane, distance)) = deepest else {
+            continue;
+        };
+        let normal = -plane.normal;
+        if distance < 0.0 && normal.dot(tri.normal) > 0.0 {
+            out.push(Contact {
+                point: p + normal * distance,
+                normal,
+                depth: -distance,
+            });
+        }
+    }
+}
+
+/// Contacts between a static triangle mesh or heightfield and any other
+/// shape. Normals point from the static collider toward `shape`.
+pub fn static_contacts(
+    static_shape: &Shape,
+    static_transform: &Transform,
+    shape: &Shape,
+    transform: &Transform,
+) -> Vec<Contact> {
+    let mut contacts = Vec::new();
+    let relative = static_transform.inverse() * *transform;
+    let mut pieces = Vec::new();
+    Convex::decompose(shape, &relative, &mut pieces);
+
+    for piece in &pieces {
+        let aabb = piece.aabb();
+        match static_shape {
+            Shape::TriMesh(mesh) => mesh.query(&aabb, |i| {
+                piece.collide_triangle(&mesh.triangle(i), &mut contacts);
+            }),
+            Shape::Heightfield(heightfield) => heightfield.query(&aabb, |tri| {
+                piece.collide_triangle(&tri, &mut contacts);
+            }),
+            _ => {}
+        }
+    }
+
+    // Neighboring triangles report the same smoothed contact along shared
+    // edges and vertices.
+    let mut unique: Vec<Contact> = Vec::with_capacity(contacts.len());
+    for contact in contacts {
+        let duplicate = unique.iter().any(|c| {
+            c.point.distance2(contact.point) < 1e-8 && c.normal.dot(contact.normal) > 0.999
+        });
+        if !duplicate {
+            unique.push(contact);
+        }
+    }
+    let mut contacts = unique;
+
+    for contact in &mut contacts {
+        contact.point = static_transform.transform_point(contact.point);
+        contact.normal = static_transform.transform_vector(contact.normal);
+    }
+    contacts
+}
+
+impl Contact {
+    /// The same contact seen from the other shape.
+    pub fn flipped(&self) -> Contact {
+        Contact {
+            point: self.point + self.normal * self.depth,
+            normal: -self.normal,
+            depth: self.depth,
+        }
+    }
+}
+
+/// Contacts between any two shapes, normals pointing from `a` toward `b`.
+/// Two static-only shapes never touch.
+pub fn contacts(
+    a: &Shape,
+    a_transform: &Transform,
+    b: &Shape,
+    b_transform: &Transform,
+) -> Vec<Contact> {
+    if a.is_static_only() {
+        if b.is_static_only() {
+            return Vec::new();
+        }
+        return static_contacts(a, a_transform, b, b_transform);
+    }
+    if b.is_static_only() {
+        return static_contacts(b, b_transform, a, a_transform)
+            .iter()
+            .map(Contact::flipped)
+            .collect();
+    }
+
+    let mut a_pieces = Vec::new();
+    let mut b_pieces = Vec::new();
+    Convex::decompose(a, a_transform, &mut a_pieces);
+    Convex::decompose(b, b_transform, &mut b_pieces);
+
+    let mut contacts = Vec::new();
+    for a_piece in &a_pieces {
+        let a_aabb = a_piece.aabb();
+        for b_piece in &b_pieces {
+            if a_aabb.intersects(&b_piece.aabb()) {
+                a_piece.collide(b_piece, &mut contacts);
+            }
+        }
+    }
+    contacts
+}
+
+impl Convex {
+    /// Contacts against another convex piece, normals pointing toward `other`.
+    pub fn collide(&self, other: &Convex, out: &mut Vec<Contact>) {
+        use Convex::*;
+        match (self, other) {
+            (
+                Sphere {
+                    center: a,
+                    radius: ra,
+                },
+                Sphere {
+                    center: b,
+                    radius: rb,
+                },
+            ) => sphere_sphere(*a, *ra, *b, *rb, out),
+            (Sphere { center, radius: ra }, Capsule { a, b, radius: rb }) => {
+                let (on_segment, _) = closest_segment_segment(*a, *b, *center, *center);
+                sphere_sphere(*center, *ra, on_segment, *rb, out);
+            }
+            (Capsule { a, b, radius: ra }, Sphere { center, radius: rb }) => {
+                let (on_segment, _) = closest_segment_segment(*a, *b, *center, *center);
+                sphere_sphere(on_segment, *ra, *center, *rb, out);
+            }
+            (
+                Capsule {
+                    a: a0,
+                    b: b0,
+                    radius: ra,
+                },
+                Capsule {
+                    a: a1,
+                    b: b1,
+                    radius: rb,
+                },
+            ) => {
+                let (p, q) = closest_segment_segment(*a0, *b0, *a1, *b1);
+                sphere_sphere(p, *ra, q, *rb, out);
+            }
+            (Polytope(p), Sphere { center, radius }) => {
+                polytope_sphere(&p.planes, *center, *radius, out)
+            }
+            (Polytope(p), Capsule { a, b, radius }) => {
+                polytope_sphere(&p.planes, *a, *radius, out);
+                polytope_sphere(&p.planes, *b, *radius, out);
+            }
+            (Sphere { .. } | Capsule { .. }, Polytope(_)) => {
+                let start = out.len();
+                other.collide(self, out);
+                for contact in &mut out[start..] {
+                    *contact = contact.flipped();
+                }
+            }
+            (Polytope(a), Polytope(b)) => polytope_polytope(a, b, out),
+        }
+    }
+}
+
+fn sphere_sphere(a: Point3<f32>, ra: f32, b: Point3<f32>, rb: f32, out: &mut Vec<Contact>) {
+    let offset = b - a;
+    let distance = offset.magnitude();
+    if distance >= ra + rb {
+        return;
+    }
+    let normal = if distance > 1e-6 {
+        offset / distance
+    } else {
+        Vector3::unit_y()
+    };
+    out.push(Contact {
+        point: b - normal * rb,
+        normal,
+        depth: ra + rb - distance,
+    });
+}
+
+/// Uses the face plane the center is furthest in front of, which is exact in
+/// face regions and slightly conservative near edges and corners.
+fn polytope_sphere(planes: &[Plane], center: Point3<f32>, radius: f32, out: &mut Vec<Contact>) {
+    let nearest = planes
+        .iter()
+        .map(|plane| (plane, plane.distance(center)))
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let Some((plane, distance)) = nearest else {
+        return;
+    };
+    if distance < radius {
+        out.push(Contact {
+            point: center - plane.normal * radius,
+            normal: plane.normal,
+            depth: radius - distance,
+        });
+    }
+}
+
+impl Polytope {
+    /// Each edge of the face polygons once.
+    fn edges(&self) -> Vec<[Point3<f32>; 2]> {
+        let mut edges = Vec::new();
+        for face in &self.faces {
+            for (k, &i) in face.iter().enumerate() {
+                let j = face[(k + 1) % face.len()];
+                if !edges.contains(&(i.min(j), i.max(j))) {
+                    edges.push((i.min(j), i.max(j)));
+                }
+            }
+        }
+        edges
+            .into_iter()
+            .map(|(i, j)| [self.vertices[i as usize], self.vertices[j as usize]])
+            .collect()
+    }
+
+    /// Smallest and largest projection of the vertices onto `axis`.
+    fn project(&self, axis: Vector3<f32>) -> (f32, f32) {
+        self.vertices
+            .iter()
+            .map(|v| axis.dot(v.to_vec()))
+            .fold((f32::MAX, f32::MIN), |(min, max), d| {
+                (min.min(d), max.max(d))
+            })
+    }
+}
+
+/// Separating axis test over the face normals of both polytopes and the
+/// cross products of their edges. The incident face of the other polytope
+/// is clipped against the side planes of the reference face with the least
+/// penetration, unless two edges cross clearly deeper than any face.
+fn polytope_polytope(a: &Polytope, b: &Polytope, out: &mut Vec<Contact>) {
+    let min_distance = |plane: &Plane, vertices: &[Point3<f32>]| {
+        vertices
+            .iter()
+            .map(|&v| plane.distance(v))
+            .fold(f32::MAX, f32::min)
+    };
+
+    // Separation, reference face and whether it belongs to `a`.
+    let mut best: Option<(f32, usize, bool)> = None;
+    let candidates = a
+        .planes
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (min_distance(p, &b.vertices), i, true))
+        .chain(
+            b.planes
+                .iter()
+                .enumerate()
+                .map(|(i, p)| (min_distance(p, &a.vertices), i, false)),
+        );
+    for (separation, face, on_a) in candidates {
+        if separation > 0.0 {
+            return;
+        }
+        // Prefer faces of `a` on ties to keep the manifold stable.
+        if best.is_none_or(|(s, _, _)| separation > s + 1e-4) {
+            best = Some((separation, face, on_a));
+        }
+    }
+    let Some((separation, face, on_a)) = best else {
+        return;
+    };
+
+    let a_edges = a.edges();
+    let b_edges = b.edges();
+    // Separation, axis and the edge directions of `a` and `b` it crosses.
+    let mut best_edge: Option<(f32, Vector3<f32>, [Vector3<f32>; 2])> = None;
+    for ea in unique_directions(&a_edges) {
+        for eb in unique_directions(&b_edges) {
+            let axis = ea.cross(eb);
+            if axis.magnitude2() < 1e-6 {
+                continue;
+            }
+            let axis = axis.normalize();
+            let (a_min, a_max) = a.project(axis);
+            let (b_min, b_max) = b.project(axis);
+            // Whichever side `b` is on, pointing the axis from `a` to `b`.
+            let (edge_separation, axis) = if b_min - a_max >= a_min - b_max {
+                (b_min - a_max, axis)
+            } else {
+                (a_min - b_max, -axis)
+            };
+            if edge_separation > 0.0 {
+                return;
+            }
+            if best_edge.is_none_or(|(s, ..)| edge_separation > s) {
+                best_edge = Some((edge_separation, axis, [ea, eb]));
+            }
+        }
+    }
+    // Face contacts make the more stable manifold, so edges have to be
+    // clearly shallower to win.
+    if let Some((edge_separation, axis, [ea, eb])) = best_edge {
+        if edge_separation > 0.95 * separation + 0.005 {
+            // The edge along `direction` furthest toward the other polytope,
+            // whose ends project the same since the axis is perpendicular.
+            let supporting = |edges: &[[Point3<f32>; 2]], direction: Vector3<f32>, sign: f32| {
+                edges
+                    .iter()
+                    .filter(|[p, q]| (q - p).normalize().cross(direction).magnitude2() < 1e-6)
+                    .max_by(|[p, _], [q, _]| {
+                        (sign * axis.dot(p.to_vec()))
+                            .partial_cmp(&(sign * axis.dot(q.to_vec())))
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    })
+                    .copied()
+            };
+            let on_a = supporting(&a_edges, ea, 1.0);
+            let on_b = supporting(&b_edges, eb, -1.0);
+            if let (Some([p1, q1]), Some([p2, q2])) = (on_a, on_b) {
+                let (_, point) = closest_segment_segment(p1, q1, p2, q2);
+                out.push(Contact {
+                    point,
+                    normal: axis,
+                    depth: -edge_separation,
+                });
+                return;
+            }
+        }
+    }
+
+    let (reference, incident) = if on_a { (a, b) } else { (b, a) };
+    let plane = reference.planes[face];
+    let normal = if on_a { plane.normal } else { -plane.normal };
+
+    let incident_face = incident
+        .planes
+        .iter()
+        .enumerate()
+        .min_by(|(_, p), (_, q)| {
+            p.normal
+                .dot(plane.normal)
+                .partial_cmp(&q.normal.dot(plane.normal))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })
+        .map(|(i, _)| i);
+    let Some(incident_face) = incident_face else {
+        return;
+    };
+
+    let mut polygon = incident.faces[incident_face]
+        .iter()
+        .map(|&i| incident.vertices[i as usize])
+        .collect::<Vec<_>>();
+    let corners = reference.faces[face]
+        .iter()
+        .map(|&i| reference.vertices[i as usize])
+        .collect::<Vec<_>>();
+    let centroid = Point3::centroid(&corners);
+    for (k, &start) in corners.iter().enumerate() {
+        let end = corners[(k + 1) % corners.len()];
+        let mut side = (end - start).cross(plane.normal);
+        if side.dot(centroid - start) > 0.0 {
+            side = -side;
+        }
+        polygon = clip_polygon(&polygon, start, side);
+        if polygon.is_empty() {
+            break;
+        }
+    }
+
+    let start = out.len();
+    for p in polygon {
+        let distance = plane.distance(p);
+        if distance <= 0.0 {
+            out.push(Contact {
+                // Points of `b` are on its surface already, those of `a` get
+                // projected onto the reference face of `b`.
+                point: if on_a { p } else { p - plane.normal * distance },
+                normal,
+                depth: -distance,
+            });
+        }
+    }
+
+    // Edge-edge overlaps can clip away every point.
+    if out.len() == start {
+        let deepest = b.vertices.iter().copied().min_by(|x, y| {
+            normal
+                .dot(x.to_vec())
+                .partial_cmp(&normal.dot(y.to_vec()))
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        if let Some(point) = deepest {
+            out.push(Contact {
+                point,
+                normal,
+                depth: -separation,
+            });
+        }
+    }
+}
+
+/// Directions of `edges` with parallel ones counted once.
+fn unique_directions(edges: &[[Point3<f32>; 2]]) -> Vec<Vector3<f32>> {
+    let mut directions: Vec<Vector3<f32>> = Vec::new();
+    for [p, q] in edges {
+        let direction = (q - p).normalize();
+        if direction.x.is_finite()
+            && !directions
+                .iter()
+                .any(|d| d.cross(direction).magnitude2() < 1e-6)
+        {
+            directions.push(direction);
+        }
+    }
+    directions
+}
+
+/// Keeps the part of a convex polygon behind the plane through `origin`
+/// with outward normal `normal`.
+fn clip_polygon(
+    polygon: &[Point3<f32>],
+    origin: Point3<f32>,
+    normal: Vector3<f32>,
+) -> Vec<Point3<f32>> {
+    let distance = |p: Point3<f32>| normal.dot(p - origin);
+    let mut clipped = Vec::with_capacity(polygon.len() + 1);
+    for (i, &p) in polygon.iter().enumerate() {
+        let q = polygon[(i + 1) % polygon.len()];
+        let (dp, dq) = (distance(p), distance(q));
+        if dp <= 0.0 {
+            clipped.push(p);
+        }
+        // Points on the plane are kept as they are, not cut again.
+        if (dp < 0.0 && dq > 0.0) || (dp > 0.0 && dq < 0.0) {
+            clipped.push(p + (q - p) * (dp / (dp - dq)));
+        }
+    }
+    clipped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::shape::{Obb, Sphere};
+    use cgmath::{Deg, Quaternion, Rotation3};
+
+    fn cube() -> Shape {
+        Shape::Cuboid(Obb {
+            center: Point3::origin(),
+            half_extents: Vector3::from_value(0.5),
+            rotation: Quaternion::one(),
+        })
+    }
+
+    /// Tilt of the whole setup, so that the bounding boxes overlap.
+    fn tilt() -> Quaternion<f32> {
+        Quaternion::from_angle_x(Deg(20.0)) * Quaternion::from_angle_z(Deg(30.0))
+    }
+
+    /// A cube standing on an edge along Z at the origin, and one above it
+    /// lying on an edge along X, `gap` apart between the two edges.
+    fn crossed_edges(gap: f32) -> Vec<Contact> {
+        let lower = Transform::new(
+            Vector3::zero(),
+            tilt() * Quaternion::from_angle_z(Deg(45.0)),
+        );
+        let upper = Transform::new(
+            tilt() * Vector3::new(0.0, 2.0f32.sqrt() + gap, 0.0),
+            tilt() * Quaternion::from_angle_x(Deg(45.0)),
+        );
+        assert!(cube()
+            .local_aabb()
+            .transformed(&lower)
+            .intersects(&cube().local_aabb().transformed(&upper)));
+        contacts(&cube(), &lower, &cube(), &upper)
+    }
+
+    #[test]
+    fn crossed_edges_apart_do_not_touch() {
+        // Every face normal sees the cubes overlap, only the cross product
+        // of the two edges separates them.
+        assert!(crossed_edges(0.05).is_empty());
+    }
+
+    #[test]
+    fn crossed_edges_touch_along_their_cross_product() {
+        let contacts = crossed_edges(-0.05);
+        assert_eq!(contacts.len(), 1);
+        let contact = contacts[0];
+        assert!(
+            contact.normal.dot(tilt() * Vector3::unit_y()) > 0.999,
+            "{:?}",
+            contact
+        );
+        assert!((contact.depth - 0.05).abs() < 1e-4, "{:?}", contact);
+        let expected = Point3::from_vec(tilt() * Vector3::new(0.0, 0.5f32.sqrt() - 0.05, 0.0));
+        assert!(contact.point.distance(expected) < 1e-4, "{:?}", contact);
+    }
+
+    #[test]
+    fn stacked_cubes_touch_at_four_corners() {
+        let contacts = contacts(
+            &cube(),
+            &Transform::identity(),
+            &cube(),
+            &Transform::from_position(Vector3::new(0.1, 0.99, 0.0)),
+        );
+        assert_eq!(contacts.len(), 4);
+        for contact in contacts {
+            assert!(contact.normal.dot(Vector3::unit_y()) > 0.999);
+            assert!((contact.depth - 0.01).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn sphere_on_cube() {
+        let sphere = Shape::Sphere(Sphere {
+            center: Point3::origin(),
+            radius: 0.5,
+        });
+        let contacts = contacts(
+            &cube(),
+            &Transform::identity(),
+            &sphere,
+            &Transform::from_position(Vector3::new(0.0, 0.9, 0.0)),
+        );
+        assert_eq!(contacts.len(), 1);
+        assert!((contacts[0].depth - 0.1).abs() < 1e-5);
+        assert!(contacts[0].point.distance(Point3::new(0.0, 0.4, 0.0)) < 1e-5);
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashSet;
+
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use super::quickhull::quickhull;
+use super::shape::{Aabb, ConvexHull, Shape};
+
+/// Settings for the voxel-based approximate convex decomposition.
+#[derive(Copy, Clone, Debug)]
+pub struct DecompositionParams {
+    /// Number of voxels along the longest side of the mesh bounds.
+    pub resolution: u32,
+    pub max_hulls: usize,
+    pub max_depth: u32,
+    /// Parts whose hull exceeds their voxel volume by less than this fraction
+    /// of the whole mesh volume are not split any further.
+    pub concavity: f32,
+    /// Candidate cutting planes tried per axis when splitting a part.
+    pub plane_samples: u32,
+}
+
+impl Default for DecompositionParams {
+    fn default() -> Self {
+        Self {
+            resolution: 32,
+            max_hulls: 16,
+            max_depth: 6,
+            concavity: 0.01,
+            plane_samples: 8,
+        }
+    }
+}
+
+struct VoxelGrid {
+    origin: Point3<f32>,
+    voxel_size: f32,
+    dims: [u32; 3],
+    cells: Vec<Cell>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Cell {
+    Empty,
+    Surface,
+    Exterior,
+}
+
+impl VoxelGrid {
+    fn index(&self, [x, y, z]: [u32; 3]) -> usize {
+        (x + self.dims[0] * (y + self.dims[1] * z)) as usize
+    }
+
+    fn voxelize(positions: &[Point3<f32>], triangles: &[[u32; 3]], resolution: u32) -> Self {
+        let aabb = Aabb::from_points(positions);
+        let extent = aabb.max - aabb.min;
+        let longest = extent.x.max(extent.y).max(extent.z).max(f32::EPSILON);
+        let voxel_size = longest / resolution.max(1) as f32;
+
+        // One voxel of padding on every side keeps the exterior connected.
+        let origin = aabb.min - Vector3::from_value(voxel_size);
+        let dims = [extent.x, extent.y, extent.z].map(|e| (e / voxel_size).ceil() as u32 + 3);
+        let mut grid = Self {
+            origin,
+            voxel_size,
+            dims,
+            cells: vec![Cell::Empty; (dims[0] * dims[1] * dims[2]) as usize],
+        };
+
+        for tri in triangles {
+            let [a, b, c] = tri.map(|i| positions[i as usize]);
+            let longest_edge = a.distance(b).max(b.distance(c)).max(c.distance(a));
+            let steps = (longest_edge / (voxel_size * 0.5)).ceil().max(1.0) as u32;
+            for i in 0..=steps {
+                for j in 0..=steps - i {
+                    let u = i as f32 / steps as f32;
+                    let v = j as f32 / steps as f32;
+                    let p = a + (b - a) * u + (c - a) * v;
+                    let cell = grid.cell_of(p);
+                    let index = grid.index(cell);
+                    grid.cells[index] = Cell::Surface;
+                }
+            }
+        }
+
+        let mut stack = vec![[0, 0, 0]];
+        while let Some(cell) = stack.pop() {
+            let index = grid.index(cell);
+            if grid.cells[index] != Cell::Empty {
+                continue;
+            }
+            grid.cells[index] = Cell::Exterior;
+            stack.extend(grid.neighbors(cell));
+        }
+
+        grid
+    }
+
+    fn cell_of(&self, p: Point3<f32>) -> [u32; 3] {
+        let local = (p - self.origin) / self.voxel_size;
+        let clamp = |c: f32, dim: u32| (c.max(0.0) as u32).min(dim - 1);
+        [
+            clamp(local.x, self.dims[0]),
+            clamp(local.y, self.dims[1]),
+            clamp(local.z, self.dims[2]),
+        ]
+    }
+
+    fn neighbors(&self, [x, y, z]: [u32; 3]) -> impl Iterator<Item = [u32; 3]> {
+        let dims = self.dims;
+        [
+            [x.wrapping_sub(1), y, z],
+            [x + 1, y, z],
+            [x, y.wrapping_sub(1), z],
+            [x, y + 1, z],
+            [x, y, z.wrapping_sub(1)],
+            [x, y, z + 1],
+        ]
+        .into_iter()
+        .filter(move |c| c[0] < dims[0] && c[1] < dims[1] && c[2] < dims[2])
+    }
+
+    fn corner(&self, [x, y, z]: [u32; 3]) -> Point3<f32> {
+        self.origin + Vector3::new(x as f32, y as f32, z as f32) * self.voxel_size
+    }
+
+    fn solid_voxels(&self) -> Vec<[u32; 3]> {
+        let mut voxels = Vec::new();
+        for z in 0..self.dims[2] {
+            for y in 0..self.dims[1] {
+                for x in 0..self.dims[0] {
+                    if self.cells[self.index([x, y, z])] != Cell::Exterior {
+                        voxels.push([x, y, z]);
+                    }
+                }
+            }
+        }
+        voxels
+    }
+
+    /// Hull over the corners of the part's boundary voxels.
+    fn hull(&self, part: &[[u32; 3]]) -> Option<ConvexHull> {
+        let members: HashSet<[u32; 3]> = part.iter().copied().collect();
+        let mut corners = HashSet::new();
+        for &voxel in part {
+            let interior = self.neighbors(voxel).count() == 6
+                && self.neighbors(voxel).all(|n| members.contains(&n));
+            if interior {
+                continue;
+            }
+            for i in 0..8u32 {
+                corners.insert([
+                    voxel[0] + (i & 1),
+                    voxel[1] + ((i >> 1) & 1),
+                    voxel[2] + ((i >> 2) & 1),
+                ]);
+            }
+        }
+        let points = corners
+            .into_iter()
+            .map(|c| self.corner(c))
+            .collect::<Vec<_>>();
+        quickhull(&points)
+    }
+
+    fn concavity(&self, part: &[[u32; 3]]) -> (f32, Option<ConvexHull>) {
+        let hull = self.hull(part);
+        let hull_volume = hull.as_ref().map_or(0.0, ConvexHull::volume);
+        let part_volume = part.len() as f32 * self.voxel_size.powi(3);
+        ((hull_volume - part_volume).max(0.0), hull)
+    }
+}
+
+/// Splits a possibly concave mesh into a compound of convex hulls.
+///
+/// This follows the V-HACD approach: the mesh is voxelized, then parts are
+/// recursively cut along axis-aligned planes chosen to minimize the
+/// difference between each half's hull volume and its voxel volume.
+pub fn convex_decomposition(
+    positions: &[Point3<f32>],
+    triangles: &[[u32; 3]],
+    params: &DecompositionParams,
+) -> Shape {
+    let grid = VoxelGrid::voxelize(positions, triangles, params.resolution);
+    let solid = grid.solid_voxels();
+    let total_volume = (solid.len() as f32 * grid.voxel_size.powi(3)).max(f32::EPSILON);
+
+    let mut pending = vec![(solid, 0u32)];
+    let mut done = Vec::new();
+    while let Some((part, depth)) = pending.pop() {
+        let (concavity, hull) = grid.concavity(&part);
+        let hull_budget = done.len() + pending.len() + 2 <= params.max_hulls;
+        if concavity / total_volume <= params.concavity
+            || depth >= params.max_depth
+            || !hull_budget
+            || part.len() < 2
+        {
+            done.extend(hull);
+            continue;
+        }
+
+        match best_split(&grid, &part, params.plane_samples) {
+            Some((left, right)) => {
+                pending.push((left, depth + 1));
+                pending.push((right, depth + 1));
+            }
+            None => done.extend(hull),
+        }
+    }
+
+    Shape::Compound(done.into_iter().map(Shape::ConvexHull).collect())
+}
+
+type Split = (Vec<[u32; 3]>, Vec<[u32; 3]>);
+
+fn best_split(grid: &VoxelGrid, part: &[[u32; 3]], samples: u32) -> Option<Split> {
+    let mut best: Option<(f32, Split)> = None;
+    for axis in 0..3 {
+        let min = part.iter().map(|v| v[axis]).min()?;
+        let max = part.iter().map(|v| v[axis]).max()?;
+        if max == min {
+            continue;
+        }
+
+        let span = max - min;
+        let mut planes = (1..=samples)
+            .map(|i| min + (span * i / (samples + 1)).max(1))
+            .collect::<Vec<_>>();
+        planes.dedup();
+
+        for plane in planes {
+            let (left, right): Split = part.iter().partition(|v| v[axis] < plane);
+            if left.is_empty() || right.is_empty() {
+                continue;
+            }
+            let cost = grid.concavity(&left).0 + grid.concavity(&right).0;
+            if best.as_ref().is_none_or(|(c, _)| cost < *c) {
+                best = Some((cost, (left, right)));
+            }
+        }
+    }
+    best.map(|(_, split)| split)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Closed box mesh appended to `positions` and `triangles`.
+    fn add_box(
+        positions: &mut Vec<Point3<f32>>,
+        triangles: &mut Vec<[u32; 3]>,
+        min: Point3<f32>,
+        max: Point3<f32>,
+    ) {
+        let corners = (0..8)
+            .map(|i| {
+                Point3::new(
+                    if i & 1 == 0 { min.x } else { max.x },
+                    if i & 2 == 0 { min.y } else { max.y },
+                    if i & 4 == 0 { min.z } else { max.z },
+                )
+            })
+            .collect::<Vec<_>>();
+        let hull = quickhull(&corners).unwrap();
+        let base = positions.len() as u32;
+        positions.extend(hull.points);
+        triangles.extend(hull.triangles.iter().map(|t| t.map(|i| i + base)));
+    }
+
+    fn hulls(shape: &Shape) -> &[Shape] {
+        match shape {
+            Shape::Compound(parts) => parts,
+            _ => panic!("expected a compound"),
+        }
+    }
+
+    #[test]
+    fn convex_mesh_stays_whole() {
+        let (mut positions, mut triangles) = (Vec::new(), Vec::new());
+        add_box(
+            &mut positions,
+            &mut triangles,
+            Point3::new(-1.0, -1.0, -1.0),
+            Point3::new(1.0, 1.0, 1.0),
+        );
+        let shape = convex_decomposition(&positions, &triangles, &DecompositionParams::default());
+        assert_eq!(hulls(&shape).len(), 1);
+        // Surface voxels count as solid, so the hull may grow by one voxel.
+        let voxel = 2.0f32 / 32.0;
+        assert!((8.0..=(2.0 + 2.0 * voxel).powi(3)).contains(&shape.volume()));
+    }
+
+    #[test]
+    fn separate_boxes_are_split() {
+        let (mut positions, mut triangles) = (Vec::new(), Vec::new());
+        let left = Aabb {
+            min: Point3::new(-3.0, 0.0, 0.0),
+            max: Point3::new(-1.0, 1.0, 1.0),
+        };
+        let right = Aabb {
+            min: Point3::new(1.0, 0.0, 0.0),
+            max: Point3::new(3.0, 1.0, 1.0),
+        };
+        for aabb in [left, right] {
+            add_box(&mut positions, &mut triangles, aabb.min, aabb.max);
+        }
+        let shape = convex_decomposition(&positions, &triangles, &DecompositionParams::default());
+        let voxel = 6.0f32 / 32.0;
+        let parts = hulls(&shape);
+        assert!(parts.len() >= 2);
+        for part in parts {
+            let aabb = part.local_aabb();
+            let inside = |b: Aabb| {
+                let b = b.expand(voxel);
+                b.contains(aabb.min) && b.contains(aabb.max)
+            };
+            assert!(inside(left) || inside(right), "{aabb:?} spans both boxes");
+        }
+        let grown = 2.0 * (2.0 + 2.0 * voxel) * (1.0 + 2.0 * voxel).powi(2);
+        assert!((4.0..=grown).contains(&shape.volume()));
+    }
+
+    #[test]
+    fn degenerate_input() {
+        let quad = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 0.0),
+            Point3::new(1.0, 0.0, 1.0),
+            Point3::new(0.0, 0.0, 1.0),
+        ];
+        let shape = convex_decomposition(&quad, &[[0, 1, 2], [0, 2, 3]], &Default::default());
+        assert!(shape.volume().is_finite());
+        let shape = convex_decomposition(&[], &[], &Default::default());
+        assert_eq!(shape.volume(), 0.0);
+    }
+}
@@ -0,0 +1,295 @@
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Point3, Quaternion, Vector3};
+
+use super::shape::{Aabb, Capsule, Obb, Sphere};
+
+pub fn fit_aabb(points: &[Point3<f32>]) -> Aabb {
+    Aabb::from_points(points)
+}
+
+/// Ritter's bounding sphere, tightened against the AABB-centered sphere.
+pub fn fit_sphere(points: &[Point3<f32>]) -> Sphere {
+    let Some(&first) = points.first() else {
+        return Sphere {
+            center: Point3::origin(),
+            radius: 0.0,
+        };
+    };
+
+    let farthest_from = |from: Point3<f32>| {
+        points
+            .iter()
+            .copied()
+            .max_by(|a, b| {
+                from.distance2(*a)
+                    .partial_cmp(&from.distance2(*b))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or(from)
+    };
+    let y = farthest_from(first);
+    let z = farthest_from(y);
+
+    let mut center = y.midpoint(z);
+    let mut radius = y.distance(z) * 0.5;
+    for p in points {
+        let d = p.distance(center);
+        if d > radius {
+            let new_radius = (radius + d) * 0.5;
+            center += (p - center) * ((new_radius - radius) / d);
+            radius = new_radius;
+        }
+    }
+
+    let aabb_center = Aabb::from_points(points).center();
+    let aabb_radius = points
+        .iter()
+        .map(|p| p.distance(aabb_center))
+        .fold(0.0, f32::max);
+    if aabb_radius < radius {
+        (center, radius) = (aabb_center, aabb_radius);
+    }
+
+    Sphere { center, radius }
+}
+
+/// Oriented box along the principal axes of the points, or the AABB if that
+/// turns out to be tighter.
+pub fn fit_obb(points: &[Point3<f32>]) -> Obb {
+    let aabb = Aabb::from_points(points);
+    if points.is_empty() {
+        return Aabb {
+            min: Point3::origin(),
+            max: Point3::origin(),
+        }
+        .to_obb();
+    }
+
+    let (_, axes) = principal_axes(points);
+    let mut min = Vector3::from_value(f32::MAX);
+    let mut max = Vector3::from_value(f32::MIN);
+    for p in points {
+        for (i, axis) in axes.iter().enumerate() {
+            let t = axis.dot(p.to_vec());
+            min[i] = min[i].min(t);
+            max[i] = max[i].max(t);
+        }
+    }
+    let mid = (min + max) * 0.5;
+    let obb = Obb {
+        center: Point3::from_vec(axes[0] * mid.x + axes[1] * mid.y + axes[2] * mid.z),
+        half_extents: (max - min) * 0.5,
+        rotation: Quaternion::from(Matrix3::from_cols(axes[0], axes[1], axes[2])).normalize(),
+    };
+
+    if obb.volume() < aabb.volume() {
+        obb
+    } else {
+        aabb.to_obb()
+    }
+}
+
+/// Capsule whose segment follows the dominant principal axis of the points.
+pub fn fit_capsule(points: &[Point3<f32>]) -> Capsule {
+    if points.is_empty() {
+        return Capsule {
+            a: Point3::origin(),
+            b: Point3::origin(),
+            radius: 0.0,
+        };
+    }
+
+    let (centroid, axes) = principal_axes(points);
+    let axis = axes[0];
+
+    let radius = points
+        .iter()
+        .map(|p| {
+            let v = p - centroid;
+            (v - axis * v.dot(axis)).magnitude()
+        })
+        .fold(0.0, f32::max);
+
+    // Shrink the segment as far as the hemispherical caps still cover every point.
+    let mut t0 = f32::MAX;
+    let mut t1 = f32::MIN;
+    for p in points {
+        let v = p - centroid;
+        let t = v.dot(axis);
+        let d2 = (v - axis * t).magnitude2();
+        let cap = (radius * radius - d2).max(0.0).sqrt();
+        t0 = t0.min(t + cap);
+        t1 = t1.max(t - cap);
+    }
+    if t0 > t1 {
+        let mid = (t0 + t1) * 0.5;
+        (t0, t1) = (mid, mid);
+    }
+
+    Capsule {
+        a: centroid + axis * t0,
+        b: centroid + axis * t1,
+        radius,
+    }
+}
+
+/// Centroid and covariance eigenvectors sorted by decreasing variance, forming
+/// a right-handed basis.
+pub(crate) fn principal_axes(points: &[Point3<f32>]) -> (Point3<f32>, [Vector3<f32>; 3]) {
+    let centroid = Point3::centroid(points);
+    let mut cov = [[0.0f32; 3]; 3];
+    for p in points {
+        let d = p - centroid;
+        for i in 0..3 {
+            for j in 0..3 {
+                cov[i][j] += d[i] * d[j];
+            }
+        }
+    }
+
+    let (values, vectors) = symmetric_eigen(cov);
+    let mut order = [0, 1, 2];
+    order.sort_by(|&a, &b| {
+        values[b]
+            .partial_cmp(&values[a])
+            .unwrap_or(std::cmp::Ordering::Equal)
+    });
+    let x = vectors[order[0]];
+    let y = vectors[order[1]];
+    (centroid, [x, y, x.cross(y)])
+}
+
+/// Jacobi eigenvalue iteration for a symmetric 3x3 matrix.
+fn symmetric_eigen(mut a: [[f32; 3]; 3]) -> ([f32; 3], [Vector3<f32>; 3]) {
+    let mut v = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+
+    for _ in 0..32 {
+        let (p, q) = [(0, 1), (0, 2), (1, 2)]
+            .into_iter()
+            .max_by(|&(a0, b0), &(a1, b1)| {
+                a[a0][b0]
+                    .abs()
+                    .partial_cmp(&a[a1][b1].abs())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap();
+        if a[p][q].abs() < 1e-9 {
+            break;
+        }
+
+        let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+        let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+        let c = 1.0 / (t * t + 1.0).sqrt();
+        let s = t * c;
+
+        for row in a.iter_mut() {
+            let (akp, akq) = (row[p], row[q]);
+            row[p] = c * akp - s * akq;
+            row[q] = s * akp + c * akq;
+        }
+        let (row_p, row_q) = (a[p], a[q]);
+        a[p] = std::array::from_fn(|k| c * row_p[k] - s * row_q[k]);
+        a[q] = std::array::from_fn(|k| s * row_p[k] + c * row_q[k]);
+        for row in v.iter_mut() {
+            let (vp, vq) = (row[p], row[q]);
+            row[p] = c * vp - s * vq;
+            row[q] = s * vp + c * vq;
+        }
+    }
+
+    let column = |i: usize| Vector3::new(v[0][i], v[1][i], v[2][i]).normalize();
+    (
+        [a[0][0], a[1][1], a[2][2]],
+        [column(0), column(1), column(2)],
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sorted(v: Vector3<f32>) -> [f32; 3] {
+        let mut v: [f32; 3] = v.into();
+        v.sort_by(|a, b| b.partial_cmp(a).unwrap());
+        v
+    }
+
+    #[test]
+    fn obb_of_rotated_box() {
+        let obb = Obb {
+            center: Point3::new(1.0, -2.0, 0.5),
+            half_extents: Vector3::new(3.0, 2.0, 1.0),
+            rotation: Quaternion::from_axis_angle(
+                Vector3::new(1.0, 2.0, 3.0).normalize(),
+                cgmath::Deg(40.0),
+            ),
+        };
+        let fitted = fit_obb(&obb.corners());
+        assert!(fitted.center.distance(obb.center) < 1e-3);
+        for (a, b) in sorted(fitted.half_extents).iter().zip([3.0, 2.0, 1.0]) {
+            assert!((a - b).abs() < 1e-3, "{a} != {b}");
+        }
+        assert!((fitted.volume() - obb.volume()).abs() < 1e-2);
+    }
+
+    #[test]
+    fn eigen_of_diagonal_matrix() {
+        let (values, vectors) =
+            symmetric_eigen([[2.0, 0.0, 0.0], [0.0, 5.0, 0.0], [0.0, 0.0, -1.0]]);
+        assert_eq!(values, [2.0, 5.0, -1.0]);
+        assert_eq!(
+            vectors,
+            [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()]
+        );
+    }
+
+    #[test]
+    fn eigen_of_symmetric_matrix() {
+        let a = [[4.0, 1.0, 0.5], [1.0, 3.0, -0.5], [0.5, -0.5, 1.0]];
+        let (values, vectors) = symmetric_eigen(a);
+        let m = Matrix3::from_cols(a[0].into(), a[1].into(), a[2].into());
+        for (value, vector) in values.iter().zip(vectors) {
+            assert!((m * vector - vector * *value).magnitude() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn sphere_contains_points() {
+        let points = Obb {
+            center: Point3::new(0.0, 1.0, 0.0),
+            half_extents: Vector3::new(1.0, 2.0, 0.5),
+            rotation: Quaternion::one(),
+        }
+        .corners();
+        let sphere = fit_sphere(&points);
+        for p in points {
+            assert!(p.distance(sphere.center) <= sphere.radius + 1e-4);
+        }
+    }
+
+    #[test]
+    fn degenerate_input() {
+        let empty = fit_obb(&[]);
+        assert_eq!(empty.half_extents, Vector3::zero());
+        assert_eq!(fit_sphere(&[]).radius, 0.0);
+        assert_eq!(fit_capsule(&[]).radius, 0.0);
+
+        let collinear = (0..5)
+            .map(|i| Point3::new(i as f32, i as f32, 0.0))
+            .collect::<Vec<_>>();
+        let obb = fit_obb(&collinear);
+        assert!(obb.half_extents.x.is_finite() && obb.center.x.is_finite());
+        let capsule = fit_capsule(&collinear);
+        assert!(capsule.radius < 1e-4);
+        assert!((capsule.a.distance(capsule.b) - 4.0 * 2f32.sqrt()).abs() < 1e-3);
+
+        let coplanar = [
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(2.0, 0.0, 0.0),
+            Point3::new(0.0, 0.0, 1.0),
+            Point3::new(2.0, 0.0, 1.0),
+        ];
+        let obb = fit_obb(&coplanar);
+        assert!(sorted(obb.half_extents)[2].abs() < 1e-4);
+    }
+}
@@ -0,0 +1,493 @@
+//! Position based fluids (Macklin and Müller 2013): the SPH density of
+//! every particle is held at the rest density by a constraint solved with
+//! the positions, which stays stable at frame-sized time steps where an
+//! explicit pressure would not.
+
+use std::f32::consts::PI;
+
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use super::contact::contacts;
+use super::shape::{Aabb, Shape, Sphere};
+use super::spatial_hash::SpatialHash;
+use super::transform::Transform;
+use super::world::World;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FluidSettings {
+    /// Distance between neighbors at rest, which sets the particle mass.
+    pub spacing: f32,
+    /// Support radius of the SPH kernels, a small multiple of `spacing`.
+    pub smoothing_radius: f32,
+    pub rest_density: f32,
+    /// Solver passes per step.
+    pub iterations: u32,
+    /// Softens the density constraints, so that particles with only a few
+    /// neighbors aren't thrown apart.
+    pub relaxation: f32,
+    /// XSPH viscosity: how much of the difference to the velocity of the
+    /// neighborhood is removed per step.
+    pub viscosity: f32,
+    /// Strength of the cohesion that pulls the surface together, as an
+    /// acceleration.
+    pub surface_tension: f32,
+    /// Radius particles collide with bodies and the bounds at.
+    pub particle_radius: f32,
+    /// A box the particles are kept inside.
+    pub bounds: Option<Aabb>,
+    /// Layers the particles are on, for picking the world's gravity
+    /// generators that pull on them.
+    pub layers: u32,
+}
+
+impl FluidSettings {
+    /// Water-like settings for particles `spacing` apart, as dense as rigid
+    /// bodies of unit density.
+    pub fn new(spacing: f32) -> Self {
+        Self {
+            spacing,
+            smoothing_radius: 2.0 * spacing,
+            rest_density: 1.0,
+            iterations: 4,
+            relaxation: 10.0,
+            viscosity: 0.05,
+            surface_tension: 20.0,
+            particle_radius: 0.5 * spacing,
+            bounds: None,
+            layers: 1,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FluidParticle {
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+}
+
+/// A liquid of particles, separate from the rigid body `World` but stepped
+/// against it: static bodies are boundaries, and dynamic ones also take the
+/// momentum of the particles they push aside.
+#[derive(Clone, Debug)]
+pub struct Fluid {
+    pub settings: FluidSettings,
+    pub particles: Vec<FluidParticle>,
+    hash: SpatialHash,
+    predicted: Vec<Vector3<f32>>,
+    neighbors: Vec<Vec<usize>>,
+    densities: Vec<f32>,
+    lambdas: Vec<f32>,
+}
+
+/// The SPH kernels for one support radius.
+struct Kernels {
+    h: f32,
+    poly6: f32,
+    spiky: f32,
+    cohesion: f32,
+}
+
+impl Kernels {
+    fn new(h: f32) -> Self {
+        Self {
+            h,
+            poly6: 315.0 / (64.0 * PI * h.powi(9)),
+            spiky: -45.0 / (PI * h.powi(6)),
+            cohesion: 32.0 / (PI * h.powi(9)),
+        }
+    }
+
+    fn poly6(&self, r2: f32) -> f32 {
+        let d = self.h * self.h - r2;
+        if d <= 0.0 {
+            return 0.0;
+        }
+        self.poly6 * d * d * d
+    }
+
+    /// Gradient of the spiky kernel with respect to the first particle, for
+    /// the offset `d` from the second to it.
+    fn spiky_gradient(&self, d: Vector3<f32>) -> Vector3<f32> {
+        let r = d.magnitude();
+        if r >= self.h || r <= f32::EPSILON {
+            return Vector3::zero();
+        }
+        d * (self.spiky * (self.h - r) * (self.h - r) / r)
+    }
+
+    /// Akinci's cohesion spline, attracting at mid range and repelling up
+    /// close.
+    fn cohesion(&self, r: f32) -> f32 {
+        let h = self.h;
+        if r > h || r <= 0.0 {
+            return 0.0;
+        }
+        let spline = (h - r).powi(3) * r.powi(3);
+        if 2.0 * r > h {
+            self.cohesion * spline
+        } else {
+            self.cohesion * (2.0 * spline - h.powi(6) / 64.0)
+        }
+    }
+}
+
+impl Fluid {
+    pub fn new(settings: FluidSettings) -> Self {
+        Self {
+            hash: SpatialHash::new(settings.smoothing_radius),
+            settings,
+            particles: Vec::new(),
+            predicted: Vec::new(),
+            neighbors: Vec::new(),
+            densities: Vec::new(),
+            lambdas: Vec::new(),
+        }
+    }
+
+    /// Fills `region` with particles at rest on a grid `spacing` apart.
+    /// Returns how many were added.
+    pub fn fill(&mut self, region: Aabb) -> usize {
+        let spacing = self.settings.spacing;
+        let size = region.max - region.min;
+        let count = |extent: f32| (extent / spacing).floor().max(0.0) as usize + 1;
+        let (nx, ny, nz) = (count(size.x), count(size.y), count(size.z));
+        let before = self.particles.len();
+        for x in 0..nx {
+            for y in 0..ny {
+                for z in 0..nz {
+                    let offset = Vector3::new(x as f32, y as f32, z as f32) * spacing;
+                    self.particles.push(FluidParticle {
+                        position: region.min.to_vec() + offset,
+                        velocity: Vector3::zero(),
+                    });
+                }
+            }
+        }
+        self.particles.len() - before
+    }
+
+    /// The mass that puts a particle inside a grid `spacing` apart at the
+    /// rest density.
+    pub fn particle_mass(&self) -> f32 {
+        let kernels = Kernels::new(self.settings.smoothing_radius);
+        let reach = (self.settings.smoothing_radius / self.settings.spacing).ceil() as i32;
+        let mut density = 0.0;
+        for x in -reach..=reach {
+            for y in -reach..=reach {
+                for z in -reach..=reach {
+                    let r2 = (x * x + y * y + z * z) as f32;
+                    density += kernels.poly6(r2 * self.settings.spacing * self.settings.spacing);
+                }
+            }
+        }
+        self.settings.rest_density / density
+    }
+
+    /// Advances the particles by `dt` under the world's gravity. Bodies are
+    /// not moved, only given the impulses of the particles they hold back.
+    pub fn step(&mut self, dt: f32, world: &mut World) {
+        if self.particles.is_empty() || dt <= 0.0 {
+            return;
+        }
+        let kernels = Kernels::new(self.settings.smoothing_radius);
+        let mass = self.particle_mass();
+        let layers = self.settings.layers;
+
+        self.predicted.clear();
+        for particle in &mut self.particles {
+            let gravity = world.gravity_at(Point3::from_vec(particle.position), layers);
+            particle.velocity += gravity * dt;
+            self.predicted
+                .push(particle.position + particle.velocity * dt);
+        }
+        self.find_neighbors();
+
+        // Bodies near each particle, with some slack for the solver moving it.
+        let margin = self.settings.particle_radius + self.settings.spacing;
+        let colliders = world
+            .bodies()
+            .map(|(handle, body)| (handle, body, body.aabb().expand(margin)))
+            .collect::<Vec<_>>();
+        let mut candidates = Vec::new();
+        for (i, &p) in self.predicted.iter().enumerate() {
+            for (c, (_, _, aabb)) in colliders.iter().enumerate() {
+                if aabb.contains(Point3::from_vec(p)) {
+                    candidates.push((i, c));
+                }
+            }
+        }
+
+        let particle = Shape::Sphere(Sphere {
+            center: Point3::origin(),
+            radius: self.settings.particle_radius,
+        });
+        let mut impulses = Vec::new();
+        for _ in 0..self.settings.iterations {
+            self.solve_densities(&kernels, mass);
+
+            for &(i, c) in &candidates {
+                let (handle, body, _) = colliders[c];
+                let transform = Transform::from_position(self.predicted[i]);
+                let deepest = contacts(&particle, &transform, body.shape(), &body.transform)
+                    .into_iter()
+                    .max_by(|a, b| a.depth.total_cmp(&b.depth));
+                if let Some(contact) = deepest.filter(|contact| contact.depth > 0.0) {
+                    let push = contact.normal * contact.depth;
+                    self.predicted[i] -= push;
+                    if !body.is_fixed() {
+                        impulses.push((handle, push, contact.point));
+                    }
+                }
+            }
+            if let Some(bounds) = self.settings.bounds {
+                let r = self.settings.particle_radius;
+                for p in &mut self.predicted {
+                    p.x = p.x.clamp(bounds.min.x + r, bounds.max.x - r);
+                    p.y = p.y.clamp(bounds.min.y + r, bounds.max.y - r);
+                    p.z = p.z.clamp(bounds.min.z + r, bounds.max.z - r);
+                }
+            }
+        }
+
+        for (particle, &predicted) in self.particles.iter_mut().zip(&self.predicted) {
+            particle.velocity = (predicted - particle.position) / dt;
+        }
+        self.compute_densities(&kernels, mass);
+        self.apply_viscosity_and_tension(&kernels, mass, dt);
+        for (particle, &predicted) in self.particles.iter_mut().zip(&self.predicted) {
+            particle.position = predicted;
+        }
+
+        // Whatever momentum a body took from a particle, it gets back.
+        for (handle, push, point) in impulses {
+            if let Some(body) = world.body_mut(handle) {
+                body.apply_impulse(push * (mass / dt), point);
+            }
+        }
+    }
+
+    fn find_neighbors(&mut self) {
+        let h = self.settings.smoothing_radius;
+        if self.hash.spacing() != h {
+            self.hash = SpatialHash::new(h);
+        }
+        self.hash.build(&self.predicted);
+        self.neighbors.resize_with(self.predicted.len(), Vec::new);
+        for (i, neighbors) in self.neighbors.iter_mut().enumerate() {
+            neighbors.clear();
+            let p = self.predicted[i];
+            self.hash.query(p, h, |j| {
+                if j != i && (self.predicted[j] - p).magnitude2() < h * h {
+                    neighbors.push(j);
+                }
+            });
+        }
+    }
+
+    fn compute_densities(&mut self, kernels: &Kernels, mass: f32) {
+        self.densities.clear();
+        for (i, neighbors) in self.neighbors.iter().enumerate() {
+            let p = self.predicted[i];
+            let density = neighbors
+                .iter()
+                .map(|&j| kernels.poly6((self.predicted[j] - p).magnitude2()))
+                .sum::<f32>()
+                + kernels.poly6(0.0);
+            self.densities.push(density * mass);
+        }
+    }
+
+    /// One Jacobi pass over the density constraints. They only push
+    /// particles apart, so unlike two-sided ones they don't clump particles
+    /// at the surface; holding it together is left to the surface tension.
+    fn solve_densities(&mut self, kernels: &Kernels, mass: f32) {
+        self.compute_densities(kernels, mass);
+        let rest_density = self.settings.rest_density;
+        let scale = mass / rest_density;
+
+        self.lambdas.clear();
+        for (i, neighbors) in self.neighbors.iter().enumerate() {
+            let constraint = (self.densities[i] / rest_density - 1.0).max(0.0);
+            if constraint == 0.0 {
+                self.lambdas.push(0.0);
+                continue;
+            }
+            let p = self.predicted[i];
+            let mut sum = Vector3::zero();
+            let mut sum2 = 0.0;
+            for &j in neighbors {
+                let gradient = kernels.spiky_gradient(p - self.predicted[j]) * scale;
+                sum += gradient;
+                sum2 += gradient.magnitude2();
+            }
+            self.lambdas
+                .push(-constraint / (sum2 + sum.magnitude2() + self.settings.relaxation));
+        }
+
+        let corrections = self
+            .neighbors
+            .iter()
+            .enumerate()
+            .map(|(i, neighbors)| {
+                let p = self.predicted[i];
+                neighbors
+                    .iter()
+                    .map(|&j| {
+                        kernels.spiky_gradient(p - self.predicted[j])
+                            * (self.lambdas[i] + self.lambdas[j])
+                    })
+                    .sum::<Vector3<f32>>()
+                    * scale
+            })
+            .collect::<Vec<_>>();
+        for (p, correction) in self.predicted.iter_mut().zip(corrections) {
+            *p += correction;
+        }
+    }
+
+    /// Blends each velocity toward its neighbors' (XSPH), then adds Akinci's
+    /// cohesion.
+    fn apply_viscosity_and_tension(&mut self, kernels: &Kernels, mass: f32, dt: f32) {
+        let rest_density = self.settings.rest_density;
+        let volume = mass / rest_density;
+        let velocities = self
+            .neighbors
+            .iter()
+            .enumerate()
+            .map(|(i, neighbors)| {
+                let (p, v) = (self.predicted[i], self.particles[i].velocity);
+                let mut blend = Vector3::zero();
+                let mut cohesion = Vector3::zero();
+                for &j in neighbors {
+                    let d = p - self.predicted[j];
+                    let r = d.magnitude();
+                    let weight = mass / self.densities[j] * kernels.poly6(r * r);
+                    blend += (self.particles[j].velocity - v) * weight;
+                    if r > f32::EPSILON {
+                        // Stronger where either particle lacks neighbors,
+                        // which is at the surface.
+                        let k = 2.0 * rest_density / (self.densities[i] + self.densities[j]);
+                        cohesion -= d * (k * volume * kernels.cohesion(r) / r);
+                    }
+                }
+                v + blend * self.settings.viscosity
+                    + cohesion * (self.settings.surface_tension * dt)
+            })
+            .collect::<Vec<_>>();
+        for (particle, velocity) in self.particles.iter_mut().zip(velocities) {
+            particle.velocity = velocity;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::body::RigidBody;
+    use crate::physics::shape::Obb;
+
+    fn block(min: [f32; 3], max: [f32; 3]) -> Aabb {
+        Aabb {
+            min: Point3::from(min),
+            max: Point3::from(max),
+        }
+    }
+
+    #[test]
+    fn particle_mass_gives_the_rest_density_on_a_grid() {
+        let mut settings = FluidSettings::new(0.1);
+        settings.rest_density = 2.5;
+        let mut fluid = Fluid::new(settings);
+        assert_eq!(fluid.fill(block([0.0; 3], [0.8; 3])), 9 * 9 * 9);
+
+        fluid.predicted = fluid.particles.iter().map(|p| p.position).collect();
+        fluid.find_neighbors();
+        let kernels = Kernels::new(fluid.settings.smoothing_radius);
+        fluid.compute_densities(&kernels, fluid.particle_mass());
+
+        // The middle of the 9³ grid has a full neighborhood.
+        let center = fluid
+            .particles
+            .iter()
+            .position(|p| (p.position - Vector3::from_value(0.4)).magnitude() < 1e-4)
+            .unwrap();
+        assert!(
+            (fluid.densities[center] - 2.5).abs() < 1e-3,
+            "{}",
+            fluid.densities[center]
+        );
+    }
+
+    #[test]
+    fn a_filled_block_settles_inside_its_bounds() {
+        let bounds = block([0.0; 3], [1.0, 2.0, 1.0]);
+        let mut settings = FluidSettings::new(0.1);
+        settings.bounds = Some(bounds);
+        let mut fluid = Fluid::new(settings);
+        let count = fluid.fill(block([0.05; 3], [0.55; 3]));
+        let mut world = World::new();
+
+        for _ in 0..180 {
+            fluid.step(1.0 / 60.0, &mut world);
+        }
+
+        assert_eq!(fluid.particles.len(), count);
+        let r = fluid.settings.particle_radius;
+        let mut speed = 0.0;
+        for particle in &fluid.particles {
+            let p = particle.position;
+            assert!(
+                p.x.is_finite() && p.y.is_finite() && p.z.is_finite(),
+                "{:?}",
+                p
+            );
+            assert!(
+                bounds.expand(-r + 1e-4).contains(Point3::from_vec(p)),
+                "{:?}",
+                p
+            );
+            // The surface keeps rippling, but nothing is thrown around.
+            assert!(
+                particle.velocity.magnitude() < 2.0,
+                "{:?}",
+                particle.velocity
+            );
+            speed += particle.velocity.magnitude() / count as f32;
+        }
+        assert!(speed < 0.25, "{}", speed);
+        // Spread out over the floor from a block with its center at 0.3.
+        let mean_height = fluid.particles.iter().map(|p| p.position.y).sum::<f32>() / count as f32;
+        assert!(mean_height < 0.15, "{}", mean_height);
+    }
+
+    #[test]
+    fn particles_push_dynamic_bodies_they_hit() {
+        let mut world = World::new();
+        let cube = Shape::Cuboid(Obb {
+            center: Point3::origin(),
+            half_extents: Vector3::from_value(0.5),
+            rotation: cgmath::Quaternion::one(),
+        });
+        let handle = world.add_body(RigidBody::dynamic(cube, 1.0, Transform::identity()));
+        // A wall of particles just touching the cube's left face, moving
+        // into it.
+        let mut fluid = Fluid::new(FluidSettings::new(0.1));
+        fluid.fill(block([-0.55, -0.3, -0.3], [-0.55, 0.3, 0.3]));
+        for particle in &mut fluid.particles {
+            particle.velocity = Vector3::new(3.0, 0.0, 0.0);
+        }
+        let momentum = |fluid: &Fluid| {
+            fluid.particles.iter().map(|p| p.velocity.x).sum::<f32>() * fluid.particle_mass()
+        };
+        let before = momentum(&fluid);
+
+        fluid.step(1.0 / 60.0, &mut world);
+
+        let body = world.body(handle).unwrap();
+        let taken = body.linear_velocity.x * body.mass();
+        assert!(taken > 0.0, "{:?}", body.linear_velocity);
+        let lost = before - momentum(&fluid);
+        assert!((taken - lost).abs() < 0.1 * lost, "{} != {}", taken, lost);
+    }
+}
@@ -0,0 +1,594 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use super::body::RigidBody;
+use super::shape::{Aabb, Sphere};
+
+/// What a generator knows about the step it is applied in.
+#[derive(Copy, Clone, Debug)]
+pub struct ForceContext {
+    pub dt: f32,
+    /// Simulation time at the start of the step.
+    pub time: f32,
+    /// Gravity at the body being pushed, from every generator in scope
+    /// there. Zero in [`ForceGenerator::end_step`].
+    pub gravity: Vector3<f32>,
+}
+
+/// Something that pushes bodies around, applied by the `World` at the start
+/// of every step to each dynamic body in its [`ForceScope`].
+///
+/// Generators add forces with [`RigidBody::add_force`] and friends, which are
+/// turned into velocity once all generators ran, or apply impulses directly.
+pub trait ForceGenerator {
+    fn apply(&mut self, body: &mut RigidBody, context: &ForceContext);
+
+    /// Called once per step after every body in scope had its turn.
+    fn end_step(&mut self, _context: &ForceContext) {}
+
+    /// Finished generators are removed from the world after the step.
+    fn is_finished(&self) -> bool {
+        false
+    }
+
+    /// Whether sleeping bodies are passed to the generator too. They are
+    /// skipped by default, so a steady force doesn't keep them awake.
+    fn wakes_bodies(&self) -> bool {
+        false
+    }
+
+    /// The part of the gravity field at `point` this generator stands for,
+    /// if any. [`World::gravity_at`] sums it over the generators in scope.
+    ///
+    /// [`World::gravity_at`]: super::World::gravity_at
+    fn gravity_at(&self, _point: Point3<f32>) -> Vector3<f32> {
+        Vector3::zero()
+    }
+}
+
+/// A volume bodies are in when their center of mass is.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Region {
+    Aabb(Aabb),
+    Sphere(Sphere),
+}
+
+impl Region {
+    pub fn contains(&self, p: Point3<f32>) -> bool {
+        match self {
+            Region::Aabb(aabb) => aabb.contains(p),
+            Region::Sphere(sphere) => sphere.center.distance2(p) <= sphere.radius * sphere.radius,
+        }
+    }
+}
+
+/// Which bodies a generator affects: those on any of `layers` and, with a
+/// `region`, only while inside it.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ForceScope {
+    pub layers: u32,
+    pub region: Option<Region>,
+}
+
+impl Default for ForceScope {
+    fn default() -> Self {
+        Self::all()
+    }
+}
+
+impl ForceScope {
+    pub fn all() -> Self {
+        Self {
+            layers: u32::MAX,
+            region: None,
+        }
+    }
+
+    pub fn with_layers(self, layers: u32) -> Self {
+        Self { layers, ..self }
+    }
+
+    pub fn within(self, region: Region) -> Self {
+        Self {
+            region: Some(region),
+            ..self
+        }
+    }
+
+    pub fn contains(&self, body: &RigidBody) -> bool {
+        self.contains_point(Point3::from_vec(body.transform.position), body.layers)
+    }
+
+    /// Whether something at `point` on `layers` is in scope.
+    pub fn contains_point(&self, point: Point3<f32>, layers: u32) -> bool {
+        layers & self.layers != 0 && self.region.is_none_or(|r| r.contains(point))
+    }
+}
+
+/// Constant acceleration. Every `World` starts with one pulling everything
+/// down at 9.81 m/s².
+#[derive(Copy, Clone, Debug)]
+pub struct Gravity {
+    pub acceleration: Vector3<f32>,
+}
+
+impl ForceGenerator for Gravity {
+    fn apply(&mut self, body: &mut RigidBody, _context: &ForceContext) {
+        body.add_force(self.acceleration * body.mass());
+    }
+
+    fn gravity_at(&self, _point: Point3<f32>) -> Vector3<f32> {
+        self.acceleration
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Falloff {
+    /// The same pull at any distance.
+    Constant,
+    /// Pull proportional to `1 / distance²`, like a point mass.
+    InverseSquare,
+}
+
+/// Pulls bodies toward a point. A negative strength pushes them away.
+#[derive(Copy, Clone, Debug)]
+pub struct Attractor {
+    pub center: Point3<f32>,
+    /// Acceleration toward the center, at a distance of one for
+    /// `Falloff::InverseSquare` (the gravitational parameter `G * M`).
+    pub strength: f32,
+    pub falloff: Falloff,
+    /// Distances are clamped to this, so bodies passing through the center
+    /// don't get flung out.
+    pub min_distance: f32,
+}
+
+impl Attractor {
+    pub fn point(center: Point3<f32>, strength: f32) -> Self {
+        Self {
+            center,
+            strength,
+            falloff: Falloff::Constant,
+            min_distance: 0.1,
+        }
+    }
+
+    pub fn inverse_square(center: Point3<f32>, strength: f32) -> Self {
+        Self {
+            falloff: Falloff::InverseSquare,
+            ..Self::point(center, strength)
+        }
+    }
+}
+
+impl ForceGenerator for Attractor {
+    fn apply(&mut self, body: &mut RigidBody, _context: &ForceContext) {
+        let acceleration = self.gravity_at(Point3::from_vec(body.transform.position));
+        body.add_force(acceleration * body.mass());
+    }
+
+    fn gravity_at(&self, point: Point3<f32>) -> Vector3<f32> {
+        let offset = self.center - point;
+        let distance = offset.magnitude();
+        if distance <= f32::EPSILON {
+            return Vector3::zero();
+        }
+        let acceleration = match self.falloff {
+            Falloff::Constant => self.strength,
+            Falloff::InverseSquare => self.strength / distance.max(self.min_distance).powi(2),
+        };
+        offset / distance * acceleration
+    }
+}
+
+/// Resistance of a surrounding medium: `-(linear + quadratic * |v|) * v`.
+#[derive(Copy, Clone, Debug)]
+pub struct Drag {
+    pub linear: f32,
+    pub quadratic: f32,
+    pub angular: f32,
+}
+
+impl ForceGenerator for Drag {
+    fn apply(&mut self, body: &mut RigidBody, _context: &ForceContext) {
+        let v = body.linear_velocity;
+        body.add_force(-v * (self.linear + self.quadratic * v.magnitude()));
+        body.add_torque(-body.angular_velocity * self.angular);
+    }
+}
+
+/// Floats bodies in water below a plane, pushing against the world's gravity.
+///
+/// The submerged volume is estimated per octant of the body's bounding box,
+/// so a tilted body gets a righting torque.
+#[derive(Copy, Clone, Debug)]
+pub struct Buoyancy {
+    /// Up direction of the water surface.
+    pub normal: Vector3<f32>,
+    /// Height of the surface along `normal`.
+    pub level: f32,
+    /// Density of the water, in the same units as the body densities.
+    pub density: f32,
+    /// Velocity of the water, for drag.
+    pub current: Vector3<f32>,
+    /// Drag on the submerged part of the body.
+    pub linear_drag: f32,
+    pub angular_drag: f32,
+}
+
+impl Buoyancy {
+    pub fn new(level: f32, density: f32) -> Self {
+        Self {
+            normal: Vector3::unit_y(),
+            level,
+            density,
+            current: Vector3::zero(),
+            linear_drag: 1.0,
+            angular_drag: 0.5,
+        }
+    }
+}
+
+impl ForceGenerator for Buoyancy {
+    fn apply(&mut self, body: &mut RigidBody, context: &ForceContext) {
+        let aabb = body.shape().local_aabb();
+        let h = aabb.half_extents() * 0.5;
+        let center = aabb.center();
+        let rotation = body.transform.rotation;
+        // Half the extent of an octant along the normal.
+        let reach = (rotation * Vector3::unit_x()).dot(self.normal).abs() * h.x
+            + (rotation * Vector3::unit_y()).dot(self.normal).abs() * h.y
+            + (rotation * Vector3::unit_z()).dot(self.normal).abs() * h.z;
+        let lift = -context.gravity * (self.density * body.shape().volume() / 8.0);
+
+        let mut submerged = 0.0;
+        for i in 0..8 {
+            let corner = Vector3::new(
+                if i & 1 == 0 { -h.x } else { h.x },
+                if i & 2 == 0 { -h.y } else { h.y },
+                if i & 4 == 0 { -h.z } else { h.z },
+            );
+            let point = body.transform.transform_point(center + corner);
+            let depth = self.level - self.normal.dot(point.to_vec());
+            let fraction = if reach > 0.0 {
+                ((depth + reach) / (2.0 * reach)).clamp(0.0, 1.0)
+            } else if depth > 0.0 {
+                1.0
+            } else {
+                0.0
+            };
+            if fraction > 0.0 {
+                body.add_force_at(lift * fraction, point);
+                submerged += fraction / 8.0;
+            }
+        }
+
+        if submerged > 0.0 {
+            let relative = body.linear_velocity - self.current;
+            body.add_force(-relative * (self.linear_drag * submerged));
+            body.add_torque(-body.angular_velocity * (self.angular_drag * submerged));
+        }
+    }
+}
+
+/// Air moving at `velocity` plus smoothly varying gusts, dragging bodies
+/// along in proportion to their cross section.
+#[derive(Copy, Clone, Debug)]
+pub struct Wind {
+    pub velocity: Vector3<f32>,
+    /// Largest gust speed added on each axis.
+    pub turbulence: f32,
+    /// How quickly the gusts change, in cycles per second.
+    pub frequency: f32,
+    /// Rough size of a gust in meters.
+    pub gust_size: f32,
+    /// Half the air density times the drag coefficient.
+    pub drag: f32,
+    pub seed: u32,
+}
+
+impl Wind {
+    pub fn new(velocity: Vector3<f32>) -> Self {
+        Self {
+            velocity,
+            turbulence: 0.0,
+            frequency: 0.5,
+            gust_size: 10.0,
+            drag: 0.6,
+            seed: 0,
+        }
+    }
+
+    pub fn velocity_at(&self, p: Point3<f32>, time: f32) -> Vector3<f32> {
+        if self.turbulence == 0.0 {
+            return self.velocity;
+        }
+        let p = p.to_vec() / self.gust_size + Vector3::from_value(time * self.frequency);
+        let gust = Vector3::new(
+            value_noise(p, self.seed),
+            value_noise(p, self.seed.wrapping_add(1)),
+            value_noise(p, self.seed.wrapping_add(2)),
+        );
+        self.velocity + gust * self.turbulence
+    }
+}
+
+impl ForceGenerator for Wind {
+    fn apply(&mut self, body: &mut RigidBody, context: &ForceContext) {
+        let position = Point3::from_vec(body.transform.position);
+        let relative = self.velocity_at(position, context.time) - body.linear_velocity;
+        let speed = relative.magnitude();
+        if speed <= f32::EPSILON {
+            return;
+        }
+        // Cross section of the bounding box seen from the wind direction.
+        let d = relative / speed;
+        let aabb = body.aabb();
+        let e = aabb.max - aabb.min;
+        let area = d.x.abs() * e.y * e.z + d.y.abs() * e.x * e.z + d.z.abs() * e.x * e.y;
+        body.add_force(relative * (self.drag * area * speed));
+    }
+}
+
+/// Swirls bodies around an axis through `center`, like a Rankine vortex:
+/// solid rotation inside `radius`, falling off with distance outside.
+#[derive(Copy, Clone, Debug)]
+pub struct Vortex {
+    pub center: Point3<f32>,
+    /// Unit direction of the axis; bodies turn counterclockwise around it.
+    pub axis: Vector3<f32>,
+    pub radius: f32,
+    /// Tangential acceleration at `radius`.
+    pub swirl: f32,
+    /// Acceleration toward the axis at `radius`.
+    pub pull: f32,
+    /// Acceleration along the axis at `radius`.
+    pub lift: f32,
+}
+
+impl ForceGenerator for Vortex {
+    fn apply(&mut self, body: &mut RigidBody, _context: &ForceContext) {
+        let offset = body.transform.position - self.center.to_vec();
+        let radial = offset - self.axis * offset.dot(self.axis);
+        let r = radial.magnitude();
+        if r <= f32::EPSILON {
+            return;
+        }
+        let outward = radial / r;
+        let scale = if r < self.radius {
+            r / self.radius
+        } else {
+            self.radius / r
+        };
+        let acceleration =
+            self.axis.cross(outward) * self.swirl - outward * self.pull + self.axis * self.lift;
+        body.add_force(acceleration * (scale * body.mass()));
+    }
+}
+
+/// A single outward impulse from `center`, fading linearly to nothing at
+/// `radius`. Wakes the bodies it hits and removes itself after one step.
+#[derive(Copy, Clone, Debug)]
+pub struct Explosion {
+    pub center: Point3<f32>,
+    pub radius: f32,
+    /// Impulse on a body right at the center.
+    pub impulse: f32,
+    fired: bool,
+}
+
+impl Explosion {
+    pub fn new(center: Point3<f32>, radius: f32, impulse: f32) -> Self {
+        Self {
+            center,
+            radius,
+            impulse,
+            fired: false,
+        }
+    }
+}
+
+impl ForceGenerator for Explosion {
+    fn apply(&mut self, body: &mut RigidBody, _context: &ForceContext) {
+        if self.fired {
+            return;
+        }
+        let offset = body.transform.position - self.center.to_vec();
+        let distance = offset.magnitude();
+        if distance >= self.radius {
+            return;
+        }
+        let direction = if distance > f32::EPSILON {
+            offset / distance
+        } else {
+            Vector3::unit_y()
+        };
+        // Hitting the side facing the blast makes bodies tumble.
+        let aabb = body.aabb();
+        let point = Point3::new(
+            self.center.x.clamp(aabb.min.x, aabb.max.x),
+            self.center.y.clamp(aabb.min.y, aabb.max.y),
+            self.center.z.clamp(aabb.min.z, aabb.max.z),
+        );
+        let falloff = 1.0 - distance / self.radius;
+        body.apply_impulse(direction * (self.impulse * falloff), point);
+    }
+
+    fn end_step(&mut self, _context: &ForceContext) {
+        self.fired = true;
+    }
+
+    fn is_finished(&self) -> bool {
+        self.fired
+    }
+
+    fn wakes_bodies(&self) -> bool {
+        true
+    }
+}
+
+/// Smooth noise in `[-1, 1]`, interpolated between hashed lattice values.
+fn value_noise(p: Vector3<f32>, seed: u32) -> f32 {
+    let cell = p.map(f32::floor);
+    let f = p - cell;
+    let s = f.map(|t| t * t * (3.0 - 2.0 * t));
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let corner = |dx, dy, dz| lattice_value(x + dx, y + dy, z + dz, seed);
+    let plane = |dz| {
+        lerp(
+            lerp(corner(0, 0, dz), corner(1, 0, dz), s.x),
+            lerp(corner(0, 1, dz), corner(1, 1, dz), s.x),
+            s.y,
+        )
+    };
+    lerp(plane(0), plane(1), s.z)
+}
+
+fn lattice_value(x: i32, y: i32, z: i32, seed: u32) -> f32 {
+    let mut h = seed
+        ^ (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 13;
+    h = h.wrapping_mul(0x5bd1_e995);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::shape::{Obb, Shape};
+    use crate::physics::Transform;
+
+    /// A unit cube with a mass of 2 at `position`.
+    fn cube(position: Vector3<f32>) -> RigidBody {
+        let shape = Shape::Cuboid(Obb {
+            center: Point3::origin(),
+            half_extents: Vector3::from_value(0.5),
+            rotation: cgmath::Quaternion::one(),
+        });
+        RigidBody::dynamic(shape, 2.0, Transform::from_position(position))
+    }
+
+    /// The force `generator` puts on `body` in one step.
+    fn force_on(generator: &mut impl ForceGenerator, body: &mut RigidBody) -> Vector3<f32> {
+        let context = ForceContext {
+            dt: 1.0,
+            time: 0.0,
+            gravity: Vector3::new(0.0, -10.0, 0.0),
+        };
+        generator.apply(body, &context);
+        let before = body.linear_velocity;
+        body.apply_forces(context.dt);
+        (body.linear_velocity - before) * body.mass()
+    }
+
+    /// Equal to within a relative error of 1e-4.
+    fn assert_close(actual: Vector3<f32>, expected: Vector3<f32>) {
+        assert!(
+            (actual - expected).magnitude() < 1e-4 * expected.magnitude().max(1.0),
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn inverse_square_attraction_falls_off_with_distance() {
+        let mut attractor = Attractor::inverse_square(Point3::origin(), 8.0);
+        // Acceleration 8 / d² toward the center, on a mass of 2.
+        let mut near = cube(Vector3::new(2.0, 0.0, 0.0));
+        assert_close(
+            force_on(&mut attractor, &mut near),
+            Vector3::new(-4.0, 0.0, 0.0),
+        );
+        let mut far = cube(Vector3::new(0.0, 0.0, -4.0));
+        assert_close(
+            force_on(&mut attractor, &mut far),
+            Vector3::new(0.0, 0.0, 1.0),
+        );
+        // Clamped to the minimum distance of 0.1 near the center.
+        let mut inside = cube(Vector3::new(0.0, 0.05, 0.0));
+        assert_close(
+            force_on(&mut attractor, &mut inside),
+            Vector3::new(0.0, -1600.0, 0.0),
+        );
+
+        let mut constant = Attractor::point(Point3::origin(), 8.0);
+        assert_close(
+            force_on(&mut constant, &mut far),
+            Vector3::new(0.0, 0.0, 16.0),
+        );
+    }
+
+    #[test]
+    fn linear_drag_scales_with_speed_and_quadratic_with_its_square() {
+        let mut linear = Drag {
+            linear: 0.5,
+            quadratic: 0.0,
+            angular: 0.0,
+        };
+        let mut quadratic = Drag {
+            linear: 0.0,
+            quadratic: 0.5,
+            angular: 0.0,
+        };
+        for speed in [1.0, 2.0, 4.0] {
+            let velocity = Vector3::new(0.0, 0.0, speed);
+            let mut body = cube(Vector3::zero());
+            body.linear_velocity = velocity;
+            assert_close(force_on(&mut linear, &mut body), -velocity * 0.5);
+            body.linear_velocity = velocity;
+            assert_close(force_on(&mut quadratic, &mut body), -velocity * 0.5 * speed);
+        }
+    }
+
+    #[test]
+    fn buoyancy_lifts_by_the_submerged_fraction() {
+        // Water of density 3 displaced by a unit cube, against a gravity of
+        // 10, lifts with up to 30.
+        let mut water = Buoyancy::new(0.0, 3.0);
+        for (height, fraction) in [
+            (-2.0, 1.0),
+            (-0.25, 0.75),
+            (0.0, 0.5),
+            (0.25, 0.25),
+            (2.0, 0.0),
+        ] {
+            let mut body = cube(Vector3::new(0.0, height, 0.0));
+            let force = force_on(&mut water, &mut body);
+            assert_close(force, Vector3::new(0.0, 30.0 * fraction, 0.0));
+            assert!(body.angular_velocity.magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn scopes_filter_by_layer_and_region() {
+        let mut body = cube(Vector3::new(1.0, 0.0, 0.0));
+        body.layers = 0b10;
+        assert!(ForceScope::all().contains(&body));
+        assert!(!ForceScope::all().with_layers(0b01).contains(&body));
+        assert!(ForceScope::all().with_layers(0b11).contains(&body));
+
+        let ball = Region::Sphere(Sphere {
+            center: Point3::origin(),
+            radius: 2.0,
+        });
+        let slab = Region::Aabb(Aabb {
+            min: Point3::new(2.0, -1.0, -1.0),
+            max: Point3::new(3.0, 1.0, 1.0),
+        });
+        assert!(ForceScope::all().within(ball).contains(&body));
+        assert!(!ForceScope::all().within(slab).contains(&body));
+        assert!(!ForceScope::all()
+            .with_layers(0b01)
+            .within(ball)
+            .contains(&body));
+
+        body.transform.position.x = 2.5;
+        assert!(!ForceScope::all().within(ball).contains(&body));
+        assert!(ForceScope::all().within(slab).contains(&body));
+    }
+}
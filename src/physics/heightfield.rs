@@ -0,0 +1,296 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use super::shape::Aabb;
+use super::triangle::{Edge, Triangle};
+
+/// Static terrain collider sampled on a regular grid in the XZ plane and
+/// centered on the origin. Heights are in `[0, 1]` and get multiplied by
+/// `scale.y`; `scale.x` and `scale.z` are the spacing between samples.
+#[derive(Clone, Debug)]
+pub struct Heightfield {
+    heights: Vec<f32>,
+    rows: usize,
+    cols: usize,
+    scale: Vector3<f32>,
+}
+
+impl Heightfield {
+    /// `heights` is laid out row by row, with rows running along Z.
+    ///
+    /// Panics if there are fewer than two rows or columns.
+    pub fn new(heights: Vec<f32>, rows: usize, cols: usize, scale: Vector3<f32>) -> Self {
+        assert!(
+            rows >= 2 && cols >= 2,
+            "heightfield needs at least 2x2 samples"
+        );
+        assert_eq!(heights.len(), rows * cols);
+        Self {
+            heights,
+            rows,
+            cols,
+            scale,
+        }
+    }
+
+    /// Uses the luminance of every pixel as a height sample. Returns `None`
+    /// for images less than two pixels wide or high.
+    pub fn from_image(img: &image::DynamicImage, scale: Vector3<f32>) -> Option<Self> {
+        let luma = img.to_luma16();
+        let (cols, rows) = luma.dimensions();
+        if rows < 2 || cols < 2 {
+            return None;
+        }
+        let heights = luma
+            .pixels()
+            .map(|p| p.0[0] as f32 / u16::MAX as f32)
+            .collect();
+        Some(Self::new(heights, rows as usize, cols as usize, scale))
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    pub fn scale(&self) -> Vector3<f32> {
+        self.scale
+    }
+
+    /// The same samples with the spacing and height range stretched.
+    /// Mirroring is ignored, the terrain always faces up.
+    pub fn scaled(&self, scale: Vector3<f32>) -> Self {
+        Self {
+            heights: self.heights.clone(),
+            rows: self.rows,
+            cols: self.cols,
+            scale: Vector3::new(
+                self.scale.x * scale.x.abs(),
+                self.scale.y * scale.y.abs(),
+                self.scale.z * scale.z.abs(),
+            ),
+        }
+    }
+
+    fn origin(&self) -> Point3<f32> {
+        Point3::new(
+            -0.5 * (self.cols - 1) as f32 * self.scale.x,
+            0.0,
+            -0.5 * (self.rows - 1) as f32 * self.scale.z,
+        )
+    }
+
+    pub fn point(&self, row: usize, col: usize) -> Point3<f32> {
+        self.origin()
+            + Vector3::new(
+                col as f32 * self.scale.x,
+                self.heights[row * self.cols + col] * self.scale.y,
+                row as f32 * self.scale.z,
+            )
+    }
+
+    /// Interpolated terrain height below `(x, z)`, `None` outside the grid.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let origin = self.origin();
+        let fx = (x - origin.x) / self.scale.x;
+        let fz = (z - origin.z) / self.scale.z;
+        if fx < 0.0 || fz < 0.0 || fx > (self.cols - 1) as f32 || fz > (self.rows - 1) as f32 {
+            return None;
+        }
+        let col = (fx as usize).min(self.cols - 2);
+        let row = (fz as usize).min(self.rows - 2);
+        let (u, v) = (fx - col as f32, fz - row as f32);
+        // Follow the same diagonal split as `cell_triangles`.
+        let h = |r, c| self.heights[r * self.cols + c];
+        let height = if v >= u {
+            h(row, col)
+                + (h(row + 1, col + 1) - h(row + 1, col)) * u
+                + (h(row + 1, col) - h(row, col)) * v
+        } else {
+            h(row, col)
+                + (h(row, col + 1) - h(row, col)) * u
+                + (h(row + 1, col + 1) - h(row, col + 1)) * v
+        };
+        Some(height * self.scale.y)
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let (min, max) = self
+            .heights
+            .iter()
+            .fold((f32::MAX, f32::MIN), |(lo, hi), &h| (lo.min(h), hi.max(h)));
+        let origin = self.origin();
+        Aabb {
+            min: Point3::new(origin.x, min * self.scale.y, origin.z),
+            max: Point3::new(-origin.x, max * self.scale.y, -origin.z),
+        }
+    }
+
+    /// Corners of the two triangles covering a grid cell, without adjacency.
+    fn corners(&self, row: usize, col: usize, half: usize) -> [Point3<f32>; 3] {
+        let p00 = self.point(row, col);
+        let p01 = self.point(row + 1, col);
+        let p11 = self.point(row + 1, col + 1);
+        let p10 = self.point(row, col + 1);
+        if half == 0 {
+            [p00, p01, p11]
+        } else {
+            [p00, p11, p10]
+        }
+    }
+
+    fn neighbor(&self, row: isize, col: isize, half: usize) -> Option<[Point3<f32>; 3]> {
+        let in_grid = row >= 0
+            && col >= 0
+            && (row as usize) < self.rows - 1
+            && (col as usize) < self.cols - 1;
+        in_grid.then(|| self.corners(row as usize, col as usize, half))
+    }
+
+    /// Both triangles of a cell, with their edges classified against the
+    /// neighboring cells.
+    pub fn cell_triangles(&self, row: usize, col: usize) -> [Triangle; 2] {
+        let (r, c) = (row as isize, col as isize);
+        let lower = self.corners(row, col, 0);
+        let upper = self.corners(row, col, 1);
+        let classify = |tri: [Point3<f32>; 3], neighbor| {
+            Edge::classify(super::triangle::face_normal(tri), tri[0], neighbor)
+        };
+        [
+            Triangle::new(
+                lower,
+                [
+                    classify(lower, self.neighbor(r, c - 1, 1)),
+                    classify(lower, self.neighbor(r + 1, c, 1)),
+                    classify(lower, Some(upper)),
+                ],
+            ),
+            Triangle::new(
+                upper,
+                [
+                    classify(upper, Some(lower)),
+                    classify(upper, self.neighbor(r, c + 1, 0)),
+                    classify(upper, self.neighbor(r - 1, c, 0)),
+                ],
+            ),
+        ]
+    }
+
+    /// Calls `f` for every triangle in the cells overlapping `aabb` on XZ.
+    pub fn query(&self, aabb: &Aabb, mut f: impl FnMut(Triangle)) {
+        let bounds = self.aabb();
+        if !bounds.intersects(aabb) {
+            return;
+        }
+        let origin = self.origin();
+        let cell =
+            |v: f32, o: f32, s: f32, n: usize| (((v - o) / s).floor().max(0.0) as usize).min(n - 2);
+        let col0 = cell(aabb.min.x, origin.x, self.scale.x, self.cols);
+        let col1 = cell(aabb.max.x, origin.x, self.scale.x, self.cols);
+        let row0 = cell(aabb.min.z, origin.z, self.scale.z, self.rows);
+        let row1 = cell(aabb.max.z, origin.z, self.scale.z, self.rows);
+        for row in row0..=row1 {
+            for col in col0..=col1 {
+                for tri in self.cell_triangles(row, col) {
+                    let tri_aabb = Aabb::from_points(&tri.vertices);
+                    if tri_aabb.intersects(aabb) {
+                        f(tri);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Smooth per-sample normal from central differences.
+    pub fn normal(&self, row: usize, col: usize) -> Vector3<f32> {
+        let h = |r: usize, c: usize| self.heights[r * self.cols + c] * self.scale.y;
+        let (c0, c1) = (col.saturating_sub(1), (col + 1).min(self.cols - 1));
+        let (r0, r1) = (row.saturating_sub(1), (row + 1).min(self.rows - 1));
+        let dx = (h(row, c1) - h(row, c0)) / ((c1 - c0) as f32 * self.scale.x);
+        let dz = (h(r1, col) - h(r0, col)) / ((r1 - r0) as f32 * self.scale.z);
+        Vector3::new(-dx, 1.0, -dz).normalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 5x4 grid rising along X with a slope of one half.
+    fn ramp() -> Heightfield {
+        let (rows, cols) = (4, 5);
+        let heights = (0..rows * cols)
+            .map(|i| (i % cols) as f32 / (cols - 1) as f32)
+            .collect();
+        Heightfield::new(heights, rows, cols, Vector3::new(1.0, 2.0, 1.0))
+    }
+
+    #[test]
+    fn heights_follow_the_samples() {
+        let ramp = ramp();
+        assert_eq!(ramp.point(0, 0), Point3::new(-2.0, 0.0, -1.5));
+        assert_eq!(ramp.point(3, 4), Point3::new(2.0, 2.0, 1.5));
+        for (x, z) in [(-2.0, -1.5), (0.0, 0.0), (0.3, 1.2), (1.9, -0.7)] {
+            let height = ramp.height_at(x, z).unwrap();
+            assert!((height - (x + 2.0) * 0.5).abs() < 1e-5, "({x}, {z})");
+        }
+        assert!(ramp.height_at(2.1, 0.0).is_none());
+        assert!(ramp.height_at(0.0, -1.6).is_none());
+    }
+
+    #[test]
+    fn from_image_needs_two_samples_each_way() {
+        let scale = Vector3::new(1.0, 1.0, 1.0);
+        let line = image::DynamicImage::new_luma8(5, 1);
+        assert!(Heightfield::from_image(&line, scale).is_none());
+
+        let mut img = image::GrayImage::new(3, 2);
+        img.put_pixel(2, 1, image::Luma([255]));
+        let heightfield =
+            Heightfield::from_image(&image::DynamicImage::ImageLuma8(img), scale).unwrap();
+        assert_eq!((heightfield.rows(), heightfield.cols()), (2, 3));
+        assert_eq!(heightfield.point(1, 2).y, 1.0);
+        assert_eq!(heightfield.point(0, 2).y, 0.0);
+    }
+
+    #[test]
+    fn normals() {
+        let ramp = ramp();
+        let expected = Vector3::new(-0.5, 1.0, 0.0).normalize();
+        for (row, col) in [(0, 0), (1, 2), (3, 4)] {
+            assert!((ramp.normal(row, col) - expected).magnitude() < 1e-5);
+        }
+        for tri in ramp.cell_triangles(1, 1) {
+            assert!((tri.normal - expected).magnitude() < 1e-5);
+            // The ramp is one plane, so no edge between cells is convex.
+            assert!(!tri.edges.iter().any(|e| matches!(e, Edge::Convex(_))));
+        }
+        let flat = Heightfield::new(vec![0.5; 9], 3, 3, Vector3::new(1.0, 1.0, 1.0));
+        assert_eq!(flat.normal(1, 1), Vector3::unit_y());
+    }
+
+    #[test]
+    fn query_visits_cells_under_the_box() {
+        let ramp = ramp();
+        let mut count = 0;
+        let inside = Aabb {
+            min: Point3::new(-0.9, 0.0, -0.9),
+            max: Point3::new(-0.1, 2.0, 0.4),
+        };
+        ramp.query(&inside, |tri| {
+            assert!(Aabb::from_points(&tri.vertices).intersects(&inside));
+            count += 1;
+        });
+        // One column of cells, two rows, two triangles each.
+        assert_eq!(count, 4);
+
+        let above = Aabb {
+            min: Point3::new(-0.9, 3.0, -0.4),
+            max: Point3::new(-0.1, 4.0, 0.4),
+        };
+        ramp.query(&above, |_| panic!("above the terrain"));
+    }
+}
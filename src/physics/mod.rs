@@ -0,0 +1,98 @@
+pub mod body;
+pub mod bvh;
+pub mod cloth;
+pub mod contact;
+pub mod decomposition;
+pub mod fitting;
+pub mod fluid;
+pub mod forces;
+pub mod heightfield;
+pub mod nbody;
+pub mod particles;
+pub mod quickhull;
+pub mod shape;
+pub mod spatial_hash;
+pub mod transform;
+pub mod triangle;
+pub mod trimesh;
+pub mod world;
+
+pub use body::RigidBody;
+pub use cloth::{Cloth, ClothParticle, ClothSettings};
+pub use contact::{contacts, static_contacts, Contact};
+pub use decomposition::{convex_decomposition, DecompositionParams};
+pub use fluid::{Fluid, FluidParticle, FluidSettings};
+pub use forces::{
+    Attractor, Buoyancy, Drag, Explosion, Falloff, ForceContext, ForceGenerator, ForceScope,
+    Gravity, Region, Vortex, Wind,
+};
+pub use heightfield::Heightfield;
+pub use nbody::{Diagnostics, GravityKernel, NBody, Particle};
+pub use particles::{Emitter, HalfSpace, ParticleSettings, ParticleState, ParticleSystem};
+pub use quickhull::quickhull;
+pub use shape::{Aabb, Capsule, ConvexHull, Obb, Shape, Sphere};
+pub use spatial_hash::SpatialHash;
+pub use transform::Transform;
+pub use trimesh::TriMesh;
+pub use world::{BodyContact, BodyHandle, ForceHandle, World};
+
+#[derive(Copy, Clone, Debug)]
+pub enum ColliderKind {
+    Aabb,
+    Obb,
+    Sphere,
+    Capsule,
+    ConvexHull,
+    ConvexDecomposition(DecompositionParams),
+    /// The raw triangles, meant for static geometry only.
+    TriMesh,
+}
+
+/// Builds a collision shape of the requested kind from indexed triangle data.
+///
+/// Returns `None` for empty geometry. A convex hull of flat geometry falls
+/// back to an oriented box.
+pub fn collider_from_geometry(
+    positions: &[[f32; 3]],
+    indices: &[u32],
+    kind: ColliderKind,
+) -> Option<Shape> {
+    if positions.is_empty() {
+        return None;
+    }
+
+    let mesh = TriMesh::new(positions, indices);
+    let points = mesh.vertices();
+    let shape = match kind {
+        ColliderKind::Aabb => Shape::Cuboid(fitting::fit_aabb(points).to_obb()),
+        ColliderKind::Obb => Shape::Cuboid(fitting::fit_obb(points)),
+        ColliderKind::Sphere => Shape::Sphere(fitting::fit_sphere(points)),
+        ColliderKind::Capsule => Shape::Capsule(fitting::fit_capsule(points)),
+        ColliderKind::ConvexHull => quickhull(points)
+            .map(Shape::ConvexHull)
+            .unwrap_or_else(|| Shape::Cuboid(fitting::fit_obb(points))),
+        ColliderKind::ConvexDecomposition(params) => {
+            convex_decomposition(points, mesh.triangles(), &params)
+        }
+        ColliderKind::TriMesh => Shape::TriMesh(mesh),
+    };
+    Some(shape)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_hull_falls_back_to_box() {
+        let quad = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0],
+        ];
+        let shape = collider_from_geometry(&quad, &[0, 1, 2, 0, 2, 3], ColliderKind::ConvexHull);
+        assert!(matches!(shape, Some(Shape::Cuboid(_))));
+        assert!(collider_from_geometry(&[], &[], ColliderKind::ConvexHull).is_none());
+    }
+}
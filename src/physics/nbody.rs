@@ -0,0 +1,477 @@
+use cgmath::prelude::*;
+use cgmath::{Vector3, Vector4};
+
+/// A point mass of an [`NBody`] simulation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Particle {
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    pub mass: f32,
+}
+
+/// How the pairwise gravitation is summed.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum GravityKernel {
+    /// Every pair, O(n²).
+    Exact,
+    /// A Barnes-Hut octree, O(n log n). Cells whose size is less than
+    /// `theta` times their distance are treated as a single point mass, so
+    /// zero is exact and larger values trade accuracy for speed.
+    BarnesHut { theta: f32 },
+}
+
+/// Conserved quantities, to check how well the integrator keeps them.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Diagnostics {
+    pub kinetic: f64,
+    pub potential: f64,
+    pub momentum: Vector3<f64>,
+    /// Angular momentum about the origin.
+    pub angular_momentum: Vector3<f64>,
+}
+
+impl Diagnostics {
+    pub fn energy(&self) -> f64 {
+        self.kinetic + self.potential
+    }
+}
+
+/// Bodies that all attract each other, separate from the rigid body `World`.
+///
+/// Steps with the leapfrog (drift-kick-drift) scheme, which is symplectic:
+/// the energy error stays bounded over long runs instead of drifting.
+#[derive(Clone, Debug)]
+pub struct NBody {
+    pub particles: Vec<Particle>,
+    pub gravitational_constant: f32,
+    /// Plummer softening length, which keeps close encounters finite.
+    pub softening: f32,
+    pub kernel: GravityKernel,
+    time: f32,
+}
+
+/// Splits that deep stop and keep the bodies that are left together, so
+/// bodies at the same spot don't split cells forever.
+const MAX_DEPTH: u32 = 32;
+
+impl NBody {
+    pub fn new(particles: Vec<Particle>, gravitational_constant: f32) -> Self {
+        Self {
+            particles,
+            gravitational_constant,
+            softening: 0.05,
+            kernel: GravityKernel::BarnesHut { theta: 0.5 },
+            time: 0.0,
+        }
+    }
+
+    /// A heavy body at the origin circled by `count - 1` light ones on
+    /// near-circular orbits in the XZ plane, out to `radius`.
+    pub fn disk(count: usize, central_mass: f32, radius: f32, gravitational_constant: f32) -> Self {
+        const INNER: f32 = 0.15;
+        const THICKNESS: f32 = 0.02;
+        let orbiting = count.saturating_sub(1);
+        let mass = central_mass * 1e-4;
+
+        let mut particles = Vec::with_capacity(count);
+        particles.push(Particle {
+            position: Vector3::zero(),
+            velocity: Vector3::zero(),
+            mass: central_mass,
+        });
+        for i in 0..orbiting {
+            // Evenly spread over the disk area, along the golden angle.
+            let t = (i as f32 + 0.5) / orbiting as f32;
+            let r = radius * (INNER * INNER + (1.0 - INNER * INNER) * t).sqrt();
+            let angle = (137.5 * i as f32).to_radians();
+            let (sin, cos) = angle.sin_cos();
+            let height = radius * THICKNESS * (i as f32 * 2.39).sin();
+            // Circular speed around the mass further in, counterclockwise.
+            let enclosed = central_mass + mass * i as f32;
+            let speed = (gravitational_constant * enclosed / r).sqrt();
+            particles.push(Particle {
+                position: Vector3::new(r * cos, height, r * sin),
+                velocity: Vector3::new(speed * sin, 0.0, -speed * cos),
+                mass,
+            });
+        }
+        // The center recoils so that the disk as a whole stays put.
+        let momentum = particles[1..]
+            .iter()
+            .fold(Vector3::zero(), |sum, p| sum + p.velocity * p.mass);
+        particles[0].velocity = -momentum / central_mass;
+        Self::new(particles, gravitational_constant)
+    }
+
+    /// Simulated time in seconds.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.drift(0.5 * dt);
+        let accelerations = self.accelerations();
+        for (particle, acceleration) in self.particles.iter_mut().zip(accelerations) {
+            particle.velocity += acceleration * dt;
+        }
+        self.drift(0.5 * dt);
+        self.time += dt;
+    }
+
+    fn drift(&mut self, dt: f32) {
+        for particle in &mut self.particles {
+            particle.position += particle.velocity * dt;
+        }
+    }
+
+    /// Gravitational acceleration of every particle, summed by `kernel`.
+    pub fn accelerations(&self) -> Vec<Vector3<f32>> {
+        self.field().into_iter().map(|f| f.truncate()).collect()
+    }
+
+    pub fn diagnostics(&self) -> Diagnostics {
+        let mut diagnostics = Diagnostics {
+            kinetic: 0.0,
+            potential: 0.0,
+            momentum: Vector3::zero(),
+            angular_momentum: Vector3::zero(),
+        };
+        for (particle, field) in self.particles.iter().zip(self.field()) {
+            let m = particle.mass as f64;
+            let x = particle.position.cast::<f64>().unwrap();
+            let v = particle.velocity.cast::<f64>().unwrap();
+            diagnostics.kinetic += 0.5 * m * v.magnitude2();
+            // Each pair shows up in the potential of both particles.
+            diagnostics.potential += 0.5 * m * field.w as f64;
+            diagnostics.momentum += v * m;
+            diagnostics.angular_momentum += x.cross(v * m);
+        }
+        diagnostics
+    }
+
+    /// Acceleration and potential per unit mass of every particle.
+    fn field(&self) -> Vec<Vector4<f32>> {
+        match self.kernel {
+            GravityKernel::Exact => self.exact_field(),
+            GravityKernel::BarnesHut { theta } => {
+                let tree = Octree::new(&self.particles);
+                (0..self.particles.len())
+                    .map(|i| {
+                        tree.field(&self.particles, i, theta, self.softening)
+                            * self.gravitational_constant
+                    })
+                    .collect()
+            }
+        }
+    }
+
+    fn exact_field(&self) -> Vec<Vector4<f32>> {
+        let mut field = vec![Vector4::zero(); self.particles.len()];
+        for (i, a) in self.particles.iter().enumerate() {
+            for (j, b) in self.particles.iter().enumerate().skip(i + 1) {
+                let d = b.position - a.position;
+                let (pull, potential) = point_mass(d.magnitude2(), self.softening);
+                field[i] += (d * pull).extend(potential) * b.mass;
+                field[j] += (-d * pull).extend(potential) * a.mass;
+            }
+        }
+        field
+            .into_iter()
+            .map(|f| f * self.gravitational_constant)
+            .collect()
+    }
+}
+
+/// Softened gravity of a unit point mass at squared distance `r2`, per unit
+/// `G`: the factor of the offset giving the acceleration, and the potential.
+fn point_mass(r2: f32, softening: f32) -> (f32, f32) {
+    let inverse = 1.0 / (r2 + softening * softening).sqrt();
+    (inverse * inverse * inverse, -inverse)
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    center: Vector3<f32>,
+    half_size: f32,
+    mass: f32,
+    /// Center of mass, once the tree is built.
+    com: Vector3<f32>,
+    count: u32,
+    /// The particle in a leaf that holds exactly one.
+    particle: Option<usize>,
+    /// Index of the first of eight consecutive children.
+    children: Option<usize>,
+}
+
+impl Node {
+    fn new(center: Vector3<f32>, half_size: f32) -> Self {
+        Self {
+            center,
+            half_size,
+            mass: 0.0,
+            com: Vector3::zero(),
+            count: 0,
+            particle: None,
+            children: None,
+        }
+    }
+
+    fn octant(&self, p: Vector3<f32>) -> usize {
+        (p.x >= self.center.x) as usize
+            | ((p.y >= self.center.y) as usize) << 1
+            | ((p.z >= self.center.z) as usize) << 2
+    }
+
+    fn contains(&self, p: Vector3<f32>) -> bool {
+        let d = (p - self.center).map(f32::abs);
+        d.x <= self.half_size && d.y <= self.half_size && d.z <= self.half_size
+    }
+}
+
+/// Barnes-Hut tree of cubic cells, each with the mass and center of mass of
+/// the particles inside.
+struct Octree {
+    nodes: Vec<Node>,
+}
+
+impl Octree {
+    fn new(particles: &[Particle]) -> Self {
+        let (min, max) = particles.iter().fold(
+            (Vector3::from_value(f32::MAX), Vector3::from_value(f32::MIN)),
+            |(min, max), p| {
+                (
+                    Vector3::new(
+                        min.x.min(p.position.x),
+                        min.y.min(p.position.y),
+                        min.z.min(p.position.z),
+                    ),
+                    Vector3::new(
+                        max.x.max(p.position.x),
+                        max.y.max(p.position.y),
+                        max.z.max(p.position.z),
+                    ),
+                )
+            },
+        );
+        let extent = max - min;
+        let half_size = 0.5 * extent.x.max(extent.y).max(extent.z);
+        let mut tree = Self {
+            nodes: vec![Node::new((min + max) * 0.5, half_size.max(f32::EPSILON))],
+        };
+        if particles.is_empty() {
+            return tree;
+        }
+        for (i, particle) in particles.iter().enumerate() {
+            tree.insert(particles, i, particle);
+        }
+        for node in &mut tree.nodes {
+            if node.mass > 0.0 {
+                node.com /= node.mass;
+            }
+        }
+        tree
+    }
+
+    /// Adds a particle along the path to its leaf, splitting the leaf if it
+    /// is taken. `com` holds mass-weighted positions until the tree is done.
+    fn insert(&mut self, particles: &[Particle], index: usize, particle: &Particle) {
+        let p = particle.position;
+        let mut node = 0;
+        let mut depth = 0;
+        loop {
+            let n = &mut self.nodes[node];
+            n.mass += particle.mass;
+            n.com += p * particle.mass;
+            n.count += 1;
+            if let Some(first) = n.children {
+                node = first + n.octant(p);
+                depth += 1;
+                continue;
+            }
+            if n.count == 1 {
+                n.particle = Some(index);
+                return;
+            }
+            if depth == MAX_DEPTH {
+                n.particle = None;
+                return;
+            }
+
+            // Push the current occupant down, then carry on into the child.
+            let (center, half_size) = (n.center, n.half_size * 0.5);
+            let occupant = n.particle.take();
+            let first = self.nodes.len();
+            self.nodes[node].children = Some(first);
+            for octant in 0..8 {
+                let offset = Vector3::new(
+                    if octant & 1 == 0 {
+                        -half_size
+                    } else {
+                        half_size
+                    },
+                    if octant & 2 == 0 {
+                        -half_size
+                    } else {
+                        half_size
+                    },
+                    if octant & 4 == 0 {
+                        -half_size
+                    } else {
+                        half_size
+                    },
+                );
+                self.nodes.push(Node::new(center + offset, half_size));
+            }
+            if let Some(occupant) = occupant {
+                let o = &particles[occupant];
+                let child = first + self.nodes[node].octant(o.position);
+                let child = &mut self.nodes[child];
+                child.mass += o.mass;
+                child.com += o.position * o.mass;
+                child.count += 1;
+                child.particle = Some(occupant);
+            }
+            node = first + self.nodes[node].octant(p);
+            depth += 1;
+        }
+    }
+
+    /// Acceleration and potential per unit mass at particle `index`.
+    fn field(
+        &self,
+        particles: &[Particle],
+        index: usize,
+        theta: f32,
+        softening: f32,
+    ) -> Vector4<f32> {
+        let p = particles[index].position;
+        let mut field = Vector4::zero();
+        let mut stack = vec![0];
+        while let Some(node) = stack.pop() {
+            let node = &self.nodes[node];
+            if node.count == 0 || node.particle == Some(index) {
+                continue;
+            }
+            let d = node.com - p;
+            let r2 = d.magnitude2();
+            let size = 2.0 * node.half_size;
+            let far = size * size < theta * theta * r2 && !node.contains(p);
+            match node.children {
+                Some(first) if !far => stack.extend(first..first + 8),
+                _ => {
+                    // A leaf at `MAX_DEPTH` may hold the particle itself.
+                    let mass = if node.contains(p) && node.children.is_none() {
+                        node.mass - particles[index].mass
+                    } else {
+                        node.mass
+                    };
+                    let (pull, potential) = point_mass(r2, softening);
+                    field += (d * pull).extend(potential) * mass;
+                }
+            }
+        }
+        field
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scattered(count: usize) -> Vec<Particle> {
+        (0..count)
+            .map(|i| {
+                let t = i as f32;
+                Particle {
+                    position: Vector3::new((t * 1.7).sin(), (t * 2.3).cos(), (t * 0.9).sin() * 2.0)
+                        * (1.0 + t * 0.1),
+                    velocity: Vector3::zero(),
+                    mass: 1.0 + (t * 0.7).sin().abs(),
+                }
+            })
+            .collect()
+    }
+
+    fn assert_fields_match(nbody: &NBody) {
+        let barnes_hut = nbody.field();
+        let exact = nbody.exact_field();
+        for (a, b) in barnes_hut.iter().zip(&exact) {
+            assert!(a.x.is_finite() && a.y.is_finite() && a.z.is_finite() && a.w.is_finite());
+            assert!(
+                (a - b).magnitude() <= 1e-4 * b.magnitude(),
+                "{:?} != {:?}",
+                a,
+                b
+            );
+        }
+    }
+
+    #[test]
+    fn barnes_hut_without_opening_angle_is_exact() {
+        let mut nbody = NBody::new(scattered(40), 1.5);
+        nbody.kernel = GravityKernel::BarnesHut { theta: 0.0 };
+        assert_fields_match(&nbody);
+    }
+
+    #[test]
+    fn leapfrog_energy_stays_bounded_on_an_orbit() {
+        // Two equal masses a unit apart, slower than circular so that the
+        // orbit is eccentric.
+        let speed = 0.8 * 0.5f32.sqrt();
+        let mut nbody = NBody::new(
+            vec![
+                Particle {
+                    position: Vector3::new(-0.5, 0.0, 0.0),
+                    velocity: Vector3::new(0.0, 0.0, -speed),
+                    mass: 1.0,
+                },
+                Particle {
+                    position: Vector3::new(0.5, 0.0, 0.0),
+                    velocity: Vector3::new(0.0, 0.0, speed),
+                    mass: 1.0,
+                },
+            ],
+            1.0,
+        );
+        nbody.softening = 0.0;
+        nbody.kernel = GravityKernel::Exact;
+
+        let start = nbody.diagnostics();
+        // About eighteen orbits.
+        for _ in 0..10_000 {
+            nbody.step(0.005);
+            let now = nbody.diagnostics();
+            let drift = (now.energy() - start.energy()) / start.energy().abs();
+            assert!(drift.abs() < 1e-3, "energy drifted by {}", drift);
+            assert!((now.momentum - start.momentum).magnitude() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn coincident_particles_have_finite_field() {
+        let mut particles = scattered(6);
+        let spot = Vector3::new(0.25, -0.5, 1.0);
+        for _ in 0..5 {
+            particles.push(Particle {
+                position: spot,
+                velocity: Vector3::zero(),
+                mass: 2.0,
+            });
+        }
+        let mut nbody = NBody::new(particles, 1.0);
+        nbody.kernel = GravityKernel::BarnesHut { theta: 0.0 };
+
+        let tree = Octree::new(&nbody.particles);
+        assert!(tree.nodes.iter().all(|node| node.mass >= 0.0));
+        assert_fields_match(&nbody);
+        let field = nbody.field();
+        assert!(field.iter().all(|f| f.w < 0.0));
+
+        nbody.kernel = GravityKernel::BarnesHut { theta: 0.5 };
+        nbody.step(0.01);
+        assert!(nbody
+            .particles
+            .iter()
+            .all(|p| p.position.x.is_finite() && p.velocity.x.is_finite()));
+    }
+}
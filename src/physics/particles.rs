@@ -0,0 +1,324 @@
+//! Point particles that collide with static planes and spheres but not with
+//! each other. This is the CPU reference of the compute shader in
+//! `particle_sim.wgsl`: both take the same steps in the same order, with the
+//! same hash for respawning, so their results can be compared.
+
+use std::f32::consts::TAU;
+
+use cgmath::prelude::*;
+use cgmath::Vector3;
+
+use super::shape::Sphere;
+
+/// Solid space below the plane `normal · p = offset`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct HalfSpace {
+    pub normal: Vector3<f32>,
+    pub offset: f32,
+}
+
+/// Where particles are born, again and again as they expire.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Emitter {
+    /// Center of the disk in the XZ plane particles start on.
+    pub position: Vector3<f32>,
+    pub radius: f32,
+    pub velocity: Vector3<f32>,
+    /// Largest random change of the velocity on each axis.
+    pub spread: f32,
+    /// Seconds a particle lives before it is born again.
+    pub lifetime: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ParticleSettings {
+    pub gravity: Vector3<f32>,
+    pub damping: f32,
+    /// Radius particles collide with.
+    pub radius: f32,
+    pub restitution: f32,
+    /// Fraction of the tangential velocity lost in a collision.
+    pub friction: f32,
+    pub emitter: Emitter,
+    pub planes: Vec<HalfSpace>,
+    pub spheres: Vec<Sphere>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ParticleState {
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    /// Seconds since birth. Negative while waiting to be born.
+    pub age: f32,
+}
+
+pub struct ParticleSystem {
+    pub settings: ParticleSettings,
+    pub particles: Vec<ParticleState>,
+    frame: u32,
+}
+
+impl ParticleSystem {
+    /// Creates `count` unborn particles, born one after another over the
+    /// first lifetime so that they don't all expire at once.
+    pub fn new(settings: ParticleSettings, count: usize) -> Self {
+        let lifetime = settings.emitter.lifetime;
+        let particles = (0..count)
+            .map(|i| ParticleState {
+                position: settings.emitter.position,
+                velocity: Vector3::zero(),
+                age: -lifetime * i as f32 / count as f32,
+            })
+            .collect();
+        Self::from_particles(settings, particles, 0)
+    }
+
+    /// Continues a simulation at step `frame`, e.g. from a GPU read back.
+    pub fn from_particles(
+        settings: ParticleSettings,
+        particles: Vec<ParticleState>,
+        frame: u32,
+    ) -> Self {
+        Self {
+            settings,
+            particles,
+            frame,
+        }
+    }
+
+    /// Number of steps taken, which seeds the respawns.
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        for (i, particle) in self.particles.iter_mut().enumerate() {
+            step_particle(particle, i as u32, self.frame, &self.settings, dt);
+        }
+        self.frame = self.frame.wrapping_add(1);
+    }
+}
+
+/// Advances one particle by one step.
+pub fn step_particle(
+    particle: &mut ParticleState,
+    index: u32,
+    frame: u32,
+    settings: &ParticleSettings,
+    dt: f32,
+) {
+    if particle.age < 0.0 {
+        particle.age += dt;
+        if particle.age >= 0.0 {
+            *particle = spawn(index, frame, &settings.emitter);
+        }
+        return;
+    }
+
+    let mut v = (particle.velocity + settings.gravity * dt) / (1.0 + settings.damping * dt);
+    let mut p = particle.position + v * dt;
+    for plane in &settings.planes {
+        let depth = plane.offset + settings.radius - plane.normal.dot(p);
+        if depth > 0.0 {
+            collide(&mut p, &mut v, plane.normal, depth, settings);
+        }
+    }
+    for sphere in &settings.spheres {
+        let offset = p - sphere.center.to_vec();
+        let distance = offset.magnitude();
+        let depth = sphere.radius + settings.radius - distance;
+        if depth > 0.0 && distance > 0.0 {
+            collide(&mut p, &mut v, offset / distance, depth, settings);
+        }
+    }
+
+    particle.position = p;
+    particle.velocity = v;
+    particle.age += dt;
+    if particle.age >= settings.emitter.lifetime {
+        *particle = spawn(index, frame, &settings.emitter);
+    }
+}
+
+/// Pushes the particle out along `normal` and reflects the approaching
+/// velocity.
+fn collide(
+    p: &mut Vector3<f32>,
+    v: &mut Vector3<f32>,
+    normal: Vector3<f32>,
+    depth: f32,
+    settings: &ParticleSettings,
+) {
+    *p += normal * depth;
+    let vn = v.dot(normal);
+    if vn < 0.0 {
+        let tangent = *v - normal * vn;
+        *v = tangent * (1.0 - settings.friction) - normal * (vn * settings.restitution);
+    }
+}
+
+/// A newborn particle, randomized by its index and the step it is born in.
+pub fn spawn(index: u32, frame: u32, emitter: &Emitter) -> ParticleState {
+    let mut seed = hash(index ^ hash(frame));
+    let mut random = || {
+        seed = hash(seed);
+        (seed >> 8) as f32 / (1 << 24) as f32
+    };
+    let angle = TAU * random();
+    let distance = emitter.radius * random().sqrt();
+    let jitter = Vector3::new(random() - 0.5, random() - 0.5, random() - 0.5);
+    ParticleState {
+        position: emitter.position
+            + Vector3::new(angle.cos() * distance, 0.0, angle.sin() * distance),
+        velocity: emitter.velocity + jitter * (2.0 * emitter.spread),
+        age: 0.0,
+    }
+}
+
+/// PCG hash, as in the shader.
+pub fn hash(x: u32) -> u32 {
+    let state = x.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cgmath::Point3;
+
+    fn settings() -> ParticleSettings {
+        ParticleSettings {
+            gravity: Vector3::zero(),
+            damping: 0.0,
+            radius: 0.1,
+            restitution: 0.5,
+            friction: 0.25,
+            emitter: Emitter {
+                position: Vector3::new(0.0, 5.0, 0.0),
+                radius: 1.0,
+                velocity: Vector3::new(0.0, 2.0, 0.0),
+                spread: 0.5,
+                lifetime: 2.0,
+            },
+            planes: Vec::new(),
+            spheres: Vec::new(),
+        }
+    }
+
+    fn assert_near(a: Vector3<f32>, b: Vector3<f32>) {
+        assert!((a - b).magnitude() < 1e-5, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn plane_bounce_applies_restitution_and_friction() {
+        let mut settings = settings();
+        settings.planes.push(HalfSpace {
+            normal: Vector3::unit_y(),
+            offset: 0.0,
+        });
+        let mut particle = ParticleState {
+            position: Vector3::new(0.0, 0.105, 0.0),
+            velocity: Vector3::new(1.0, -2.0, 0.0),
+            age: 0.0,
+        };
+        step_particle(&mut particle, 0, 0, &settings, 0.01);
+
+        assert_near(particle.position, Vector3::new(0.01, 0.1, 0.0));
+        // Half the normal speed comes back, a quarter of the tangential one
+        // is lost.
+        assert_near(particle.velocity, Vector3::new(0.75, 1.0, 0.0));
+    }
+
+    #[test]
+    fn separating_particle_keeps_its_velocity() {
+        let mut settings = settings();
+        settings.planes.push(HalfSpace {
+            normal: Vector3::unit_y(),
+            offset: 0.0,
+        });
+        let mut particle = ParticleState {
+            position: Vector3::new(0.0, 0.05, 0.0),
+            velocity: Vector3::new(1.0, 1.0, 0.0),
+            age: 0.0,
+        };
+        step_particle(&mut particle, 0, 0, &settings, 0.01);
+
+        assert_near(particle.position, Vector3::new(0.01, 0.1, 0.0));
+        assert_near(particle.velocity, Vector3::new(1.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn sphere_pushes_particle_out() {
+        let mut settings = settings();
+        settings.spheres.push(Sphere {
+            center: Point3::new(1.0, 0.0, 0.0),
+            radius: 1.0,
+        });
+        let direction = Vector3::new(0.0, 3.0, 4.0) / 5.0;
+        let mut particle = ParticleState {
+            position: Vector3::new(1.0, 0.0, 0.0) + direction * 1.05,
+            velocity: -direction,
+            age: 0.0,
+        };
+        step_particle(&mut particle, 0, 0, &settings, 0.01);
+
+        assert_near(
+            particle.position,
+            Vector3::new(1.0, 0.0, 0.0) + direction * 1.1,
+        );
+        assert_near(particle.velocity, direction * 0.5);
+    }
+
+    #[test]
+    fn births_are_staggered_and_particles_respawn_at_lifetime() {
+        let settings = settings();
+        let emitter = settings.emitter;
+        let mut system = ParticleSystem::new(settings, 4);
+        let ages = system.particles.iter().map(|p| p.age).collect::<Vec<_>>();
+        assert_eq!(ages, [0.0, -0.5, -1.0, -1.5]);
+
+        // Particle 1 is born on the second step, the others still wait.
+        system.step(0.25);
+        system.step(0.25);
+        assert_eq!(system.particles[1], spawn(1, 1, &emitter));
+        assert!(system.particles[2].age < 0.0);
+        assert!(system.particles[3].age < 0.0);
+
+        // Particle 0 reaches its lifetime on the eighth step.
+        for _ in 2..7 {
+            system.step(0.25);
+        }
+        assert!((system.particles[0].age - 1.75).abs() < 1e-6);
+        system.step(0.25);
+        assert_eq!(system.particles[0], spawn(0, 7, &emitter));
+        assert_eq!(system.frame(), 8);
+    }
+
+    #[test]
+    fn spawns_depend_only_on_index_and_frame() {
+        let emitter = settings().emitter;
+        assert_eq!(spawn(3, 10, &emitter), spawn(3, 10, &emitter));
+        assert_ne!(spawn(3, 10, &emitter), spawn(4, 10, &emitter));
+        assert_ne!(spawn(3, 10, &emitter), spawn(3, 11, &emitter));
+
+        for index in 0..64 {
+            let particle = spawn(index, 5, &emitter);
+            let offset = particle.position - emitter.position;
+            assert_eq!(offset.y, 0.0);
+            assert!(offset.magnitude() <= emitter.radius);
+            let jitter = particle.velocity - emitter.velocity;
+            assert!(jitter.x.abs().max(jitter.y.abs()).max(jitter.z.abs()) <= emitter.spread);
+            assert_eq!(particle.age, 0.0);
+        }
+    }
+
+    #[test]
+    fn hash_matches_pcg() {
+        assert_eq!(hash(0), 129708002);
+        assert_eq!(hash(1), 2831084092);
+        assert_eq!(hash(2), 2055130248);
+        assert_eq!(hash(42), 1223963391);
+        assert_eq!(hash(u32::MAX), 3861530882);
+    }
+}
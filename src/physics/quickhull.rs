@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+use super::shape::ConvexHull;
+
+struct Face {
+    vertices: [usize; 3],
+    normal: Vector3<f32>,
+    offset: f32,
+    outside: Vec<usize>,
+    alive: bool,
+}
+
+impl Face {
+    fn new(points: &[Point3<f32>], vertices: [usize; 3]) -> Self {
+        let [a, b, c] = vertices.map(|i| points[i]);
+        let normal = (b - a).cross(c - a);
+        let len = normal.magnitude();
+        let normal = if len > 0.0 {
+            normal / len
+        } else {
+            Vector3::zero()
+        };
+        Self {
+            vertices,
+            normal,
+            offset: normal.dot(a.to_vec()),
+            outside: Vec::new(),
+            alive: true,
+        }
+    }
+
+    fn distance(&self, p: Point3<f32>) -> f32 {
+        self.normal.dot(p.to_vec()) - self.offset
+    }
+
+    fn edges(&self) -> [(usize, usize); 3] {
+        let [a, b, c] = self.vertices;
+        [(a, b), (b, c), (c, a)]
+    }
+}
+
+/// Builds the convex hull of a point cloud with the quickhull algorithm.
+///
+/// Returns `None` when the points are degenerate (fewer than four points, or
+/// all of them collinear or coplanar).
+pub fn quickhull(points: &[Point3<f32>]) -> Option<ConvexHull> {
+    if points.len() < 4 {
+        return None;
+    }
+
+    let scale = points.iter().fold(0.0f32, |acc, p| {
+        acc.max(p.x.abs()).max(p.y.abs()).max(p.z.abs())
+    });
+    let eps = scale.max(1.0) * 1e-5;
+
+    let [i0, i1, i2, i3] = initial_simplex(points, eps)?;
+    let centroid = Point3::centroid(&[points[i0], points[i1], points[i2], points[i3]]);
+
+    let mut faces = Vec::new();
+    for tri in [[i0, i1, i2], [i0, i3, i1], [i1, i3, i2], [i2, i3, i0]] {
+        let mut face = Face::new(points, tri);
+        if face.distance(centroid) > 0.0 {
+            face = Face::new(points, [tri[0], tri[2], tri[1]]);
+        }
+        faces.push(face);
+    }
+
+    let mut edges = HashMap::new();
+    for (i, face) in faces.iter().enumerate() {
+        for edge in face.edges() {
+            edges.insert(edge, i);
+        }
+    }
+
+    let remaining = (0..points.len()).filter(|&i| ![i0, i1, i2, i3].contains(&i));
+    assign_outside(&mut faces, 0..4, remaining, points, eps);
+
+    // Every iteration consumes at least one outside point, so this bounds the loop.
+    for _ in 0..points.len() {
+        let Some(start) = faces.iter().position(|f| f.alive && !f.outside.is_empty()) else {
+            break;
+        };
+
+        let eye = *faces[start]
+            .outside
+            .iter()
+            .max_by(|&&a, &&b| {
+                let da = faces[start].distance(points[a]);
+                let db = faces[start].distance(points[b]);
+                da.partial_cmp(&db).unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap();
+        let eye_point = points[eye];
+
+        // Flood fill the faces visible from the eye point and collect the horizon.
+        let mut visible = vec![start];
+        let mut is_visible = HashMap::from([(start, true)]);
+        let mut horizon = Vec::new();
+        let mut stack = vec![start];
+        while let Some(f) = stack.pop() {
+            for (a, b) in faces[f].edges() {
+                let Some(&neighbor) = edges.get(&(b, a)) else {
+                    continue;
+                };
+                let seen = *is_visible
+                    .entry(neighbor)
+                    .or_insert_with(|| faces[neighbor].distance(eye_point) > eps);
+                if seen {
+                    if !visible.contains(&neighbor) {
+                        visible.push(neighbor);
+                        stack.push(neighbor);
+                    }
+                } else {
+                    horizon.push((a, b));
+                }
+            }
+        }
+
+        let mut orphans = Vec::new();
+        for &f in &visible {
+            faces[f].alive = false;
+            for edge in faces[f].edges() {
+                edges.remove(&edge);
+            }
+            orphans.append(&mut faces[f].outside);
+        }
+
+        let first_new = faces.len();
+        for (a, b) in horizon {
+            let face = Face::new(points, [a, b, eye]);
+            for edge in face.edges() {
+                edges.insert(edge, faces.len());
+            }
+            faces.push(face);
+        }
+
+        let new_faces = first_new..faces.len();
+        let orphans = orphans.into_iter().filter(|&i| i != eye);
+        assign_outside(&mut faces, new_faces, orphans, points, eps);
+    }
+
+    let mut remap = HashMap::new();
+    let mut hull_points = Vec::new();
+    let mut triangles = Vec::new();
+    for face in faces.iter().filter(|f| f.alive) {
+        let tri = face.vertices.map(|v| {
+            *remap.entry(v).or_insert_with(|| {
+                hull_points.push(points[v]);
+                hull_points.len() as u32 - 1
+            })
+        });
+        triangles.push(tri);
+    }
+
+    Some(ConvexHull {
+        points: hull_points,
+        triangles,
+    })
+}
+
+fn initial_simplex(points: &[Point3<f32>], eps: f32) -> Option<[usize; 4]> {
+    let mut extremes = [0usize; 6];
+    for (i, p) in points.iter().enumerate() {
+        for axis in 0..3 {
+            if p[axis] < points[extremes[axis * 2]][axis] {
+                extremes[axis * 2] = i;
+            }
+            if p[axis] > points[extremes[axis * 2 + 1]][axis] {
+                extremes[axis * 2 + 1] = i;
+            }
+        }
+    }
+
+    let (mut i0, mut i1, mut best) = (0, 0, 0.0);
+    for &a in &extremes {
+        for &b in &extremes {
+            let d = points[a].distance2(points[b]);
+            if d > best {
+                (i0, i1, best) = (a, b, d);
+            }
+        }
+    }
+    if best.sqrt() <= eps {
+        return None;
+    }
+
+    let line = (points[i1] - points[i0]).normalize();
+    let i2 = farthest(points, |p| {
+        let v = p - points[i0];
+        (v - line * v.dot(line)).magnitude()
+    })?;
+    let dist = {
+        let v = points[i2] - points[i0];
+        (v - line * v.dot(line)).magnitude()
+    };
+    if dist <= eps {
+        return None;
+    }
+
+    let normal = (points[i1] - points[i0])
+        .cross(points[i2] - points[i0])
+        .normalize();
+    let i3 = farthest(points, |p| normal.dot(p - points[i0]).abs())?;
+    if normal.dot(points[i3] - points[i0]).abs() <= eps {
+        return None;
+    }
+
+    Some([i0, i1, i2, i3])
+}
+
+fn farthest(points: &[Point3<f32>], metric: impl Fn(Point3<f32>) -> f32) -> Option<usize> {
+    points
+        .iter()
+        .map(|p| metric(*p))
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+        .map(|(i, _)| i)
+}
+
+fn assign_outside(
+    faces: &mut [Face],
+    candidates: std::ops::Range<usize>,
+    points_to_assign: impl Iterator<Item = usize>,
+    points: &[Point3<f32>],
+    eps: f32,
+) {
+    for i in points_to_assign {
+        let best = candidates
+            .clone()
+            .map(|f| (f, faces[f].distance(points[i])))
+            .filter(|&(_, d)| d > eps)
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        if let Some((f, _)) = best {
+            faces[f].outside.push(i);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cube_corners() -> Vec<Point3<f32>> {
+        (0..8)
+            .map(|i| {
+                Point3::new(
+                    if i & 1 == 0 { -1.0 } else { 1.0 },
+                    if i & 2 == 0 { -1.0 } else { 1.0 },
+                    if i & 4 == 0 { -1.0 } else { 1.0 },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cube_with_interior_points() {
+        let mut points = cube_corners();
+        points.extend([
+            Point3::new(0.0, 0.0, 0.0),
+            Point3::new(0.5, -0.25, 0.1),
+            Point3::new(-0.9, 0.9, -0.9),
+            Point3::new(0.3, 0.7, -0.6),
+        ]);
+        let hull = quickhull(&points).unwrap();
+        assert_eq!(hull.points.len(), 8);
+        assert_eq!(hull.triangles.len(), 12);
+        assert!((hull.volume() - 8.0).abs() < 1e-4);
+        for p in &hull.points {
+            assert!(p.x.abs() == 1.0 && p.y.abs() == 1.0 && p.z.abs() == 1.0);
+        }
+    }
+
+    #[test]
+    fn triangles_face_outward() {
+        let hull = quickhull(&cube_corners()).unwrap();
+        for tri in &hull.triangles {
+            let [a, b, c] = tri.map(|i| hull.points[i as usize]);
+            let normal = (b - a).cross(c - a);
+            assert!(normal.dot(a.to_vec()) > 0.0);
+        }
+    }
+
+    #[test]
+    fn degenerate_input() {
+        assert!(quickhull(&cube_corners()[..3]).is_none());
+        let coplanar = (0..10)
+            .map(|i| Point3::new(i as f32, (i * i) as f32 * 0.1, 2.0))
+            .collect::<Vec<_>>();
+        assert!(quickhull(&coplanar).is_none());
+        let collinear = (0..10)
+            .map(|i| Point3::new(i as f32, 2.0 * i as f32, -(i as f32)))
+            .collect::<Vec<_>>();
+        assert!(quickhull(&collinear).is_none());
+    }
+}
@@ -0,0 +1,355 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Quaternion, Vector3};
+
+use super::heightfield::Heightfield;
+use super::quickhull::quickhull;
+use super::transform::Transform;
+use super::trimesh::TriMesh;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point3<f32>,
+    pub max: Point3<f32>,
+}
+
+impl Aabb {
+    pub fn empty() -> Self {
+        Self {
+            min: Point3::new(f32::MAX, f32::MAX, f32::MAX),
+            max: Point3::new(f32::MIN, f32::MIN, f32::MIN),
+        }
+    }
+
+    pub fn from_points(points: &[Point3<f32>]) -> Self {
+        points.iter().fold(Self::empty(), |aabb, p| aabb.grow(*p))
+    }
+
+    pub fn grow(self, point: Point3<f32>) -> Self {
+        Self {
+            min: Point3::new(
+                self.min.x.min(point.x),
+                self.min.y.min(point.y),
+                self.min.z.min(point.z),
+            ),
+            max: Point3::new(
+                self.max.x.max(point.x),
+                self.max.y.max(point.y),
+                self.max.z.max(point.z),
+            ),
+        }
+    }
+
+    pub fn merge(self, other: Aabb) -> Self {
+        self.grow(other.min).grow(other.max)
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        self.min.x <= other.max.x
+            && self.max.x >= other.min.x
+            && self.min.y <= other.max.y
+            && self.max.y >= other.min.y
+            && self.min.z <= other.max.z
+            && self.max.z >= other.min.z
+    }
+
+    pub fn contains(&self, p: Point3<f32>) -> bool {
+        self.min.x <= p.x
+            && p.x <= self.max.x
+            && self.min.y <= p.y
+            && p.y <= self.max.y
+            && self.min.z <= p.z
+            && p.z <= self.max.z
+    }
+
+    pub fn expand(self, margin: f32) -> Self {
+        Self {
+            min: self.min - Vector3::from_value(margin),
+            max: self.max + Vector3::from_value(margin),
+        }
+    }
+
+    /// Bounds of this box after it has been moved by `transform`.
+    pub fn transformed(&self, transform: &Transform) -> Self {
+        if self.is_empty() {
+            return *self;
+        }
+        let center = transform.transform_point(self.center());
+        let h = self.half_extents();
+        let [x, y, z] = [Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z()]
+            .map(|axis| transform.transform_vector(axis));
+        let extent = Vector3::new(
+            x.x.abs() * h.x + y.x.abs() * h.y + z.x.abs() * h.z,
+            x.y.abs() * h.x + y.y.abs() * h.y + z.y.abs() * h.z,
+            x.z.abs() * h.x + y.z.abs() * h.y + z.z.abs() * h.z,
+        );
+        Self {
+            min: center - extent,
+            max: center + extent,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    pub fn center(&self) -> Point3<f32> {
+        self.min.midpoint(self.max)
+    }
+
+    pub fn half_extents(&self) -> Vector3<f32> {
+        (self.max - self.min) * 0.5
+    }
+
+    pub fn volume(&self) -> f32 {
+        let size = self.max - self.min;
+        size.x * size.y * size.z
+    }
+
+    pub fn to_obb(&self) -> Obb {
+        Obb {
+            center: self.center(),
+            half_extents: self.half_extents(),
+            rotation: Quaternion::one(),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Obb {
+    pub center: Point3<f32>,
+    pub half_extents: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+}
+
+impl Obb {
+    pub fn volume(&self) -> f32 {
+        8.0 * self.half_extents.x * self.half_extents.y * self.half_extents.z
+    }
+
+    pub fn axes(&self) -> [Vector3<f32>; 3] {
+        [
+            self.rotation * Vector3::unit_x(),
+            self.rotation * Vector3::unit_y(),
+            self.rotation * Vector3::unit_z(),
+        ]
+    }
+
+    pub fn corners(&self) -> [Point3<f32>; 8] {
+        let [x, y, z] = self.axes();
+        let h = self.half_extents;
+        let mut corners = [self.center; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            let sx = if i & 1 == 0 { -h.x } else { h.x };
+            let sy = if i & 2 == 0 { -h.y } else { h.y };
+            let sz = if i & 4 == 0 { -h.z } else { h.z };
+            *corner += x * sx + y * sy + z * sz;
+        }
+        corners
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Sphere {
+    pub center: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Sphere {
+    pub fn volume(&self) -> f32 {
+        4.0 / 3.0 * std::f32::consts::PI * self.radius.powi(3)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Capsule {
+    pub a: Point3<f32>,
+    pub b: Point3<f32>,
+    pub radius: f32,
+}
+
+impl Capsule {
+    pub fn volume(&self) -> f32 {
+        let r = self.radius;
+        std::f32::consts::PI * r * r * ((self.b - self.a).magnitude() + 4.0 / 3.0 * r)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ConvexHull {
+    pub points: Vec<Point3<f32>>,
+    pub triangles: Vec<[u32; 3]>,
+}
+
+impl ConvexHull {
+    pub fn volume(&self) -> f32 {
+        signed_volume(&self.points, &self.triangles).abs()
+    }
+
+    pub fn support(&self, dir: Vector3<f32>) -> Point3<f32> {
+        self.points
+            .iter()
+            .copied()
+            .max_by(|a, b| {
+                dir.dot(a.to_vec())
+                    .partial_cmp(&dir.dot(b.to_vec()))
+                    .unwrap_or(std::cmp::Ordering::Equal)
+            })
+            .unwrap_or_else(Point3::origin)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Shape {
+    Sphere(Sphere),
+    Cuboid(Obb),
+    Capsule(Capsule),
+    ConvexHull(ConvexHull),
+    TriMesh(TriMesh),
+    Heightfield(Heightfield),
+    Compound(Vec<Shape>),
+}
+
+impl Shape {
+    pub fn local_aabb(&self) -> Aabb {
+        match self {
+            Shape::Sphere(s) => Aabb {
+                min: s.center - Vector3::from_value(s.radius),
+                max: s.center + Vector3::from_value(s.radius),
+            },
+            Shape::Cuboid(obb) => Aabb::from_points(&obb.corners()),
+            Shape::Capsule(c) => Aabb {
+                min: Point3::new(c.a.x.min(c.b.x), c.a.y.min(c.b.y), c.a.z.min(c.b.z))
+                    - Vector3::from_value(c.radius),
+                max: Point3::new(c.a.x.max(c.b.x), c.a.y.max(c.b.y), c.a.z.max(c.b.z))
+                    + Vector3::from_value(c.radius),
+            },
+            Shape::ConvexHull(hull) => Aabb::from_points(&hull.points),
+            Shape::TriMesh(mesh) => mesh.aabb(),
+            Shape::Heightfield(heightfield) => heightfield.aabb(),
+            Shape::Compound(shapes) => shapes
+                .iter()
+                .fold(Aabb::empty(), |aabb, s| aabb.merge(s.local_aabb())),
+        }
+    }
+
+    /// The shape stretched along its local axes, matching an instance drawn
+    /// with the same per-axis scale.
+    ///
+    /// Spheres, capsules and rotated boxes that don't keep their form under
+    /// the scale become convex hulls approximating the stretched shape.
+    pub fn scaled(&self, scale: Vector3<f32>) -> Shape {
+        let uniform = scale.x == scale.y && scale.y == scale.z;
+        let scale_point = |p: Point3<f32>| Point3::new(p.x * scale.x, p.y * scale.y, p.z * scale.z);
+        match self {
+            Shape::Sphere(s) if uniform => Shape::Sphere(Sphere {
+                center: scale_point(s.center),
+                radius: s.radius * scale.x.abs(),
+            }),
+            Shape::Sphere(s) => {
+                let points = sphere_points(s.center, s.radius, SCALED_HULL_POINTS);
+                hull_or_box(points.into_iter().map(scale_point).collect())
+            }
+            Shape::Capsule(c) if uniform => Shape::Capsule(Capsule {
+                a: scale_point(c.a),
+                b: scale_point(c.b),
+                radius: c.radius * scale.x.abs(),
+            }),
+            Shape::Capsule(c) => {
+                let points = sphere_points(c.a, c.radius, SCALED_HULL_POINTS / 2)
+                    .into_iter()
+                    .chain(sphere_points(c.b, c.radius, SCALED_HULL_POINTS / 2));
+                hull_or_box(points.map(scale_point).collect())
+            }
+            Shape::Cuboid(obb) if uniform || obb.rotation == Quaternion::one() => {
+                Shape::Cuboid(Obb {
+                    center: scale_point(obb.center),
+                    half_extents: Vector3::new(
+                        obb.half_extents.x * scale.x.abs(),
+                        obb.half_extents.y * scale.y.abs(),
+                        obb.half_extents.z * scale.z.abs(),
+                    ),
+                    rotation: obb.rotation,
+                })
+            }
+            Shape::Cuboid(obb) => hull_or_box(obb.corners().into_iter().map(scale_point).collect()),
+            Shape::ConvexHull(hull) => Shape::ConvexHull(ConvexHull {
+                points: hull.points.iter().map(|&p| scale_point(p)).collect(),
+                triangles: oriented(&hull.triangles, scale),
+            }),
+            Shape::TriMesh(mesh) => {
+                let positions = mesh
+                    .vertices()
+                    .iter()
+                    .map(|&p| scale_point(p).into())
+                    .collect::<Vec<_>>();
+                let indices = oriented(mesh.triangles(), scale).concat();
+                Shape::TriMesh(TriMesh::new(&positions, &indices))
+            }
+            Shape::Heightfield(heightfield) => Shape::Heightfield(heightfield.scaled(scale)),
+            Shape::Compound(shapes) => {
+                Shape::Compound(shapes.iter().map(|shape| shape.scaled(scale)).collect())
+            }
+        }
+    }
+
+    /// Whether the shape can only be used for static colliders.
+    pub fn is_static_only(&self) -> bool {
+        matches!(self, Shape::TriMesh(_) | Shape::Heightfield(_))
+    }
+
+    pub fn volume(&self) -> f32 {
+        match self {
+            Shape::Sphere(s) => s.volume(),
+            Shape::Cuboid(obb) => obb.volume(),
+            Shape::Capsule(c) => c.volume(),
+            Shape::ConvexHull(hull) => hull.volume(),
+            Shape::TriMesh(mesh) => mesh.volume(),
+            Shape::Heightfield(_) => 0.0,
+            Shape::Compound(shapes) => shapes.iter().map(Shape::volume).sum(),
+        }
+    }
+}
+
+/// Number of surface samples used when a curved shape becomes a hull.
+const SCALED_HULL_POINTS: usize = 64;
+
+/// Evenly spread points on a sphere, along a Fibonacci spiral.
+fn sphere_points(center: Point3<f32>, radius: f32, count: usize) -> Vec<Point3<f32>> {
+    let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+    (0..count)
+        .map(|i| {
+            let y = 1.0 - 2.0 * (i as f32 + 0.5) / count as f32;
+            let r = (1.0 - y * y).sqrt();
+            let (sin, cos) = (golden_angle * i as f32).sin_cos();
+            center + Vector3::new(r * cos, y, r * sin) * radius
+        })
+        .collect()
+}
+
+fn hull_or_box(points: Vec<Point3<f32>>) -> Shape {
+    quickhull(&points)
+        .map(Shape::ConvexHull)
+        .unwrap_or_else(|| Shape::Cuboid(Aabb::from_points(&points).to_obb()))
+}
+
+/// Mirroring scales turn the triangles inside out, so their winding flips.
+fn oriented(triangles: &[[u32; 3]], scale: Vector3<f32>) -> Vec<[u32; 3]> {
+    if scale.x * scale.y * scale.z < 0.0 {
+        triangles.iter().map(|&[a, b, c]| [a, c, b]).collect()
+    } else {
+        triangles.to_vec()
+    }
+}
+
+pub(crate) fn signed_volume(points: &[Point3<f32>], triangles: &[[u32; 3]]) -> f32 {
+    triangles
+        .iter()
+        .map(|&[a, b, c]| {
+            let a = points[a as usize].to_vec();
+            let b = points[b as usize].to_vec();
+            let c = points[c as usize].to_vec();
+            a.dot(b.cross(c))
+        })
+        .sum::<f32>()
+        / 6.0
+}
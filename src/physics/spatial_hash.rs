@@ -0,0 +1,90 @@
+use cgmath::Vector3;
+
+/// Points sorted into the cells of an unbounded uniform grid, with the cells
+/// hashed into a table about twice as large as the number of points.
+///
+/// Queries visit every point in the cells a cube touches, so callers still
+/// check the actual distance.
+#[derive(Clone, Debug)]
+pub struct SpatialHash {
+    spacing: f32,
+    /// Where the entries of each bucket begin, plus the end of the last.
+    starts: Vec<u32>,
+    /// Point indices grouped by bucket.
+    entries: Vec<u32>,
+}
+
+impl SpatialHash {
+    /// An empty hash with cells `spacing` wide, best about the query radius.
+    pub fn new(spacing: f32) -> Self {
+        Self {
+            spacing,
+            starts: vec![0; 2],
+            entries: Vec::new(),
+        }
+    }
+
+    pub fn spacing(&self) -> f32 {
+        self.spacing
+    }
+
+    /// Replaces the contents with `points`, which are referred to by index.
+    pub fn build(&mut self, points: &[Vector3<f32>]) {
+        let buckets = (2 * points.len()).next_power_of_two();
+        self.starts.clear();
+        self.starts.resize(buckets + 1, 0);
+        for &p in points {
+            let bucket = self.bucket(self.cell(p));
+            self.starts[bucket] += 1;
+        }
+        // Running totals give the end of each bucket, and filling counts them
+        // back down to the start.
+        let mut total = 0;
+        for start in &mut self.starts {
+            total += *start;
+            *start = total;
+        }
+        self.entries.clear();
+        self.entries.resize(points.len(), 0);
+        for (i, &p) in points.iter().enumerate() {
+            let bucket = self.bucket(self.cell(p));
+            self.starts[bucket] -= 1;
+            self.entries[self.starts[bucket] as usize] = i as u32;
+        }
+    }
+
+    /// Calls `f` once with every point in the cells within `radius` of
+    /// `point` on each axis.
+    pub fn query(&self, point: Vector3<f32>, radius: f32, mut f: impl FnMut(usize)) {
+        let offset = Vector3::new(radius, radius, radius);
+        let (min, max) = (self.cell(point - offset), self.cell(point + offset));
+        // Cells that share a bucket would otherwise list its points twice.
+        let mut visited = Vec::new();
+        for x in min[0]..=max[0] {
+            for y in min[1]..=max[1] {
+                for z in min[2]..=max[2] {
+                    let bucket = self.bucket([x, y, z]);
+                    if visited.contains(&bucket) {
+                        continue;
+                    }
+                    visited.push(bucket);
+                    let (start, end) = (self.starts[bucket], self.starts[bucket + 1]);
+                    for &entry in &self.entries[start as usize..end as usize] {
+                        f(entry as usize);
+                    }
+                }
+            }
+        }
+    }
+
+    fn cell(&self, p: Vector3<f32>) -> [i32; 3] {
+        [p.x, p.y, p.z].map(|c| (c / self.spacing).floor() as i32)
+    }
+
+    fn bucket(&self, cell: [i32; 3]) -> usize {
+        let hash = (cell[0] as u32).wrapping_mul(92837111)
+            ^ (cell[1] as u32).wrapping_mul(689287499)
+            ^ (cell[2] as u32).wrapping_mul(283923481);
+        hash as usize & (self.starts.len() - 2)
+    }
+}
@@ -0,0 +1,71 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Quaternion, Vector3};
+
+/// Rigid placement of a shape: rotation followed by translation.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Transform {
+    pub position: Vector3<f32>,
+    pub rotation: Quaternion<f32>,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    pub fn new(position: Vector3<f32>, rotation: Quaternion<f32>) -> Self {
+        Self { position, rotation }
+    }
+
+    pub fn identity() -> Self {
+        Self {
+            position: Vector3::zero(),
+            rotation: Quaternion::one(),
+        }
+    }
+
+    pub fn from_position(position: Vector3<f32>) -> Self {
+        Self {
+            position,
+            rotation: Quaternion::one(),
+        }
+    }
+
+    pub fn transform_point(&self, p: Point3<f32>) -> Point3<f32> {
+        Point3::from_vec(self.rotation * p.to_vec() + self.position)
+    }
+
+    pub fn transform_vector(&self, v: Vector3<f32>) -> Vector3<f32> {
+        self.rotation * v
+    }
+
+    pub fn inverse_transform_point(&self, p: Point3<f32>) -> Point3<f32> {
+        Point3::from_vec(self.rotation.conjugate() * (p.to_vec() - self.position))
+    }
+
+    pub fn inverse_transform_vector(&self, v: Vector3<f32>) -> Vector3<f32> {
+        self.rotation.conjugate() * v
+    }
+
+    pub fn inverse(&self) -> Self {
+        let rotation = self.rotation.conjugate();
+        Self {
+            position: rotation * -self.position,
+            rotation,
+        }
+    }
+}
+
+impl std::ops::Mul for Transform {
+    type Output = Transform;
+
+    /// Composes two transforms, `rhs` being expressed in the frame of `self`.
+    fn mul(self, rhs: Transform) -> Transform {
+        Transform {
+            position: self.rotation * rhs.position + self.position,
+            rotation: (self.rotation * rhs.rotation).normalize(),
+        }
+    }
+}
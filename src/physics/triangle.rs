@@ -0,0 +1,267 @@
+use cgmath::prelude::*;
+use cgmath::{Point3, Vector3};
+
+/// How a triangle edge relates to the triangle sharing it. Used to smooth
+/// contact normals so that shapes sliding over a flat or concave seam do not
+/// catch on the internal edge.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Edge {
+    /// No neighbor, contacts keep their geometric normal.
+    Boundary,
+    /// The neighbor bends away, normals are clamped between both faces.
+    Convex(Vector3<f32>),
+    /// The neighbor is coplanar or bends up, contacts use the face normal.
+    Smooth,
+}
+
+impl Edge {
+    /// Classifies the edge between a triangle and its `neighbor`.
+    pub fn classify(
+        normal: Vector3<f32>,
+        on_plane: Point3<f32>,
+        neighbor: Option<[Point3<f32>; 3]>,
+    ) -> Self {
+        let Some(neighbor) = neighbor else {
+            return Edge::Boundary;
+        };
+        let neighbor_normal = face_normal(neighbor);
+        // The two shared vertices lie on the plane, so this is the height of
+        // the opposite vertex.
+        let height: f32 = neighbor.iter().map(|v| normal.dot(v - on_plane)).sum();
+        let scale = neighbor[0]
+            .distance(neighbor[1])
+            .max(neighbor[1].distance(neighbor[2]));
+        if height < -1e-4 * scale && normal.dot(neighbor_normal) < 1.0 - 1e-4 {
+            Edge::Convex(neighbor_normal)
+        } else {
+            Edge::Smooth
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Feature {
+    Face,
+    /// Edge `i` runs from vertex `i` to vertex `i + 1`.
+    Edge(usize),
+    Vertex(usize),
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct Triangle {
+    pub vertices: [Point3<f32>; 3],
+    pub normal: Vector3<f32>,
+    pub edges: [Edge; 3],
+}
+
+impl Triangle {
+    pub fn new(vertices: [Point3<f32>; 3], edges: [Edge; 3]) -> Self {
+        Self {
+            vertices,
+            normal: face_normal(vertices),
+            edges,
+        }
+    }
+
+    pub fn signed_distance(&self, p: Point3<f32>) -> f32 {
+        self.normal.dot(p - self.vertices[0])
+    }
+
+    pub fn contains_projection(&self, p: Point3<f32>) -> bool {
+        (0..3).all(|i| {
+            let a = self.vertices[i];
+            let b = self.vertices[(i + 1) % 3];
+            (b - a).cross(p - a).dot(self.normal) >= 0.0
+        })
+    }
+
+    /// Closest point on the triangle to `p` and the feature it lies on.
+    pub fn closest_point(&self, p: Point3<f32>) -> (Point3<f32>, Feature) {
+        let [a, b, c] = self.vertices;
+        let ab = b - a;
+        let ac = c - a;
+        let ap = p - a;
+        let d1 = ab.dot(ap);
+        let d2 = ac.dot(ap);
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return (a, Feature::Vertex(0));
+        }
+
+        let bp = p - b;
+        let d3 = ab.dot(bp);
+        let d4 = ac.dot(bp);
+        if d3 >= 0.0 && d4 <= d3 {
+            return (b, Feature::Vertex(1));
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            let v = d1 / (d1 - d3);
+            return (a + ab * v, Feature::Edge(0));
+        }
+
+        let cp = p - c;
+        let d5 = ab.dot(cp);
+        let d6 = ac.dot(cp);
+        if d6 >= 0.0 && d5 <= d6 {
+            return (c, Feature::Vertex(2));
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            let w = d2 / (d2 - d6);
+            return (a + ac * w, Feature::Edge(2));
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+            let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+            return (b + (c - b) * w, Feature::Edge(1));
+        }
+
+        let denom = 1.0 / (va + vb + vc);
+        let v = vb * denom;
+        let w = vc * denom;
+        (a + ab * v + ac * w, Feature::Face)
+    }
+
+    /// Replaces the geometric normal of a contact on `feature` by one that
+    /// ignores internal edges.
+    pub fn smooth_normal(&self, feature: Feature, normal: Vector3<f32>) -> Vector3<f32> {
+        match feature {
+            Feature::Face => self.normal,
+            Feature::Edge(i) => match self.edges[i] {
+                Edge::Boundary => normal,
+                Edge::Smooth => self.normal,
+                Edge::Convex(other) => {
+                    let limit = self.normal.dot(other);
+                    if normal.dot(self.normal) >= limit && normal.dot(other) >= limit {
+                        normal
+                    } else if normal.dot(self.normal) >= normal.dot(other) {
+                        self.normal
+                    } else {
+                        other
+                    }
+                }
+            },
+            Feature::Vertex(i) => {
+                let incoming = self.edges[(i + 2) % 3];
+                let outgoing = self.edges[i];
+                if incoming == Edge::Smooth && outgoing == Edge::Smooth {
+                    self.normal
+                } else {
+                    normal
+                }
+            }
+        }
+    }
+}
+
+pub fn face_normal([a, b, c]: [Point3<f32>; 3]) -> Vector3<f32> {
+    let n = (b - a).cross(c - a);
+    let len = n.magnitude();
+    if len > 0.0 {
+        n / len
+    } else {
+        Vector3::unit_y()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> Triangle {
+        Triangle::new(
+            [
+                Point3::new(0.0, 0.0, 0.0),
+                Point3::new(0.0, 0.0, 2.0),
+                Point3::new(2.0, 0.0, 0.0),
+            ],
+            [Edge::Boundary; 3],
+        )
+    }
+
+    #[test]
+    fn closest_point_regions() {
+        let tri = triangle();
+        assert_eq!(tri.normal, Vector3::unit_y());
+        let cases = [
+            (
+                Point3::new(0.5, 1.0, 0.5),
+                Point3::new(0.5, 0.0, 0.5),
+                Feature::Face,
+            ),
+            (
+                Point3::new(-1.0, 1.0, -1.0),
+                Point3::new(0.0, 0.0, 0.0),
+                Feature::Vertex(0),
+            ),
+            (
+                Point3::new(-0.5, 0.0, 3.0),
+                Point3::new(0.0, 0.0, 2.0),
+                Feature::Vertex(1),
+            ),
+            (
+                Point3::new(3.0, -1.0, -0.5),
+                Point3::new(2.0, 0.0, 0.0),
+                Feature::Vertex(2),
+            ),
+            (
+                Point3::new(-1.0, 0.0, 1.0),
+                Point3::new(0.0, 0.0, 1.0),
+                Feature::Edge(0),
+            ),
+            (
+                Point3::new(2.0, 0.0, 2.0),
+                Point3::new(1.0, 0.0, 1.0),
+                Feature::Edge(1),
+            ),
+            (
+                Point3::new(1.0, 0.5, -1.0),
+                Point3::new(1.0, 0.0, 0.0),
+                Feature::Edge(2),
+            ),
+        ];
+        for (p, closest, feature) in cases {
+            let (point, found) = tri.closest_point(p);
+            assert!(point.distance(closest) < 1e-5, "{p:?}: {point:?}");
+            assert_eq!(found, feature, "{p:?}");
+        }
+    }
+
+    #[test]
+    fn edge_classification() {
+        let tri = triangle();
+        let [a, b, _] = tri.vertices;
+        let classify =
+            |opposite: Point3<f32>| Edge::classify(tri.normal, a, Some([b, a, opposite]));
+        assert_eq!(Edge::classify(tri.normal, a, None), Edge::Boundary);
+        // Coplanar and folding up toward the normal.
+        assert_eq!(classify(Point3::new(-2.0, 0.0, 1.0)), Edge::Smooth);
+        assert_eq!(classify(Point3::new(-2.0, 1.0, 1.0)), Edge::Smooth);
+        // Folding down away from it.
+        let down = classify(Point3::new(-2.0, -1.0, 1.0));
+        let expected = face_normal([b, a, Point3::new(-2.0, -1.0, 1.0)]);
+        assert_eq!(down, Edge::Convex(expected));
+    }
+
+    #[test]
+    fn smooth_normals() {
+        let mut tri = triangle();
+        let tilted = Vector3::new(-1.0, 1.0, 0.0).normalize();
+        assert_eq!(tri.smooth_normal(Feature::Face, tilted), tri.normal);
+        assert_eq!(tri.smooth_normal(Feature::Edge(0), tilted), tilted);
+
+        tri.edges[0] = Edge::Smooth;
+        assert_eq!(tri.smooth_normal(Feature::Edge(0), tilted), tri.normal);
+        // Vertex 0 also touches edge 2, which is still a boundary.
+        assert_eq!(tri.smooth_normal(Feature::Vertex(0), tilted), tilted);
+
+        let other = Vector3::new(-1.0, 0.0, 0.0);
+        tri.edges[0] = Edge::Convex(other);
+        assert_eq!(tri.smooth_normal(Feature::Edge(0), tilted), tilted);
+        let beyond = Vector3::new(-1.0, -0.5, 0.0).normalize();
+        assert_eq!(tri.smooth_normal(Feature::Edge(0), beyond), other);
+    }
+}
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+use cgmath::Point3;
+
+use super::bvh::Bvh;
+use super::shape::{signed_volume, Aabb};
+use super::triangle::{Edge, Triangle};
+
+/// Static triangle soup collider with a BVH for finding candidate triangles.
+#[derive(Clone, Debug)]
+pub struct TriMesh {
+    vertices: Vec<Point3<f32>>,
+    triangles: Vec<[u32; 3]>,
+    edges: Vec<[Edge; 3]>,
+    bvh: Bvh,
+}
+
+impl TriMesh {
+    pub fn new(positions: &[[f32; 3]], indices: &[u32]) -> Self {
+        let vertices = positions
+            .iter()
+            .map(|p| Point3::from(*p))
+            .collect::<Vec<_>>();
+        let triangles = indices
+            .chunks_exact(3)
+            .map(|t| [t[0], t[1], t[2]])
+            .filter(|t| t.iter().all(|&i| (i as usize) < vertices.len()))
+            .collect::<Vec<_>>();
+
+        let aabbs = triangles
+            .iter()
+            .map(|t| Aabb::from_points(&t.map(|i| vertices[i as usize])))
+            .collect::<Vec<_>>();
+        let bvh = Bvh::build(&aabbs);
+        let edges = classify_edges(&vertices, &triangles);
+
+        Self {
+            vertices,
+            triangles,
+            edges,
+            bvh,
+        }
+    }
+
+    pub fn vertices(&self) -> &[Point3<f32>] {
+        &self.vertices
+    }
+
+    pub fn triangles(&self) -> &[[u32; 3]] {
+        &self.triangles
+    }
+
+    pub fn triangle(&self, i: usize) -> Triangle {
+        let [a, b, c] = self.triangles[i];
+        Triangle::new(
+            [
+                self.vertices[a as usize],
+                self.vertices[b as usize],
+                self.vertices[c as usize],
+            ],
+            self.edges[i],
+        )
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        self.bvh.root_aabb()
+    }
+
+    /// Indices of the triangles whose bounds overlap `aabb`.
+    pub fn query(&self, aabb: &Aabb, f: impl FnMut(usize)) {
+        self.bvh.query(aabb, f);
+    }
+
+    /// Enclosed volume, only meaningful for closed meshes.
+    pub fn volume(&self) -> f32 {
+        signed_volume(&self.vertices, &self.triangles).abs()
+    }
+}
+
+/// Finds triangle neighbors through welded positions, since OBJ loading
+/// duplicates vertices along UV and normal seams.
+fn classify_edges(vertices: &[Point3<f32>], triangles: &[[u32; 3]]) -> Vec<[Edge; 3]> {
+    let scale = Aabb::from_points(vertices)
+        .half_extents()
+        .magnitude()
+        .max(1.0);
+    let quantum = scale * 1e-5;
+    let mut welded = HashMap::new();
+    let ids = vertices
+        .iter()
+        .map(|p| {
+            let key = [p.x, p.y, p.z].map(|c| (c / quantum).round() as i64);
+            let next = welded.len();
+            *welded.entry(key).or_insert(next)
+        })
+        .collect::<Vec<_>>();
+
+    let mut owners: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+    for (t, tri) in triangles.iter().enumerate() {
+        for i in 0..3 {
+            let a = ids[tri[i] as usize];
+            let b = ids[tri[(i + 1) % 3] as usize];
+            owners.entry((a.min(b), a.max(b))).or_default().push(t);
+        }
+    }
+
+    let corners = |t: usize| triangles[t].map(|i| vertices[i as usize]);
+    triangles
+        .iter()
+        .enumerate()
+        .map(|(t, tri)| {
+            let points = corners(t);
+            let normal = super::triangle::face_normal(points);
+            std::array::from_fn(|i| {
+                let a = ids[tri[i] as usize];
+                let b = ids[tri[(i + 1) % 3] as usize];
+                // Edges shared by more than two triangles are not manifold, so
+                // there is no single neighbor to smooth against.
+                let neighbor = match owners[&(a.min(b), a.max(b))].as_slice() {
+                    [x, y] => Some(if *x == t { *y } else { *x }),
+                    _ => None,
+                };
+                Edge::classify(normal, points[0], neighbor.map(corners))
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Vector3;
+
+    use super::*;
+    use crate::physics::contact::static_contacts;
+    use crate::physics::shape::{Shape, Sphere};
+    use crate::physics::transform::Transform;
+
+    /// Two triangles of the unit quad on y = 0, unwelded along the diagonal
+    /// like a mesh with a UV seam there.
+    fn flat_quad() -> TriMesh {
+        TriMesh::new(
+            &[
+                [0.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 0.0, 1.0],
+                [0.0, 0.0, 0.0],
+                [1.0, 0.0, 1.0],
+                [1.0, 0.0, 0.0],
+            ],
+            &[0, 1, 2, 3, 4, 5],
+        )
+    }
+
+    #[test]
+    fn welded_flat_quad_has_no_convex_edges() {
+        let mesh = flat_quad();
+        assert_eq!(mesh.edges[0][2], Edge::Smooth);
+        assert_eq!(mesh.edges[1][0], Edge::Smooth);
+        let boundaries = mesh
+            .edges
+            .iter()
+            .flatten()
+            .filter(|&&e| e == Edge::Boundary)
+            .count();
+        assert_eq!(boundaries, 4);
+        assert!(!mesh
+            .edges
+            .iter()
+            .flatten()
+            .any(|e| matches!(e, Edge::Convex(_))));
+    }
+
+    #[test]
+    fn ridge_is_convex() {
+        let mesh = TriMesh::new(
+            &[
+                [0.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0],
+                [1.0, 0.5, 1.0],
+                [1.0, 0.5, 0.0],
+                [2.0, 0.0, 1.0],
+                [2.0, 0.0, 0.0],
+            ],
+            &[0, 1, 2, 0, 2, 3, 3, 2, 4, 3, 4, 5],
+        );
+        let convex = mesh
+            .edges
+            .iter()
+            .flatten()
+            .filter(|e| matches!(e, Edge::Convex(_)))
+            .count();
+        // The ridge edge, seen from either side.
+        assert_eq!(convex, 2);
+    }
+
+    #[test]
+    fn sphere_on_shared_edge_gets_face_normal() {
+        let mesh = Shape::TriMesh(flat_quad());
+        let sphere = Shape::Sphere(Sphere {
+            center: Point3::new(0.0, 0.0, 0.0),
+            radius: 0.5,
+        });
+        // Sunk into the second triangle next to the diagonal, which is the
+        // closest feature of the first one.
+        let transform = Transform::from_position(Vector3::new(0.6, 0.4, 0.4));
+        let contacts = static_contacts(&mesh, &Transform::identity(), &sphere, &transform);
+        assert!(!contacts.is_empty());
+        for contact in contacts {
+            assert!((contact.normal - Vector3::unit_y()).magnitude() < 1e-5);
+            assert!(contact.depth > 0.0);
+        }
+    }
+
+    #[test]
+    fn query_finds_overlapping_triangles() {
+        let mesh = flat_quad();
+        let mut found = Vec::new();
+        let corner = Aabb {
+            min: Point3::new(0.8, -0.1, 0.0),
+            max: Point3::new(1.0, 0.1, 0.1),
+        };
+        mesh.query(&corner, |i| found.push(i));
+        found.sort();
+        assert_eq!(found, [0, 1]);
+
+        found.clear();
+        let away = corner.transformed(&Transform::from_position(Vector3::new(0.0, 2.0, 0.0)));
+        mesh.query(&away, |i| found.push(i));
+        assert!(found.is_empty());
+    }
+}
@@ -0,0 +1,675 @@
+use std::collections::HashMap;
+
+use cgmath::prelude::*;
+use cgmath::{Matrix3, Point3, Vector3};
+
+use super::body::RigidBody;
+use super::contact::{contacts, Contact};
+use super::forces::{ForceContext, ForceGenerator, ForceScope, Gravity};
+
+/// Stable reference to a body in a `World`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct BodyHandle(usize);
+
+/// Reference to a force generator added to a `World`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ForceHandle(usize);
+
+/// Bodies below these squared speeds for `SLEEP_TIME` seconds go to sleep.
+const SLEEP_LINEAR: f32 = 0.01;
+const SLEEP_ANGULAR: f32 = 0.01;
+const SLEEP_TIME: f32 = 0.5;
+
+/// Penetration that is left alone to keep resting contacts stable.
+const SLOP: f32 = 0.005;
+const BAUMGARTE: f32 = 0.2;
+/// Approach speed below which contacts don't bounce.
+const RESTITUTION_THRESHOLD: f32 = 1.0;
+/// How far a contact may move between steps and still count as the same.
+const WARM_START_DISTANCE: f32 = 0.05;
+
+pub struct World {
+    /// Length of a single simulation step in seconds.
+    pub timestep: f32,
+    /// Upper bound on steps per `update`, so a long frame doesn't snowball.
+    pub max_steps: u32,
+    pub solver_iterations: u32,
+    bodies: Vec<Option<RigidBody>>,
+    free: Vec<usize>,
+    accumulator: f32,
+    time: f32,
+    forces: Vec<Option<ScopedForce>>,
+    contacts: Vec<BodyContact>,
+    /// Impulses solved in the previous step, used to warm start the solver.
+    impulses: HashMap<(usize, usize), Vec<CachedImpulse>>,
+}
+
+struct ScopedForce {
+    generator: Box<dyn ForceGenerator>,
+    scope: ForceScope,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct CachedImpulse {
+    point: Point3<f32>,
+    normal: f32,
+    tangent: [f32; 2],
+}
+
+/// A contact between two bodies, normal pointing from `a` toward `b`.
+#[derive(Copy, Clone, Debug)]
+pub struct BodyContact {
+    pub a: BodyHandle,
+    pub b: BodyHandle,
+    pub contact: Contact,
+}
+
+impl Default for World {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl World {
+    /// The generator for the gravity every world starts out with.
+    pub const GRAVITY: ForceHandle = ForceHandle(0);
+
+    /// Creates an empty world with Earth gravity on every body, added as a
+    /// [`Gravity`] generator under [`World::GRAVITY`]. Remove it or narrow
+    /// its scope to set up gravity differently.
+    pub fn new() -> Self {
+        let mut world = Self {
+            timestep: 1.0 / 120.0,
+            max_steps: 8,
+            solver_iterations: 10,
+            bodies: Vec::new(),
+            free: Vec::new(),
+            accumulator: 0.0,
+            time: 0.0,
+            forces: Vec::new(),
+            contacts: Vec::new(),
+            impulses: HashMap::new(),
+        };
+        world.add_force(
+            Gravity {
+                acceleration: Vector3::new(0.0, -9.81, 0.0),
+            },
+            ForceScope::all(),
+        );
+        world
+    }
+
+    pub fn add_body(&mut self, body: RigidBody) -> BodyHandle {
+        match self.free.pop() {
+            Some(i) => {
+                self.bodies[i] = Some(body);
+                BodyHandle(i)
+            }
+            None => {
+                self.bodies.push(Some(body));
+                BodyHandle(self.bodies.len() - 1)
+            }
+        }
+    }
+
+    pub fn remove_body(&mut self, handle: BodyHandle) -> Option<RigidBody> {
+        let body = self.bodies.get_mut(handle.0)?.take()?;
+        self.free.push(handle.0);
+        // A body added to the slot later must not start from these impulses.
+        self.impulses
+            .retain(|&(a, b), _| a != handle.0 && b != handle.0);
+        // Whatever was resting on the body has to fall now.
+        for c in &self.contacts {
+            let other = if c.a == handle {
+                c.b
+            } else if c.b == handle {
+                c.a
+            } else {
+                continue;
+            };
+            if let Some(Some(other)) = self.bodies.get_mut(other.0) {
+                other.wake();
+            }
+        }
+        Some(body)
+    }
+
+    pub fn body(&self, handle: BodyHandle) -> Option<&RigidBody> {
+        self.bodies.get(handle.0)?.as_ref()
+    }
+
+    pub fn body_mut(&mut self, handle: BodyHandle) -> Option<&mut RigidBody> {
+        self.bodies.get_mut(handle.0)?.as_mut()
+    }
+
+    pub fn bodies(&self) -> impl Iterator<Item = (BodyHandle, &RigidBody)> {
+        self.bodies
+            .iter()
+            .enumerate()
+            .filter_map(|(i, b)| Some((BodyHandle(i), b.as_ref()?)))
+    }
+
+    pub fn len(&self) -> usize {
+        self.bodies.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Adds a generator applied every step to the dynamic bodies in `scope`.
+    pub fn add_force(
+        &mut self,
+        generator: impl ForceGenerator + 'static,
+        scope: ForceScope,
+    ) -> ForceHandle {
+        let force = Some(ScopedForce {
+            generator: Box::new(generator),
+            scope,
+        });
+        match self.forces.iter().position(Option::is_none) {
+            Some(i) => {
+                self.forces[i] = force;
+                ForceHandle(i)
+            }
+            None => {
+                self.forces.push(force);
+                ForceHandle(self.forces.len() - 1)
+            }
+        }
+    }
+
+    /// Removes a generator. Generators that finished on their own are gone
+    /// already.
+    pub fn remove_force(&mut self, handle: ForceHandle) -> Option<Box<dyn ForceGenerator>> {
+        Some(self.forces.get_mut(handle.0)?.take()?.generator)
+    }
+
+    pub fn force_scope_mut(&mut self, handle: ForceHandle) -> Option<&mut ForceScope> {
+        Some(&mut self.forces.get_mut(handle.0)?.as_mut()?.scope)
+    }
+
+    /// Gravitational acceleration at `point` for something on `layers`, from
+    /// every generator in scope there. Simulations that don't run the force
+    /// generators, like fluids and cloth, take their gravity from this.
+    pub fn gravity_at(&self, point: Point3<f32>, layers: u32) -> Vector3<f32> {
+        self.forces
+            .iter()
+            .flatten()
+            .filter(|force| force.scope.contains_point(point, layers))
+            .map(|force| force.generator.gravity_at(point))
+            .sum()
+    }
+
+    /// Simulated time in seconds, advanced by every step.
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Contacts found during the last step.
+    pub fn contacts(&self) -> &[BodyContact] {
+        &self.contacts
+    }
+
+    /// Advances the simulation by `dt` seconds in fixed-size steps, carrying
+    /// the remainder over to the next call.
+    pub fn update(&mut self, dt: f32) {
+        self.accumulator += dt;
+        let mut steps = 0;
+        while self.accumulator >= self.timestep {
+            self.accumulator -= self.timestep;
+            if steps == self.max_steps {
+                self.accumulator = 0.0;
+                break;
+            }
+            self.step(self.timestep);
+            steps += 1;
+        }
+    }
+
+    pub fn step(&mut self, dt: f32) {
+        self.apply_forces(dt);
+
+        self.find_contacts();
+        self.update_islands(false);
+        self.solve(dt);
+
+        for body in self.bodies.iter_mut().flatten() {
+            if body.is_fixed() || body.sleeping {
+                continue;
+            }
+            body.integrate(dt);
+            let resting = body.linear_velocity.magnitude2() < SLEEP_LINEAR
+                && body.angular_velocity.magnitude2() < SLEEP_ANGULAR;
+            body.sleep_time = if resting { body.sleep_time + dt } else { 0.0 };
+        }
+        self.update_islands(true);
+        self.time += dt;
+    }
+
+    /// Runs the force generators, turning the forces into velocity.
+    fn apply_forces(&mut self, dt: f32) {
+        let context = ForceContext {
+            dt,
+            time: self.time,
+            gravity: Vector3::zero(),
+        };
+        let gravity = self
+            .bodies
+            .iter()
+            .map(|body| match body {
+                Some(body) if !body.is_fixed() => {
+                    self.gravity_at(Point3::from_vec(body.transform.position), body.layers)
+                }
+                _ => Vector3::zero(),
+            })
+            .collect::<Vec<_>>();
+        for force in self.forces.iter_mut().flatten() {
+            let wakes = force.generator.wakes_bodies();
+            for (body, &gravity) in self.bodies.iter_mut().zip(&gravity) {
+                let Some(body) = body else { continue };
+                if body.is_fixed() || (body.sleeping && !wakes) || !force.scope.contains(body) {
+                    continue;
+                }
+                force
+                    .generator
+                    .apply(body, &ForceContext { gravity, ..context });
+            }
+            force.generator.end_step(&context);
+        }
+        for force in &mut self.forces {
+            if force.as_ref().is_some_and(|f| f.generator.is_finished()) {
+                *force = None;
+            }
+        }
+
+        for body in self.bodies.iter_mut().flatten() {
+            body.apply_forces(dt);
+        }
+    }
+
+    /// Sweep and prune along X, then narrow phase on the overlapping pairs.
+    fn find_contacts(&mut self) {
+        self.contacts.clear();
+        let mut order = self
+            .bodies()
+            .map(|(handle, body)| (handle, body.aabb()))
+            .collect::<Vec<_>>();
+        order.sort_by(|(_, a), (_, b)| {
+            a.min
+                .x
+                .partial_cmp(&b.min.x)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        for (i, (ha, aabb_a)) in order.iter().enumerate() {
+            let a = self.bodies[ha.0].as_ref().unwrap();
+            for (hb, aabb_b) in &order[i + 1..] {
+                if aabb_b.min.x > aabb_a.max.x {
+                    break;
+                }
+                let b = self.bodies[hb.0].as_ref().unwrap();
+                let a_moves = !a.is_fixed() && !a.sleeping;
+                let b_moves = !b.is_fixed() && !b.sleeping;
+                if !(a_moves || b_moves) || !aabb_a.intersects(aabb_b) {
+                    continue;
+                }
+                for contact in contacts(a.shape(), &a.transform, b.shape(), &b.transform) {
+                    self.contacts.push(BodyContact {
+                        a: *ha,
+                        b: *hb,
+                        contact,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Groups dynamic bodies connected through contacts. Islands with a
+    /// moving body get woken up; with `sleep`, islands that have been resting
+    /// long enough are put to sleep as a whole.
+    fn update_islands(&mut self, sleep: bool) {
+        let mut parent = (0..self.bodies.len()).collect::<Vec<_>>();
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        for c in &self.contacts {
+            let fixed = |h: BodyHandle| self.bodies[h.0].as_ref().is_none_or(RigidBody::is_fixed);
+            if fixed(c.a) || fixed(c.b) {
+                continue;
+            }
+            let (ra, rb) = (root(&mut parent, c.a.0), root(&mut parent, c.b.0));
+            parent[ra] = rb;
+        }
+
+        let mut awake = vec![false; self.bodies.len()];
+        let mut restless = vec![false; self.bodies.len()];
+        for (i, body) in self.bodies.iter().enumerate() {
+            let Some(body) = body else { continue };
+            let r = root(&mut parent, i);
+            awake[r] |= !body.is_fixed() && !body.sleeping;
+            restless[r] |= !body.is_fixed() && body.sleep_time < SLEEP_TIME;
+        }
+
+        for (i, body) in self.bodies.iter_mut().enumerate() {
+            let Some(body) = body else { continue };
+            let r = root(&mut parent, i);
+            body.island = r;
+            if body.is_fixed() {
+                continue;
+            }
+            if sleep && !restless[r] {
+                body.sleeping = true;
+                body.linear_velocity = Vector3::zero();
+                body.angular_velocity = Vector3::zero();
+            } else if awake[r] && body.sleeping {
+                body.wake();
+            }
+        }
+    }
+
+    /// Sequential impulses with accumulated clamping, Coulomb friction and
+    /// Baumgarte stabilization, warm started from the previous step.
+    fn solve(&mut self, dt: f32) {
+        struct Constraint {
+            point: Point3<f32>,
+            a: usize,
+            b: usize,
+            ra: Vector3<f32>,
+            rb: Vector3<f32>,
+            normal: Vector3<f32>,
+            tangents: [Vector3<f32>; 2],
+            normal_mass: f32,
+            tangent_mass: [f32; 2],
+            bias: f32,
+            friction: f32,
+            normal_impulse: f32,
+            tangent_impulse: [f32; 2],
+        }
+
+        struct Velocity {
+            linear: Vector3<f32>,
+            angular: Vector3<f32>,
+            inverse_mass: f32,
+            inverse_inertia: Matrix3<f32>,
+        }
+
+        let mut velocities = self
+            .bodies
+            .iter()
+            .map(|body| match body {
+                Some(body) if !body.is_fixed() && !body.sleeping => Velocity {
+                    linear: body.linear_velocity,
+                    angular: body.angular_velocity,
+                    inverse_mass: body.inverse_mass(),
+                    inverse_inertia: body.world_inverse_inertia(),
+                },
+                _ => Velocity {
+                    linear: Vector3::zero(),
+                    angular: Vector3::zero(),
+                    inverse_mass: 0.0,
+                    inverse_inertia: Matrix3::zero(),
+                },
+            })
+            .collect::<Vec<_>>();
+
+        let effective_mass =
+            |va: &Velocity, vb: &Velocity, ra: Vector3<f32>, rb: Vector3<f32>, n: Vector3<f32>| {
+                let ka = (va.inverse_inertia * ra.cross(n)).cross(ra);
+                let kb = (vb.inverse_inertia * rb.cross(n)).cross(rb);
+                let k = va.inverse_mass + vb.inverse_mass + n.dot(ka + kb);
+                if k > 0.0 {
+                    1.0 / k
+                } else {
+                    0.0
+                }
+            };
+        let relative_velocity =
+            |va: &Velocity, vb: &Velocity, ra: Vector3<f32>, rb: Vector3<f32>| {
+                (vb.linear + vb.angular.cross(rb)) - (va.linear + va.angular.cross(ra))
+            };
+
+        let mut constraints = Vec::with_capacity(self.contacts.len());
+        for c in &self.contacts {
+            let (Some(a), Some(b)) = (&self.bodies[c.a.0], &self.bodies[c.b.0]) else {
+                continue;
+            };
+            let point: Point3<f32> = c.contact.point;
+            let normal = c.contact.normal;
+            let ra = point.to_vec() - a.transform.position;
+            let rb = point.to_vec() - b.transform.position;
+            let (va, vb) = (&velocities[c.a.0], &velocities[c.b.0]);
+
+            let tangent = if normal.x.abs() > 0.57 {
+                Vector3::new(normal.y, -normal.x, 0.0)
+            } else {
+                Vector3::new(0.0, normal.z, -normal.y)
+            }
+            .normalize();
+            let tangents = [tangent, normal.cross(tangent)];
+
+            let approach = relative_velocity(va, vb, ra, rb).dot(normal);
+            let restitution = a.restitution.max(b.restitution);
+            let bounce = if approach < -RESTITUTION_THRESHOLD {
+                -restitution * approach
+            } else {
+                0.0
+            };
+            let push = BAUMGARTE / dt * (c.contact.depth - SLOP).max(0.0);
+
+            let cached = self
+                .impulses
+                .get(&(c.a.0, c.b.0))
+                .and_then(|cached| {
+                    cached.iter().find(|i| {
+                        i.point.distance2(point) < WARM_START_DISTANCE * WARM_START_DISTANCE
+                    })
+                })
+                .copied();
+
+            constraints.push(Constraint {
+                point,
+                a: c.a.0,
+                b: c.b.0,
+                ra,
+                rb,
+                normal,
+                tangents,
+                normal_mass: effective_mass(va, vb, ra, rb, normal),
+                tangent_mass: tangents.map(|t| effective_mass(va, vb, ra, rb, t)),
+                bias: bounce.max(push),
+                friction: (a.friction * b.friction).sqrt(),
+                normal_impulse: cached.map_or(0.0, |i| i.normal),
+                tangent_impulse: cached.map_or([0.0; 2], |i| i.tangent),
+            });
+        }
+
+        let apply = |velocities: &mut [Velocity], c: &Constraint, impulse: Vector3<f32>| {
+            let va = &mut velocities[c.a];
+            va.linear -= impulse * va.inverse_mass;
+            va.angular -= va.inverse_inertia * c.ra.cross(impulse);
+            let vb = &mut velocities[c.b];
+            vb.linear += impulse * vb.inverse_mass;
+            vb.angular += vb.inverse_inertia * c.rb.cross(impulse);
+        };
+
+        for c in &constraints {
+            let impulse = c.normal * c.normal_impulse
+                + c.tangents[0] * c.tangent_impulse[0]
+                + c.tangents[1] * c.tangent_impulse[1];
+            apply(&mut velocities, c, impulse);
+        }
+
+        for _ in 0..self.solver_iterations {
+            for c in &mut constraints {
+                for k in 0..2 {
+                    let v = relative_velocity(&velocities[c.a], &velocities[c.b], c.ra, c.rb);
+                    let limit = c.friction * c.normal_impulse;
+                    let lambda = -v.dot(c.tangents[k]) * c.tangent_mass[k];
+                    let total = (c.tangent_impulse[k] + lambda).clamp(-limit, limit);
+                    let delta = total - c.tangent_impulse[k];
+                    c.tangent_impulse[k] = total;
+                    apply(&mut velocities, c, c.tangents[k] * delta);
+                }
+
+                let v = relative_velocity(&velocities[c.a], &velocities[c.b], c.ra, c.rb);
+                let lambda = (c.bias - v.dot(c.normal)) * c.normal_mass;
+                let total = (c.normal_impulse + lambda).max(0.0);
+                let delta = total - c.normal_impulse;
+                c.normal_impulse = total;
+                apply(&mut velocities, c, c.normal * delta);
+            }
+        }
+
+        self.impulses.clear();
+        for c in &constraints {
+            self.impulses
+                .entry((c.a, c.b))
+                .or_default()
+                .push(CachedImpulse {
+                    point: c.point,
+                    normal: c.normal_impulse,
+                    tangent: c.tangent_impulse,
+                });
+        }
+
+        for (body, velocity) in self.bodies.iter_mut().zip(velocities) {
+            if let Some(body) = body {
+                if !body.is_fixed() && !body.sleeping {
+                    body.linear_velocity = velocity.linear;
+                    body.angular_velocity = velocity.angular;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::physics::shape::{Obb, Shape, Sphere};
+    use crate::physics::transform::Transform;
+    use cgmath::Quaternion;
+
+    fn ground() -> RigidBody {
+        RigidBody::fixed(
+            Shape::Cuboid(Obb {
+                center: Point3::origin(),
+                half_extents: Vector3::new(10.0, 0.5, 10.0),
+                rotation: Quaternion::one(),
+            }),
+            Transform::from_position(Vector3::new(0.0, -0.5, 0.0)),
+        )
+    }
+
+    fn cube(position: Vector3<f32>) -> RigidBody {
+        RigidBody::dynamic(
+            Shape::Cuboid(Obb {
+                center: Point3::origin(),
+                half_extents: Vector3::from_value(0.5),
+                rotation: Quaternion::one(),
+            }),
+            1.0,
+            Transform::from_position(position),
+        )
+    }
+
+    fn ball(position: Vector3<f32>, velocity: Vector3<f32>) -> RigidBody {
+        let mut body = RigidBody::dynamic(
+            Shape::Sphere(Sphere {
+                center: Point3::origin(),
+                radius: 0.5,
+            }),
+            1.0,
+            Transform::from_position(position),
+        );
+        body.linear_velocity = velocity;
+        body.linear_damping = 0.0;
+        body
+    }
+
+    #[test]
+    fn bodies_fall_with_gravity() {
+        let mut world = World::new();
+        let handle = world.add_body(ball(Vector3::new(0.0, 10.0, 0.0), Vector3::zero()));
+        for _ in 0..60 {
+            world.update(world.timestep);
+        }
+
+        let body = world.body(handle).unwrap();
+        assert!((world.time() - 0.5).abs() < 1e-4);
+        assert!((body.linear_velocity.y + 9.81 * 0.5).abs() < 1e-3);
+        // Semi-implicit Euler lands slightly ahead of ½gt².
+        let fallen = 10.0 - body.transform.position.y;
+        assert!((fallen - 0.5 * 9.81 * 0.25).abs() < 0.03, "{}", fallen);
+    }
+
+    #[test]
+    fn stacked_cubes_come_to_rest_and_sleep() {
+        let mut world = World::new();
+        world.add_body(ground());
+        let lower = world.add_body(cube(Vector3::new(0.0, 0.55, 0.0)));
+        let upper = world.add_body(cube(Vector3::new(0.05, 1.65, 0.0)));
+        for _ in 0..60 {
+            world.step(world.timestep);
+        }
+        assert_eq!(
+            world.body(lower).unwrap().island(),
+            world.body(upper).unwrap().island()
+        );
+        for _ in 0..600 {
+            world.step(world.timestep);
+        }
+
+        for (handle, height) in [(lower, 0.5), (upper, 1.5)] {
+            let body = world.body(handle).unwrap();
+            assert!(
+                (body.transform.position.y - height).abs() < 0.02,
+                "{:?}",
+                body.transform
+            );
+            assert!(body.transform.rotation.s.abs() > 0.999);
+            assert!(body.is_sleeping());
+        }
+    }
+
+    #[test]
+    fn equal_balls_swap_velocities_in_an_elastic_collision() {
+        let mut world = World::new();
+        world.remove_force(World::GRAVITY);
+        let mut left = ball(Vector3::new(-1.0, 0.0, 0.0), Vector3::new(3.0, 0.0, 0.0));
+        let mut right = ball(Vector3::new(1.0, 0.0, 0.0), Vector3::new(-3.0, 0.0, 0.0));
+        left.restitution = 1.0;
+        right.restitution = 1.0;
+        let left = world.add_body(left);
+        let right = world.add_body(right);
+        for _ in 0..60 {
+            world.step(world.timestep);
+        }
+
+        let left = world.body(left).unwrap().linear_velocity;
+        let right = world.body(right).unwrap().linear_velocity;
+        assert!((left.x + 3.0).abs() < 0.1, "{:?}", left);
+        assert!((right.x - 3.0).abs() < 0.1, "{:?}", right);
+        assert!((left + right).magnitude() < 1e-4);
+    }
+
+    #[test]
+    fn removed_bodies_leave_no_warm_start_behind() {
+        let mut world = World::new();
+        let floor = world.add_body(ground());
+        let resting = world.add_body(cube(Vector3::new(0.0, 0.5, 0.0)));
+        world.step(world.timestep);
+        assert!(world.impulses.contains_key(&(floor.0, resting.0)));
+
+        world.remove_body(resting);
+        assert!(world.impulses.is_empty());
+        // The slot is reused by the next body.
+        let dropped = world.add_body(cube(Vector3::new(0.0, 0.5, 0.0)));
+        assert_eq!(dropped, resting);
+    }
+}
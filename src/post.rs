@@ -0,0 +1,433 @@
+/// Format of the target the scene is rendered into before post-processing.
+pub const HDR_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+
+/// Number of halvings in the bloom chain, fewer for tiny surfaces.
+const BLOOM_MIPS: u32 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ToneMapping {
+    Reinhard,
+    Aces,
+    AgX,
+}
+
+impl ToneMapping {
+    pub fn next(self) -> Self {
+        match self {
+            ToneMapping::Reinhard => ToneMapping::Aces,
+            ToneMapping::Aces => ToneMapping::AgX,
+            ToneMapping::AgX => ToneMapping::Reinhard,
+        }
+    }
+
+    /// Matches the `TONE_MAPPING_*` constants in `post.wgsl`.
+    fn shader_id(self) -> u32 {
+        match self {
+            ToneMapping::Reinhard => 0,
+            ToneMapping::Aces => 1,
+            ToneMapping::AgX => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct PostSettings {
+    /// Exposure compensation in stops.
+    pub exposure: f32,
+    pub tone_mapping: ToneMapping,
+    pub bloom: bool,
+    pub bloom_intensity: f32,
+    /// Brightness above which pixels start to bloom.
+    pub bloom_threshold: f32,
+}
+
+impl Default for PostSettings {
+    fn default() -> Self {
+        Self {
+            exposure: 0.0,
+            tone_mapping: ToneMapping::AgX,
+            bloom: true,
+            bloom_intensity: 0.3,
+            bloom_threshold: 1.0,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct PostUniform {
+    exposure: f32,
+    tone_mapping: u32,
+    bloom_intensity: f32,
+    bloom_threshold: f32,
+    encode_srgb: u32,
+    _padding: [u32; 3],
+}
+
+/// Size-dependent targets, recreated on resize.
+struct Targets {
+    hdr_view: wgpu::TextureView,
+    bloom_views: Vec<wgpu::TextureView>,
+    /// Samples the previous level for each bloom level, the HDR target first.
+    downsample_bind_groups: Vec<wgpu::BindGroup>,
+    /// Samples level `i + 1` for drawing onto level `i`.
+    upsample_bind_groups: Vec<wgpu::BindGroup>,
+    tonemap_bind_group: wgpu::BindGroup,
+}
+
+/// Post-processing chain run after the main pass: bloom extracted from the
+/// HDR target, then exposure and tone mapping onto the surface.
+pub struct PostProcess {
+    pub settings: PostSettings,
+    encode_srgb: bool,
+    uniform_buffer: wgpu::Buffer,
+    sampler: wgpu::Sampler,
+    sample_layout: wgpu::BindGroupLayout,
+    tonemap_layout: wgpu::BindGroupLayout,
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
+    upsample_pipeline: wgpu::RenderPipeline,
+    tonemap_pipeline: wgpu::RenderPipeline,
+    targets: Targets,
+}
+
+impl PostProcess {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let uniform_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Post Buffer"),
+            size: std::mem::size_of::<PostUniform>() as wgpu::BufferAddress,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("post_sampler"),
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            },
+            count: None,
+        };
+        let sample_entries = [
+            texture_entry(0),
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 2,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ];
+        let sample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &sample_entries,
+            label: Some("post_sample_bind_group_layout"),
+        });
+        let [source, sampler_entry, uniform] = sample_entries;
+        let tonemap_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[source, sampler_entry, uniform, texture_entry(3)],
+            label: Some("post_tonemap_bind_group_layout"),
+        });
+
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Post Shader"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("post.wgsl").into()),
+        });
+        let pipeline = |layout, entry_point, format, blend| {
+            create_pipeline(device, layout, &shader, entry_point, format, blend)
+        };
+        let additive = wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        };
+        let prefilter_pipeline = pipeline(&sample_layout, "fs_prefilter", HDR_FORMAT, None);
+        let downsample_pipeline = pipeline(&sample_layout, "fs_downsample", HDR_FORMAT, None);
+        let upsample_pipeline = pipeline(&sample_layout, "fs_upsample", HDR_FORMAT, Some(additive));
+        let tonemap_pipeline = pipeline(&tonemap_layout, "fs_tonemap", config.format, None);
+
+        let targets = Targets::new(
+            device,
+            config,
+            &sampler,
+            &uniform_buffer,
+            &sample_layout,
+            &tonemap_layout,
+        );
+
+        Self {
+            settings: PostSettings::default(),
+            encode_srgb: !config.format.is_srgb(),
+            uniform_buffer,
+            sampler,
+            sample_layout,
+            tonemap_layout,
+            prefilter_pipeline,
+            downsample_pipeline,
+            upsample_pipeline,
+            tonemap_pipeline,
+            targets,
+        }
+    }
+
+    /// The target the main pass renders, or resolves, into.
+    pub fn hdr_view(&self) -> &wgpu::TextureView {
+        &self.targets.hdr_view
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.targets = Targets::new(
+            device,
+            config,
+            &self.sampler,
+            &self.uniform_buffer,
+            &self.sample_layout,
+            &self.tonemap_layout,
+        );
+    }
+
+    /// Records the bloom passes and the tone mapping onto `output`.
+    pub fn render(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        queue: &wgpu::Queue,
+        output: &wgpu::TextureView,
+    ) {
+        let settings = &self.settings;
+        let uniform = PostUniform {
+            exposure: settings.exposure.exp2(),
+            tone_mapping: settings.tone_mapping.shader_id(),
+            bloom_intensity: if settings.bloom {
+                settings.bloom_intensity
+            } else {
+                0.0
+            },
+            bloom_threshold: settings.bloom_threshold,
+            encode_srgb: self.encode_srgb as u32,
+            _padding: [0; 3],
+        };
+        queue.write_buffer(&self.uniform_buffer, 0, bytemuck::cast_slice(&[uniform]));
+
+        let targets = &self.targets;
+        if settings.bloom {
+            for (i, (view, bind_group)) in targets
+                .bloom_views
+                .iter()
+                .zip(&targets.downsample_bind_groups)
+                .enumerate()
+            {
+                let pipeline = match i {
+                    0 => &self.prefilter_pipeline,
+                    _ => &self.downsample_pipeline,
+                };
+                fullscreen_pass(
+                    encoder,
+                    "Bloom Downsample Pass",
+                    view,
+                    true,
+                    pipeline,
+                    bind_group,
+                );
+            }
+            for (view, bind_group) in targets
+                .bloom_views
+                .iter()
+                .zip(&targets.upsample_bind_groups)
+                .rev()
+            {
+                fullscreen_pass(
+                    encoder,
+                    "Bloom Upsample Pass",
+                    view,
+                    false,
+                    &self.upsample_pipeline,
+                    bind_group,
+                );
+            }
+        }
+
+        fullscreen_pass(
+            encoder,
+            "Tonemap Pass",
+            output,
+            true,
+            &self.tonemap_pipeline,
+            &targets.tonemap_bind_group,
+        );
+    }
+}
+
+impl Targets {
+    fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sampler: &wgpu::Sampler,
+        uniform_buffer: &wgpu::Buffer,
+        sample_layout: &wgpu::BindGroupLayout,
+        tonemap_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let create_view = |label, width: u32, height: u32| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: width.max(1),
+                        height: height.max(1),
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format: HDR_FORMAT,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let hdr_view = create_view("hdr_texture", config.width, config.height);
+
+        let mips = BLOOM_MIPS.min(config.width.min(config.height).max(2).ilog2());
+        let bloom_views = (1..=mips)
+            .map(|i| create_view("bloom_texture", config.width >> i, config.height >> i))
+            .collect::<Vec<_>>();
+
+        let bind_group = |layout, source: &wgpu::TextureView, bloom: Option<&wgpu::TextureView>| {
+            let mut entries = vec![
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(source),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: uniform_buffer.as_entire_binding(),
+                },
+            ];
+            if let Some(bloom) = bloom {
+                entries.push(wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: wgpu::BindingResource::TextureView(bloom),
+                });
+            }
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                layout,
+                entries: &entries,
+                label: Some("post_bind_group"),
+            })
+        };
+
+        let downsample_bind_groups = std::iter::once(&hdr_view)
+            .chain(&bloom_views)
+            .take(bloom_views.len())
+            .map(|source| bind_group(sample_layout, source, None))
+            .collect();
+        let upsample_bind_groups = bloom_views[1..]
+            .iter()
+            .map(|source| bind_group(sample_layout, source, None))
+            .collect();
+        let tonemap_bind_group = bind_group(tonemap_layout, &hdr_view, Some(&bloom_views[0]));
+
+        Self {
+            hdr_view,
+            bloom_views,
+            downsample_bind_groups,
+            upsample_bind_groups,
+            tonemap_bind_group,
+        }
+    }
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    bind_group_layout: &wgpu::BindGroupLayout,
+    shader: &wgpu::ShaderModule,
+    entry_point: &str,
+    format: wgpu::TextureFormat,
+    blend: Option<wgpu::BlendState>,
+) -> wgpu::RenderPipeline {
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Post Pipeline Layout"),
+        bind_group_layouts: &[bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some(entry_point),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: shader,
+            entry_point,
+            targets: &[Some(wgpu::ColorTargetState {
+                format,
+                blend,
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
+
+fn fullscreen_pass(
+    encoder: &mut wgpu::CommandEncoder,
+    label: &str,
+    view: &wgpu::TextureView,
+    clear: bool,
+    pipeline: &wgpu::RenderPipeline,
+    bind_group: &wgpu::BindGroup,
+) {
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: if clear {
+                    wgpu::LoadOp::Clear(wgpu::Color::BLACK)
+                } else {
+                    wgpu::LoadOp::Load
+                },
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, bind_group, &[]);
+    render_pass.draw(0..3, 0..1);
+}
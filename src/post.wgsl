@@ -0,0 +1,164 @@
+// Post-processing: bloom down- and upsampling of the HDR scene and tone
+// mapping onto the surface.
+
+const TONE_MAPPING_REINHARD: u32 = 0u;
+const TONE_MAPPING_ACES: u32 = 1u;
+const TONE_MAPPING_AGX: u32 = 2u;
+
+struct Post {
+    // Linear multiplier applied before tone mapping.
+    exposure: f32,
+    tone_mapping: u32,
+    bloom_intensity: f32,
+    bloom_threshold: f32,
+    // Non-zero when the surface format does not encode sRGB itself.
+    encode_srgb: u32,
+}
+@group(0) @binding(0)
+var t_source: texture_2d<f32>;
+@group(0) @binding(1)
+var s_source: sampler;
+@group(0) @binding(2)
+var<uniform> post: Post;
+@group(0) @binding(3)
+var t_bloom: texture_2d<f32>;
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) uv: vec2<f32>,
+}
+
+// A single triangle covering the screen.
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> VertexOutput {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    var out: VertexOutput;
+    out.clip_position = vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+fn sample_offset(uv: vec2<f32>, texel: vec2<f32>, x: f32, y: f32) -> vec3<f32> {
+    return textureSample(t_source, s_source, uv + texel * vec2<f32>(x, y)).rgb;
+}
+
+// 13-tap downsample from Jimenez, "Next Generation Post Processing in Call of
+// Duty: Advanced Warfare".
+fn downsample(uv: vec2<f32>) -> vec3<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    let a = sample_offset(uv, texel, -2.0, 2.0);
+    let b = sample_offset(uv, texel, 0.0, 2.0);
+    let c = sample_offset(uv, texel, 2.0, 2.0);
+    let d = sample_offset(uv, texel, -2.0, 0.0);
+    let e = sample_offset(uv, texel, 0.0, 0.0);
+    let f = sample_offset(uv, texel, 2.0, 0.0);
+    let g = sample_offset(uv, texel, -2.0, -2.0);
+    let h = sample_offset(uv, texel, 0.0, -2.0);
+    let i = sample_offset(uv, texel, 2.0, -2.0);
+    let j = sample_offset(uv, texel, -1.0, 1.0);
+    let k = sample_offset(uv, texel, 1.0, 1.0);
+    let l = sample_offset(uv, texel, -1.0, -1.0);
+    let m = sample_offset(uv, texel, 1.0, -1.0);
+    return e * 0.125 + (a + c + g + i) * 0.03125 + (b + d + f + h) * 0.0625
+        + (j + k + l + m) * 0.125;
+}
+
+// First downsample, keeping only what is brighter than the threshold.
+@fragment
+fn fs_prefilter(in: VertexOutput) -> @location(0) vec4<f32> {
+    let color = downsample(in.uv);
+    let brightness = max(color.r, max(color.g, color.b));
+    let contribution = max(brightness - post.bloom_threshold, 0.0) / max(brightness, 1e-4);
+    return vec4<f32>(color * contribution, 1.0);
+}
+
+@fragment
+fn fs_downsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(downsample(in.uv), 1.0);
+}
+
+// 3x3 tent filter, blended additively onto the next larger level.
+@fragment
+fn fs_upsample(in: VertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(t_source));
+    var color = sample_offset(in.uv, texel, 0.0, 0.0) * 4.0;
+    color += (sample_offset(in.uv, texel, -1.0, 0.0) + sample_offset(in.uv, texel, 1.0, 0.0)
+        + sample_offset(in.uv, texel, 0.0, -1.0) + sample_offset(in.uv, texel, 0.0, 1.0)) * 2.0;
+    color += sample_offset(in.uv, texel, -1.0, -1.0) + sample_offset(in.uv, texel, 1.0, -1.0)
+        + sample_offset(in.uv, texel, -1.0, 1.0) + sample_offset(in.uv, texel, 1.0, 1.0);
+    return vec4<f32>(color / 16.0, 1.0);
+}
+
+fn reinhard(color: vec3<f32>) -> vec3<f32> {
+    return color / (1.0 + color);
+}
+
+// Narkowicz's fit of the ACES filmic curve.
+fn aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return clamp((color * (a * color + b)) / (color * (c * color + d) + e), vec3<f32>(0.0), vec3<f32>(1.0));
+}
+
+// Polynomial fit of the AgX default contrast curve by Benjamin Wrensch.
+fn agx_contrast(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2
+        + 0.1191 * x - 0.00232;
+}
+
+fn agx(color: vec3<f32>) -> vec3<f32> {
+    let inset = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+    var c = log2(max(inset * color, vec3<f32>(1e-10)));
+    c = (clamp(c, vec3<f32>(min_ev), vec3<f32>(max_ev)) - min_ev) / (max_ev - min_ev);
+    c = outset * agx_contrast(c);
+    // The curve produces display values; undo the sRGB encoding the surface
+    // applies on store.
+    return pow(max(c, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn linear_to_srgb(color: vec3<f32>) -> vec3<f32> {
+    let low = color * 12.92;
+    let high = 1.055 * pow(color, vec3<f32>(1.0 / 2.4)) - 0.055;
+    return select(high, low, color <= vec3<f32>(0.0031308));
+}
+
+@fragment
+fn fs_tonemap(in: VertexOutput) -> @location(0) vec4<f32> {
+    let scene = textureSample(t_source, s_source, in.uv).rgb;
+    let bloom = textureSample(t_bloom, s_source, in.uv).rgb;
+    let color = (scene + bloom * post.bloom_intensity) * post.exposure;
+
+    var mapped: vec3<f32>;
+    switch post.tone_mapping {
+        case TONE_MAPPING_REINHARD: {
+            mapped = reinhard(color);
+        }
+        case TONE_MAPPING_ACES: {
+            mapped = aces(color);
+        }
+        case TONE_MAPPING_AGX, default: {
+            mapped = agx(color);
+        }
+    }
+
+    if (post.encode_srgb != 0u) {
+        mapped = linear_to_srgb(mapped);
+    }
+    return vec4<f32>(mapped, 1.0);
+}
@@ -0,0 +1,456 @@
+use std::collections::HashMap;
+use std::f32::consts::{PI, TAU};
+
+use cgmath::prelude::*;
+use cgmath::{Quaternion, Vector3};
+
+use crate::model::{Mesh, MeshData};
+use crate::physics::{Shape, Transform};
+
+/// A point of a surface of revolution, given in the (radius, height) plane.
+struct ProfilePoint {
+    radius: f32,
+    y: f32,
+    normal: [f32; 2],
+    v: f32,
+}
+
+/// Sweeps a profile ordered from top to bottom around the Y axis.
+fn revolve(profile: &[ProfilePoint], sectors: u32) -> MeshData {
+    let sectors = sectors.max(3);
+    let ring = sectors + 1;
+    let mut data = MeshData::default();
+
+    for p in profile {
+        for j in 0..=sectors {
+            let u = j as f32 / sectors as f32;
+            let (sin, cos) = (u * TAU).sin_cos();
+            data.positions.push([p.radius * cos, p.y, p.radius * sin]);
+            data.normals
+                .push([p.normal[0] * cos, p.normal[1], p.normal[0] * sin]);
+            data.tex_coords.push([u, p.v]);
+        }
+    }
+
+    for (i, pair) in profile.windows(2).enumerate() {
+        let (top, bottom) = (&pair[0], &pair[1]);
+        if top.radius == bottom.radius && top.y == bottom.y {
+            continue;
+        }
+        for j in 0..sectors {
+            let k1 = i as u32 * ring + j;
+            let k2 = k1 + ring;
+            if top.radius > 0.0 {
+                data.indices.extend_from_slice(&[k1, k1 + 1, k2]);
+            }
+            if bottom.radius > 0.0 {
+                data.indices.extend_from_slice(&[k1 + 1, k2 + 1, k2]);
+            }
+        }
+    }
+
+    data
+}
+
+/// Points of a circular arc in the profile plane, `angle` measured from +Y.
+fn arc(
+    radius: f32,
+    center_y: f32,
+    angles: std::ops::Range<f32>,
+    steps: u32,
+    v: std::ops::Range<f32>,
+) -> impl Iterator<Item = ProfilePoint> {
+    (0..=steps).map(move |i| {
+        let t = i as f32 / steps as f32;
+        let (sin, cos) = (angles.start + (angles.end - angles.start) * t).sin_cos();
+        ProfilePoint {
+            radius: radius * sin,
+            y: center_y + radius * cos,
+            normal: [sin, cos],
+            v: v.start + (v.end - v.start) * t,
+        }
+    })
+}
+
+pub fn uv_sphere(radius: f32, sectors: u32, stacks: u32) -> MeshData {
+    let profile = arc(radius, 0.0, 0.0..PI, stacks.max(2), 0.0..1.0).collect::<Vec<_>>();
+    revolve(&profile, sectors)
+}
+
+/// Capsule along the Y axis, `half_height` being half the length of its
+/// cylindrical part.
+pub fn capsule(radius: f32, half_height: f32, sectors: u32, stacks: u32) -> MeshData {
+    let cap_stacks = (stacks / 2).max(1);
+    let total = 2.0 * half_height + PI * radius;
+    let cap_v = PI * radius * 0.5 / total;
+    let profile = arc(radius, half_height, 0.0..PI / 2.0, cap_stacks, 0.0..cap_v)
+        .chain(arc(
+            radius,
+            -half_height,
+            PI / 2.0..PI,
+            cap_stacks,
+            1.0 - cap_v..1.0,
+        ))
+        .collect::<Vec<_>>();
+    revolve(&profile, sectors)
+}
+
+/// Capped cylinder along the Y axis.
+pub fn cylinder(radius: f32, half_height: f32, sectors: u32) -> MeshData {
+    let point = |radius, y, normal, v| ProfilePoint {
+        radius,
+        y,
+        normal,
+        v,
+    };
+    let profile = [
+        point(0.0, half_height, [0.0, 1.0], 0.0),
+        point(radius, half_height, [0.0, 1.0], 0.25),
+        point(radius, half_height, [1.0, 0.0], 0.25),
+        point(radius, -half_height, [1.0, 0.0], 0.75),
+        point(radius, -half_height, [0.0, -1.0], 0.75),
+        point(0.0, -half_height, [0.0, -1.0], 1.0),
+    ];
+    revolve(&profile, sectors)
+}
+
+/// Cone with its apex up, centered halfway along its height.
+pub fn cone(radius: f32, height: f32, sectors: u32) -> MeshData {
+    let slant = Vector3::new(height, radius, 0.0).normalize();
+    let half = height * 0.5;
+    let point = |radius, y, normal, v| ProfilePoint {
+        radius,
+        y,
+        normal,
+        v,
+    };
+    let profile = [
+        point(0.0, half, [slant.x, slant.y], 0.0),
+        point(radius, -half, [slant.x, slant.y], 0.5),
+        point(radius, -half, [0.0, -1.0], 0.5),
+        point(0.0, -half, [0.0, -1.0], 1.0),
+    ];
+    revolve(&profile, sectors)
+}
+
+/// Torus lying in the XZ plane.
+pub fn torus(
+    major_radius: f32,
+    minor_radius: f32,
+    major_segments: u32,
+    minor_segments: u32,
+) -> MeshData {
+    let steps = minor_segments.max(3);
+    // Walking the tube clockwise keeps the winding pointing outward.
+    let profile = (0..=steps)
+        .map(|i| {
+            let t = i as f32 / steps as f32;
+            let (sin, cos) = (PI / 2.0 - t * TAU).sin_cos();
+            ProfilePoint {
+                radius: major_radius + minor_radius * cos,
+                y: minor_radius * sin,
+                normal: [cos, sin],
+                v: t,
+            }
+        })
+        .collect::<Vec<_>>();
+    revolve(&profile, major_segments)
+}
+
+/// Flat grid in the XZ plane facing up, centered on the origin.
+pub fn plane(size_x: f32, size_z: f32, cols: u32, rows: u32) -> MeshData {
+    let (cols, rows) = (cols.max(1), rows.max(1));
+    let mut data = MeshData::default();
+    for row in 0..=rows {
+        for col in 0..=cols {
+            let u = col as f32 / cols as f32;
+            let v = row as f32 / rows as f32;
+            data.positions
+                .push([(u - 0.5) * size_x, 0.0, (v - 0.5) * size_z]);
+            data.normals.push([0.0, 1.0, 0.0]);
+            data.tex_coords.push([u, v]);
+        }
+    }
+    for row in 0..rows {
+        for col in 0..cols {
+            let p00 = row * (cols + 1) + col;
+            let p10 = p00 + 1;
+            let p01 = p00 + cols + 1;
+            let p11 = p01 + 1;
+            data.indices
+                .extend_from_slice(&[p00, p01, p11, p00, p11, p10]);
+        }
+    }
+    data
+}
+
+pub fn cuboid(half_extents: Vector3<f32>) -> MeshData {
+    let (x, y, z) = (Vector3::unit_x(), Vector3::unit_y(), Vector3::unit_z());
+    // Face normal with two in-plane axes such that `u × v = normal`.
+    let faces = [
+        (x, -z, y),
+        (-x, z, y),
+        (y, x, -z),
+        (-y, x, z),
+        (z, x, y),
+        (-z, -x, y),
+    ];
+
+    let mut data = MeshData::default();
+    for (normal, u, v) in faces {
+        let base = data.positions.len() as u32;
+        for (su, sv) in [(-1.0, -1.0), (1.0, -1.0), (1.0, 1.0), (-1.0, 1.0)] {
+            let p = normal + u * su + v * sv;
+            data.positions.push([
+                p.x * half_extents.x,
+                p.y * half_extents.y,
+                p.z * half_extents.z,
+            ]);
+            data.normals.push(normal.into());
+            data.tex_coords.push([(su + 1.0) * 0.5, (1.0 - sv) * 0.5]);
+        }
+        data.indices
+            .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+    }
+    data
+}
+
+/// Sphere made of subdivided icosahedron faces, which spreads triangles
+/// more evenly than `uv_sphere`.
+pub fn icosphere(radius: f32, subdivisions: u32) -> MeshData {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let mut points = [
+        [-1.0, t, 0.0],
+        [1.0, t, 0.0],
+        [-1.0, -t, 0.0],
+        [1.0, -t, 0.0],
+        [0.0, -1.0, t],
+        [0.0, 1.0, t],
+        [0.0, -1.0, -t],
+        [0.0, 1.0, -t],
+        [t, 0.0, -1.0],
+        [t, 0.0, 1.0],
+        [-t, 0.0, -1.0],
+        [-t, 0.0, 1.0],
+    ]
+    .iter()
+    .map(|p| Vector3::from(*p).normalize())
+    .collect::<Vec<_>>();
+    let mut triangles: Vec<[u32; 3]> = vec![
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+
+    for _ in 0..subdivisions {
+        let mut midpoints = HashMap::new();
+        let mut midpoint = |a: u32, b: u32| {
+            *midpoints.entry((a.min(b), a.max(b))).or_insert_with(|| {
+                let p = (points[a as usize] + points[b as usize]).normalize();
+                points.push(p);
+                points.len() as u32 - 1
+            })
+        };
+        triangles = triangles
+            .iter()
+            .flat_map(|&[a, b, c]| {
+                let ab = midpoint(a, b);
+                let bc = midpoint(b, c);
+                let ca = midpoint(c, a);
+                [[a, ab, ca], [b, bc, ab], [c, ca, bc], [ab, bc, ca]]
+            })
+            .collect();
+    }
+
+    let uv = |p: Vector3<f32>| [0.5 + p.z.atan2(p.x) / TAU, p.y.clamp(-1.0, 1.0).acos() / PI];
+    let mut data = MeshData {
+        positions: points.iter().map(|p| (p * radius).into()).collect(),
+        normals: points.iter().map(|&p| p.into()).collect(),
+        tex_coords: points.iter().map(|&p| uv(p)).collect(),
+        colors: Vec::new(),
+        indices: Vec::new(),
+    };
+
+    // Triangles straddling the texture seam get their own copies of the
+    // vertices with wrapped U coordinates.
+    let mut wrapped = HashMap::new();
+    for tri in &triangles {
+        let us = tri.map(|i| data.tex_coords[i as usize][0]);
+        let max_u = us.iter().copied().fold(0.0, f32::max);
+        for (k, &i) in tri.iter().enumerate() {
+            let index = if max_u - us[k] > 0.5 {
+                *wrapped.entry(i).or_insert_with(|| {
+                    let [u, v] = data.tex_coords[i as usize];
+                    data.positions.push(data.positions[i as usize]);
+                    data.normals.push(data.normals[i as usize]);
+                    data.tex_coords.push([u + 1.0, v]);
+                    data.positions.len() as u32 - 1
+                })
+            } else {
+                i
+            };
+            data.indices.push(index);
+        }
+    }
+    data
+}
+
+/// Render geometry matching a collision shape, in the shape's local space.
+pub fn shape_data(shape: &Shape) -> MeshData {
+    match shape {
+        Shape::Sphere(sphere) => {
+            let mut data = uv_sphere(sphere.radius, 32, 16);
+            data.transform(&Transform::from_position(sphere.center.to_vec()));
+            data
+        }
+        Shape::Cuboid(obb) => {
+            let mut data = cuboid(obb.half_extents);
+            data.transform(&Transform::new(obb.center.to_vec(), obb.rotation));
+            data
+        }
+        Shape::Capsule(c) => {
+            let axis = c.b - c.a;
+            let length = axis.magnitude();
+            let rotation = if length > f32::EPSILON {
+                Quaternion::from_arc(Vector3::unit_y(), axis / length, None)
+            } else {
+                Quaternion::one()
+            };
+            let mut data = capsule(c.radius, length * 0.5, 32, 16);
+            data.transform(&Transform::new(c.a.midpoint(c.b).to_vec(), rotation));
+            data
+        }
+        Shape::ConvexHull(hull) => MeshData::flat_shaded(&hull.points, &hull.triangles),
+        Shape::TriMesh(mesh) => MeshData::from_trimesh(mesh),
+        Shape::Heightfield(heightfield) => MeshData::from_heightfield(heightfield),
+        Shape::Compound(shapes) => {
+            let mut data = MeshData::default();
+            for shape in shapes {
+                data.append(shape_data(shape));
+            }
+            data
+        }
+    }
+}
+
+/// Uploads a render mesh for `shape`, keeping the CPU-side copy.
+pub fn shape_mesh(name: &str, device: &wgpu::Device, shape: &Shape, material: usize) -> Mesh {
+    Mesh::from_data(name.to_string(), device, shape_data(shape), material)
+}
+
+/// A mesh of `shape` in a single `color`, drawn with the vertex-colored
+/// pipeline.
+pub fn shape_colored_mesh(
+    name: &str,
+    device: &wgpu::Device,
+    shape: &Shape,
+    color: [f32; 3],
+) -> Mesh {
+    let mut data = shape_data(shape);
+    data.colors = vec![color; data.positions.len()];
+    Mesh::from_colored_data(name.to_string(), device, data, 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks that a convex mesh centered on the origin has an attribute of
+    /// each kind per vertex, unit normals, and triangles wound
+    /// counterclockwise seen from outside that face the same way as the
+    /// normals of their corners.
+    fn assert_closed_convex(data: &MeshData) {
+        let count = data.positions.len();
+        assert_eq!(data.normals.len(), count);
+        assert_eq!(data.tex_coords.len(), count);
+        assert_eq!(data.indices.len() % 3, 0);
+        for normal in &data.normals {
+            assert!((Vector3::from(*normal).magnitude() - 1.0).abs() < 1e-5);
+        }
+        for tri in data.indices.chunks_exact(3) {
+            let [a, b, c] = [tri[0], tri[1], tri[2]].map(|i| {
+                assert!((i as usize) < count);
+                Vector3::from(data.positions[i as usize])
+            });
+            let face = (b - a).cross(c - a);
+            assert!(face.magnitude() > 1e-6, "degenerate triangle {:?}", tri);
+            assert!(face.dot(a + b + c) > 0.0, "inward triangle {:?}", tri);
+            for &i in tri {
+                assert!(face.dot(data.normals[i as usize].into()) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn cuboid_has_four_vertices_per_face() {
+        let data = cuboid(Vector3::new(1.0, 2.0, 3.0));
+        assert_eq!(data.positions.len(), 24);
+        assert_eq!(data.indices.len(), 36);
+        assert_closed_convex(&data);
+        for p in &data.positions {
+            assert_eq!(p.map(f32::abs), [1.0, 2.0, 3.0]);
+        }
+    }
+
+    #[test]
+    fn uv_sphere_has_a_ring_per_stack() {
+        let data = uv_sphere(2.0, 8, 4);
+        // Five rings of nine vertices, the last one closing the seam.
+        assert_eq!(data.positions.len(), 45);
+        // A triangle per sector at the poles, two in the bands between.
+        assert_eq!(data.indices.len(), 3 * 8 * (1 + 2 + 2 + 1));
+        assert_closed_convex(&data);
+        for (p, n) in data.positions.iter().zip(&data.normals) {
+            let p = Vector3::from(*p);
+            assert!((p.magnitude() - 2.0).abs() < 1e-5);
+            assert!((p / 2.0 - Vector3::from(*n)).magnitude() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn icosphere_subdivides_every_face() {
+        let data = icosphere(1.5, 2);
+        assert_eq!(data.indices.len(), 3 * 20 * 16);
+        assert_closed_convex(&data);
+        for p in &data.positions {
+            assert!((Vector3::from(*p).magnitude() - 1.5).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn cone_has_a_side_and_a_base() {
+        let data = cone(1.0, 2.0, 8);
+        assert_eq!(data.positions.len(), 4 * 9);
+        assert_eq!(data.indices.len(), 3 * 2 * 8);
+        assert_closed_convex(&data);
+        let heights = data.positions.iter().map(|p| p[1]);
+        assert_eq!(heights.clone().fold(f32::MIN, f32::max), 1.0);
+        assert_eq!(heights.fold(f32::MAX, f32::min), -1.0);
+    }
+
+    #[test]
+    fn cylinder_has_caps() {
+        let data = cylinder(0.5, 1.0, 6);
+        assert_eq!(data.positions.len(), 6 * 7);
+        // The side takes two triangles per sector, each cap one.
+        assert_eq!(data.indices.len(), 3 * 4 * 6);
+        assert_closed_convex(&data);
+    }
+}
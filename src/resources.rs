@@ -2,66 +2,304 @@ use std::io::{BufReader, Cursor};
 
 use cfg_if::cfg_if;
 
-use crate::{model, texture};
+use crate::{model, physics, texture};
 
-#[cfg(target_arch = "wasm32")]
-fn format_url(file_name: &str) -> reqwest::Url {
-    let window = web_sys::window().unwrap();
-    let location = window.location();
-    let mut origin = location.origin().unwrap();
-    if !origin.ends_with("learn-wgpu") {
-        origin = format!("{}/learn-wgpu", origin);
+/// Why a resource couldn't be loaded.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ResourceError {
+    #[error("{file} not found")]
+    NotFound { file: String },
+    /// The file exists but couldn't be read or fetched.
+    #[error("failed to read {file}: {message}")]
+    Read { file: String, message: String },
+    /// The contents are malformed, at `line` for text formats when known.
+    #[error("{file}{}: {message}", .line.map(|line| format!(":{line}")).unwrap_or_default())]
+    Parse {
+        file: String,
+        line: Option<usize>,
+        message: String,
+    },
+    #[error("{file} is in an unsupported format: {message}")]
+    UnsupportedFormat { file: String, message: String },
+    /// The device can't create the resource, e.g. because it exceeds its
+    /// limits.
+    #[error("{file} can't be created on the GPU: {message}")]
+    Gpu { file: String, message: String },
+}
+
+impl ResourceError {
+    fn from_io(file: &str, error: std::io::Error) -> Self {
+        match error.kind() {
+            std::io::ErrorKind::NotFound => Self::NotFound {
+                file: file.to_string(),
+            },
+            _ => Self::Read {
+                file: file.to_string(),
+                message: error.to_string(),
+            },
+        }
+    }
+
+    pub(crate) fn from_image(file: &str, error: image::ImageError) -> Self {
+        let file = file.to_string();
+        match error {
+            image::ImageError::Unsupported(e) => Self::UnsupportedFormat {
+                file,
+                message: e.to_string(),
+            },
+            image::ImageError::IoError(e) => Self::from_io(&file, e),
+            e => Self::Parse {
+                file,
+                line: None,
+                message: e.to_string(),
+            },
+        }
+    }
+
+    /// An error from `tobj` reading `text`, with the line of the statement it
+    /// rejected where that can be told.
+    fn from_obj(file: &str, text: &str, error: tobj::LoadError) -> Self {
+        Self::Parse {
+            file: file.to_string(),
+            line: obj_error_line(text, error),
+            message: error.to_string(),
+        }
     }
-    let base = reqwest::Url::parse(&format!("{}/", origin,)).unwrap();
-    base.join(file_name).unwrap()
 }
 
-pub async fn load_string(file_name: &str) -> anyhow::Result<String> {
-    cfg_if! {
-        if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_name);
-            let txt = reqwest::get(url)
-                .await?
-                .text()
-                .await?;
-        } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
-            let txt = std::fs::read_to_string(path)?;
+/// Finds the first statement in an OBJ file that fails the way `error`
+/// describes, since `tobj` doesn't report where it stopped.
+fn obj_error_line(text: &str, error: tobj::LoadError) -> Option<usize> {
+    use tobj::LoadError;
+
+    let (mut positions, mut tex_coords, mut normals) = (0, 0, 0);
+    let is_float = |word: &str| word.parse::<f32>().is_ok();
+    // Like `tobj`, takes the first `n` arguments and needs all of them.
+    let has_floats = |args: std::str::SplitWhitespace, n| {
+        let args = args.take(n).collect::<Vec<_>>();
+        args.len() == n && args.iter().all(|arg| is_float(arg))
+    };
+    // OBJ indices start at 1 and count back from the end when negative.
+    let in_bounds = |index: &str, count: usize| match index.parse::<isize>() {
+        Ok(index) if index > 0 => index as usize <= count,
+        Ok(index) if index < 0 => index.unsigned_abs() <= count,
+        _ => false,
+    };
+
+    let position = text.lines().position(|line| {
+        let mut words = line.split_whitespace();
+        match (words.next(), &error) {
+            (Some("v"), LoadError::PositionParseError) => !has_floats(words, 3),
+            (Some("vt"), LoadError::TexcoordParseError) => !has_floats(words, 2),
+            (Some("vn"), LoadError::NormalParseError) => !has_floats(words, 3),
+            (Some("v"), _) => {
+                positions += 1;
+                false
+            }
+            (Some("vt"), _) => {
+                tex_coords += 1;
+                false
+            }
+            (Some("vn"), _) => {
+                normals += 1;
+                false
+            }
+            (
+                Some("f"),
+                LoadError::FaceParseError
+                | LoadError::FaceVertexOutOfBounds
+                | LoadError::FaceTexCoordOutOfBounds
+                | LoadError::FaceNormalOutOfBounds,
+            ) => !words.all(|vertex| {
+                let mut indices = vertex.split('/');
+                let valid = indices.next().is_some_and(|v| in_bounds(v, positions));
+                let valid = valid
+                    && indices
+                        .next()
+                        .is_none_or(|t| t.is_empty() || in_bounds(t, tex_coords));
+                valid && indices.next().is_none_or(|n| in_bounds(n, normals))
+            }),
+            _ => false,
         }
+    });
+    position.map(|i| i + 1)
+}
+
+/// Environment variable naming a directory to read resources from.
+#[cfg(not(target_arch = "wasm32"))]
+pub const RESOURCE_DIR_VAR: &str = "PHYSICS_ENGINE_RES";
+
+/// Files from `res/` built into the binary with the `embedded-resources`
+/// feature, by path relative to `res/`.
+#[cfg(feature = "embedded-resources")]
+static EMBEDDED: &[(&str, &[u8])] = include!(concat!(env!("OUT_DIR"), "/embedded_resources.rs"));
+
+/// Where resources are read from, chosen once at startup.
+#[derive(Debug, Clone)]
+pub struct ResourceRoot {
+    /// Directories searched in order for each file.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub search_paths: Vec<std::path::PathBuf>,
+    /// URL resource paths are resolved against.
+    #[cfg(target_arch = "wasm32")]
+    pub base_url: reqwest::Url,
+}
+
+static ROOT: std::sync::OnceLock<ResourceRoot> = std::sync::OnceLock::new();
+
+impl ResourceRoot {
+    /// The directory given by the `--res <dir>` command line flag or the
+    /// [`RESOURCE_DIR_VAR`] environment variable, falling back to `res/`
+    /// next to the executable, in the working directory and in the source
+    /// tree the binary was built from.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn from_env() -> Self {
+        let mut args = std::env::args();
+        let flag = std::iter::from_fn(|| args.next())
+            .skip_while(|arg| arg != "--res")
+            .nth(1);
+        let configured = flag.or_else(|| std::env::var(RESOURCE_DIR_VAR).ok());
+
+        let exe_dir = std::env::current_exe()
+            .ok()
+            .and_then(|exe| Some(exe.parent()?.join("res")));
+        let search_paths = configured
+            .map(std::path::PathBuf::from)
+            .into_iter()
+            .chain(exe_dir)
+            .chain([
+                "res".into(),
+                std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("res"),
+            ])
+            .collect();
+        Self { search_paths }
+    }
+
+    /// The URL given by the page's `res` query parameter, falling back to
+    /// the page's directory.
+    #[cfg(target_arch = "wasm32")]
+    pub fn from_env() -> Self {
+        let location = web_sys::window().unwrap().location();
+        let page = reqwest::Url::parse(&location.href().unwrap()).unwrap();
+        let configured = page
+            .query_pairs()
+            .find(|(key, _)| key == "res")
+            .and_then(|(_, value)| page.join(&value).ok());
+        let mut base_url = configured.unwrap_or_else(|| page.join(".").unwrap());
+        // Without the trailing slash, joining would replace the last segment.
+        if !base_url.path().ends_with('/') {
+            base_url.set_path(&format!("{}/", base_url.path()));
+        }
+        Self { base_url }
     }
 
-    Ok(txt)
+    /// The first file named `file_name` in the search paths.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn find(&self, file_name: &str) -> Option<std::path::PathBuf> {
+        self.search_paths
+            .iter()
+            .map(|dir| dir.join(file_name))
+            .find(|path| path.is_file())
+    }
+}
+
+/// Sets where resources are read from. Only the first call takes effect,
+/// and only before the first resource is loaded; otherwise
+/// [`ResourceRoot::from_env`] is used.
+pub fn set_resource_root(root: ResourceRoot) {
+    if let Err(root) = ROOT.set(root) {
+        log::warn!("Resource root is already set, ignoring {:?}", root);
+    }
+}
+
+/// Where resources are read from.
+pub fn resource_root() -> &'static ResourceRoot {
+    ROOT.get_or_init(|| {
+        let root = ResourceRoot::from_env();
+        log::info!("Resource root: {:?}", root);
+        root
+    })
+}
+
+#[cfg(feature = "embedded-resources")]
+fn embedded(file_name: &str) -> Option<&'static [u8]> {
+    EMBEDDED
+        .iter()
+        .find(|(name, _)| *name == file_name)
+        .map(|(_, data)| *data)
 }
 
-pub async fn load_binary(file_name: &str) -> anyhow::Result<Vec<u8>> {
+pub async fn load_string(file_name: &str) -> Result<String, ResourceError> {
+    String::from_utf8(load_binary(file_name).await?).map_err(|e| ResourceError::Parse {
+        file: file_name.to_string(),
+        line: None,
+        message: e.to_string(),
+    })
+}
+
+/// Reads a resource from the [`resource_root`]. Files built into the binary
+/// are used when the root doesn't have them on native, and without fetching
+/// on wasm.
+pub async fn load_binary(file_name: &str) -> Result<Vec<u8>, ResourceError> {
+    let root = resource_root();
     cfg_if! {
         if #[cfg(target_arch = "wasm32")] {
-            let url = format_url(file_name);
-            let data = reqwest::get(url)
-                .await?
+            #[cfg(feature = "embedded-resources")]
+            if let Some(data) = embedded(file_name) {
+                return Ok(data.to_vec());
+            }
+            let read_error = |e: reqwest::Error| ResourceError::Read {
+                file: file_name.to_string(),
+                message: e.to_string(),
+            };
+            let url = root.base_url.join(file_name).map_err(|e| ResourceError::Read {
+                file: file_name.to_string(),
+                message: e.to_string(),
+            })?;
+            let response = reqwest::get(url).await.map_err(read_error)?;
+            if response.status() == reqwest::StatusCode::NOT_FOUND {
+                return Err(ResourceError::NotFound { file: file_name.to_string() });
+            }
+            let data = response
+                .error_for_status()
+                .map_err(read_error)?
                 .bytes()
-                .await?
+                .await
+                .map_err(read_error)?
                 .to_vec();
         } else {
-            let path = std::path::Path::new(env!("OUT_DIR"))
-                .join("res")
-                .join(file_name);
-            let data = std::fs::read(path)?;
+            let Some(path) = root.find(file_name) else {
+                #[cfg(feature = "embedded-resources")]
+                if let Some(data) = embedded(file_name) {
+                    return Ok(data.to_vec());
+                }
+                log::debug!("{} is in none of {:?}", file_name, root.search_paths);
+                return Err(ResourceError::NotFound { file: file_name.to_string() });
+            };
+            let data = std::fs::read(path).map_err(|e| ResourceError::from_io(file_name, e))?;
         }
     }
 
     Ok(data)
 }
 
+/// Loads an HDR image such as a Radiance `.hdr` file for the sky, as linear
+/// RGBA floats.
+pub async fn load_environment_image(file_name: &str) -> Result<image::Rgba32FImage, ResourceError> {
+    let data = load_binary(file_name).await?;
+    let img =
+        image::load_from_memory(&data).map_err(|e| ResourceError::from_image(file_name, e))?;
+    Ok(img.into_rgba32f())
+}
+
 pub async fn load_texture(
     file_name: &str,
     device: &wgpu::Device,
     queue: &wgpu::Queue,
-) -> anyhow::Result<texture::Texture> {
+    sampler: &texture::SamplerSettings,
+) -> Result<texture::Texture, ResourceError> {
     let data = load_binary(file_name).await?;
-    texture::Texture::from_bytes(device, queue, &data, file_name)
+    texture::Texture::from_bytes(device, queue, &data, file_name, sampler)
 }
 
 pub async fn load_model(
@@ -69,78 +307,479 @@ pub async fn load_model(
     device: &wgpu::Device,
     queue: &wgpu::Queue,
     layout: &wgpu::BindGroupLayout,
-) -> anyhow::Result<model::Model> {
+    options: &MeshOptions,
+) -> Result<model::Model, ResourceError> {
+    let source = load_model_source(file_name, options).await?;
+
+    // Textures that fail to load are replaced rather than failing the model.
+    let mut materials = Vec::new();
+    for m in &source.materials {
+        let (diffuse_file, sampler) = parse_diffuse_map(m);
+        let diffuse_texture = if diffuse_file.is_empty() {
+            texture::Texture::from_color(device, queue, [255; 4], &m.name)
+        } else {
+            match load_texture(diffuse_file, device, queue, &sampler).await {
+                Ok(texture) => texture,
+                Err(e) => {
+                    log::warn!("Failed to load {}: {}", diffuse_file, e);
+                    texture::Texture::checkerboard(device, queue, 64)
+                }
+            }
+        };
+        materials.push(create_material(
+            device,
+            layout,
+            &m.name,
+            std::rc::Rc::new(diffuse_texture),
+        ));
+    }
+    if materials.is_empty() {
+        let white = texture::Texture::from_color(device, queue, [255; 4], "default");
+        materials.push(create_material(
+            device,
+            layout,
+            "default",
+            std::rc::Rc::new(white),
+        ));
+    }
+
+    Ok(source.upload(device, materials))
+}
+
+/// How vertex attributes an OBJ file leaves out are generated.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MeshOptions {
+    /// Faces meeting at up to this angle get smooth normals, zero gives flat
+    /// shading.
+    pub crease_angle: cgmath::Deg<f32>,
+    pub uv_projection: model::UvProjection,
+}
+
+impl Default for MeshOptions {
+    fn default() -> Self {
+        Self {
+            crease_angle: cgmath::Deg(60.0),
+            uv_projection: model::UvProjection::Box,
+        }
+    }
+}
+
+// Lets models loaded with different options be cached apart.
+impl Eq for MeshOptions {}
+
+impl std::hash::Hash for MeshOptions {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        self.crease_angle.0.to_bits().hash(state);
+        self.uv_projection.hash(state);
+    }
+}
+
+/// Loads an OBJ model for the vertex-colored pipeline, ignoring its
+/// materials. Vertices without colors are white.
+pub async fn load_colored_model(
+    file_name: &str,
+    device: &wgpu::Device,
+    options: &MeshOptions,
+) -> Result<model::Model, ResourceError> {
+    Ok(load_model_source(file_name, options)
+        .await?
+        .upload_colored(device))
+}
+
+/// A model read from disk but not uploaded yet.
+pub(crate) struct ModelSource {
+    name: String,
+    /// Meshes with their material index, per level of detail starting with
+    /// the model itself.
+    levels: Vec<Vec<(model::MeshData, usize)>>,
+    pub materials: Vec<tobj::Material>,
+    /// The level of detail OBJ files found next to the model, including
+    /// ones that failed to load, so fixing them reloads the model.
+    pub lod_files: Vec<String>,
+}
+
+impl ModelSource {
+    /// Creates the meshes of every level, drawn with `materials`.
+    pub fn upload(self, device: &wgpu::Device, materials: Vec<model::Material>) -> model::Model {
+        self.upload_with(device, materials, model::Mesh::from_data)
+    }
+
+    /// Creates the meshes of every level for the vertex-colored pipeline,
+    /// without materials.
+    pub fn upload_colored(self, device: &wgpu::Device) -> model::Model {
+        self.upload_with(device, Vec::new(), model::Mesh::from_colored_data)
+    }
+
+    fn upload_with(
+        self,
+        device: &wgpu::Device,
+        materials: Vec<model::Material>,
+        create_mesh: fn(String, &wgpu::Device, model::MeshData, usize) -> model::Mesh,
+    ) -> model::Model {
+        let mut levels = self.levels.into_iter().map(|meshes| {
+            meshes
+                .into_iter()
+                .map(|(data, material)| create_mesh(self.name.clone(), device, data, material))
+                .collect::<Vec<_>>()
+        });
+        let meshes = levels.next().unwrap_or_default();
+        let lods = levels
+            .enumerate()
+            .map(|(i, meshes)| model::Lod {
+                meshes,
+                screen_size: model::Lod::default_screen_size(i + 1),
+            })
+            .collect();
+
+        model::Model {
+            meshes,
+            materials,
+            lods,
+        }
+    }
+}
+
+/// Reads an OBJ model, its materials and its levels of detail without
+/// touching the GPU.
+pub(crate) async fn load_model_source(
+    file_name: &str,
+    options: &MeshOptions,
+) -> Result<ModelSource, ResourceError> {
     let obj_text = load_string(file_name).await?;
-    let obj_cursor = Cursor::new(obj_text);
-    let mut obj_reader = BufReader::new(obj_cursor);
+    let (models, materials) = tobj::load_obj_buf_async(
+        &mut BufReader::new(Cursor::new(&obj_text)),
+        &obj_load_options(),
+        |p| async move {
+            match load_string(&p).await {
+                Ok(mat_text) => tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text))),
+                Err(e) => {
+                    log::warn!("{}", e);
+                    Err(tobj::LoadError::OpenFileFailed)
+                }
+            }
+        },
+    )
+    .await
+    .map_err(|e| ResourceError::from_obj(file_name, &obj_text, e))?;
+
+    // Without its materials the model is still drawn, with the default one.
+    let materials = materials.unwrap_or_else(|e| {
+        log::warn!("Failed to load the materials of {}: {}", file_name, e);
+        Vec::new()
+    });
+
+    // Levels of detail sit next to the model as `name_lod1.obj`,
+    // `name_lod2.obj` and so on, coarsest last. Their `usemtl` statements
+    // name the model's materials, whatever MTL file they point at. A level
+    // that fails to load is left out rather than failing the model.
+    let stem = file_name.strip_suffix(".obj").unwrap_or(file_name);
+    let mut levels = vec![mesh_data(models, options)];
+    let mut lod_files = Vec::new();
+    for level in 1.. {
+        let lod = format!("{}_lod{}.obj", stem, level);
+        let lod_text = match load_string(&lod).await {
+            Ok(lod_text) => lod_text,
+            Err(ResourceError::NotFound { .. }) => break,
+            Err(e) => {
+                log::warn!("Skipping level of detail {}: {}", lod, e);
+                lod_files.push(lod);
+                continue;
+            }
+        };
+        let loaded = tobj::load_obj_buf_async(
+            &mut BufReader::new(Cursor::new(&lod_text)),
+            &obj_load_options(),
+            |_| {
+                let materials = materials.clone();
+                async move {
+                    let material_map = materials
+                        .iter()
+                        .enumerate()
+                        .map(|(i, m)| (m.name.clone(), i))
+                        .collect();
+                    Ok((materials, material_map))
+                }
+            },
+        )
+        .await;
+        match loaded {
+            Ok((models, _)) => levels.push(mesh_data(models, options)),
+            Err(e) => log::warn!(
+                "Skipping level of detail {}: {}",
+                lod,
+                ResourceError::from_obj(&lod, &lod_text, e)
+            ),
+        }
+        lod_files.push(lod);
+    }
+
+    Ok(ModelSource {
+        name: file_name.to_string(),
+        levels,
+        materials,
+        lod_files,
+    })
+}
+
+/// A material sampling `diffuse_texture` through the layout of the main
+/// pipeline's texture bind group.
+pub(crate) fn create_material(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    name: &str,
+    diffuse_texture: std::rc::Rc<texture::Texture>,
+) -> model::Material {
+    let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+        layout,
+        entries: &[
+            wgpu::BindGroupEntry {
+                binding: 0,
+                resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
+            },
+            wgpu::BindGroupEntry {
+                binding: 1,
+                resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
+            },
+        ],
+        label: None,
+    });
+
+    model::Material {
+        name: name.to_string(),
+        diffuse_texture,
+        bind_group,
+    }
+}
+
+/// Splits a material's `map_Kd` statement such as `-clamp on -s 2 2 wood.png`
+/// into the file name and the sampler it asks for. Besides `-clamp`,
+/// materials can set the non-standard `sampler_filter nearest|linear` and
+/// `sampler_anisotropy <1-16>` statements.
+pub(crate) fn parse_diffuse_map(material: &tobj::Material) -> (&str, texture::SamplerSettings) {
+    let params = &material.unknown_param;
+    let mut sampler = texture::SamplerSettings::default();
+    let mut rest = material.diffuse_texture.trim();
+    while let Some(option) = rest.strip_prefix('-') {
+        let (name, args) = option
+            .split_once(char::is_whitespace)
+            .unwrap_or((option, ""));
+        let mut args = args.trim_start();
+        // Options take one argument, except the vectors `-o`, `-s` and `-t`
+        // with up to three and `-mm` with two numbers.
+        let count = match name {
+            "o" | "s" | "t" => 3,
+            "mm" => 2,
+            _ => 1,
+        };
+        for i in 0..count {
+            let (arg, after) = args.split_once(char::is_whitespace).unwrap_or((args, ""));
+            if i > 0 && arg.parse::<f32>().is_err() {
+                break;
+            }
+            if name == "clamp" {
+                sampler.address_mode = match arg {
+                    "on" => wgpu::AddressMode::ClampToEdge,
+                    _ => wgpu::AddressMode::Repeat,
+                };
+            }
+            args = after.trim_start();
+        }
+        rest = args;
+    }
+
+    if let Some(filter) = params.get("sampler_filter") {
+        let filter = match filter.trim() {
+            "nearest" => wgpu::FilterMode::Nearest,
+            _ => wgpu::FilterMode::Linear,
+        };
+        sampler.mag_filter = filter;
+        sampler.min_filter = filter;
+        sampler.mipmap_filter = filter;
+    }
+    if let Some(anisotropy) = params
+        .get("sampler_anisotropy")
+        .and_then(|a| a.trim().parse().ok())
+    {
+        sampler.anisotropy = anisotropy;
+    }
+    (rest, sampler)
+}
+
+fn obj_load_options() -> tobj::LoadOptions {
+    tobj::LoadOptions {
+        triangulate: true,
+        single_index: true,
+        ..Default::default()
+    }
+}
 
-    let (models, obj_materials) = tobj::load_obj_buf_async(
-        &mut obj_reader,
+/// The meshes of `models` with their material index, generating the
+/// attributes they leave out as `options` say.
+fn mesh_data(models: Vec<tobj::Model>, options: &MeshOptions) -> Vec<(model::MeshData, usize)> {
+    models
+        .into_iter()
+        .map(|m| {
+            let mut data = model::MeshData {
+                positions: m
+                    .mesh
+                    .positions
+                    .chunks_exact(3)
+                    .map(|p| [p[0], p[1], p[2]])
+                    .collect(),
+                normals: m
+                    .mesh
+                    .normals
+                    .chunks_exact(3)
+                    .map(|n| [n[0], n[1], n[2]])
+                    .collect(),
+                tex_coords: m
+                    .mesh
+                    .texcoords
+                    .chunks_exact(2)
+                    .map(|t| [t[0], 1.0 - t[1]])
+                    .collect(),
+                colors: m
+                    .mesh
+                    .vertex_color
+                    .chunks_exact(3)
+                    .map(|c| [c[0], c[1], c[2]])
+                    .collect(),
+                indices: m.mesh.indices,
+            };
+            // Attributes only some vertices have are dropped and generated
+            // like ones no vertex has.
+            let count = data.positions.len();
+            if data.colors.len() != count {
+                data.colors.clear();
+            }
+            if data.tex_coords.len() != count {
+                data.tex_coords.clear();
+            }
+            if data.normals.len() != count {
+                data.normals.clear();
+                data.generate_normals(options.crease_angle.into());
+            }
+            if data.tex_coords.is_empty() {
+                data.generate_tex_coords(options.uv_projection);
+            }
+            (data, m.mesh.material_id.unwrap_or(0))
+        })
+        .collect()
+}
+
+pub async fn load_collider(
+    file_name: &str,
+    kind: physics::ColliderKind,
+) -> Result<physics::Shape, ResourceError> {
+    let obj_text = load_string(file_name).await?;
+    let (models, _) = tobj::load_obj_buf(
+        &mut BufReader::new(Cursor::new(&obj_text)),
         &tobj::LoadOptions {
             triangulate: true,
             single_index: true,
             ..Default::default()
         },
-        |p| async move {
-            let mat_text = load_string(&p).await.unwrap();
-            tobj::load_mtl_buf(&mut BufReader::new(Cursor::new(mat_text)))
-        },
+        |_| Ok(Default::default()),
     )
-    .await?;
+    .map_err(|e| ResourceError::from_obj(file_name, &obj_text, e))?;
 
-    let mut materials = Vec::new();
-    for m in obj_materials? {
-        let diffuse_texture = load_texture(&m.diffuse_texture, device, queue).await?;
-        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            layout,
-            entries: &[
-                wgpu::BindGroupEntry {
-                    binding: 0,
-                    resource: wgpu::BindingResource::TextureView(&diffuse_texture.view),
-                },
-                wgpu::BindGroupEntry {
-                    binding: 1,
-                    resource: wgpu::BindingResource::Sampler(&diffuse_texture.sampler),
-                },
-            ],
-            label: None,
-        });
+    let mut positions = Vec::new();
+    let mut indices = Vec::new();
+    for m in models {
+        let offset = positions.len() as u32;
+        positions.extend(m.mesh.positions.chunks_exact(3).map(|p| [p[0], p[1], p[2]]));
+        indices.extend(m.mesh.indices.iter().map(|i| i + offset));
+    }
 
-        materials.push(model::Material {
-            name: m.name,
-            diffuse_texture,
-            bind_group,
-        })
+    physics::collider_from_geometry(&positions, &indices, kind).ok_or_else(|| {
+        ResourceError::Parse {
+            file: file_name.to_string(),
+            line: None,
+            message: "no geometry".to_string(),
+        }
+    })
+}
+
+pub async fn load_heightfield(
+    file_name: &str,
+    scale: cgmath::Vector3<f32>,
+) -> Result<physics::Heightfield, ResourceError> {
+    let data = load_binary(file_name).await?;
+    let img =
+        image::load_from_memory(&data).map_err(|e| ResourceError::from_image(file_name, e))?;
+    physics::Heightfield::from_image(&img, scale).ok_or_else(|| ResourceError::Parse {
+        file: file_name.to_string(),
+        line: None,
+        message: format!(
+            "a heightfield needs at least 2x2 pixels, not {}x{}",
+            img.width(),
+            img.height()
+        ),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_attributes_are_regenerated() {
+        // A quad where only the first vertex has a texture coordinate and a
+        // normal.
+        let mesh = tobj::Mesh {
+            positions: vec![0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0, 1.0],
+            normals: vec![0.0, 1.0, 0.0],
+            texcoords: vec![0.5, 0.5],
+            indices: vec![0, 2, 1, 0, 3, 2],
+            ..Default::default()
+        };
+        let meshes = mesh_data(
+            vec![tobj::Model::new(mesh, "quad".to_string())],
+            &MeshOptions::default(),
+        );
+
+        let (data, _) = &meshes[0];
+        let count = data.positions.len();
+        assert!(count >= 4);
+        assert_eq!(data.normals.len(), count);
+        assert_eq!(data.tex_coords.len(), count);
+        assert!(data.colors.is_empty());
+        for normal in &data.normals {
+            assert!((normal[1] - 1.0).abs() < 1e-5, "{:?}", normal);
+        }
     }
 
-    let meshes = models
-        .into_iter()
-        .map(|m| {
-            let vertices = (0..m.mesh.positions.len() / 3)
-                .map(|i| model::ModelVertex {
-                    position: [
-                        m.mesh.positions[i * 3],
-                        m.mesh.positions[i * 3 + 1],
-                        m.mesh.positions[i * 3 + 2],
-                    ],
-                    tex_coords: [m.mesh.texcoords[i * 2], 1.0 - m.mesh.texcoords[i * 2 + 1]],
-                    normal: [
-                        m.mesh.normals[i * 3],
-                        m.mesh.normals[i * 3 + 1],
-                        m.mesh.normals[i * 3 + 2],
-                    ],
-                })
-                .collect::<Vec<_>>();
-
-            model::Mesh::new(
-                file_name.to_string(),
-                &device,
-                &vertices,
-                &m.mesh.indices,
-                m.mesh.material_id.unwrap_or(0),
-            )
-        })
-        .collect::<Vec<_>>();
+    #[test]
+    fn levels_of_detail_share_materials_and_skip_broken_files() {
+        let dir = std::env::temp_dir().join(format!("physics_engine_lods_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let triangle = "mtllib box.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl b\nf 1 2 3\n";
+        std::fs::write(
+            dir.join("box.mtl"),
+            "newmtl a\nKd 1 0 0\nnewmtl b\nKd 0 1 0\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("box.obj"), triangle).unwrap();
+        std::fs::write(dir.join("box_lod1.obj"), triangle).unwrap();
+        std::fs::write(dir.join("box_lod2.obj"), [0xff, 0xfe]).unwrap();
+        std::fs::write(dir.join("box_lod3.obj"), triangle).unwrap();
+        set_resource_root(ResourceRoot {
+            search_paths: vec![dir.clone()],
+        });
+
+        let source =
+            pollster::block_on(load_model_source("box.obj", &MeshOptions::default())).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
 
-    Ok(model::Model { meshes, materials })
+        assert_eq!(source.materials.len(), 2);
+        assert_eq!(
+            source.lod_files,
+            ["box_lod1.obj", "box_lod2.obj", "box_lod3.obj"]
+        );
+        assert_eq!(source.levels.len(), 3);
+        for level in &source.levels {
+            assert_eq!(level.len(), 1);
+            assert_eq!(level[0].1, 1);
+        }
+    }
 }
@@ -0,0 +1,130 @@
+//! Scene files listing the bodies placed at startup. Debug builds re-apply
+//! the scene when its file changes, so a layout can be tuned while the
+//! simulation runs.
+//!
+//! Each line of a scene is a statement, with `#` starting a comment:
+//!
+//! ```text
+//! # body <x> <y> <z> <scale x> <scale y> <scale z> [<axis x> <axis y> <axis z> <degrees>]
+//! body 0 2 0 1 1 1
+//! body 3 2 0 0.5 0.5 0.5 0 1 0 45
+//! ```
+
+use cgmath::prelude::*;
+
+use crate::physics;
+use crate::resources::{self, ResourceError};
+
+/// The scene loaded at startup.
+pub const SCENE_FILE: &str = "scene.txt";
+
+/// A body shaped like the model, stretched by `scale`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SceneBody {
+    pub transform: physics::Transform,
+    pub scale: cgmath::Vector3<f32>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scene {
+    pub bodies: Vec<SceneBody>,
+}
+
+impl Scene {
+    pub async fn load(file_name: &str) -> Result<Self, ResourceError> {
+        let text = resources::load_string(file_name).await?;
+        Self::parse(file_name, &text)
+    }
+
+    pub fn parse(file_name: &str, text: &str) -> Result<Self, ResourceError> {
+        let mut bodies = Vec::new();
+        for (i, line) in text.lines().enumerate() {
+            let error = |message: String| ResourceError::Parse {
+                file: file_name.to_string(),
+                line: Some(i + 1),
+                message,
+            };
+            let line = line.split('#').next().unwrap_or_default();
+            let mut words = line.split_whitespace();
+            let Some(statement) = words.next() else {
+                continue;
+            };
+            if statement != "body" {
+                return Err(error(format!("unknown statement `{}`", statement)));
+            }
+            let numbers = words
+                .map(|word| {
+                    word.parse::<f32>()
+                        .map_err(|_| error(format!("`{}` is not a number", word)))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let rotation = match numbers[..] {
+                [_, _, _, _, _, _] => cgmath::Quaternion::one(),
+                [_, _, _, _, _, _, x, y, z, degrees] => {
+                    let axis = cgmath::Vector3::new(x, y, z);
+                    if axis.magnitude2() == 0.0 {
+                        return Err(error("the rotation axis is zero".to_string()));
+                    }
+                    cgmath::Quaternion::from_axis_angle(axis.normalize(), cgmath::Deg(degrees))
+                }
+                _ => {
+                    return Err(error(format!(
+                        "a body takes 6 or 10 numbers, not {}",
+                        numbers.len()
+                    )))
+                }
+            };
+            bodies.push(SceneBody {
+                transform: physics::Transform::new(
+                    cgmath::Vector3::new(numbers[0], numbers[1], numbers[2]),
+                    rotation,
+                ),
+                scale: cgmath::Vector3::new(numbers[3], numbers[4], numbers[5]),
+            });
+        }
+        Ok(Self { bodies })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bodies_with_and_without_rotation() {
+        let scene = Scene::parse(
+            "scene.txt",
+            "# Two bodies\nbody 1 2 3 1 1 1\n\nbody 0 0 0 0.5 1 2 0 2 0 90 # turned\n",
+        )
+        .unwrap();
+
+        assert_eq!(scene.bodies.len(), 2);
+        assert_eq!(
+            scene.bodies[0].transform.position,
+            cgmath::Vector3::new(1.0, 2.0, 3.0)
+        );
+        assert_eq!(
+            scene.bodies[0].transform.rotation,
+            cgmath::Quaternion::one()
+        );
+        assert_eq!(scene.bodies[1].scale, cgmath::Vector3::new(0.5, 1.0, 2.0));
+        let turned = scene.bodies[1].transform.rotation * cgmath::Vector3::unit_x();
+        assert!(
+            (turned - -cgmath::Vector3::unit_z()).magnitude() < 1e-5,
+            "{:?}",
+            turned
+        );
+    }
+
+    #[test]
+    fn errors_name_the_line() {
+        let line = |text| match Scene::parse("scene.txt", text) {
+            Err(ResourceError::Parse { line, .. }) => line,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(line("body 0 0 0 1 1 1\nbox 0 0 0 1 1 1"), Some(2));
+        assert_eq!(line("\nbody 0 0 0 1 1"), Some(2));
+        assert_eq!(line("body 0 0 0 1 one 1"), Some(1));
+        assert_eq!(line("body 0 0 0 1 1 1 0 0 0 45"), Some(1));
+    }
+}
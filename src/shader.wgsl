@@ -1,16 +1,10 @@
+// Textured models, compiled after lighting.wgsl.
+
 // Vertex shader
 
-struct Light {
-    position: vec3<f32>,
-    color: vec3<f32>,
-}
 @group(2) @binding(0)
 var<uniform> light: Light;
 
-struct Camera {
-    view_pos: vec4<f32>,
-    view_proj: mat4x4<f32>,
-}
 @group(1) @binding(0)
 var<uniform> camera: Camera;
 
@@ -19,21 +13,26 @@ struct VertexInput {
     @location(1) tex_coords: vec2<f32>,
     @location(2) normal: vec3<f32>,
 }
-struct InstanceInput {
-    @location(5) model_matrix_0: vec4<f32>,
-    @location(6) model_matrix_1: vec4<f32>,
-    @location(7) model_matrix_2: vec4<f32>,
-    @location(8) model_matrix_3: vec4<f32>,
-    @location(9) normal_matrix_0: vec3<f32>,
-    @location(10) normal_matrix_1: vec3<f32>,
-    @location(11) normal_matrix_2: vec3<f32>,
-}
+
+@group(3) @binding(0)
+var<uniform> shading: Shading;
+@group(3) @binding(1)
+var t_irradiance: texture_cube<f32>;
+@group(3) @binding(2)
+var t_prefiltered: texture_cube<f32>;
+@group(3) @binding(3)
+var t_brdf: texture_2d<f32>;
+@group(3) @binding(4)
+var s_environment: sampler;
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
     @location(0) tex_coords: vec2<f32>,
     @location(1) world_normal: vec3<f32>,
     @location(2) world_position: vec3<f32>,
+    @location(3) color: vec4<f32>,
+    @location(4) emissive_scalar: vec4<f32>,
+    @location(5) @interpolate(flat) material: u32,
 }
 
 @vertex
@@ -41,25 +40,15 @@ fn vs_main(
     model: VertexInput,
     instance: InstanceInput,
 ) -> VertexOutput {
-    let model_matrix = mat4x4<f32>(
-        instance.model_matrix_0,
-        instance.model_matrix_1,
-        instance.model_matrix_2,
-        instance.model_matrix_3,
-    );
-
-    let normal_matrix = mat3x3<f32>(
-        instance.normal_matrix_0,
-        instance.normal_matrix_1,
-        instance.normal_matrix_2,
-    );
-
     var out: VertexOutput;
     out.tex_coords = model.tex_coords;
-    out.world_normal = normal_matrix * model.normal;
-    var world_position: vec4<f32> = model_matrix * vec4<f32>(model.position, 1.0);
+    out.world_normal = normalize(normal_matrix(instance) * model.normal);
+    var world_position: vec4<f32> = model_matrix(instance) * vec4<f32>(model.position, 1.0);
     out.world_position = world_position.xyz;
     out.clip_position = camera.view_proj * world_position;
+    out.color = instance.color;
+    out.emissive_scalar = instance.emissive_scalar;
+    out.material = instance.material;
     return out;
 }
 
@@ -70,25 +59,44 @@ var t_diffuse: texture_2d<f32>;
 @group(0)@binding(1)
 var s_diffuse: sampler;
 
-@fragment
-fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    let object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
-
-    let ambient_strength = 0.1;
-    let ambient_color = light.color * ambient_strength;
+fn shade(in: VertexOutput) -> vec4<f32> {
+    var object_color: vec4<f32> = textureSample(t_diffuse, s_diffuse, in.tex_coords);
+    if (instance_color_map(in.material) == COLOR_MAP_VIRIDIS) {
+        object_color = vec4<f32>(viridis(in.emissive_scalar.w), 1.0);
+    }
+    object_color = object_color * in.color;
 
-    let light_dir = normalize(light.position - in.world_position);
+    let result = light_surface(object_color.rgb, in.world_normal, in.world_position, instance_material(in.material))
+        + in.emissive_scalar.xyz;
 
-    let diffuse_strength = max(dot(in.world_normal, light_dir), 0.0);
-    let diffuse_color = light.color * diffuse_strength;
-
-    let view_dir = normalize(camera.view_pos.xyz - in.world_position);
-    let half_dir = normalize(view_dir + light_dir);
+    return vec4<f32>(result, object_color.a);
+}
 
-    let specular_strength = pow(max(dot(in.world_normal, half_dir), 0.0), 32.0);
-    let specular_color = specular_strength * light.color;
+@fragment
+fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
+    return shade(in);
+}
 
-    let result = (ambient_color + diffuse_color + specular_color) * object_color.xyz;
+struct OitOutput {
+    // Premultiplied color and alpha, summed with weights.
+    @location(0) accum: vec4<f32>,
+    // Product of the transmittances, in the first channel.
+    @location(1) revealage: vec4<f32>,
+}
 
-    return vec4<f32>(result, object_color.a);
+// Weighted blended order-independent transparency (McGuire and Bavoil 2013),
+// with the distance weight of their equation 10.
+@fragment
+fn fs_oit(in: VertexOutput) -> OitOutput {
+    let color = shade(in);
+    let z = distance(camera.view_pos.xyz, in.world_position);
+    let weight = color.a * clamp(
+        10.0 / (1e-5 + pow(z / 5.0, 2.0) + pow(z / 200.0, 6.0)),
+        1e-2,
+        3e3,
+    );
+    var out: OitOutput;
+    out.accum = vec4<f32>(color.rgb * color.a, color.a) * weight;
+    out.revealage = vec4<f32>(color.a);
+    return out;
 }
@@ -1,5 +1,6 @@
 use image::GenericImageView;
-use anyhow::*;
+
+use crate::resources::ResourceError;
 
 pub struct Texture {
     pub texture: wgpu::Texture,
@@ -7,43 +8,162 @@ pub struct Texture {
     pub sampler: wgpu::Sampler,
 }
 
+/// How a texture is filtered and addressed when sampled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SamplerSettings {
+    pub address_mode: wgpu::AddressMode,
+    pub mag_filter: wgpu::FilterMode,
+    pub min_filter: wgpu::FilterMode,
+    pub mipmap_filter: wgpu::FilterMode,
+    /// Maximum anisotropy from 1 to 16, only used when every filter is
+    /// linear and the device supports it.
+    pub anisotropy: u16,
+}
+
+impl Default for SamplerSettings {
+    /// Repeating trilinear filtering with 16x anisotropy.
+    fn default() -> Self {
+        Self {
+            address_mode: wgpu::AddressMode::Repeat,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Linear,
+            anisotropy: 16,
+        }
+    }
+}
+
+impl SamplerSettings {
+    pub fn create_sampler(&self, device: &wgpu::Device, label: Option<&str>) -> wgpu::Sampler {
+        let linear = [self.mag_filter, self.min_filter, self.mipmap_filter]
+            .iter()
+            .all(|&filter| filter == wgpu::FilterMode::Linear);
+        device.create_sampler(&wgpu::SamplerDescriptor {
+            label,
+            address_mode_u: self.address_mode,
+            address_mode_v: self.address_mode,
+            address_mode_w: self.address_mode,
+            mag_filter: self.mag_filter,
+            min_filter: self.min_filter,
+            mipmap_filter: self.mipmap_filter,
+            anisotropy_clamp: if linear {
+                self.anisotropy.clamp(1, 16)
+            } else {
+                1
+            },
+            ..Default::default()
+        })
+    }
+}
+
 impl Texture {
+    /// Decodes a KTX2 container or any image format the `image` crate reads,
+    /// including Radiance HDR.
     pub fn from_bytes(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
-        bytes: &[u8], 
-        label: &str
-    ) -> Result<Self> {
-        let img = image::load_from_memory(bytes)?;
-        Self::from_image(device, queue, &img, Some(label))
+        bytes: &[u8],
+        label: &str,
+        sampler: &SamplerSettings,
+    ) -> Result<Self, ResourceError> {
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            return Self::from_ktx2(device, queue, bytes, label, sampler);
+        }
+        let img =
+            image::load_from_memory(bytes).map_err(|e| ResourceError::from_image(label, e))?;
+        Self::from_image(device, queue, &img, Some(label), sampler)
     }
 
+    /// A `size`×`size` texture of 8×8 pixel light and dark gray squares,
+    /// which stands in for textures that are loading or failed to load.
+    pub fn checkerboard(device: &wgpu::Device, queue: &wgpu::Queue, size: u32) -> Self {
+        let img = image::RgbaImage::from_fn(size, size, |x, y| {
+            let value = if (x / 8 + y / 8) % 2 == 0 { 200 } else { 80 };
+            image::Rgba([value, value, value, 255])
+        });
+        let sampler = SamplerSettings {
+            mag_filter: wgpu::FilterMode::Nearest,
+            ..Default::default()
+        };
+        Self::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(img),
+            Some("checkerboard_texture"),
+            &sampler,
+        )
+        .expect("small textures fit on every device")
+    }
+
+    /// A single pixel of `color`, for materials without a texture.
+    pub fn from_color(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        color: [u8; 4],
+        label: &str,
+    ) -> Self {
+        let img = image::RgbaImage::from_pixel(1, 1, image::Rgba(color));
+        Self::from_image(
+            device,
+            queue,
+            &image::DynamicImage::ImageRgba8(img),
+            Some(label),
+            &SamplerSettings::default(),
+        )
+        .expect("small textures fit on every device")
+    }
+
+    /// Uploads `img` with a full mip chain. Floating point images keep their
+    /// range in `Rgba16Float`, everything else is treated as sRGB color.
     pub fn from_image(
         device: &wgpu::Device,
         queue: &wgpu::Queue,
         img: &image::DynamicImage,
-        label: Option<&str>
-    ) -> Result<Self> {
-        let rgba = img.to_rgba8();
+        label: Option<&str>,
+        sampler: &SamplerSettings,
+    ) -> Result<Self, ResourceError> {
         let dimensions = img.dimensions();
+        check_size(
+            device,
+            label.unwrap_or("texture"),
+            dimensions.0,
+            dimensions.1,
+        )?;
+        let (format, data) = match img {
+            image::DynamicImage::ImageRgb32F(_) | image::DynamicImage::ImageRgba32F(_) => {
+                let halves = img
+                    .to_rgba32f()
+                    .iter()
+                    .map(|&x| f16_bits(x))
+                    .collect::<Vec<_>>();
+                (
+                    wgpu::TextureFormat::Rgba16Float,
+                    bytemuck::cast_slice(&halves).to_vec(),
+                )
+            }
+            _ => (
+                wgpu::TextureFormat::Rgba8UnormSrgb,
+                img.to_rgba8().into_raw(),
+            ),
+        };
 
         let size = wgpu::Extent3d {
             width: dimensions.0,
             height: dimensions.1,
             depth_or_array_layers: 1,
         };
-        let texture = device.create_texture(
-            &wgpu::TextureDescriptor {
-                label,
-                size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: wgpu::TextureFormat::Rgba8UnormSrgb,
-                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
-                view_formats: &[],
-            }
-        );
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count: size.max_mips(wgpu::TextureDimension::D2),
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
 
         queue.write_texture(
             wgpu::ImageCopyTexture {
@@ -52,67 +172,392 @@ impl Texture {
                 mip_level: 0,
                 origin: wgpu::Origin3d::ZERO,
             },
-            &rgba,
+            &data,
             wgpu::ImageDataLayout {
                 offset: 0,
-                bytes_per_row: Some(4 * dimensions.0),
+                bytes_per_row: Some(format.block_size(None).unwrap() * dimensions.0),
                 rows_per_image: Some(dimensions.1),
             },
             size,
         );
+        generate_mipmaps(device, queue, &texture);
 
         let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-        let sampler = device.create_sampler(
-            &wgpu::SamplerDescriptor {
-                address_mode_u: wgpu::AddressMode::ClampToEdge,
-                address_mode_v: wgpu::AddressMode::ClampToEdge,
-                address_mode_w: wgpu::AddressMode::ClampToEdge,
-                mag_filter: wgpu::FilterMode::Linear,
-                min_filter: wgpu::FilterMode::Nearest,
-                mipmap_filter: wgpu::FilterMode::Nearest,
-                ..Default::default()
-            }
-        );
-        
-        Ok(Self { texture, view, sampler })
+        let sampler = sampler.create_sampler(device, label);
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
+    }
+
+    /// Loads a 2D KTX2 texture with its own mip levels, generating them when
+    /// the file has only the base level. Supercompressed files, including
+    /// Basis Universal, are not supported.
+    pub fn from_ktx2(
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        bytes: &[u8],
+        label: &str,
+        sampler: &SamplerSettings,
+    ) -> Result<Self, ResourceError> {
+        let truncated = || ResourceError::Parse {
+            file: label.to_string(),
+            line: None,
+            message: "KTX2 file is truncated".to_string(),
+        };
+        let unsupported = |message: String| ResourceError::UnsupportedFormat {
+            file: label.to_string(),
+            message,
+        };
+        let word = |offset: usize| -> Result<u32, ResourceError> {
+            let word = bytes.get(offset..offset + 4).ok_or_else(truncated)?;
+            Ok(u32::from_le_bytes(word.try_into().unwrap()))
+        };
+        let vk_format = word(12)?;
+        let (width, height, depth) = (word(20)?, word(24)?, word(28)?);
+        let (layers, faces, levels) = (word(32)?, word(36)?, word(40)?.max(1));
+        let supercompression = word(44)?;
+        if vk_format == 0 || supercompression == 1 {
+            return Err(unsupported(
+                "Basis Universal KTX2 textures need transcoding, which is not supported"
+                    .to_string(),
+            ));
+        }
+        if supercompression != 0 {
+            return Err(unsupported(format!(
+                "KTX2 supercompression scheme {supercompression} is not supported"
+            )));
+        }
+        if height == 0 || depth > 1 || layers > 1 || faces > 1 {
+            return Err(unsupported(
+                "only 2D KTX2 textures are supported".to_string(),
+            ));
+        }
+        let format = ktx2_format(vk_format)
+            .ok_or_else(|| unsupported(format!("KTX2 format {vk_format} is not supported")))?;
+        if !device.features().contains(format.required_features()) {
+            return Err(ResourceError::Gpu {
+                file: label.to_string(),
+                message: format!("{format:?} textures are not supported by this device"),
+            });
+        }
+        check_size(device, label, width, height)?;
+
+        // The level index follows the 80 byte header, largest level first.
+        let mut data = Vec::new();
+        for level in 0..levels as usize {
+            let entry = 80 + level * 24;
+            let offset = word(entry)? as usize;
+            let length = word(entry + 8)? as usize;
+            let level_data = bytes.get(offset..offset + length).ok_or_else(truncated)?;
+            data.extend_from_slice(level_data);
+        }
+
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+        let generate = levels == 1
+            && format
+                .guaranteed_format_features(device.features())
+                .allowed_usages
+                .contains(wgpu::TextureUsages::RENDER_ATTACHMENT);
+        let mut usage = wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST;
+        if generate {
+            usage |= wgpu::TextureUsages::RENDER_ATTACHMENT;
+        }
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: if generate {
+                size.max_mips(wgpu::TextureDimension::D2)
+            } else {
+                levels
+            },
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage,
+            view_formats: &[],
+        };
+        let texture = if generate {
+            let texture = device.create_texture(&desc);
+            queue.write_texture(
+                texture.as_image_copy(),
+                &data,
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(format.block_size(None).unwrap() * width),
+                    rows_per_image: Some(height),
+                },
+                size,
+            );
+            generate_mipmaps(device, queue, &texture);
+            texture
+        } else {
+            use wgpu::util::DeviceExt;
+            device.create_texture_with_data(queue, &desc, &data)
+        };
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = sampler.create_sampler(device, Some(label));
+
+        Ok(Self {
+            texture,
+            view,
+            sampler,
+        })
     }
 
     pub const DEPTH_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Depth32Float;
 
-    pub fn create_depth_texture(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration, label: &str) -> Self {
-            let size = wgpu::Extent3d {
+    pub fn create_depth_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let size = wgpu::Extent3d {
+            width: config.width,
+            height: config.height,
+            depth_or_array_layers: 1,
+        };
+        let desc = wgpu::TextureDescriptor {
+            label: Some(label),
+            size,
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format: Self::DEPTH_FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        };
+        let texture = device.create_texture(&desc);
+
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            address_mode_u: wgpu::AddressMode::ClampToEdge,
+            address_mode_v: wgpu::AddressMode::ClampToEdge,
+            address_mode_w: wgpu::AddressMode::ClampToEdge,
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            mipmap_filter: wgpu::FilterMode::Nearest,
+            compare: Some(wgpu::CompareFunction::LessEqual),
+            lod_min_clamp: 0.0,
+            lod_max_clamp: 100.0,
+            ..Default::default()
+        });
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+
+    /// Multisampled color target of the surface size, to be resolved into a
+    /// single-sampled one.
+    pub fn create_msaa_texture(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        format: wgpu::TextureFormat,
+        sample_count: u32,
+        label: &str,
+    ) -> Self {
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(label),
+            size: wgpu::Extent3d {
                 width: config.width,
                 height: config.height,
                 depth_or_array_layers: 1,
-            };
-            let desc = wgpu::TextureDescriptor {
-                label: Some(label),
-                size,
-                mip_level_count: 1,
-                sample_count: 1,
-                dimension: wgpu::TextureDimension::D2,
-                format: Self::DEPTH_FORMAT,
-                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
-                    | wgpu::TextureUsages::TEXTURE_BINDING,
-                view_formats: &[],
-            };
-            let texture = device.create_texture(&desc);
+            },
+            mip_level_count: 1,
+            sample_count,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            view_formats: &[],
+        });
 
-            let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
-            let sampler = device.create_sampler(
-                &wgpu::SamplerDescriptor { 
-                    address_mode_u: wgpu::AddressMode::ClampToEdge,
-                    address_mode_v: wgpu::AddressMode::ClampToEdge,
-                    address_mode_w: wgpu::AddressMode::ClampToEdge,
-                    mag_filter: wgpu::FilterMode::Linear,
-                    min_filter: wgpu::FilterMode::Linear,
-                    mipmap_filter: wgpu::FilterMode::Nearest,
-                    compare: Some(wgpu::CompareFunction::LessEqual),
-                    lod_min_clamp: 0.0,
-                    lod_max_clamp: 100.0,
-                    ..Default::default()
-                }
-            );
-        Self { texture, view, sampler }
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor::default());
+        Self {
+            texture,
+            view,
+            sampler,
+        }
+    }
+}
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+
+/// The texture format for a Vulkan `VkFormat`, for the filterable formats
+/// KTX2 files commonly hold.
+fn ktx2_format(vk_format: u32) -> Option<wgpu::TextureFormat> {
+    use wgpu::{AstcBlock, AstcChannel, TextureFormat};
+    Some(match vk_format {
+        9 => TextureFormat::R8Unorm,
+        16 => TextureFormat::Rg8Unorm,
+        37 => TextureFormat::Rgba8Unorm,
+        43 => TextureFormat::Rgba8UnormSrgb,
+        44 => TextureFormat::Bgra8Unorm,
+        50 => TextureFormat::Bgra8UnormSrgb,
+        97 => TextureFormat::Rgba16Float,
+        122 => TextureFormat::Rg11b10Float,
+        133 => TextureFormat::Bc1RgbaUnorm,
+        134 => TextureFormat::Bc1RgbaUnormSrgb,
+        137 => TextureFormat::Bc3RgbaUnorm,
+        138 => TextureFormat::Bc3RgbaUnormSrgb,
+        139 => TextureFormat::Bc4RUnorm,
+        141 => TextureFormat::Bc5RgUnorm,
+        143 => TextureFormat::Bc6hRgbUfloat,
+        145 => TextureFormat::Bc7RgbaUnorm,
+        146 => TextureFormat::Bc7RgbaUnormSrgb,
+        151 => TextureFormat::Etc2Rgba8Unorm,
+        152 => TextureFormat::Etc2Rgba8UnormSrgb,
+        157 => TextureFormat::Astc {
+            block: AstcBlock::B4x4,
+            channel: AstcChannel::Unorm,
+        },
+        158 => TextureFormat::Astc {
+            block: AstcBlock::B4x4,
+            channel: AstcChannel::UnormSrgb,
+        },
+        _ => return None,
+    })
+}
+
+/// Fails with a GPU error for textures larger than the device allows.
+fn check_size(
+    device: &wgpu::Device,
+    label: &str,
+    width: u32,
+    height: u32,
+) -> Result<(), ResourceError> {
+    let max = device.limits().max_texture_dimension_2d;
+    if width > max || height > max {
+        return Err(ResourceError::Gpu {
+            file: label.to_string(),
+            message: format!("{width}x{height} exceeds the device's {max}x{max} limit"),
+        });
+    }
+    Ok(())
+}
+
+/// Fills every mip level of `texture` below the first by rendering a
+/// filtered copy of the level above.
+pub fn generate_mipmaps(device: &wgpu::Device, queue: &wgpu::Queue, texture: &wgpu::Texture) {
+    if texture.mip_level_count() < 2 {
+        return;
+    }
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Mipmap Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("mipmap.wgsl").into()),
+    });
+    let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Mipmap Pipeline"),
+        layout: None,
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(texture.format().into())],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    });
+    let bind_group_layout = pipeline.get_bind_group_layout(0);
+    let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+        label: Some("mipmap_sampler"),
+        address_mode_u: wgpu::AddressMode::ClampToEdge,
+        address_mode_v: wgpu::AddressMode::ClampToEdge,
+        address_mode_w: wgpu::AddressMode::ClampToEdge,
+        mag_filter: wgpu::FilterMode::Linear,
+        min_filter: wgpu::FilterMode::Linear,
+        mipmap_filter: wgpu::FilterMode::Nearest,
+        ..Default::default()
+    });
+
+    let views = (0..texture.mip_level_count())
+        .map(|mip| {
+            texture.create_view(&wgpu::TextureViewDescriptor {
+                label: Some("mip"),
+                base_mip_level: mip,
+                mip_level_count: Some(1),
+                ..Default::default()
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+        label: Some("Mipmap Encoder"),
+    });
+    for pair in views.windows(2) {
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: &bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&pair[0]),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&sampler),
+                },
+            ],
+            label: None,
+        });
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Mipmap Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: &pair[1],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&pipeline);
+        render_pass.set_bind_group(0, &bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+    queue.submit(std::iter::once(encoder.finish()));
+}
+
+/// Rounds to the nearest half precision float, overflowing to infinity and
+/// underflowing through subnormals to zero.
+fn f16_bits(x: f32) -> u16 {
+    let bits = x.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xff) as i32 - 127 + 15;
+    let mantissa = bits & 0x7f_ffff;
+    if x.is_nan() {
+        sign | 0x7e00
+    } else if exponent >= 0x1f {
+        sign | 0x7c00
+    } else if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
+        }
+        let shift = (14 - exponent) as u32;
+        let m = mantissa | 0x80_0000;
+        sign | ((m + (1 << (shift - 1))) >> shift) as u16
+    } else {
+        let half = sign | ((exponent as u16) << 10) | (mantissa >> 13) as u16;
+        half + ((mantissa >> 12) & 1) as u16
     }
 }
@@ -0,0 +1,188 @@
+use std::collections::VecDeque;
+
+use crate::post::HDR_FORMAT;
+use crate::texture;
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct TrailVertex {
+    position: [f32; 3],
+    color: [f32; 4],
+}
+
+impl TrailVertex {
+    const ATTRIBUTES: [wgpu::VertexAttribute; 2] =
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<TrailVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBUTES,
+        }
+    }
+}
+
+/// Lines through the last positions of a set of moving points, fading out
+/// toward the oldest. Drawn blended over the scene without writing depth.
+pub struct Trails {
+    /// Positions kept per trail.
+    length: usize,
+    history: Vec<VecDeque<[f32; 3]>>,
+    colors: Vec<[f32; 3]>,
+    pipeline_layout: wgpu::PipelineLayout,
+    pipeline: wgpu::RenderPipeline,
+    vertex_buffer: wgpu::Buffer,
+    /// Vertices the buffer has room for.
+    capacity: usize,
+    vertex_count: u32,
+}
+
+impl Trails {
+    pub fn new(
+        device: &wgpu::Device,
+        camera_bind_group_layout: &wgpu::BindGroupLayout,
+        sample_count: u32,
+        length: usize,
+    ) -> Self {
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trail Pipeline Layout"),
+            bind_group_layouts: &[camera_bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let pipeline = create_pipeline(device, &pipeline_layout, sample_count);
+        Self {
+            length: length.max(2),
+            history: Vec::new(),
+            colors: Vec::new(),
+            pipeline_layout,
+            pipeline,
+            vertex_buffer: create_vertex_buffer(device, 1),
+            capacity: 1,
+            vertex_count: 0,
+        }
+    }
+
+    pub fn set_sample_count(&mut self, device: &wgpu::Device, sample_count: u32) {
+        self.pipeline = create_pipeline(device, &self.pipeline_layout, sample_count);
+    }
+
+    /// Appends the current position of every trail, dropping the oldest ones
+    /// past the length. A change in the number of points starts over.
+    pub fn record(&mut self, points: impl ExactSizeIterator<Item = ([f32; 3], [f32; 3])>) {
+        if points.len() != self.history.len() {
+            self.history = vec![VecDeque::with_capacity(self.length); points.len()];
+            self.colors = vec![[1.0; 3]; points.len()];
+        }
+        for ((history, color), (position, new_color)) in
+            self.history.iter_mut().zip(&mut self.colors).zip(points)
+        {
+            if history.len() == self.length {
+                history.pop_front();
+            }
+            history.push_back(position);
+            *color = new_color;
+        }
+    }
+
+    /// Rebuilds the line segments from the recorded positions.
+    pub fn upload(&mut self, device: &wgpu::Device, queue: &wgpu::Queue) {
+        let mut vertices = Vec::new();
+        for (history, color) in self.history.iter().zip(&self.colors) {
+            let fade = |i: usize| {
+                let alpha = (i + 1) as f32 / history.len() as f32;
+                [color[0], color[1], color[2], alpha * alpha]
+            };
+            for i in 1..history.len() {
+                vertices.push(TrailVertex {
+                    position: history[i - 1],
+                    color: fade(i - 1),
+                });
+                vertices.push(TrailVertex {
+                    position: history[i],
+                    color: fade(i),
+                });
+            }
+        }
+
+        if vertices.len() > self.capacity {
+            self.capacity = vertices.len().next_power_of_two();
+            self.vertex_buffer = create_vertex_buffer(device, self.capacity);
+        }
+        if !vertices.is_empty() {
+            queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+        }
+        self.vertex_count = vertices.len() as u32;
+    }
+
+    /// Draws the trails as of the last `upload`. Leaves the trail pipeline
+    /// bound.
+    pub fn draw<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        camera_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.vertex_count == 0 {
+            return;
+        }
+        render_pass.set_pipeline(&self.pipeline);
+        render_pass.set_bind_group(0, camera_bind_group, &[]);
+        render_pass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        render_pass.draw(0..self.vertex_count, 0..1);
+    }
+}
+
+fn create_vertex_buffer(device: &wgpu::Device, capacity: usize) -> wgpu::Buffer {
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some("Trail Vertex Buffer"),
+        size: (capacity * std::mem::size_of::<TrailVertex>()) as wgpu::BufferAddress,
+        usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
+
+fn create_pipeline(
+    device: &wgpu::Device,
+    layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Trail Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("trail.wgsl").into()),
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Trail Pipeline"),
+        layout: Some(layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[TrailVertex::desc()],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_main",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: HDR_FORMAT,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState {
+            topology: wgpu::PrimitiveTopology::LineList,
+            ..Default::default()
+        },
+        depth_stencil: Some(wgpu::DepthStencilState {
+            format: texture::Texture::DEPTH_FORMAT,
+            depth_write_enabled: false,
+            depth_compare: wgpu::CompareFunction::Less,
+            stencil: wgpu::StencilState::default(),
+            bias: wgpu::DepthBiasState::default(),
+        }),
+        multisample: wgpu::MultisampleState {
+            count: sample_count,
+            mask: !0,
+            alpha_to_coverage_enabled: false,
+        },
+        multiview: None,
+    })
+}
@@ -0,0 +1,417 @@
+use std::ops::Range;
+
+use crate::culling::View;
+use crate::model::{self, InstanceBuffer, Vertex};
+use crate::{post, texture, Blending};
+
+/// Format of the weighted blended revealage target, a single transmittance.
+pub const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransparencyMode {
+    /// Instances alpha blended back to front. Exact between separate
+    /// instances, but not where they intersect.
+    Sorted,
+    /// Weighted blended order-independent transparency, which needs no
+    /// sorting but only approximates the blend order by distance.
+    WeightedBlended,
+}
+
+impl TransparencyMode {
+    pub fn next(self) -> Self {
+        match self {
+            TransparencyMode::Sorted => TransparencyMode::WeightedBlended,
+            TransparencyMode::WeightedBlended => TransparencyMode::Sorted,
+        }
+    }
+}
+
+/// Size-dependent targets of weighted blended transparency, recreated on
+/// resize.
+struct Targets {
+    accum_view: wgpu::TextureView,
+    revealage_view: wgpu::TextureView,
+    /// Multisampled targets resolved into the views above, absent without
+    /// MSAA.
+    msaa: Option<[texture::Texture; 2]>,
+    composite_bind_group: wgpu::BindGroup,
+}
+
+/// Draws the translucent instances of a model after the opaque scene with
+/// alpha blending and depth testing but no depth writes, either sorted back
+/// to front or with weighted blended order-independent transparency.
+pub struct TransparentPass {
+    pub mode: TransparencyMode,
+    instances: InstanceBuffer,
+    /// Runs of consecutive sorted instances sharing a level of detail.
+    batches: Vec<(usize, Range<usize>)>,
+    sorted_pipeline: wgpu::RenderPipeline,
+    oit_pipeline: wgpu::RenderPipeline,
+    composite_layout: wgpu::BindGroupLayout,
+    composite_pipeline: wgpu::RenderPipeline,
+    targets: Targets,
+}
+
+impl TransparentPass {
+    pub fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+        shader: &str,
+    ) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                multisampled: false,
+                view_dimension: wgpu::TextureViewDimension::D2,
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+            },
+            count: None,
+        };
+        let composite_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            entries: &[texture_entry(0), texture_entry(1)],
+            label: Some("composite_bind_group_layout"),
+        });
+        let composite_pipeline = create_composite_pipeline(device, &composite_layout);
+        let (sorted_pipeline, oit_pipeline) =
+            create_pipelines(device, render_pipeline_layout, sample_count, shader);
+        let targets = Targets::new(device, config, sample_count, &composite_layout);
+
+        Self {
+            mode: TransparencyMode::Sorted,
+            instances: InstanceBuffer::new(device, 1),
+            batches: Vec::new(),
+            sorted_pipeline,
+            oit_pipeline,
+            composite_layout,
+            composite_pipeline,
+            targets,
+        }
+    }
+
+    /// Rebuilds the pipelines and targets for a new MSAA sample count.
+    pub fn set_sample_count(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        render_pipeline_layout: &wgpu::PipelineLayout,
+        sample_count: u32,
+        shader: &str,
+    ) {
+        self.set_pipelines(create_pipelines(
+            device,
+            render_pipeline_layout,
+            sample_count,
+            shader,
+        ));
+        self.resize(device, config, sample_count);
+    }
+
+    /// Replaces the sorted and weighted blended pipelines, as made by
+    /// [`create_pipelines`].
+    pub fn set_pipelines(&mut self, pipelines: (wgpu::RenderPipeline, wgpu::RenderPipeline)) {
+        (self.sorted_pipeline, self.oit_pipeline) = pipelines;
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+    ) {
+        self.targets = Targets::new(device, config, sample_count, &self.composite_layout);
+    }
+
+    /// Collects the visible translucent `instances` of `model`, farthest
+    /// first.
+    pub fn prepare<'i>(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        view: &View,
+        model: &model::Model,
+        instances: impl IntoIterator<Item = &'i model::Instance>,
+    ) {
+        use cgmath::MetricSpace;
+
+        let local_sphere = model.bounding_sphere();
+        let mut visible = instances
+            .into_iter()
+            .filter(|instance| instance.is_transparent())
+            .filter_map(|instance| {
+                let sphere = instance.bounding_sphere(local_sphere);
+                view.frustum.intersects_sphere(sphere).then(|| {
+                    let level = model.select_lod(view.screen_size(sphere));
+                    (view.eye.distance2(sphere.center), level, instance.to_raw())
+                })
+            })
+            .collect::<Vec<_>>();
+        visible.sort_by(|a, b| b.0.total_cmp(&a.0));
+
+        self.batches.clear();
+        for (i, &(_, level, _)) in visible.iter().enumerate() {
+            match self.batches.last_mut() {
+                Some((last, range)) if *last == level => range.end = i + 1,
+                _ => self.batches.push((level, i..i + 1)),
+            }
+        }
+        let data = visible
+            .into_iter()
+            .map(|(_, _, raw)| raw)
+            .collect::<Vec<_>>();
+        if !data.is_empty() {
+            self.instances.write(device, queue, &data);
+        }
+    }
+
+    /// Draws the prepared instances into the main pass in sorted mode. Leaves
+    /// the transparent pipeline bound.
+    pub fn draw_sorted<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a model::Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+        shading_bind_group: &'a wgpu::BindGroup,
+    ) {
+        if self.mode != TransparencyMode::Sorted || self.batches.is_empty() {
+            return;
+        }
+        render_pass.set_pipeline(&self.sorted_pipeline);
+        render_pass.set_bind_group(3, shading_bind_group, &[]);
+        self.draw_batches(render_pass, model, camera_bind_group, light_bind_group);
+    }
+
+    /// Accumulates the prepared instances against the scene's depth and
+    /// composites them onto `output` in weighted blended mode.
+    #[allow(clippy::too_many_arguments)]
+    pub fn render_weighted(
+        &self,
+        encoder: &mut wgpu::CommandEncoder,
+        depth_view: &wgpu::TextureView,
+        output: &wgpu::TextureView,
+        model: &model::Model,
+        camera_bind_group: &wgpu::BindGroup,
+        light_bind_group: &wgpu::BindGroup,
+        shading_bind_group: &wgpu::BindGroup,
+    ) {
+        if self.mode != TransparencyMode::WeightedBlended || self.batches.is_empty() {
+            return;
+        }
+        let targets = &self.targets;
+        let [accum_msaa, revealage_msaa] = match &targets.msaa {
+            Some([accum, revealage]) => [Some(accum), Some(revealage)],
+            None => [None, None],
+        };
+
+        {
+            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Transparency Accumulation Pass"),
+                color_attachments: &[
+                    attachment(&targets.accum_view, accum_msaa, wgpu::Color::TRANSPARENT),
+                    attachment(&targets.revealage_view, revealage_msaa, wgpu::Color::WHITE),
+                ],
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: depth_view,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: wgpu::StoreOp::Store,
+                    }),
+                    stencil_ops: None,
+                }),
+                occlusion_query_set: None,
+                timestamp_writes: None,
+            });
+            render_pass.set_pipeline(&self.oit_pipeline);
+            render_pass.set_bind_group(3, shading_bind_group, &[]);
+            self.draw_batches(&mut render_pass, model, camera_bind_group, light_bind_group);
+        }
+
+        let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+            label: Some("Transparency Composite Pass"),
+            color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                view: output,
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Load,
+                    store: wgpu::StoreOp::Store,
+                },
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: None,
+        });
+        render_pass.set_pipeline(&self.composite_pipeline);
+        render_pass.set_bind_group(0, &targets.composite_bind_group, &[]);
+        render_pass.draw(0..3, 0..1);
+    }
+
+    fn draw_batches<'a>(
+        &'a self,
+        render_pass: &mut wgpu::RenderPass<'a>,
+        model: &'a model::Model,
+        camera_bind_group: &'a wgpu::BindGroup,
+        light_bind_group: &'a wgpu::BindGroup,
+    ) {
+        use model::DrawModel;
+
+        for (level, range) in &self.batches {
+            render_pass.set_vertex_buffer(1, self.instances.slice_range(range.clone()));
+            render_pass.draw_model_lod_instanced(
+                model,
+                *level,
+                0..range.len() as u32,
+                camera_bind_group,
+                light_bind_group,
+            );
+        }
+    }
+}
+
+impl Targets {
+    fn new(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        sample_count: u32,
+        composite_layout: &wgpu::BindGroupLayout,
+    ) -> Self {
+        let create_view = |label, format| {
+            device
+                .create_texture(&wgpu::TextureDescriptor {
+                    label: Some(label),
+                    size: wgpu::Extent3d {
+                        width: config.width,
+                        height: config.height,
+                        depth_or_array_layers: 1,
+                    },
+                    mip_level_count: 1,
+                    sample_count: 1,
+                    dimension: wgpu::TextureDimension::D2,
+                    format,
+                    usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                        | wgpu::TextureUsages::TEXTURE_BINDING,
+                    view_formats: &[],
+                })
+                .create_view(&wgpu::TextureViewDescriptor::default())
+        };
+        let accum_view = create_view("accum_texture", post::HDR_FORMAT);
+        let revealage_view = create_view("revealage_texture", REVEALAGE_FORMAT);
+        let msaa = (sample_count > 1).then(|| {
+            [
+                (post::HDR_FORMAT, "accum_msaa_texture"),
+                (REVEALAGE_FORMAT, "revealage_msaa_texture"),
+            ]
+            .map(|(format, label)| {
+                texture::Texture::create_msaa_texture(device, config, format, sample_count, label)
+            })
+        });
+
+        let composite_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            layout: composite_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&accum_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&revealage_view),
+                },
+            ],
+            label: Some("composite_bind_group"),
+        });
+
+        Self {
+            accum_view,
+            revealage_view,
+            msaa,
+            composite_bind_group,
+        }
+    }
+}
+
+/// A cleared color attachment, rendered through `msaa` and resolved into
+/// `resolved` when multisampling.
+fn attachment<'t>(
+    resolved: &'t wgpu::TextureView,
+    msaa: Option<&'t texture::Texture>,
+    clear: wgpu::Color,
+) -> Option<wgpu::RenderPassColorAttachment<'t>> {
+    Some(wgpu::RenderPassColorAttachment {
+        view: msaa.map_or(resolved, |t| &t.view),
+        resolve_target: msaa.map(|_| resolved),
+        ops: wgpu::Operations {
+            load: wgpu::LoadOp::Clear(clear),
+            store: wgpu::StoreOp::Store,
+        },
+    })
+}
+
+/// The sorted and the weighted blended variants of the material pipeline.
+/// Creates the sorted and weighted blended pipelines from the WGSL of the
+/// main shader.
+pub fn create_pipelines(
+    device: &wgpu::Device,
+    render_pipeline_layout: &wgpu::PipelineLayout,
+    sample_count: u32,
+    shader: &str,
+) -> (wgpu::RenderPipeline, wgpu::RenderPipeline) {
+    let pipeline = |blending| {
+        crate::create_render_pipeline(
+            device,
+            render_pipeline_layout,
+            post::HDR_FORMAT,
+            Some(texture::Texture::DEPTH_FORMAT),
+            sample_count,
+            blending,
+            &[model::ModelVertex::desc(), model::InstanceRaw::desc()],
+            wgpu::ShaderModuleDescriptor {
+                label: Some("Transparent Shader"),
+                source: wgpu::ShaderSource::Wgsl(shader.into()),
+            },
+        )
+    };
+    (
+        pipeline(Blending::Alpha),
+        pipeline(Blending::WeightedBlended),
+    )
+}
+
+fn create_composite_pipeline(
+    device: &wgpu::Device,
+    composite_layout: &wgpu::BindGroupLayout,
+) -> wgpu::RenderPipeline {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("Composite Shader"),
+        source: wgpu::ShaderSource::Wgsl(include_str!("transparency.wgsl").into()),
+    });
+    let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+        label: Some("Composite Pipeline Layout"),
+        bind_group_layouts: &[composite_layout],
+        push_constant_ranges: &[],
+    });
+    device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+        label: Some("Composite Pipeline"),
+        layout: Some(&layout),
+        vertex: wgpu::VertexState {
+            module: &shader,
+            entry_point: "vs_main",
+            buffers: &[],
+        },
+        fragment: Some(wgpu::FragmentState {
+            module: &shader,
+            entry_point: "fs_composite",
+            targets: &[Some(wgpu::ColorTargetState {
+                format: post::HDR_FORMAT,
+                blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                write_mask: wgpu::ColorWrites::ALL,
+            })],
+        }),
+        primitive: wgpu::PrimitiveState::default(),
+        depth_stencil: None,
+        multisample: wgpu::MultisampleState::default(),
+        multiview: None,
+    })
+}
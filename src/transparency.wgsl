@@ -0,0 +1,25 @@
+// Resolves the weighted blended transparency targets over the opaque scene.
+
+@group(0) @binding(0)
+var t_accum: texture_2d<f32>;
+@group(0) @binding(1)
+var t_revealage: texture_2d<f32>;
+
+@vertex
+fn vs_main(@builtin(vertex_index) index: u32) -> @builtin(position) vec4<f32> {
+    let uv = vec2<f32>(f32((index << 1u) & 2u), f32(index & 2u));
+    return vec4<f32>(uv * vec2<f32>(2.0, -2.0) + vec2<f32>(-1.0, 1.0), 0.0, 1.0);
+}
+
+@fragment
+fn fs_composite(@builtin(position) position: vec4<f32>) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(position.xy);
+    let revealage = textureLoad(t_revealage, coords, 0).r;
+    if (revealage >= 1.0) {
+        discard;
+    }
+    let accum = textureLoad(t_accum, coords, 0);
+    // Blended over the scene by alpha, so the average color is covered by
+    // one minus the revealage.
+    return vec4<f32>(accum.rgb / max(accum.a, 1e-5), 1.0 - revealage);
+}